@@ -0,0 +1,15 @@
+-- Add migration script here
+BEGIN;
+CREATE TABLE password_reset_tokens (
+    "token_hash" TEXT NOT NULL,
+    "profile_id" UUID NOT NULL,
+    "expires_at" timestamptz(3) NOT NULL,
+    "used_at" timestamptz(3),
+    "created_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (token_hash),
+    CONSTRAINT fk_profile_password_reset FOREIGN KEY(profile_id) REFERENCES profile(id) ON DELETE CASCADE
+);
+-- Bumped whenever credentials change so that outstanding sessions and JWTs can be revoked
+ALTER TABLE profile
+ADD COLUMN token_version INT NOT NULL DEFAULT 0;
+COMMIT;
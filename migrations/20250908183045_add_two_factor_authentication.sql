@@ -0,0 +1,23 @@
+-- Add migration script here
+BEGIN;
+ALTER TABLE profile
+ADD COLUMN totp_secret TEXT NULL,
+    ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT false;
+CREATE TABLE totp_recovery_codes (
+    "profile_id" UUID NOT NULL,
+    "code_hash" TEXT NOT NULL,
+    "used_at" timestamptz(3),
+    "created_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (profile_id, code_hash),
+    CONSTRAINT fk_profile_recovery_code FOREIGN KEY(profile_id) REFERENCES profile(id) ON DELETE CASCADE
+);
+CREATE TABLE login_challenges (
+    "challenge_hash" TEXT NOT NULL,
+    "profile_id" UUID NOT NULL,
+    "attempts" INT NOT NULL DEFAULT 0,
+    "expires_at" timestamptz(3) NOT NULL,
+    "created_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (challenge_hash),
+    CONSTRAINT fk_profile_login_challenge FOREIGN KEY(profile_id) REFERENCES profile(id) ON DELETE CASCADE
+);
+COMMIT;
@@ -0,0 +1,22 @@
+-- Add migration script here
+BEGIN;
+CREATE TABLE login_attempts (
+    "scope" TEXT NOT NULL,
+    "key" TEXT NOT NULL,
+    "failures" INT NOT NULL DEFAULT 0,
+    "last_failure_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "next_attempt_at" timestamptz(3),
+    PRIMARY KEY (scope, key)
+);
+-- Entries outlive the profiles they refer to, hence no foreign keys
+CREATE TABLE audit_log (
+    "id" UUID NOT NULL,
+    "actor_id" UUID,
+    "profile_id" UUID,
+    "action" TEXT NOT NULL,
+    "details" JSONB NOT NULL DEFAULT '{}',
+    "created_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id)
+);
+CREATE INDEX audit_log_profile_id_idx ON audit_log (profile_id);
+COMMIT;
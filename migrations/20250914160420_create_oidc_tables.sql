@@ -0,0 +1,20 @@
+-- Add migration script here
+CREATE TABLE oidc_login_states (
+    state_hash TEXT NOT NULL,
+    provider TEXT NOT NULL,
+    code_verifier TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    expires_at timestamptz NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (state_hash)
+);
+
+CREATE TABLE external_identities (
+    provider TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    profile_id UUID NOT NULL REFERENCES profile (id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (provider, subject)
+);
+
+CREATE INDEX external_identities_profile_id_idx ON external_identities (profile_id);
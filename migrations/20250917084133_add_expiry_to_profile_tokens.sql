@@ -0,0 +1,14 @@
+-- Add migration script here
+ALTER TABLE profile_tokens
+ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+    ADD COLUMN expires_at timestamptz NOT NULL DEFAULT now() + interval '24 hours',
+    ADD COLUMN used_at timestamptz NULL;
+
+CREATE INDEX profile_tokens_expires_at_idx ON profile_tokens (expires_at);
+
+CREATE TABLE confirmation_resends (
+    email TEXT NOT NULL,
+    sent_count INT NOT NULL,
+    window_started_at timestamptz NOT NULL,
+    PRIMARY KEY (email)
+);
@@ -0,0 +1,17 @@
+-- Add migration script here
+CREATE TABLE email_change_requests (
+    id UUID NOT NULL,
+    profile_id UUID NOT NULL REFERENCES profile (id) ON DELETE CASCADE,
+    old_email TEXT NOT NULL,
+    new_email TEXT NOT NULL,
+    confirm_token_hash TEXT NOT NULL UNIQUE,
+    revert_token_hash TEXT NOT NULL UNIQUE,
+    expires_at timestamptz NOT NULL,
+    revert_expires_at timestamptz NOT NULL,
+    confirmed_at timestamptz NULL,
+    reverted_at timestamptz NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX email_change_requests_profile_id_idx ON email_change_requests (profile_id);
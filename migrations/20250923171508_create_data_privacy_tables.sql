@@ -0,0 +1,22 @@
+-- Add migration script here
+BEGIN;
+CREATE TABLE data_exports (
+    "id" UUID NOT NULL,
+    "profile_id" UUID NOT NULL,
+    "status" TEXT NOT NULL DEFAULT 'pending',
+    "bundle" JSONB,
+    "created_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "completed_at" timestamptz(3),
+    "expires_at" timestamptz(3),
+    PRIMARY KEY (id),
+    CONSTRAINT fk_profile_data_export FOREIGN KEY(profile_id) REFERENCES profile(id) ON DELETE CASCADE
+);
+CREATE INDEX data_exports_profile_id_idx ON data_exports (profile_id);
+CREATE TABLE deletion_requests (
+    "profile_id" UUID NOT NULL,
+    "requested_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "scheduled_for" timestamptz(3) NOT NULL,
+    PRIMARY KEY (profile_id),
+    CONSTRAINT fk_profile_deletion_request FOREIGN KEY(profile_id) REFERENCES profile(id) ON DELETE CASCADE
+);
+COMMIT;
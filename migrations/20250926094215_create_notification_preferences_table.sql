@@ -0,0 +1,18 @@
+-- Add migration script here
+BEGIN;
+ALTER TABLE task
+ADD COLUMN workspace TEXT NULL;
+ALTER TABLE profile
+ADD COLUMN digest_frequency TEXT NOT NULL DEFAULT 'immediate';
+-- A NULL task_type or workspace matches any value; the most specific rule wins
+CREATE TABLE notification_preferences (
+    "profile_id" UUID NOT NULL,
+    "event_type" TEXT NOT NULL,
+    "task_type" TEXT,
+    "workspace" TEXT,
+    "enabled" BOOLEAN NOT NULL,
+    "updated_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_profile_notification_preference FOREIGN KEY(profile_id) REFERENCES profile(id) ON DELETE CASCADE,
+    CONSTRAINT notification_preferences_rule_key UNIQUE NULLS NOT DISTINCT (profile_id, event_type, task_type, workspace)
+);
+COMMIT;
@@ -0,0 +1,13 @@
+-- Add migration script here
+BEGIN;
+CREATE TABLE profile_roles (
+    "profile_id" UUID NOT NULL,
+    "role" TEXT NOT NULL,
+    "granted_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (profile_id, role),
+    CONSTRAINT fk_profile_role FOREIGN KEY(profile_id) REFERENCES profile(id) ON DELETE CASCADE
+);
+-- Lets a reactivated profile return to where it was before the suspension
+ALTER TABLE profile
+ADD COLUMN status_before_suspension TEXT NULL;
+COMMIT;
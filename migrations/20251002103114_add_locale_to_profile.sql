@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE profile ADD COLUMN locale TEXT NOT NULL DEFAULT 'en';
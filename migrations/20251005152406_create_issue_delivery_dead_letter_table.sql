@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE issue_delivery_dead_letter (
+    "id" UUID PRIMARY KEY,
+    "task_issue_id" UUID NOT NULL,
+    "profile_email" TEXT NOT NULL,
+    "n_retries" INT NOT NULL,
+    "last_error" TEXT NOT NULL,
+    "queued_at" timestamptz(3) NOT NULL,
+    "failed_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_dead_letter_issue FOREIGN KEY(task_issue_id) REFERENCES task(id) ON DELETE CASCADE
+);
@@ -0,0 +1,19 @@
+-- Add migration script here
+BEGIN;
+CREATE TABLE delivery_log (
+    "id" UUID PRIMARY KEY,
+    "task_issue_id" UUID NOT NULL,
+    "profile_email" TEXT NOT NULL,
+    "status" TEXT NOT NULL,
+    "provider_message_id" TEXT,
+    "error" TEXT,
+    "latency_ms" INT NOT NULL,
+    "attempted_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_delivery_log_task FOREIGN KEY(task_issue_id) REFERENCES task(id) ON DELETE CASCADE
+);
+CREATE INDEX delivery_log_task_issue_id_idx ON delivery_log (task_issue_id, profile_email);
+-- Running totals so that a task's delivery summary does not need the log
+ALTER TABLE task
+ADD COLUMN deliveries_sent INT NOT NULL DEFAULT 0,
+    ADD COLUMN deliveries_failed INT NOT NULL DEFAULT 0;
+COMMIT;
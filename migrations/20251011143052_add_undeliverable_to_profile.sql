@@ -0,0 +1,5 @@
+-- Add migration script here
+-- Set from provider bounce and complaint callbacks; a suppressed profile gets no notifications
+ALTER TABLE profile
+ADD COLUMN undeliverable_reason TEXT NULL,
+    ADD COLUMN undeliverable_at timestamptz(3) NULL;
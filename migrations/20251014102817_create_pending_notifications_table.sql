@@ -0,0 +1,16 @@
+-- Add migration script here
+BEGIN;
+ALTER TABLE profile
+ADD COLUMN last_digest_at timestamptz(3) NULL;
+-- Notifications held back for profiles that get hourly or daily digests
+CREATE TABLE pending_notifications (
+    "id" UUID PRIMARY KEY,
+    "profile_id" UUID NOT NULL,
+    "task_id" UUID NOT NULL,
+    "event_type" TEXT NOT NULL,
+    "created_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_profile_pending_notification FOREIGN KEY(profile_id) REFERENCES profile(id) ON DELETE CASCADE,
+    CONSTRAINT fk_task_pending_notification FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE
+);
+CREATE INDEX pending_notifications_profile_id_idx ON pending_notifications (profile_id, created_at);
+COMMIT;
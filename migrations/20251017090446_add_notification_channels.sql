@@ -0,0 +1,39 @@
+-- Add migration script here
+BEGIN;
+-- Existing rows were all queued for new tasks over email
+ALTER TABLE issue_delivery_queue
+ADD COLUMN channel TEXT NOT NULL DEFAULT 'email',
+    ADD COLUMN event_type TEXT NOT NULL DEFAULT 'task_created';
+ALTER TABLE issue_delivery_queue DROP CONSTRAINT issue_delivery_queue_pkey;
+ALTER TABLE issue_delivery_queue
+ADD PRIMARY KEY (task_issue_id, profile_email, channel, event_type);
+ALTER TABLE issue_delivery_dead_letter
+ADD COLUMN channel TEXT NOT NULL DEFAULT 'email',
+    ADD COLUMN event_type TEXT NOT NULL DEFAULT 'task_created';
+ALTER TABLE delivery_log
+ADD COLUMN channel TEXT NOT NULL DEFAULT 'email',
+    ADD COLUMN event_type TEXT NOT NULL DEFAULT 'task_created';
+ALTER TABLE profile
+ADD COLUMN notification_webhook_url TEXT NULL;
+-- Events without any row here are sent by email
+CREATE TABLE notification_channels (
+    "profile_id" UUID NOT NULL,
+    "event_type" TEXT NOT NULL,
+    "channel" TEXT NOT NULL,
+    PRIMARY KEY (profile_id, event_type, channel),
+    CONSTRAINT fk_profile_notification_channel FOREIGN KEY(profile_id) REFERENCES profile(id) ON DELETE CASCADE
+);
+CREATE TABLE inbox_notifications (
+    "id" UUID PRIMARY KEY,
+    "profile_id" UUID NOT NULL,
+    "task_id" UUID NOT NULL,
+    "event_type" TEXT NOT NULL,
+    "message" TEXT NOT NULL,
+    "read_at" timestamptz(3) NULL,
+    "created_at" timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_profile_inbox_notification FOREIGN KEY(profile_id) REFERENCES profile(id) ON DELETE CASCADE,
+    CONSTRAINT fk_task_inbox_notification FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE,
+    CONSTRAINT inbox_notifications_event_key UNIQUE (profile_id, task_id, event_type)
+);
+CREATE INDEX inbox_notifications_profile_id_idx ON inbox_notifications (profile_id, created_at);
+COMMIT;
@@ -0,0 +1,9 @@
+-- Add migration script here
+BEGIN;
+-- Keys sent to endpoints that need no login are not tied to a profile
+ALTER TABLE idempotency DROP CONSTRAINT idempotency_pkey;
+ALTER TABLE idempotency
+ALTER COLUMN profile_id DROP NOT NULL;
+ALTER TABLE idempotency
+ADD CONSTRAINT idempotency_profile_key UNIQUE NULLS NOT DISTINCT (profile_id, idempotency_key);
+COMMIT;
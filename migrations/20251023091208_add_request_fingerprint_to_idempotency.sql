@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Keys saved before fingerprints existed match any request
+ALTER TABLE idempotency
+ADD COLUMN request_fingerprint TEXT NULL;
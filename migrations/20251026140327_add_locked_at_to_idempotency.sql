@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Keys without a saved response are held by a request in flight until this is too old
+ALTER TABLE idempotency
+ADD COLUMN locked_at timestamptz(3) NOT NULL DEFAULT CURRENT_TIMESTAMP;
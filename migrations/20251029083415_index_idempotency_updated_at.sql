@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Lets the expiry sweep find expired keys without scanning the table
+CREATE INDEX idempotency_updated_at_idx ON idempotency (updated_at);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Set the first time a task moves to in progress, to measure how long tasks run
+ALTER TABLE task ADD COLUMN started_at timestamptz(3) NULL;
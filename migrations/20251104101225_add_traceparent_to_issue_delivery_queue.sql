@@ -0,0 +1,3 @@
+-- Add migration script here
+-- W3C trace context of the request that queued the delivery
+ALTER TABLE issue_delivery_queue ADD COLUMN traceparent TEXT NULL;
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- The last TOTP time step a code was accepted for, so that a code cannot be used twice
+ALTER TABLE profile ADD COLUMN totp_last_step BIGINT NULL;
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Set by every claim, so that a request whose key was taken over can no longer touch it
+ALTER TABLE idempotency
+ADD COLUMN claim_token UUID;
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- A digest that failed to send is retried from then on, without counting as the last digest
+ALTER TABLE profile ADD COLUMN digest_retry_at timestamptz NULL;
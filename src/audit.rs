@@ -0,0 +1,58 @@
+use sqlx::PgExecutor;
+use strum_macros::Display;
+use uuid::Uuid;
+
+#[derive(Display, Debug, Clone, Copy)]
+#[strum(serialize_all = "snake_case")]
+pub enum AuditAction {
+    AccountLocked,
+    AddressSuppressed,
+    AccountReactivated,
+    AccountSuspended,
+    AccountUnlocked,
+    ConfirmationResent,
+    DeadLetterRequeued,
+    DeadLettersPurged,
+    DeletionCancelled,
+    DeletionRequested,
+    EmailChanged,
+    EmailChangeReverted,
+    ExternalIdentityLinked,
+    ImpersonatedRequest,
+    IpAddressLocked,
+    PasswordResetForced,
+    ProfileErased,
+    ProfileImpersonated,
+    ProfilesListed,
+    ProfileViewed,
+    SuppressionCleared,
+}
+
+pub struct AuditEvent {
+    /// Profile that performed the action, `None` for actions taken by the system
+    pub actor_id: Option<Uuid>,
+    /// Profile the action was performed on
+    pub profile_id: Option<Uuid>,
+    pub action: AuditAction,
+    pub details: serde_json::Value,
+}
+
+#[tracing::instrument(name = "Record audit event", skip(executor, event), fields(action = %event.action))]
+pub async fn record_audit_event<'e, E>(executor: E, event: AuditEvent) -> Result<(), sqlx::Error>
+where
+    E: PgExecutor<'e>,
+{
+    sqlx::query(
+        "INSERT INTO audit_log (id, actor_id, profile_id, action, details)
+                VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(Uuid::new_v4())
+    .bind(event.actor_id)
+    .bind(event.profile_id)
+    .bind(event.action.to_string())
+    .bind(event.details)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
@@ -1,5 +1,9 @@
+use crate::audit::{AuditAction, AuditEvent, record_audit_event};
+use crate::authorization::{Role, has_role};
+use crate::configuration::PasswordHashingSettings;
 use crate::domain::id::ProfileId;
 use crate::error::authentication::{AuthError, StdResponse};
+use crate::repository::pgdb::get_token_version;
 use crate::session_state::TypedSession;
 use crate::startup::SecretKey;
 use crate::telemetry::spawn_blocking_with_tracing;
@@ -50,30 +54,104 @@ async fn get_stored_credentials(
     Ok(result)
 }
 
+/// Start of the PHC string of every hash computed with the given parameters
+fn hash_prefix(settings: &PasswordHashingSettings) -> String {
+    format!(
+        "$argon2id$v=19$m={},t={},p={}$",
+        settings.memory_kib, settings.iterations, settings.parallelism
+    )
+}
+
+/// Verified against when the username is unknown. It uses the configured
+/// parameters so that the check costs as much as one for an existing profile
+fn dummy_password_hash(settings: &PasswordHashingSettings) -> String {
+    format!(
+        "{}h1UJKS5nfDpeNWSscpDd6g$Hm5+wPVIJo5N+Rt+PUlHLhk88e5EHYdb7lRUKCWiW8s",
+        hash_prefix(settings)
+    )
+}
+
+/// bcrypt hashes imported from other systems are not PHC strings and are told apart by prefix
+fn is_bcrypt_hash(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2y$"]
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+}
+
 #[tracing::instrument(name = "verify password", skip(expected_password, password))]
 fn verify_password(
-    expected_password: SecretBox<String>,
-    password: SecretBox<String>,
+    expected_password: &SecretBox<String>,
+    password: &SecretBox<String>,
 ) -> Result<(), AuthError> {
+    if is_bcrypt_hash(expected_password.expose_secret()) {
+        let is_valid = bcrypt::verify(password.expose_secret(), expected_password.expose_secret())
+            .context("Failed to parse bcrypt hash")
+            .map_err(AuthError::UnexpectedError)?;
+
+        return is_valid
+            .then_some(())
+            .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Invalid password")));
+    }
+
     let expected_password = PasswordHash::new(expected_password.expose_secret())
         .context("Failed to parse hash in PHC string format")
         .map_err(AuthError::UnexpectedError)?;
 
+    // The parameters are read from the PHC string, so any Argon2 variant verifies
     Argon2::default()
         .verify_password(password.expose_secret().as_bytes(), &expected_password)
         .context("Invalid password")
         .map_err(AuthError::InvalidCredentials)
 }
 
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+/// Whether a stored hash was computed with anything but the current algorithm and parameters
+pub fn needs_rehash(stored_hash: &str, settings: &PasswordHashingSettings) -> bool {
+    let Ok(hash) = PasswordHash::new(stored_hash) else {
+        return true;
+    };
+
+    hash.algorithm != argon2::Algorithm::Argon2id.ident()
+        || hash.version != Some(argon2::Version::V0x13.into())
+        || Params::try_from(&hash).map_or(true, |params| {
+            (params.m_cost(), params.t_cost(), params.p_cost())
+                != (
+                    settings.memory_kib,
+                    settings.iterations,
+                    settings.parallelism,
+                )
+        })
+}
+
+#[tracing::instrument(
+    name = "Store upgraded password hash",
+    skip(stored_hash, upgraded_hash, pool)
+)]
+async fn store_upgraded_hash(
+    profile_id: Uuid,
+    stored_hash: &str,
+    upgraded_hash: &str,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    // Only replace the hash that was verified, in case the password changed in the meantime
+    sqlx::query("UPDATE profile SET password = $1 WHERE id = $2 AND password = $3")
+        .bind(upgraded_hash)
+        .bind(profile_id)
+        .bind(stored_hash)
+        .execute(pool)
+        .await
+        .context("Failed to store the upgraded password hash")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool, settings))]
 pub async fn validate_credentials(
     credentials: Credentials,
     pool: &PgPool,
+    settings: &PasswordHashingSettings,
 ) -> Result<Uuid, AuthError> {
     let mut profile_id = None;
-    let mut expected_password = SecretBox::new(Box::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$h1UJKS5nfDpeNWSscpDd6g$Hm5+wPVIJo5N+Rt+PUlHLhk88e5EHYdb7lRUKCWiW8s".to_string(),
-    ));
+    let mut expected_password = SecretBox::new(Box::new(dummy_password_hash(settings)));
     if let Some((stored_profile_id, stored_expected_password)) =
         get_stored_credentials(&credentials.username, pool)
             .await
@@ -82,13 +160,35 @@ pub async fn validate_credentials(
         profile_id = Some(stored_profile_id);
         expected_password = stored_expected_password;
     };
+    let stored_hash = expected_password.expose_secret().clone();
+    let settings = settings.clone();
+
+    // Hashes computed with outdated parameters are upgraded while the plaintext is at hand
+    let upgraded_hash = spawn_blocking_with_tracing(move || {
+        verify_password(&expected_password, &credentials.password)?;
+
+        if needs_rehash(expected_password.expose_secret(), &settings) {
+            compute_password(credentials.password.expose_secret().clone(), &settings)
+                .map(Some)
+                .map_err(AuthError::UnexpectedError)
+        } else {
+            Ok(None)
+        }
+    })
+    .await
+    .context("Failed to spawn blocking task")
+    .map_err(AuthError::UnexpectedError)??;
 
-    spawn_blocking_with_tracing(move || verify_password(expected_password, credentials.password))
-        .await
-        .context("Failed to spawn blocking task")
-        .map_err(AuthError::UnexpectedError)??;
+    let profile_id = profile_id
+        .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username. ")))?;
+
+    if let Some(upgraded_hash) = upgraded_hash
+        && let Err(e) = store_upgraded_hash(profile_id, &stored_hash, &upgraded_hash, pool).await
+    {
+        tracing::warn!(error.cause_chain = ?e, error.message = %e, "Failed to upgrade password hash");
+    }
 
-    profile_id.ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username. ")))
+    Ok(profile_id)
 }
 
 #[tracing::instrument(name = "Read request access token", skip(headers))]
@@ -111,13 +211,15 @@ pub fn read_request_access_token(headers: &HeaderMap) -> Result<String, anyhow::
 //     let c = req.cookie("refresh_token")
 // }
 
-#[tracing::instrument(name = "Update Password", skip(password, pool))]
+#[tracing::instrument(name = "Update Password", skip(password, pool, settings))]
 pub async fn update_password(
     profile_id: Uuid,
     password: String,
     pool: &PgPool,
+    settings: &PasswordHashingSettings,
 ) -> Result<(), anyhow::Error> {
-    let password = spawn_blocking_with_tracing(move || compute_password(password))
+    let settings = settings.clone();
+    let password = spawn_blocking_with_tracing(move || compute_password(password, &settings))
         .await?
         .context("Failed to hash password")?;
 
@@ -131,23 +233,60 @@ pub async fn update_password(
     Ok(())
 }
 
-pub fn compute_password(password: String) -> Result<String, anyhow::Error> {
-    let salt = SaltString::generate(&mut rand_core::OsRng);
-    let password = Argon2::new(
-        argon2::Algorithm::Argon2id,
-        argon2::Version::V0x13,
-        Params::new(15000, 2, 1, None).unwrap(),
+pub fn compute_password(
+    password: String,
+    settings: &PasswordHashingSettings,
+) -> Result<String, anyhow::Error> {
+    let params = Params::new(
+        settings.memory_kib,
+        settings.iterations,
+        settings.parallelism,
+        None,
     )
-    .hash_password(password.as_bytes(), &salt)
-    .unwrap()
-    .to_string();
+    .map_err(|e| anyhow::anyhow!("Invalid password hashing parameters: {e}"))?;
+
+    let salt = SaltString::generate(&mut rand_core::OsRng);
+    let password = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {e}"))?
+        .to_string();
 
     Ok(password)
 }
 
-#[tracing::instrument(name = "Anonymous Check", skip(req, next))]
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct PasswordHashStats {
+    pub total: i64,
+    pub legacy: i64,
+}
+
+/// Counts the stored hashes that will be upgraded the next time their owner logs in
+#[tracing::instrument(name = "Count legacy password hashes", skip(pool, settings))]
+pub async fn password_hash_stats(
+    pool: &PgPool,
+    settings: &PasswordHashingSettings,
+) -> Result<PasswordHashStats, anyhow::Error> {
+    let current_prefix = hash_prefix(settings);
+
+    let row = sqlx::query(
+        "SELECT COUNT(*) AS total, COUNT(*) FILTER (WHERE NOT starts_with(password, $1)) AS legacy
+                FROM profile",
+    )
+    .bind(current_prefix)
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform query to count legacy password hashes")?;
+
+    Ok(PasswordHashStats {
+        total: row.get("total"),
+        legacy: row.get("legacy"),
+    })
+}
+
+#[tracing::instrument(name = "Anonymous Check", skip(req, next, pool))]
 pub async fn reject_anonymous_users(
     secret: Data<SecretKey>,
+    pool: Data<PgPool>,
     mut req: ServiceRequest,
     next: Next<impl MessageBody>,
 ) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
@@ -157,11 +296,56 @@ pub async fn reject_anonymous_users(
         TypedSession::from_request(http_request, payload).await
     }?;
 
-    match (
+    let identity = match (
         session.get_profile_id().map_err(e500)?,
         validate_access_token(&req, &secret.into_inner().0),
     ) {
-        (Some(profile_id), Ok(_)) | (Some(profile_id), Err(_)) | (None, Ok(profile_id)) => {
+        (Some(profile_id), _) => Some((
+            profile_id,
+            session
+                .get_token_version()
+                .map_err(e500)?
+                .unwrap_or_default(),
+            None,
+        )),
+        (None, Ok(claims)) => Some((claims.sub, claims.ver, claims.act)),
+        (None, Err(_)) => None,
+    };
+
+    // Sessions and tokens issued before the last credential change are revoked,
+    // and so are impersonation tokens of admins who have since lost the role
+    let profile_id = match identity {
+        Some((profile_id, token_version, actor_id)) => {
+            let current_version = get_token_version(profile_id, &pool).await.map_err(e500)?;
+            let actor_allowed = match actor_id {
+                Some(actor_id) => has_role(&pool, actor_id, Role::Admin).await.map_err(e500)?,
+                None => true,
+            };
+            (current_version == Some(token_version) && actor_allowed)
+                .then_some((profile_id, actor_id))
+        }
+        None => None,
+    };
+
+    match profile_id {
+        Some((profile_id, actor_id)) => {
+            if let Some(actor_id) = actor_id {
+                record_audit_event(
+                    pool.get_ref(),
+                    AuditEvent {
+                        actor_id: Some(actor_id),
+                        profile_id: Some(profile_id),
+                        action: AuditAction::ImpersonatedRequest,
+                        details: serde_json::json!({
+                            "method": req.method().as_str(),
+                            "path": req.path(),
+                        }),
+                    },
+                )
+                .await
+                .context("Failed to record the impersonated request in the audit log")
+                .map_err(e500)?;
+            }
             req.extensions_mut().insert(ProfileId(profile_id));
             let mut res = next.call(req).await?;
 
@@ -172,7 +356,7 @@ pub async fn reject_anonymous_users(
             Ok(res.map_body(|_, body| EitherBody::left(body)))
         }
 
-        (None, Err(_)) => {
+        None => {
             let message = "You are not logged in. Please log in...";
             let response = HttpResponse::Unauthorized()
                 .append_header((header::WWW_AUTHENTICATE, default_www))
@@ -188,14 +372,49 @@ pub async fn reject_anonymous_users(
     }
 }
 
+/// Impersonation tokens are short-lived whatever the access token expiry is
+pub const IMPERSONATION_EXPIRY_MINUTES: u64 = 15;
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
-struct Claims {
-    sub: Uuid,
-    exp: usize,
+pub struct Claims {
+    pub sub: Uuid,
+    pub exp: usize,
+    pub ver: i32,
+    /// The admin acting as the profile, for impersonation tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Uuid>,
 }
 
 pub fn create_token(
     profile_id: Uuid,
+    token_version: i32,
+    expiry: u64,
+    secret_key: &str,
+) -> Result<String, anyhow::Error> {
+    encode_token(profile_id, token_version, None, expiry, secret_key)
+}
+
+/// Issues an access token that lets an admin act as the profile. Every request
+/// made with it is recorded in the audit log under the admin
+pub fn create_impersonation_token(
+    profile_id: Uuid,
+    token_version: i32,
+    admin_id: Uuid,
+    secret_key: &str,
+) -> Result<String, anyhow::Error> {
+    encode_token(
+        profile_id,
+        token_version,
+        Some(admin_id),
+        IMPERSONATION_EXPIRY_MINUTES,
+        secret_key,
+    )
+}
+
+fn encode_token(
+    profile_id: Uuid,
+    token_version: i32,
+    actor_id: Option<Uuid>,
     expiry: u64,
     secret_key: &str,
 ) -> Result<String, anyhow::Error> {
@@ -209,6 +428,8 @@ pub fn create_token(
     let claims = Claims {
         sub: profile_id,
         exp,
+        ver: token_version,
+        act: actor_id,
     };
 
     let jwt = encode(
@@ -223,17 +444,20 @@ pub fn create_token(
 pub fn validate_access_token(
     req: &ServiceRequest,
     secret_key: &str,
-) -> Result<Uuid, anyhow::Error> {
+) -> Result<Claims, anyhow::Error> {
     let access_token = read_request_access_token(req.headers())?;
     let token_data = decode::<Claims>(
         &access_token,
         &DecodingKey::from_secret(secret_key.as_ref()),
         &Validation::new(Algorithm::HS256),
     )?;
-    Ok(token_data.claims.sub)
+    Ok(token_data.claims)
 }
 
-pub fn validate_refresh_token(req: &HttpRequest, secret_key: &str) -> Result<Uuid, anyhow::Error> {
+pub fn validate_refresh_token(
+    req: &HttpRequest,
+    secret_key: &str,
+) -> Result<Claims, anyhow::Error> {
     let refresh_token = req.cookie("refresh_token");
 
     match refresh_token {
@@ -247,7 +471,7 @@ pub fn validate_refresh_token(req: &HttpRequest, secret_key: &str) -> Result<Uui
                         &DecodingKey::from_secret(secret_key.as_ref()),
                         &Validation::new(Algorithm::HS256),
                     )?;
-                    Ok(token_data.claims.sub)
+                    Ok(token_data.claims)
                 }
                 None => Err(anyhow::anyhow!("No refresh token found")),
             }
@@ -255,3 +479,45 @@ pub fn validate_refresh_token(req: &HttpRequest, secret_key: &str) -> Result<Uui
         None => Err(anyhow::anyhow!("No refresh token found")),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{compute_password, dummy_password_hash, needs_rehash, verify_password};
+    use crate::configuration::PasswordHashingSettings;
+    use claims::assert_ok;
+    use secrecy::SecretBox;
+
+    #[test]
+    fn fresh_hash_does_not_need_rehash() {
+        let settings = PasswordHashingSettings::default();
+        let hash = compute_password("correct horse".to_string(), &settings).unwrap();
+
+        assert!(!needs_rehash(&hash, &settings));
+    }
+
+    #[test]
+    fn hash_with_outdated_params_needs_rehash() {
+        let hash = "$argon2id$v=19$m=4096,t=3,p=1$h1UJKS5nfDpeNWSscpDd6g$Hm5+wPVIJo5N+Rt+PUlHLhk88e5EHYdb7lRUKCWiW8s";
+
+        assert!(needs_rehash(hash, &PasswordHashingSettings::default()));
+    }
+
+    #[test]
+    fn dummy_hash_uses_the_configured_params() {
+        let settings = PasswordHashingSettings::default();
+        let hash = dummy_password_hash(&settings);
+
+        assert!(!needs_rehash(&hash, &settings));
+    }
+
+    #[test]
+    fn bcrypt_hash_is_verified_and_needs_rehash() {
+        let hash = bcrypt::hash("correct horse", 4).unwrap();
+
+        assert_ok!(verify_password(
+            &SecretBox::new(Box::new(hash.clone())),
+            &SecretBox::new(Box::new("correct horse".to_string())),
+        ));
+        assert!(needs_rehash(&hash, &PasswordHashingSettings::default()));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::domain::id::ProfileId;
+use crate::error::authentication::StdResponse;
+use crate::util::e500;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{
+    HttpMessage, HttpResponse,
+    body::{EitherBody, MessageBody},
+    web::Data,
+};
+use sqlx::PgPool;
+use strum_macros::Display;
+use uuid::Uuid;
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+pub enum Role {
+    Admin,
+}
+
+#[tracing::instrument(name = "Check profile role", skip(pool))]
+pub async fn has_role(pool: &PgPool, profile_id: Uuid, role: Role) -> Result<bool, sqlx::Error> {
+    let row =
+        sqlx::query("SELECT 1 AS granted FROM profile_roles WHERE profile_id = $1 AND role = $2")
+            .bind(profile_id)
+            .bind(role.to_string())
+            .fetch_optional(pool)
+            .await?;
+
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(name = "Get profile roles", skip(pool))]
+pub async fn get_roles(pool: &PgPool, profile_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar::<_, String>(
+        "SELECT role FROM profile_roles WHERE profile_id = $1 ORDER BY role",
+    )
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Only lets profiles holding the admin role through. Has to run after
+/// `reject_anonymous_users`, which identifies the profile
+#[tracing::instrument(name = "Admin Check", skip(req, next, pool))]
+pub async fn require_admin_role(
+    pool: Data<PgPool>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let profile_id = req.extensions().get::<ProfileId>().copied();
+
+    let is_admin = match profile_id {
+        Some(profile_id) => has_role(&pool, profile_id.0, Role::Admin)
+            .await
+            .map_err(e500)?,
+        None => false,
+    };
+
+    if is_admin {
+        let res = next.call(req).await?;
+        return Ok(res.map_body(|_, body| EitherBody::left(body)));
+    }
+
+    let message = "You are not allowed to perform this action";
+    tracing::warn!(message);
+
+    let response = HttpResponse::Forbidden().json(StdResponse { message });
+    let res = req.into_response(response);
+    Ok(res.map_body(|_, body| EitherBody::right(body)))
+}
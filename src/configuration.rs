@@ -1,12 +1,21 @@
 //! src/configuration.rs
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+
 use envconfig::Envconfig;
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
 use serde::Deserialize;
 use sqlx::ConnectOptions;
 use sqlx::postgres::PgConnectOptions;
 use sqlx::postgres::PgSslMode;
 
 use crate::domain::email::ProfileEmail;
-use crate::email_client::EmailClient;
+use crate::email_client::{
+    EmailClient, FileTransport, HttpApiTransport, SmtpTransport, StdoutTransport,
+};
+use crate::notification::UnsubscribeLinks;
+use crate::suppression::WebhookSignatures;
 
 #[derive(Deserialize, Envconfig)]
 pub struct DatabaseSettings {
@@ -57,24 +66,98 @@ pub struct ApplicationSettings {
     pub app_uri: String,
     #[envconfig(from = "SECRET_KEY")]
     pub secret_key: String,
-    #[envconfig(from = "IDEMPOTENCY_EXPIRATION")]
-    pub idempotency_expiration: u64,
     #[envconfig(from = "ACCESS_TOKEN_EXPIRE_MINUTES")]
     pub access_token_expire_minutes: u64,
+    #[envconfig(from = "PASSWORD_RESET_EXPIRE_MINUTES", default = "30")]
+    pub password_reset_expire_minutes: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTransportKind {
+    HttpApi,
+    Smtp,
+    File,
+    Stdout,
+}
+
+impl std::str::FromStr for EmailTransportKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "http_api" => Ok(Self::HttpApi),
+            "smtp" => Ok(Self::Smtp),
+            "file" => Ok(Self::File),
+            "stdout" => Ok(Self::Stdout),
+            other => Err(format!(
+                "{} is not a supported email transport. \
+            Use either `http_api`, `smtp`, `file` or `stdout`.",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    StartTls,
+    Tls,
+    None,
+}
+
+impl std::str::FromStr for SmtpTls {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "starttls" => Ok(Self::StartTls),
+            "tls" => Ok(Self::Tls),
+            "none" => Ok(Self::None),
+            other => Err(format!(
+                "{} is not a supported SMTP encryption. \
+            Use either `starttls`, `tls` or `none`.",
+                other
+            )),
+        }
+    }
 }
 
 #[derive(Deserialize, Envconfig)]
 pub struct EmailClientSettings {
+    #[envconfig(from = "EMAIL_TRANSPORT", default = "http_api")]
+    pub transport: EmailTransportKind,
     #[envconfig(from = "EMAIL_BASE_URI")]
     pub base_uri: String,
     #[envconfig(from = "SENDER_EMAIL")]
     pub sender_email: String,
+    #[envconfig(from = "SENDER_NAME", default = "Taskx")]
+    pub sender_name: String,
     #[envconfig(from = "PUBLIC_EMAIL_KEY")]
     pub public_email_key: String,
     #[envconfig(from = "PRIVATE_EMAIL_KEY")]
     pub private_email_key: String,
     #[envconfig(from = "TIMEOUT_MS")]
     pub timeout_milliseconds: u64,
+    #[envconfig(from = "SMTP_HOST", default = "localhost")]
+    pub smtp_host: String,
+    #[envconfig(from = "SMTP_PORT", default = "587")]
+    pub smtp_port: u16,
+    #[envconfig(from = "SMTP_TLS", default = "starttls")]
+    pub smtp_tls: SmtpTls,
+    #[envconfig(from = "SMTP_USERNAME")]
+    pub smtp_username: Option<String>,
+    #[envconfig(from = "SMTP_PASSWORD")]
+    pub smtp_password: Option<String>,
+    #[envconfig(from = "EMAIL_FILE_DIR", default = "emails")]
+    pub file_directory: String,
+    /// Shared with the provider to sign bounce and complaint callbacks. Callbacks
+    /// are rejected while it is unset
+    #[envconfig(from = "EMAIL_WEBHOOK_SECRET")]
+    pub webhook_secret: Option<String>,
+}
+
+impl ApplicationSettings {
+    pub fn unsubscribe_links(&self) -> UnsubscribeLinks {
+        UnsubscribeLinks::new(&self.app_uri, &self.secret_key)
+    }
 }
 
 impl EmailClientSettings {
@@ -86,17 +169,318 @@ impl EmailClientSettings {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
 
+    /// SMTP username and password, which are only usable together
+    pub fn smtp_credentials(&self) -> Result<Option<(String, String)>, envconfig::Error> {
+        match (&self.smtp_username, &self.smtp_password) {
+            (Some(username), Some(password)) => Ok(Some((username.clone(), password.clone()))),
+            (None, None) => Ok(None),
+            (Some(_), None) => Err(envconfig::Error::EnvVarMissing {
+                name: "SMTP_PASSWORD",
+            }),
+            (None, Some(_)) => Err(envconfig::Error::EnvVarMissing {
+                name: "SMTP_USERNAME",
+            }),
+        }
+    }
+
+    pub fn webhook_signatures(&self) -> WebhookSignatures {
+        WebhookSignatures::new(self.webhook_secret.clone())
+    }
+
     pub fn client(&self) -> EmailClient {
         let sender_email = self.sender().expect("Invalid sender email address");
         let timeout = self.timeout();
 
-        EmailClient::new(
-            &self.base_uri,
-            sender_email,
-            &self.private_email_key,
-            &self.public_email_key,
-            timeout,
-        )
+        match self.transport {
+            EmailTransportKind::HttpApi => EmailClient::new(
+                sender_email,
+                &self.sender_name,
+                HttpApiTransport::new(
+                    &self.base_uri,
+                    &self.private_email_key,
+                    &self.public_email_key,
+                    timeout,
+                ),
+            ),
+            EmailTransportKind::Smtp => EmailClient::new(
+                sender_email,
+                &self.sender_name,
+                SmtpTransport::new(
+                    &self.smtp_host,
+                    self.smtp_port,
+                    self.smtp_tls,
+                    self.smtp_credentials().expect("Invalid SMTP credentials"),
+                    timeout,
+                )
+                .expect("Invalid SMTP settings"),
+            ),
+            EmailTransportKind::File => EmailClient::new(
+                sender_email,
+                &self.sender_name,
+                FileTransport::new(&self.file_directory).expect("Invalid email directory"),
+            ),
+            EmailTransportKind::Stdout => {
+                EmailClient::new(sender_email, &self.sender_name, StdoutTransport)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Envconfig, Clone, Debug)]
+pub struct LoginProtectionSettings {
+    #[envconfig(from = "LOGIN_MAX_FAILED_ATTEMPTS", default = "5")]
+    pub max_failed_attempts: u32,
+    #[envconfig(from = "LOGIN_MAX_FAILED_ATTEMPTS_PER_IP", default = "50")]
+    pub max_failed_attempts_per_ip: u32,
+    #[envconfig(from = "LOGIN_LOCKOUT_SECONDS", default = "900")]
+    pub lockout_seconds: u64,
+    #[envconfig(from = "LOGIN_BACKOFF_BASE_SECONDS", default = "1")]
+    pub backoff_base_seconds: u64,
+    /// Proxies whose `Forwarded` and `X-Forwarded-For` headers name the client;
+    /// the peer address is used for everyone else
+    #[envconfig(from = "LOGIN_TRUSTED_PROXIES", default = "")]
+    pub trusted_proxies: TrustedProxies,
+}
+
+/// Addresses of the reverse proxies in front of the service, comma separated in
+/// `LOGIN_TRUSTED_PROXIES`
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+impl std::str::FromStr for TrustedProxies {
+    type Err = std::net::AddrParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|address| !address.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+#[derive(Deserialize, Envconfig, Clone, Debug)]
+pub struct ProfileConfirmationSettings {
+    #[envconfig(from = "PROFILE_CONFIRMATION_EXPIRE_MINUTES", default = "1440")]
+    pub token_expire_minutes: u64,
+    #[envconfig(from = "CONFIRMATION_RESEND_LIMIT", default = "3")]
+    pub resend_limit: u32,
+    #[envconfig(from = "CONFIRMATION_RESEND_WINDOW_MINUTES", default = "60")]
+    pub resend_window_minutes: u64,
+    #[envconfig(from = "PROFILE_TOKEN_CLEANUP_INTERVAL_SECONDS", default = "3600")]
+    pub cleanup_interval_seconds: u64,
+    /// How long tokens are kept once expired, so that a late click is still told
+    /// the link was used or has expired rather than that it is unknown
+    #[envconfig(from = "PROFILE_TOKEN_RETENTION_MINUTES", default = "10080")]
+    pub token_retention_minutes: u64,
+}
+
+#[derive(Deserialize, Envconfig, Clone, Debug)]
+pub struct EmailChangeSettings {
+    #[envconfig(from = "EMAIL_CHANGE_EXPIRE_MINUTES", default = "1440")]
+    pub confirm_expire_minutes: u64,
+    #[envconfig(from = "EMAIL_CHANGE_REVERT_EXPIRE_MINUTES", default = "10080")]
+    pub revert_expire_minutes: u64,
+}
+
+/// What happens to a profile once its deletion grace period has ended
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeletionMode {
+    /// Remove the profile row and everything that cascades from it
+    HardDelete,
+    /// Keep the profile row but strip every piece of personal data from it
+    Anonymize,
+}
+
+impl std::str::FromStr for DeletionMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hard_delete" => Ok(Self::HardDelete),
+            "anonymize" => Ok(Self::Anonymize),
+            other => Err(format!(
+                "{} is not a supported deletion mode.\
+            Use either `hard_delete` or `anonymize`.",
+                other
+            )),
+        }
+    }
+}
+
+/// What happens to the tasks reported by a deleted profile
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeletionTaskPolicy {
+    Purge,
+    Reassign,
+}
+
+impl std::str::FromStr for DeletionTaskPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "purge" => Ok(Self::Purge),
+            "reassign" => Ok(Self::Reassign),
+            other => Err(format!(
+                "{} is not a supported task policy.\
+            Use either `purge` or `reassign`.",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize, Envconfig, Clone, Debug)]
+pub struct DataPrivacySettings {
+    #[envconfig(from = "DATA_EXPORT_EXPIRE_HOURS", default = "168")]
+    pub export_expire_hours: u64,
+    #[envconfig(from = "DELETION_GRACE_PERIOD_HOURS", default = "720")]
+    pub deletion_grace_period_hours: u64,
+    #[envconfig(from = "DELETION_MODE", default = "hard_delete")]
+    pub deletion_mode: DeletionMode,
+    #[envconfig(from = "DELETION_TASK_POLICY", default = "purge")]
+    pub task_policy: DeletionTaskPolicy,
+    /// Profile that inherits the tasks when `task_policy` is `reassign`
+    #[envconfig(from = "DELETION_TASK_REASSIGN_TO")]
+    pub task_reassign_to: Option<uuid::Uuid>,
+    #[envconfig(from = "PRIVACY_WORKER_INTERVAL_SECONDS", default = "60")]
+    pub worker_interval_seconds: u64,
+}
+
+/// Spans are only exported when a collector is configured
+#[derive(Deserialize, Envconfig, Clone, Debug)]
+pub struct TelemetrySettings {
+    /// Base URI of an OTLP/HTTP collector, e.g. `http://localhost:4318`
+    #[envconfig(from = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Deserialize, Envconfig, Clone, Debug)]
+pub struct HealthSettings {
+    /// How long `/health/ready` fails before the servers stop on SIGTERM, so
+    /// that load balancers take the instance out of rotation first
+    #[envconfig(from = "SHUTDOWN_DRAIN_SECONDS", default = "5")]
+    pub shutdown_drain_seconds: u64,
+}
+
+impl HealthSettings {
+    pub fn shutdown_drain(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_drain_seconds)
+    }
+}
+
+/// `/metrics` is served on its own port, so that it can stay off the public ingress
+#[derive(Deserialize, Envconfig, Clone, Debug)]
+pub struct MetricsSettings {
+    #[envconfig(from = "METRICS_PORT", default = "9090")]
+    pub port: u16,
+}
+
+#[derive(Deserialize, Envconfig, Clone, Debug)]
+pub struct IdempotencySettings {
+    /// Saved responses are replayed for this many seconds after the last request
+    #[envconfig(from = "IDEMPOTENCY_EXPIRATION")]
+    pub retention_seconds: u64,
+    /// How often expired keys are swept
+    #[envconfig(from = "IDEMPOTENCY_SWEEP_INTERVAL_SECONDS", default = "300")]
+    pub sweep_interval_seconds: u64,
+    /// Keys deleted per statement, so that a sweep never holds many row locks at once
+    #[envconfig(from = "IDEMPOTENCY_SWEEP_BATCH_SIZE", default = "1000")]
+    pub sweep_batch_size: i64,
+    /// How long a retry waits for the request holding its key before getting a 409
+    #[envconfig(from = "IDEMPOTENCY_IN_FLIGHT_WAIT_MILLISECONDS", default = "2000")]
+    pub in_flight_wait_milliseconds: u64,
+    /// Keys held longer than this without a saved response are taken over, as the
+    /// request holding them is assumed to have crashed
+    #[envconfig(from = "IDEMPOTENCY_LOCK_TIMEOUT_SECONDS", default = "60")]
+    pub lock_timeout_seconds: u64,
+}
+
+impl IdempotencySettings {
+    pub fn retention(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.retention_seconds)
+    }
+
+    pub fn sweep_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.sweep_interval_seconds)
+    }
+
+    pub fn in_flight_wait(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.in_flight_wait_milliseconds)
+    }
+
+    pub fn lock_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.lock_timeout_seconds)
+    }
+}
+
+#[derive(Deserialize, Envconfig, Clone, Debug)]
+pub struct DeliverySettings {
+    /// Failed sends are retried this many times before the delivery is dead-lettered
+    #[envconfig(from = "DELIVERY_MAX_RETRIES", default = "5")]
+    pub max_retries: i32,
+    #[envconfig(from = "DELIVERY_WORKERS", default = "4")]
+    pub workers: usize,
+    /// Deliveries each worker attempts per pass, each in its own transaction
+    #[envconfig(from = "DELIVERY_BATCH_SIZE", default = "20")]
+    pub batch_size: i64,
+    /// Cap on the notification emails sent by all workers together, unlimited if unset
+    #[envconfig(from = "DELIVERY_MAX_EMAILS_PER_SECOND")]
+    pub max_emails_per_second: Option<NonZeroU32>,
+    /// Idle workers wake up on new deliveries, and poll this often for due retries
+    #[envconfig(from = "DELIVERY_POLL_INTERVAL_SECONDS", default = "10")]
+    pub poll_interval_seconds: u64,
+    /// How often the digest worker looks for hourly and daily digests that are due
+    #[envconfig(from = "DIGEST_WORKER_INTERVAL_SECONDS", default = "60")]
+    pub digest_interval_seconds: u64,
+    /// Lets webhooks reach loopback and private addresses, for local development only
+    #[envconfig(from = "WEBHOOK_ALLOW_PRIVATE_ADDRESSES", default = "false")]
+    pub webhook_allow_private_addresses: bool,
+}
+
+impl DeliverySettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+
+    pub fn rate_limiter(&self) -> Option<DefaultDirectRateLimiter> {
+        self.max_emails_per_second
+            .map(|n| RateLimiter::direct(Quota::per_second(n)))
+    }
+}
+
+#[derive(Deserialize, Envconfig, Clone, Debug)]
+pub struct PasswordHashingSettings {
+    #[envconfig(from = "PASSWORD_HASH_MEMORY_KIB", default = "15000")]
+    pub memory_kib: u32,
+    #[envconfig(from = "PASSWORD_HASH_ITERATIONS", default = "2")]
+    pub iterations: u32,
+    #[envconfig(from = "PASSWORD_HASH_PARALLELISM", default = "1")]
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHashingSettings {
+    /// The envconfig defaults above, ignoring the environment
+    fn default() -> Self {
+        Self::init_from_hashmap(&HashMap::new()).expect("Password hashing defaults must be valid")
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct OidcProviderSettings {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+/// OpenID Connect providers, given as a JSON array in `OIDC_PROVIDERS`
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct OidcProviders(pub Vec<OidcProviderSettings>);
+
+impl std::str::FromStr for OidcProviders {
+    type Err = serde_json::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map(Self)
     }
 }
 
@@ -108,6 +492,28 @@ pub struct Settings {
     pub application: ApplicationSettings,
     #[envconfig(nested)]
     pub email_client: EmailClientSettings,
+    #[envconfig(nested)]
+    pub login_protection: LoginProtectionSettings,
+    #[envconfig(nested)]
+    pub password_hashing: PasswordHashingSettings,
+    #[envconfig(nested)]
+    pub profile_confirmation: ProfileConfirmationSettings,
+    #[envconfig(nested)]
+    pub email_change: EmailChangeSettings,
+    #[envconfig(nested)]
+    pub data_privacy: DataPrivacySettings,
+    #[envconfig(nested)]
+    pub delivery: DeliverySettings,
+    #[envconfig(nested)]
+    pub idempotency: IdempotencySettings,
+    #[envconfig(nested)]
+    pub metrics: MetricsSettings,
+    #[envconfig(nested)]
+    pub telemetry: TelemetrySettings,
+    #[envconfig(nested)]
+    pub health: HealthSettings,
+    #[envconfig(from = "OIDC_PROVIDERS", default = "[]")]
+    pub oidc_providers: OidcProviders,
     #[envconfig(from = "REDIS_URI")]
     pub redis_uri: String,
 }
@@ -116,6 +522,7 @@ pub fn get_configuration() -> Result<Settings, envconfig::Error> {
     // Initialize our configuration reader
     let settings = Settings::init_from_env()
         .expect("Failed to parse required application environment variables");
+    settings.email_client.smtp_credentials()?;
 
     // Try to convert the configuration values it read into the Settings type
     Ok(settings)
@@ -143,3 +550,55 @@ impl DatabaseSettings {
             .log_statements(tracing_log::log::LevelFilter::Trace)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::EmailClientSettings;
+    use envconfig::Envconfig;
+    use std::collections::HashMap;
+
+    fn email_settings(credentials: &[(&str, &str)]) -> EmailClientSettings {
+        let mut vars: HashMap<String, String> = [
+            ("EMAIL_BASE_URI", "http://localhost"),
+            ("SENDER_EMAIL", "taskx@example.com"),
+            ("PUBLIC_EMAIL_KEY", "public"),
+            ("PRIVATE_EMAIL_KEY", "private"),
+            ("TIMEOUT_MS", "1000"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        vars.extend(
+            credentials
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+        EmailClientSettings::init_from_hashmap(&vars).unwrap()
+    }
+
+    #[test]
+    fn smtp_credentials_are_set_together_or_not_at_all() {
+        assert_eq!(email_settings(&[]).smtp_credentials(), Ok(None));
+        assert_eq!(
+            email_settings(&[("SMTP_USERNAME", "taskx"), ("SMTP_PASSWORD", "secret")])
+                .smtp_credentials(),
+            Ok(Some(("taskx".to_string(), "secret".to_string())))
+        );
+    }
+
+    #[test]
+    fn smtp_username_without_a_password_is_rejected() {
+        assert_eq!(
+            email_settings(&[("SMTP_USERNAME", "taskx")]).smtp_credentials(),
+            Err(envconfig::Error::EnvVarMissing {
+                name: "SMTP_PASSWORD"
+            })
+        );
+        assert_eq!(
+            email_settings(&[("SMTP_PASSWORD", "secret")]).smtp_credentials(),
+            Err(envconfig::Error::EnvVarMissing {
+                name: "SMTP_USERNAME"
+            })
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::configuration::Settings;
+use crate::domain::email::ProfileEmail;
+use crate::email_client::EmailClient;
+use crate::email_template::{EmailTemplate, render_email};
+use crate::health::heartbeat;
+use crate::issue_delivery::ExecutionOutcome;
+use crate::notification::{DigestFrequency, NotificationEvent, UnsubscribeLinks};
+use crate::startup::get_connection_pool;
+
+/// How long a digest that failed to send waits before it is tried again
+const DIGEST_RETRY_DELAY_SECONDS: i64 = 300;
+
+#[derive(FromRow)]
+struct DigestRecipient {
+    id: Uuid,
+    email: String,
+    locale: String,
+}
+
+#[derive(Serialize, FromRow)]
+struct DigestEntry {
+    #[serde(skip)]
+    id: Uuid,
+    #[serde(skip)]
+    event_type: String,
+    task_type: String,
+    source_file: String,
+    workspace: Option<String>,
+}
+
+async fn send_digest(
+    email_client: &EmailClient,
+    unsubscribe_links: &UnsubscribeLinks,
+    recipient: &DigestRecipient,
+    entries: &[DigestEntry],
+) -> Result<(), anyhow::Error> {
+    let email = ProfileEmail::parse(recipient.email.clone())
+        .map_err(|e| anyhow::anyhow!("The stored contact details are invalid: {e}"))?;
+    let of_event = |event: NotificationEvent| {
+        entries
+            .iter()
+            .filter(|entry| entry.event_type == event.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let unsubscribe_url = unsubscribe_links.digest_url(recipient.id)?;
+    let message = render_email(
+        EmailTemplate::Digest,
+        &recipient.locale,
+        serde_json::json!({
+            "tasks": of_event(NotificationEvent::TaskCreated),
+            "completed": of_event(NotificationEvent::TaskCompleted),
+            "failed": of_event(NotificationEvent::TaskFailed),
+            "unsubscribe_url": unsubscribe_url.as_str(),
+        }),
+    )?;
+
+    email_client
+        .send_notification_email(
+            &email,
+            &message.subject,
+            &message.html,
+            &message.text,
+            unsubscribe_url.as_str(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Sends the next digest that is due: one period after the previous digest or,
+/// for the first one, after the oldest pending notification. Profiles that went
+/// back to immediate notifications get their leftovers right away
+#[tracing::instrument(skip_all, fields(profile_id=tracing::field::Empty, n_notifications=tracing::field::Empty))]
+pub async fn try_send_digest(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    unsubscribe_links: &UnsubscribeLinks,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut tx = pool.begin().await?;
+    let Some(recipient) = sqlx::query_as::<_, DigestRecipient>(
+        "SELECT p.id, p.email, p.locale FROM profile p
+            WHERE p.status = 'confirmed'
+            AND p.undeliverable_reason IS NULL
+            AND (p.digest_retry_at IS NULL OR p.digest_retry_at <= now())
+            AND EXISTS (SELECT 1 FROM pending_notifications n WHERE n.profile_id = p.id)
+            AND COALESCE(
+                p.last_digest_at,
+                (SELECT MIN(n.created_at) FROM pending_notifications n WHERE n.profile_id = p.id)
+            ) + CASE p.digest_frequency
+                WHEN $1 THEN interval '1 hour'
+                WHEN $2 THEN interval '1 day'
+                ELSE interval '0'
+            END <= now()
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1",
+    )
+    .bind(DigestFrequency::Hourly.to_string())
+    .bind(DigestFrequency::Daily.to_string())
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+
+    // Notifications queued while the digest is sent wait for the next one
+    let entries = sqlx::query_as::<_, DigestEntry>(
+        "SELECT n.id, n.event_type, t.task_type, t.source_file, t.workspace
+            FROM pending_notifications n
+            JOIN task t ON t.id = n.task_id
+            WHERE n.profile_id = $1
+            ORDER BY n.created_at, n.id",
+    )
+    .bind(recipient.id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let span = tracing::Span::current();
+    span.record("profile_id", tracing::field::display(recipient.id));
+    span.record("n_notifications", entries.len());
+
+    match send_digest(email_client, unsubscribe_links, &recipient, &entries).await {
+        Ok(()) => {
+            let ids: Vec<Uuid> = entries.iter().map(|entry| entry.id).collect();
+            sqlx::query("DELETE FROM pending_notifications WHERE id = ANY($1)")
+                .bind(&ids)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(
+                "UPDATE profile SET last_digest_at = now(), digest_retry_at = NULL WHERE id = $1",
+            )
+            .bind(recipient.id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to record the digest")?;
+        }
+        Err(e) => {
+            // The notifications are kept and retried a little later, so that a
+            // failing address does not hold up everybody else's
+            tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to send a digest. Retrying later");
+            sqlx::query(
+                "UPDATE profile SET digest_retry_at = now() + make_interval(secs => $2)
+                    WHERE id = $1",
+            )
+            .bind(recipient.id)
+            .bind(DIGEST_RETRY_DELAY_SECONDS as f64)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to schedule the digest's retry")?;
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Drops the notifications held for addresses that were suppressed since, as
+/// their digest will never be sent
+#[tracing::instrument(skip_all)]
+pub async fn try_drop_undeliverable_notifications(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_dropped = sqlx::query(
+        "DELETE FROM pending_notifications n
+            USING profile p
+            WHERE n.profile_id = p.id
+            AND p.undeliverable_reason IS NOT NULL",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to drop the notifications of undeliverable addresses")?
+    .rows_affected();
+
+    Ok(n_dropped)
+}
+
+async fn digest_worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    unsubscribe_links: UnsubscribeLinks,
+    interval: Duration,
+) -> Result<(), anyhow::Error> {
+    loop {
+        heartbeat("digest_worker", interval);
+        match try_send_digest(&pool, &email_client, &unsubscribe_links).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                if let Err(e) = try_drop_undeliverable_notifications(&pool).await {
+                    tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to drop undeliverable notifications");
+                }
+                tokio::time::sleep(interval).await
+            }
+            Err(_) => tokio::time::sleep(Duration::from_secs(3)).await,
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+pub async fn run_digest_worker_until_stopped(
+    configuration: Arc<Settings>,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+
+    digest_worker_loop(
+        connection_pool,
+        configuration.email_client.client(),
+        configuration.application.unsubscribe_links(),
+        Duration::from_secs(configuration.delivery.digest_interval_seconds),
+    )
+    .await
+}
@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Language preference of a profile, as a BCP 47 language tag such as `en` or `fr-CA`
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ProfileLocale(String);
+
+impl ProfileLocale {
+    pub fn parse(s: String) -> Result<ProfileLocale, anyhow::Error> {
+        let mut subtags = s.split(['-', '_']);
+        let language = subtags.next().unwrap_or_default();
+
+        let valid_language =
+            (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_alphabetic());
+        let valid_subtags = subtags
+            .all(|t| (2..=8).contains(&t.len()) && t.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        if valid_language && valid_subtags && s.len() <= 35 {
+            Ok(Self(s))
+        } else {
+            Err(anyhow::anyhow!(format!("{} is not a valid locale", s)))
+        }
+    }
+}
+
+impl Default for ProfileLocale {
+    fn default() -> Self {
+        Self(crate::email_template::DEFAULT_LOCALE.to_string())
+    }
+}
+
+impl AsRef<str> for ProfileLocale {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::locale::ProfileLocale;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn language_and_region_tags_are_valid() {
+        assert_ok!(ProfileLocale::parse("fr".to_string()));
+        assert_ok!(ProfileLocale::parse("fr-CA".to_string()));
+        assert_ok!(ProfileLocale::parse("zh_Hant_TW".to_string()));
+    }
+
+    #[test]
+    fn malformed_tags_are_rejected() {
+        assert_err!(ProfileLocale::parse("".to_string()));
+        assert_err!(ProfileLocale::parse("english".to_string()));
+        assert_err!(ProfileLocale::parse("fr-".to_string()));
+        assert_err!(ProfileLocale::parse("<script>".to_string()));
+    }
+}
@@ -1,5 +1,6 @@
 pub mod email;
 pub mod id;
+pub mod locale;
 pub mod name;
 pub mod password;
 pub mod username;
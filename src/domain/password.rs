@@ -3,6 +3,7 @@ use unicode_segmentation::UnicodeSegmentation;
 use utoipa::ToSchema;
 
 use crate::authentication::compute_password;
+use crate::configuration::PasswordHashingSettings;
 
 #[derive(Deserialize, Serialize, Debug, ToSchema)]
 pub struct PHash(String);
@@ -20,7 +21,7 @@ pub struct Password {
 }
 
 impl Password {
-    pub fn parse(s: String) -> Result<Password, anyhow::Error> {
+    pub fn parse(s: String, settings: &PasswordHashingSettings) -> Result<Password, anyhow::Error> {
         let is_empty_or_whitespace = s.trim().is_empty();
 
         let is_too_short = s.graphemes(true).count() < 8;
@@ -32,7 +33,7 @@ impl Password {
             )))
         } else {
             // Hashing the password
-            let phash = compute_password(s.clone())?;
+            let phash = compute_password(s.clone(), settings)?;
             Ok(Self {
                 pstr: s,
                 phash: PHash(phash),
@@ -54,30 +55,31 @@ impl AsRef<str> for Password {
 #[cfg(test)]
 mod tests {
 
+    use crate::configuration::PasswordHashingSettings;
     use crate::domain::password::Password;
     use claims::{assert_err, assert_ok};
 
     #[test]
     fn valid_password() {
         let s = "1".repeat(8);
-        assert_ok!(Password::parse(s));
+        assert_ok!(Password::parse(s, &PasswordHashingSettings::default()));
     }
 
     #[test]
     fn short_password_is_rejected() {
         let s = "2".repeat(4);
-        assert_err!(Password::parse(s));
+        assert_err!(Password::parse(s, &PasswordHashingSettings::default()));
     }
 
     #[test]
     fn empty_string_is_rejected() {
         let s = "".to_string();
-        assert_err!(Password::parse(s));
+        assert_err!(Password::parse(s, &PasswordHashingSettings::default()));
     }
 
     #[test]
     fn whitespace_are_rejected() {
         let s = " ".to_string();
-        assert_err!(Password::parse(s));
+        assert_err!(Password::parse(s, &PasswordHashingSettings::default()));
     }
 }
@@ -0,0 +1,77 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use super::{EmailMessage, EmailTransport};
+
+/// Drops every email as an `.eml` file in a directory instead of sending it
+#[derive(Debug)]
+pub struct FileTransport {
+    writer: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl FileTransport {
+    pub fn new(directory: &str) -> Result<Self, anyhow::Error> {
+        std::fs::create_dir_all(directory).context("Failed to create the email directory")?;
+
+        Ok(Self {
+            writer: AsyncFileTransport::new(directory),
+        })
+    }
+}
+
+#[async_trait]
+impl EmailTransport for FileTransport {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<Option<String>, anyhow::Error> {
+        let email_id = self
+            .writer
+            .send(message.to_mime()?)
+            .await
+            .context("Failed to write the email file")?;
+        tracing::info!(email_id, "Email written to file");
+
+        Ok(Some(email_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileTransport;
+    use crate::domain::email::ProfileEmail;
+    use crate::email_client::EmailClient;
+    use uuid::Uuid;
+
+    #[actix_web::test]
+    async fn send_email_writes_an_eml_file() {
+        // Arrange
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let email_client = EmailClient::new(
+            ProfileEmail::parse("sender@example.com".to_string()).unwrap(),
+            "Taskx",
+            FileTransport::new(directory.to_str().unwrap()).unwrap(),
+        );
+
+        // Act
+        email_client
+            .send_email(
+                &ProfileEmail::parse("recipient@example.com".to_string()).unwrap(),
+                "Welcome",
+                "<p>Hello</p>",
+                "Hello",
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(content.contains("Subject: Welcome"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
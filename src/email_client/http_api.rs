@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+
+use super::{EmailMessage, EmailTransport};
+
+#[derive(Serialize)]
+struct Recipient<'a> {
+    email: &'a str,
+}
+
+#[derive(Serialize)]
+struct SendEmailRequest<'a> {
+    #[serde(rename = "FromEmail")]
+    fromemail: &'a str,
+    #[serde(rename = "FromName")]
+    fromname: &'a str,
+    #[serde(rename = "Subject")]
+    subject: &'a str,
+    #[serde(rename = "Text-part")]
+    text_part: &'a str,
+    #[serde(rename = "Html-part")]
+    html_part: &'a str,
+    #[serde(rename = "Recipients")]
+    recipients: Vec<Recipient<'a>>,
+    #[serde(rename = "Headers", skip_serializing_if = "HashMap::is_empty")]
+    headers: &'a HashMap<&'a str, String>,
+}
+
+#[derive(Deserialize)]
+struct SentMessage {
+    #[serde(rename = "MessageID")]
+    message_id: u64,
+}
+
+#[derive(Deserialize)]
+struct SendEmailResponse {
+    #[serde(rename = "Sent", default)]
+    sent: Vec<SentMessage>,
+}
+
+/// Posts emails to the vendor's `v3/send` JSON API with basic auth
+#[derive(Debug)]
+pub struct HttpApiTransport {
+    http_client: Client,
+    base_url: Url,
+    private_email_key: String,
+    public_email_key: String,
+}
+
+impl HttpApiTransport {
+    pub fn new(
+        base_uri: &str,
+        private_email_key: &str,
+        public_email_key: &str,
+        timeout: std::time::Duration,
+    ) -> Self {
+        let base_url = Url::parse(base_uri).expect("Invalid email base uri");
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            base_url,
+            private_email_key: private_email_key.to_string(),
+            public_email_key: public_email_key.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl EmailTransport for HttpApiTransport {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<Option<String>, anyhow::Error> {
+        let client_uri = self.base_url.join("v3/send").expect("Invalid email path");
+
+        let request_body = SendEmailRequest {
+            fromemail: message.sender.as_ref(),
+            recipients: vec![Recipient {
+                email: message.recipient.as_ref(),
+            }],
+            fromname: message.sender_name,
+            subject: message.subject,
+            html_part: message.html_content,
+            text_part: message.text_content,
+            headers: &message.headers,
+        };
+
+        let response = self
+            .http_client
+            .post(client_uri)
+            .basic_auth(
+                self.public_email_key.clone(),
+                Some(self.private_email_key.clone()),
+            )
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        // The email is out even if the body cannot be read, so this never fails the send
+        let message_id = response
+            .json::<SendEmailResponse>()
+            .await
+            .ok()
+            .and_then(|body| body.sent.into_iter().next())
+            .map(|sent| sent.message_id.to_string());
+
+        Ok(message_id)
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::domain::email::ProfileEmail;
+use anyhow::Context;
+use async_trait::async_trait;
+use lettre::Message;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+
+mod file;
+mod http_api;
+mod smtp;
+mod stdout;
+
+pub use file::FileTransport;
+pub use http_api::HttpApiTransport;
+pub use smtp::SmtpTransport;
+pub use stdout::StdoutTransport;
+
+/// An email ready to be handed over to a transport
+#[derive(Debug)]
+pub struct EmailMessage<'a> {
+    pub sender: &'a ProfileEmail,
+    pub sender_name: &'a str,
+    pub recipient: &'a ProfileEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub headers: HashMap<&'a str, String>,
+}
+
+impl EmailMessage<'_> {
+    /// Builds the MIME message with a plain text and an HTML alternative,
+    /// as written to the wire by the SMTP and file transports, or logged by the stdout one
+    pub fn to_mime(&self) -> Result<Message, anyhow::Error> {
+        let mut builder = Message::builder()
+            .from(Mailbox::new(
+                Some(self.sender_name.to_string()),
+                self.sender
+                    .as_ref()
+                    .parse()
+                    .context("Invalid sender email address")?,
+            ))
+            .to(self
+                .recipient
+                .as_ref()
+                .parse::<Mailbox>()
+                .context("Invalid recipient email address")?)
+            .subject(self.subject);
+
+        for (name, value) in &self.headers {
+            let name = HeaderName::new_from_ascii(name.to_string())
+                .context("Invalid email header name")?;
+            builder = builder.raw_header(HeaderValue::new(name, value.clone()));
+        }
+
+        builder
+            .multipart(MultiPart::alternative_plain_html(
+                self.text_content.to_string(),
+                self.html_content.to_string(),
+            ))
+            .context("Failed to build the email message")
+    }
+}
+
+/// Delivers emails through one backend, picked with `EMAIL_TRANSPORT`
+#[async_trait]
+pub trait EmailTransport: Send + Sync + std::fmt::Debug {
+    /// Returns the id the backend gave the message, when it hands one back
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<Option<String>, anyhow::Error>;
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailClient {
+    sender: ProfileEmail,
+    sender_name: String,
+    transport: Arc<dyn EmailTransport>,
+}
+
+impl EmailClient {
+    pub fn new(
+        sender: ProfileEmail,
+        sender_name: &str,
+        transport: impl EmailTransport + 'static,
+    ) -> Self {
+        Self {
+            sender,
+            sender_name: sender_name.to_string(),
+            transport: Arc::new(transport),
+        }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &ProfileEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.send(
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            HashMap::new(),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sends a notification that mail clients can offer to unsubscribe from in one click (RFC 8058),
+    /// returning the id the backend gave it
+    pub async fn send_notification_email(
+        &self,
+        recipient: &ProfileEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: &str,
+    ) -> Result<Option<String>, anyhow::Error> {
+        let headers = HashMap::from([
+            ("List-Unsubscribe", format!("<{unsubscribe_url}>")),
+            (
+                "List-Unsubscribe-Post",
+                "List-Unsubscribe=One-Click".to_string(),
+            ),
+        ]);
+
+        self.send(recipient, subject, html_content, text_content, headers)
+            .await
+    }
+
+    async fn send(
+        &self,
+        recipient: &ProfileEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: HashMap<&str, String>,
+    ) -> Result<Option<String>, anyhow::Error> {
+        self.transport
+            .send(&EmailMessage {
+                sender: &self.sender,
+                sender_name: &self.sender_name,
+                recipient,
+                subject,
+                html_content,
+                text_content,
+                headers,
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::email::ProfileEmail;
+    use crate::email_client::{EmailClient, HttpApiTransport};
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use wiremock::Request;
+    use wiremock::matchers::{any, basic_auth, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Generate a random email subject
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    /// Generate a random email content
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    /// Generate a random email
+    fn email() -> ProfileEmail {
+        ProfileEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(base_url: String) -> (EmailClient, String, String) {
+        let prek = Faker.fake::<String>();
+        let puek = Faker.fake::<String>();
+        (
+            EmailClient::new(
+                email(),
+                "Taskx",
+                HttpApiTransport::new(
+                    &base_url,
+                    &prek,
+                    &puek,
+                    std::time::Duration::from_millis(200),
+                ),
+            ),
+            puek,
+            prek,
+        )
+    }
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+
+            if let Ok(body) = result {
+                body.get("FromName") == Some(&serde_json::json!("Taskx"))
+                    && body.get("Recipients").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("Html-part").is_some()
+                    && body.get("Text-part").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let (email_client, puek, prek) = email_client(mock_server.uri());
+
+        Mock::given(basic_auth(puek, prek))
+            .and(header("Content-Type", "application/json"))
+            .and(path("v3/send"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        // Mock expectations are checked on drop
+    }
+
+    #[actix_web::test]
+    async fn send_notification_email_sets_the_unsubscribe_headers() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let (email_client, _, _) = email_client(mock_server.uri());
+
+        Mock::given(path("v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        email_client
+            .send_notification_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                "https://example.com/unsubscribe",
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(
+            body["Headers"]["List-Unsubscribe"],
+            "<https://example.com/unsubscribe>"
+        );
+        assert_eq!(
+            body["Headers"]["List-Unsubscribe-Post"],
+            "List-Unsubscribe=One-Click"
+        );
+    }
+
+    #[actix_web::test]
+    async fn send_notification_email_returns_the_provider_message_id() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let (email_client, _, _) = email_client(mock_server.uri());
+
+        Mock::given(path("v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Sent": [{ "Email": "recipient@example.com", "MessageID": 1152921504 }]
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let message_id = email_client
+            .send_notification_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                "https://example.com/unsubscribe",
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(message_id.as_deref(), Some("1152921504"));
+    }
+
+    #[actix_web::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let (email_client, _, _) = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[actix_web::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let (email_client, _, _) = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[actix_web::test]
+    async fn send_email_timesout_if_the_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let (email_client, _, _) = email_client(mock_server.uri());
+
+        // Delay by 3 minutes
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+
+use super::{EmailMessage, EmailTransport};
+use crate::configuration::SmtpTls;
+
+/// Relays emails to an SMTP server
+#[derive(Debug)]
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub fn new(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<(String, String)>,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let builder = match tls {
+            SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .context("Invalid SMTP relay")?,
+            SmtpTls::Tls => {
+                AsyncSmtpTransport::<Tokio1Executor>::relay(host).context("Invalid SMTP relay")?
+            }
+            // Only meant for local SMTP sinks
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        };
+
+        let mut builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Self {
+            mailer: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<Option<String>, anyhow::Error> {
+        let message = message.to_mime()?;
+        // Bounce reports quote the Message-ID header, not the relay's queue id
+        let message_id = message.headers().get_raw("Message-ID").map(str::to_string);
+
+        self.mailer
+            .send(message)
+            .await
+            .context("SMTP server rejected the email")?;
+
+        Ok(message_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SmtpTransport;
+    use crate::configuration::SmtpTls;
+    use crate::domain::email::ProfileEmail;
+    use crate::email_client::EmailClient;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// Accepts a single SMTP session and hands back the DATA it received
+    fn smtp_sink() -> (u16, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut data = String::new();
+            let mut in_data = false;
+            stream.write_all(b"220 sink ESMTP\r\n").unwrap();
+
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                if in_data {
+                    if line == ".\r\n" {
+                        in_data = false;
+                        stream.write_all(b"250 Queued\r\n").unwrap();
+                    } else {
+                        data.push_str(&line);
+                    }
+                    continue;
+                }
+
+                let command = line.to_uppercase();
+                let reply: &[u8] = if command.starts_with("EHLO") {
+                    b"250-sink\r\n250 8BITMIME\r\n"
+                } else if command.starts_with("DATA") {
+                    in_data = true;
+                    b"354 End data with <CR><LF>.<CR><LF>\r\n"
+                } else if command.starts_with("QUIT") {
+                    stream.write_all(b"221 Bye\r\n").unwrap();
+                    break;
+                } else {
+                    b"250 OK\r\n"
+                };
+                stream.write_all(reply).unwrap();
+            }
+
+            tx.send(data).unwrap();
+        });
+
+        (port, rx)
+    }
+
+    #[actix_web::test]
+    async fn send_email_delivers_the_message_to_the_smtp_server() {
+        // Arrange
+        let (port, received) = smtp_sink();
+        let transport = SmtpTransport::new(
+            "127.0.0.1",
+            port,
+            SmtpTls::None,
+            None,
+            std::time::Duration::from_secs(5),
+        )
+        .unwrap();
+        let email_client = EmailClient::new(
+            ProfileEmail::parse("sender@example.com".to_string()).unwrap(),
+            "Taskx",
+            transport,
+        );
+
+        // Act
+        email_client
+            .send_email(
+                &ProfileEmail::parse("recipient@example.com".to_string()).unwrap(),
+                "Welcome",
+                "<p>Hello</p>",
+                "Hello",
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        let data = received
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        assert!(data.contains("Subject: Welcome"));
+        assert!(data.contains("To: recipient@example.com"));
+        assert!(data.contains("multipart/alternative"));
+    }
+
+    /// Only checks that the transport never falls back to plain text; the TLS
+    /// handshake itself is not covered, as the sink cannot negotiate one
+    #[actix_web::test]
+    async fn starttls_fails_when_the_server_does_not_offer_it() {
+        // Arrange
+        let (port, received) = smtp_sink();
+        let transport = SmtpTransport::new(
+            "127.0.0.1",
+            port,
+            SmtpTls::StartTls,
+            None,
+            std::time::Duration::from_secs(5),
+        )
+        .unwrap();
+        let email_client = EmailClient::new(
+            ProfileEmail::parse("sender@example.com".to_string()).unwrap(),
+            "Taskx",
+            transport,
+        );
+
+        // Act
+        let outcome = email_client
+            .send_email(
+                &ProfileEmail::parse("recipient@example.com".to_string()).unwrap(),
+                "Welcome",
+                "<p>Hello</p>",
+                "Hello",
+            )
+            .await;
+
+        // Assert
+        assert!(outcome.is_err());
+        // The sink goes away without reporting if the client drops the connection
+        let data = received
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap_or_default();
+        assert!(data.is_empty());
+    }
+}
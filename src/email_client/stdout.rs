@@ -0,0 +1,89 @@
+use async_trait::async_trait;
+
+use super::{EmailMessage, EmailTransport};
+
+/// Logs every email instead of sending it, handy for local development. The
+/// message goes through `tracing` so that it ends up as one field of a regular
+/// log record rather than raw MIME in the middle of the JSON log lines
+#[derive(Debug, Default)]
+pub struct StdoutTransport;
+
+#[async_trait]
+impl EmailTransport for StdoutTransport {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<Option<String>, anyhow::Error> {
+        let formatted = message.to_mime()?.formatted();
+
+        tracing::info!(
+            email.recipient = %message.recipient.as_ref(),
+            email.subject = %message.subject,
+            email.mime = %String::from_utf8_lossy(&formatted),
+            "Email logged instead of sent"
+        );
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StdoutTransport;
+    use crate::domain::email::ProfileEmail;
+    use crate::email_client::EmailClient;
+    use crate::telemetry::get_tracing_subscriber;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn emails_are_logged_as_one_json_record() {
+        // Arrange
+        let buffer = Buffer::default();
+        let sink = buffer.clone();
+        let subscriber =
+            get_tracing_subscriber("test".into(), "info".into(), move || sink.clone(), None);
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let email_client = EmailClient::new(
+            ProfileEmail::parse("sender@example.com".to_string()).unwrap(),
+            "Taskx",
+            StdoutTransport,
+        );
+
+        // Act
+        email_client
+            .send_email(
+                &ProfileEmail::parse("recipient@example.com".to_string()).unwrap(),
+                "Welcome",
+                "<p>Hello</p>",
+                "Hello",
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let record = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .find(|record| record["msg"] == "Email logged instead of sent")
+            .expect("The email was not logged");
+        assert_eq!(record["email.subject"], "Welcome");
+        assert!(
+            record["email.mime"]
+                .as_str()
+                .unwrap()
+                .contains("multipart/alternative")
+        );
+    }
+}
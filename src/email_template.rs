@@ -0,0 +1,220 @@
+use std::fmt::Write;
+use std::sync::LazyLock;
+
+use anyhow::Context;
+use minijinja::{
+    AutoEscape, Environment, ErrorKind, Output, State, Value, context, escape_formatter,
+};
+use serde::Serialize;
+use strum_macros::{Display, EnumIter, EnumString};
+use utoipa::ToSchema;
+
+pub const DEFAULT_LOCALE: &str = "en";
+pub const SUPPORTED_LOCALES: &[&str] = &["en", "fr"];
+
+macro_rules! sources {
+    ($($path:literal),* $(,)?) => {
+        &[$(($path, include_str!(concat!("../templates/email/", $path)))),*]
+    };
+}
+
+/// Every template is compiled into the binary. HTML parts are autoescaped, text
+/// parts hold a `subject` and a `body` block
+const SOURCES: &[(&str, &str)] = sources!(
+    "layout.html",
+    "en/confirmation.html",
+    "en/confirmation.txt",
+    "en/digest.html",
+    "en/digest.txt",
+    "en/email_change_confirm.html",
+    "en/email_change_confirm.txt",
+    "en/email_change_notice.html",
+    "en/email_change_notice.txt",
+    "en/password_reset.html",
+    "en/password_reset.txt",
+    "en/task_created.html",
+    "en/task_created.txt",
+    "fr/confirmation.html",
+    "fr/confirmation.txt",
+    "fr/digest.html",
+    "fr/digest.txt",
+    "fr/email_change_confirm.html",
+    "fr/email_change_confirm.txt",
+    "fr/email_change_notice.html",
+    "fr/email_change_notice.txt",
+    "fr/password_reset.html",
+    "fr/password_reset.txt",
+    "fr/task_created.html",
+    "fr/task_created.txt",
+);
+
+static TEMPLATES: LazyLock<Environment<'static>> = LazyLock::new(|| {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_formatter(html_formatter);
+    for (name, source) in SOURCES {
+        env.add_template(name, source)
+            .expect("Invalid email template");
+    }
+    env
+});
+
+/// Same as minijinja's default formatter, except that `/` is left alone in
+/// HTML so links stay readable by mail clients and link scanners
+fn html_formatter(out: &mut Output, state: &State, value: &Value) -> Result<(), minijinja::Error> {
+    match value.as_str() {
+        Some(text) if state.auto_escape() == AutoEscape::Html && !value.is_safe() => {
+            for c in text.chars() {
+                match c {
+                    '&' => out.write_str("&amp;"),
+                    '<' => out.write_str("&lt;"),
+                    '>' => out.write_str("&gt;"),
+                    '"' => out.write_str("&quot;"),
+                    '\'' => out.write_str("&#x27;"),
+                    c => out.write_char(c),
+                }
+                .map_err(|_| minijinja::Error::new(ErrorKind::WriteFailure, "formatting failed"))?;
+            }
+            Ok(())
+        }
+        _ => escape_formatter(out, state, value),
+    }
+}
+
+#[derive(Display, EnumString, EnumIter, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+pub enum EmailTemplate {
+    Confirmation,
+    Digest,
+    EmailChangeConfirm,
+    EmailChangeNotice,
+    PasswordReset,
+    TaskCreated,
+}
+
+impl EmailTemplate {
+    /// Context the template is rendered with in previews
+    pub fn sample_context(&self) -> serde_json::Value {
+        let base_uri = "https://taskx.example.com";
+        match self {
+            Self::Confirmation => serde_json::json!({
+                "confirmation_link": format!("{base_uri}/profile/confirm?profile_token=sample"),
+            }),
+            Self::Digest => serde_json::json!({
+                "tasks": [
+                    {"task_type": "bug", "source_file": "crash.log", "workspace": "ops"},
+                    {"task_type": "feature", "source_file": "spec.md", "workspace": null},
+                ],
+                "completed": [
+                    {"task_type": "report", "source_file": "q3.csv", "workspace": "finance"},
+                ],
+                "failed": [],
+                "unsubscribe_url": format!("{base_uri}/notifications/unsubscribe?token=sample"),
+            }),
+            Self::EmailChangeConfirm => serde_json::json!({
+                "confirm_link": format!("{base_uri}/profile/email/confirm?token=sample"),
+            }),
+            Self::EmailChangeNotice => serde_json::json!({
+                "new_email": "new-address@example.com",
+                "revert_link": format!("{base_uri}/profile/email/revert?token=sample"),
+            }),
+            Self::PasswordReset => serde_json::json!({
+                "reset_link": format!("{base_uri}/password/reset?reset_token=sample"),
+            }),
+            Self::TaskCreated => serde_json::json!({
+                "task_type": "bug",
+                "source_file": "crash.log",
+                "workspace": "ops",
+                "unsubscribe_url": format!("{base_uri}/notifications/unsubscribe?token=sample"),
+            }),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// Picks the closest supported locale: `fr-CA` falls back to `fr`, unknown
+/// languages to the default locale
+pub fn resolve_locale(locale: &str) -> &'static str {
+    let locale = locale.to_lowercase().replace('_', "-");
+    let language = locale.split('-').next().unwrap_or_default();
+
+    SUPPORTED_LOCALES
+        .iter()
+        .find(|supported| **supported == locale || **supported == language)
+        .unwrap_or(&DEFAULT_LOCALE)
+}
+
+/// Renders the subject, HTML and text parts of an email from a single context
+pub fn render_email<S: Serialize>(
+    template: EmailTemplate,
+    locale: &str,
+    context: S,
+) -> Result<RenderedEmail, anyhow::Error> {
+    let locale = resolve_locale(locale);
+    let ctx = context! { locale => locale, ..Value::from_serialize(context) };
+
+    let html = TEMPLATES
+        .get_template(&format!("{locale}/{template}.html"))?
+        .render(&ctx)
+        .with_context(|| format!("Failed to render the {template} HTML part"))?;
+
+    let text_template = TEMPLATES.get_template(&format!("{locale}/{template}.txt"))?;
+    let (subject, text) = text_template
+        .render_captured(&ctx)?
+        .with_state_mut(|state| {
+            Ok::<_, minijinja::Error>((state.render_block("subject")?, state.render_block("body")?))
+        })
+        .with_context(|| format!("Failed to render the {template} text part"))?;
+
+    Ok(RenderedEmail {
+        subject: subject.trim().to_string(),
+        html: html.trim().to_string(),
+        text: text.trim().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailTemplate, SUPPORTED_LOCALES, render_email, resolve_locale};
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn every_template_renders_in_every_locale() {
+        for template in EmailTemplate::iter() {
+            for locale in SUPPORTED_LOCALES {
+                let email = render_email(template, locale, template.sample_context()).unwrap();
+                assert!(!email.subject.is_empty(), "{locale}/{template}");
+                assert!(!email.html.is_empty(), "{locale}/{template}");
+                assert!(!email.text.is_empty(), "{locale}/{template}");
+            }
+        }
+    }
+
+    #[test]
+    fn regional_locales_fall_back_to_their_language() {
+        assert_eq!(resolve_locale("fr-CA"), "fr");
+        assert_eq!(resolve_locale("FR"), "fr");
+        assert_eq!(resolve_locale("de"), "en");
+    }
+
+    #[test]
+    fn html_part_is_escaped_but_text_part_is_not() {
+        let email = render_email(
+            EmailTemplate::TaskCreated,
+            "en",
+            serde_json::json!({"task_type": "<b>bug</b>", "source_file": "a & b"}),
+        )
+        .unwrap();
+
+        assert!(email.html.contains("&lt;b&gt;bug&lt;/b&gt;"));
+        assert!(email.text.contains("a & b"));
+        assert_eq!(email.subject, "New <b>bug</b> task");
+    }
+}
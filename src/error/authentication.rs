@@ -1,4 +1,4 @@
-use actix_web::{HttpResponse, ResponseError, http::StatusCode};
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, http::header};
 
 use crate::error::common::error_chain_fmt;
 use actix_web_flash_messages::FlashMessage;
@@ -21,6 +21,8 @@ pub struct StdResponse<'a> {
 pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("Too many failed login attempts. Try again later")]
+    TooManyAttempts(u64),
     #[error("It's not you, it's us")]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -34,7 +36,11 @@ impl std::fmt::Debug for LoginError {
 impl ResponseError for LoginError {
     fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
         FlashMessage::error(self.to_string()).send();
-        HttpResponse::build(self.status_code())
+        let mut response = HttpResponse::build(self.status_code());
+        if let LoginError::TooManyAttempts(retry_after) = self {
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        response
             // .cookie(Cookie::new("_flash", self.to_string()))
             .json(StdResponse {
                 message: &self.to_string(),
@@ -44,6 +50,7 @@ impl ResponseError for LoginError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
             LoginError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            LoginError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
             LoginError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use redis::aio::ConnectionManager;
+use serde::Serialize;
+use sqlx::PgPool;
+use sqlx::migrate::Migrator;
+use utoipa::ToSchema;
+
+/// The migrations the binary was built with
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// A dependency that takes longer than this to answer counts as down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Time a worker may spend on one pass on top of its idle interval
+const WORKER_GRACE: Duration = Duration::from_secs(60);
+
+/// When each background worker has to check in again by
+static HEARTBEATS: LazyLock<Mutex<HashMap<&'static str, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Records that a worker is alive and will be back within `next_within`, i.e.
+/// the longest it may sleep before its next pass. Readiness only covers workers
+/// that checked in at least once
+pub fn heartbeat(worker: &'static str, next_within: Duration) {
+    let deadline = Instant::now() + next_within + WORKER_GRACE;
+    HEARTBEATS
+        .lock()
+        .expect("Heartbeats lock poisoned")
+        .insert(worker, deadline);
+}
+
+/// Flipped when the process starts to shut down, so that load balancers stop
+/// routing to it while requests in flight drain
+#[derive(Clone, Default, Debug)]
+pub struct ShutdownState(Arc<AtomicBool>);
+
+impl ShutdownState {
+    pub fn begin(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Serialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentStatus {
+    Up,
+    Down,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ComponentHealth {
+    pub status: ComponentStatus,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Readiness {
+    pub ready: bool,
+    pub shutting_down: bool,
+    pub components: BTreeMap<String, ComponentHealth>,
+}
+
+/// Times a check and turns its outcome, or its timeout, into a component status
+async fn check<F>(check: F) -> ComponentHealth
+where
+    F: Future<Output = Result<(), anyhow::Error>>,
+{
+    let started = Instant::now();
+    let outcome = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(outcome) => outcome,
+        Err(_) => Err(anyhow::anyhow!("No answer within {:?}", CHECK_TIMEOUT)),
+    };
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    match outcome {
+        Ok(()) => ComponentHealth {
+            status: ComponentStatus::Up,
+            latency_ms,
+            error: None,
+        },
+        Err(e) => ComponentHealth {
+            status: ComponentStatus::Down,
+            latency_ms,
+            error: Some(format!("{e:#}")),
+        },
+    }
+}
+
+async fn check_database(pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query("SELECT 1").execute(pool).await?;
+
+    Ok(())
+}
+
+async fn check_redis(mut redis: ConnectionManager) -> Result<(), anyhow::Error> {
+    redis::cmd("PING").query_async::<String>(&mut redis).await?;
+
+    Ok(())
+}
+
+async fn check_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let applied: Vec<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await?;
+
+    let n_pending = MIGRATOR
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+        .filter(|migration| !applied.contains(&migration.version))
+        .count();
+    if n_pending > 0 {
+        anyhow::bail!("{n_pending} migrations have not been applied");
+    }
+
+    Ok(())
+}
+
+fn check_workers() -> Vec<(String, ComponentHealth)> {
+    let now = Instant::now();
+    let heartbeats = HEARTBEATS.lock().expect("Heartbeats lock poisoned");
+
+    heartbeats
+        .iter()
+        .map(|(worker, deadline)| {
+            let health = if now <= *deadline {
+                ComponentHealth {
+                    status: ComponentStatus::Up,
+                    latency_ms: 0,
+                    error: None,
+                }
+            } else {
+                ComponentHealth {
+                    status: ComponentStatus::Down,
+                    latency_ms: 0,
+                    error: Some(format!(
+                        "No heartbeat for {}s past its deadline",
+                        (now - *deadline).as_secs()
+                    )),
+                }
+            };
+            (worker.to_string(), health)
+        })
+        .collect()
+}
+
+/// Checks every dependency the API needs to serve requests. Checks run
+/// concurrently, so that one slow dependency doesn't hide the others
+#[tracing::instrument(name = "Checking readiness", skip_all)]
+pub async fn readiness(
+    pool: &PgPool,
+    redis: ConnectionManager,
+    shutdown: &ShutdownState,
+) -> Readiness {
+    let (database, redis, migrations) = tokio::join!(
+        check(check_database(pool)),
+        check(check_redis(redis)),
+        check(check_migrations(pool)),
+    );
+
+    let mut components = BTreeMap::from([
+        ("database".to_string(), database),
+        ("redis".to_string(), redis),
+        ("migrations".to_string(), migrations),
+    ]);
+    components.extend(check_workers());
+
+    let shutting_down = shutdown.is_shutting_down();
+    let ready = !shutting_down
+        && components
+            .values()
+            .all(|component| component.status == ComponentStatus::Up);
+
+    Readiness {
+        ready,
+        shutting_down,
+        components,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ComponentStatus, HEARTBEATS, check_workers, heartbeat};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn workers_are_down_once_they_miss_their_heartbeat() {
+        heartbeat("punctual_worker", Duration::from_secs(10));
+        HEARTBEATS
+            .lock()
+            .unwrap()
+            .insert("stalled_worker", Instant::now() - Duration::from_secs(1));
+
+        let workers = check_workers();
+
+        let status = |name: &str| {
+            workers
+                .iter()
+                .find(|(worker, _)| worker == name)
+                .map(|(_, health)| health.status)
+        };
+        assert_eq!(status("punctual_worker"), Some(ComponentStatus::Up));
+        assert_eq!(status("stalled_worker"), Some(ComponentStatus::Down));
+    }
+}
@@ -0,0 +1,47 @@
+use actix_web::http::Method;
+use sha2::{Digest, Sha256};
+
+/// Hash identifying what a request asked for, so that a key reused for a
+/// different request can be told apart from a retry
+pub fn request_fingerprint(method: &Method, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_str());
+    hasher.update(b"\n");
+    hasher.update(path);
+    hasher.update(b"\n");
+    hasher.update(body);
+
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::request_fingerprint;
+    use actix_web::http::Method;
+
+    #[test]
+    fn same_request_has_the_same_fingerprint() {
+        assert_eq!(
+            request_fingerprint(&Method::POST, "/profile", b"{}"),
+            request_fingerprint(&Method::POST, "/profile", b"{}")
+        );
+    }
+
+    #[test]
+    fn method_path_and_body_all_change_the_fingerprint() {
+        let fingerprint = request_fingerprint(&Method::PUT, "/task/1/fail", b"");
+
+        assert_ne!(
+            fingerprint,
+            request_fingerprint(&Method::POST, "/task/1/fail", b"")
+        );
+        assert_ne!(
+            fingerprint,
+            request_fingerprint(&Method::PUT, "/task/2/fail", b"")
+        );
+        assert_ne!(
+            fingerprint,
+            request_fingerprint(&Method::PUT, "/task/1/fail", b"{}")
+        );
+    }
+}
@@ -1,6 +1,23 @@
+use sha2::{Digest, Sha256};
+
 #[derive(Debug)]
 pub struct IdempotencyKey(String);
 
+impl IdempotencyKey {
+    /// Anonymous requests have no profile to scope their keys by. Folding the
+    /// request's fingerprint into the key means it only ever matches the request
+    /// that claimed it, so another client would have to send the same body,
+    /// secrets included, to be handed the saved response
+    pub fn scoped_to_request(&self, fingerprint: &str) -> IdempotencyKey {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.0);
+        hasher.update(b"\n");
+        hasher.update(fingerprint);
+
+        Self(hex::encode(hasher.finalize()))
+    }
+}
+
 impl TryFrom<String> for IdempotencyKey {
     type Error = anyhow::Error;
 
@@ -0,0 +1,126 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::middleware::Next;
+use actix_web::web::{Bytes, Data};
+use actix_web::{HttpMessage, HttpResponse};
+use sqlx::PgPool;
+
+use super::{
+    IdempotencyKey, NextAction, release_key, request_fingerprint, save_response,
+    try_idem_processing,
+};
+use crate::configuration::IdempotencySettings;
+use crate::domain::id::ProfileId;
+use crate::error::authentication::StdResponse;
+use crate::util::e500;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Requests in flight are usually done within a second
+const IN_FLIGHT_RETRY_AFTER_SECONDS: u64 = 1;
+
+/// Replays the saved response when a POST, PUT or DELETE is retried with the same
+/// `Idempotency-Key` header. Routes opt in by wrapping themselves with it; requests
+/// without the header go through untouched. Keys are scoped to the logged in profile,
+/// so the middleware has to sit inside `reject_anonymous_users` on protected routes;
+/// anonymous keys are scoped to the request itself.
+/// Reusing a key for a different method, path or body is rejected with 422, and a retry
+/// that outwaits the request still holding its key gets a 409 with `Retry-After`.
+/// Server errors are not saved, so that a retry gets another chance
+pub async fn idempotent_requests(
+    pool: Data<PgPool>,
+    settings: Data<IdempotencySettings>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let is_mutating = matches!(*req.method(), Method::POST | Method::PUT | Method::DELETE);
+    let Some(header) = req
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .filter(|_| is_mutating)
+    else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+
+    let idempotency_key = match header
+        .to_str()
+        .map_err(anyhow::Error::from)
+        .and_then(|key| IdempotencyKey::try_from(key.to_string()))
+    {
+        Ok(idempotency_key) => idempotency_key,
+        Err(e) => {
+            let response = HttpResponse::BadRequest().json(StdResponse {
+                message: &e.to_string(),
+            });
+            return Ok(req.into_response(response));
+        }
+    };
+    let profile_id = req
+        .extensions()
+        .get::<ProfileId>()
+        .map(|profile_id| profile_id.0);
+
+    // The body is read here to be fingerprinted, then handed back to the route
+    let body = req.extract::<Bytes>().await?;
+    let fingerprint = request_fingerprint(req.method(), req.path(), &body);
+    req.set_payload(Payload::from(body));
+    let idempotency_key = match profile_id {
+        Some(_) => idempotency_key,
+        None => idempotency_key.scoped_to_request(&fingerprint),
+    };
+
+    let claim_token =
+        match try_idem_processing(&pool, &idempotency_key, profile_id, &fingerprint, &settings)
+            .await
+            .map_err(e500)?
+        {
+            NextAction::StartProcessing(claim_token) => claim_token,
+            NextAction::ReturnSavedResponse(saved_response) => {
+                return Ok(req.into_response(saved_response));
+            }
+            NextAction::RejectKeyReuse => {
+                let response = HttpResponse::UnprocessableEntity().json(StdResponse {
+                    message: "The idempotency key was already used for a different request",
+                });
+                return Ok(req.into_response(response));
+            }
+            NextAction::InFlight => {
+                let response = HttpResponse::Conflict()
+                    .insert_header((RETRY_AFTER, IN_FLIGHT_RETRY_AFTER_SECONDS))
+                    .json(StdResponse {
+                        message: "A request with the same idempotency key is still being processed",
+                    });
+                return Ok(req.into_response(response));
+            }
+        };
+
+    let (req, response) = match next.call(req).await {
+        Ok(res) => res.into_parts(),
+        Err(e) => {
+            release_key(&pool, &idempotency_key, profile_id, claim_token)
+                .await
+                .map_err(e500)?;
+            return Err(e);
+        }
+    };
+    if response.status().is_server_error() {
+        release_key(&pool, &idempotency_key, profile_id, claim_token)
+            .await
+            .map_err(e500)?;
+        return Ok(ServiceResponse::new(req, response.map_into_boxed_body()));
+    }
+
+    let response = save_response(
+        &pool,
+        &idempotency_key,
+        profile_id,
+        claim_token,
+        response.map_into_boxed_body(),
+    )
+    .await
+    .map_err(e500)?;
+
+    Ok(ServiceResponse::new(req, response))
+}
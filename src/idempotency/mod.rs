@@ -1,7 +1,11 @@
+mod fingerprint;
 mod key;
+mod middleware;
 mod persistence;
+pub use fingerprint::request_fingerprint;
 pub use key::IdempotencyKey;
+pub use middleware::{IDEMPOTENCY_KEY_HEADER, idempotent_requests};
 pub use persistence::{
-    NextAction, get_saved_response, run_idem_worker_until_stopped, save_response,
-    try_idem_expiration, try_idem_processing,
+    IdempotencyStats, NextAction, get_saved_response, idempotency_stats, release_key,
+    run_idem_worker_until_stopped, save_response, try_idem_expiration, try_idem_processing,
 };
@@ -1,11 +1,18 @@
 use super::IdempotencyKey;
+use crate::configuration::IdempotencySettings;
+use crate::health::heartbeat;
+use crate::metrics::metrics;
 use crate::{configuration::Settings, startup::get_connection_pool};
 use actix_web::{HttpResponse, body::to_bytes, http::StatusCode};
-use sqlx::{PgPool, Postgres, Row, Transaction};
+use anyhow::Context;
+use sqlx::{PgPool, Row};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+/// How often a request waiting on a key in flight checks on it again
+const IN_FLIGHT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Debug, sqlx::Type)]
 #[sqlx(type_name = "header_pair")]
 struct HeaderPairRecord {
@@ -15,43 +22,68 @@ struct HeaderPairRecord {
 
 #[derive(Debug, sqlx::FromRow)]
 struct SavedResponse {
+    request_fingerprint: Option<String>,
     response_status_code: Option<i16>,
     response_headers: Option<Vec<HeaderPairRecord>>,
     response_body: Option<Vec<u8>>,
 }
 
-pub async fn get_saved_response(
+impl SavedResponse {
+    /// Rebuilds the response, unless the request holding the key is still in flight
+    fn into_response(self) -> Result<Option<HttpResponse>, anyhow::Error> {
+        let (Some(status_code), Some(headers), Some(body)) = (
+            self.response_status_code,
+            self.response_headers,
+            self.response_body,
+        ) else {
+            return Ok(None);
+        };
+
+        let status_code = StatusCode::from_u16(status_code.try_into()?)?;
+        let mut response = HttpResponse::build(status_code);
+        for HeaderPairRecord { name, value } in headers {
+            response.append_header((name, value));
+        }
+        Ok(Some(response.body(body)))
+    }
+}
+
+async fn get_record(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
-    profile_id: Uuid,
-) -> Result<Option<HttpResponse>, anyhow::Error> {
-    let saved_response = sqlx::query_as::<_, SavedResponse>(
-        "SELECT response_status_code, response_headers, response_body 
+    profile_id: Option<Uuid>,
+) -> Result<Option<SavedResponse>, sqlx::Error> {
+    sqlx::query_as::<_, SavedResponse>(
+        "SELECT request_fingerprint, response_status_code, response_headers, response_body 
                                                         FROM idempotency 
-                                                        WHERE profile_id = $1 
+                                                        WHERE profile_id IS NOT DISTINCT FROM $1
                                                         AND idempotency_key= $2",
     )
     .bind(profile_id)
     .bind(idempotency_key.as_ref())
     .fetch_optional(pool)
-    .await?;
+    .await
+}
 
-    if let Some(sr) = saved_response {
-        let status_code = StatusCode::from_u16(sr.response_status_code.unwrap().try_into()?)?;
-        let mut response = HttpResponse::build(status_code);
-        for HeaderPairRecord { name, value } in sr.response_headers.unwrap() {
-            response.append_header((name, value));
-        }
-        Ok(Some(response.body(sr.response_body.unwrap())))
-    } else {
-        Ok(None)
+/// Returns `None` while the request holding the key has not saved its response
+pub async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    profile_id: Option<Uuid>,
+) -> Result<Option<HttpResponse>, anyhow::Error> {
+    match get_record(pool, idempotency_key, profile_id).await? {
+        Some(record) => record.into_response(),
+        None => Ok(None),
     }
 }
 
+/// Saves the response for retries to replay, unless another request has taken the
+/// key over in the meantime
 pub async fn save_response(
-    mut tx: Transaction<'static, Postgres>,
+    pool: &PgPool,
     idempotency_key: &IdempotencyKey,
-    profile_id: Uuid,
+    profile_id: Option<Uuid>,
+    claim_token: Uuid,
     http_res: HttpResponse,
 ) -> Result<HttpResponse, anyhow::Error> {
     let (response_head, body) = http_res.into_parts();
@@ -67,104 +99,211 @@ pub async fn save_response(
         h
     };
 
-    sqlx::query(
+    let n_saved_rows = sqlx::query(
         "UPDATE idempotency 
                 SET response_status_code = $3, 
                     response_headers = $4, 
                     response_body = $5,
                     updated_at = now()
-                WHERE profile_id =$1 
-                AND idempotency_key = $2",
+                WHERE profile_id IS NOT DISTINCT FROM $1
+                AND idempotency_key = $2
+                AND claim_token = $6",
     )
     .bind(profile_id)
     .bind(idempotency_key.as_ref())
     .bind(status_code)
     .bind(headers)
     .bind(body.as_ref())
-    .execute(&mut *tx)
-    .await?;
-
-    tx.commit().await?;
+    .bind(claim_token)
+    .execute(pool)
+    .await?
+    .rows_affected();
+    if n_saved_rows == 0 {
+        tracing::warn!("The idempotency key was taken over before the response could be saved");
+    }
 
     let http_res = response_head.set_body(body).map_into_boxed_body();
     Ok(http_res)
 }
 
+/// Gives the key up without saving a response, so that the next retry is processed.
+/// A key another request has taken over in the meantime is left alone
+pub async fn release_key(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    profile_id: Option<Uuid>,
+    claim_token: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query(
+        "DELETE FROM idempotency
+                WHERE profile_id IS NOT DISTINCT FROM $1
+                AND idempotency_key = $2
+                AND response_status_code IS NULL
+                AND claim_token = $3",
+    )
+    .bind(profile_id)
+    .bind(idempotency_key.as_ref())
+    .bind(claim_token)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
 pub enum NextAction {
-    StartProcessing(Transaction<'static, Postgres>),
+    /// Holds the claim token the response has to be saved or the key released with
+    StartProcessing(Uuid),
     ReturnSavedResponse(HttpResponse),
+    /// The key was already used for a different request
+    RejectKeyReuse,
+    /// Another request with the same key is still being processed
+    InFlight,
 }
 
-pub async fn try_idem_processing(
+/// Claims the key, or takes it over from a request that held it past the lock timeout.
+/// Returns the token identifying this claim
+async fn claim_key(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
-    profile_id: Uuid,
-) -> Result<NextAction, anyhow::Error> {
-    let mut transaction = pool.begin().await?;
-    let n_inserted_rows = sqlx::query(
-        "INSERT INTO idempotency (profile_id, idempotency_key) 
-    VALUES ($1, $2) ON CONFLICT DO NOTHING",
+    profile_id: Option<Uuid>,
+    request_fingerprint: &str,
+    lock_timeout: Duration,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let claim_token = Uuid::new_v4();
+    let n_claimed_rows = sqlx::query(
+        "INSERT INTO idempotency (profile_id, idempotency_key, request_fingerprint, claim_token) 
+    VALUES ($1, $2, $3, $5)
+    ON CONFLICT ON CONSTRAINT idempotency_profile_key DO UPDATE
+        SET request_fingerprint = EXCLUDED.request_fingerprint, locked_at = now(),
+            claim_token = EXCLUDED.claim_token
+        WHERE idempotency.response_status_code IS NULL
+        AND idempotency.locked_at + $4 <= now()",
     )
     .bind(profile_id)
     .bind(idempotency_key.as_ref())
-    .execute(&mut *transaction)
+    .bind(request_fingerprint)
+    .bind(lock_timeout)
+    .bind(claim_token)
+    .execute(pool)
     .await?
     .rows_affected();
 
-    if n_inserted_rows > 0 {
-        Ok(NextAction::StartProcessing(transaction))
-    } else {
-        let saved_response = get_saved_response(pool, idempotency_key, profile_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we did not find it."))?;
+    Ok((n_claimed_rows > 0).then_some(claim_token))
+}
 
-        Ok(NextAction::ReturnSavedResponse(saved_response))
+/// Claims the key for this request. Keys sent without a login share one namespace.
+/// The claim is committed right away, so that a retry arriving while the request is
+/// processed waits for its response instead of processing it a second time
+pub async fn try_idem_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    profile_id: Option<Uuid>,
+    request_fingerprint: &str,
+    settings: &IdempotencySettings,
+) -> Result<NextAction, anyhow::Error> {
+    let deadline = Instant::now() + settings.in_flight_wait();
+
+    loop {
+        if let Some(claim_token) = claim_key(
+            pool,
+            idempotency_key,
+            profile_id,
+            request_fingerprint,
+            settings.lock_timeout(),
+        )
+        .await?
+        {
+            return Ok(NextAction::StartProcessing(claim_token));
+        }
+
+        // Gone when the request holding the key released it, so it can be claimed again
+        if let Some(record) = get_record(pool, idempotency_key, profile_id).await? {
+            if record
+                .request_fingerprint
+                .as_ref()
+                .is_some_and(|saved| saved != request_fingerprint)
+            {
+                return Ok(NextAction::RejectKeyReuse);
+            }
+            if let Some(saved_response) = record.into_response()? {
+                return Ok(NextAction::ReturnSavedResponse(saved_response));
+            }
+            if Instant::now() >= deadline {
+                return Ok(NextAction::InFlight);
+            }
+            tokio::time::sleep(IN_FLIGHT_POLL_INTERVAL).await;
+        }
     }
 }
 
-pub async fn try_idem_expiration(
-    pool: &PgPool,
-    duration: u64,
-) -> Result<Option<i64>, anyhow::Error> {
-    let n_rows = sqlx::query(
-        "SELECT COUNT(*) as count FROM idempotency WHERE NOW() - updated_at >= INTERVAL '$1 seconds'",
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct IdempotencyStats {
+    pub records: i64,
+    /// Size of the table with its indexes
+    pub table_bytes: i64,
+    /// Keys purged by this process since it started
+    pub purged_records: u64,
+}
+
+#[tracing::instrument(name = "Idempotency stats", skip(pool))]
+pub async fn idempotency_stats(pool: &PgPool) -> Result<IdempotencyStats, anyhow::Error> {
+    let row = sqlx::query(
+        "SELECT COUNT(*) AS records, pg_total_relation_size('idempotency') AS table_bytes
+                FROM idempotency",
     )
-    .bind(duration as i16)
     .fetch_one(pool)
-    .await?;
+    .await
+    .context("Failed to perform query to measure the idempotency table")?;
 
-    let n_rows: i64 = n_rows.get("count");
+    Ok(IdempotencyStats {
+        records: row.get("records"),
+        table_bytes: row.get("table_bytes"),
+        purged_records: metrics().idempotency_purged_records.get(),
+    })
+}
 
-    if n_rows > 0 {
-        sqlx::query("DELETE FROM idempotency WHERE NOW() - updated_at >= INTERVAL '$1 seconds'")
-            .bind(duration as i16)
-            .execute(pool)
-            .await?;
+/// Deletes one batch of keys past their retention. Returns how many were deleted,
+/// or `None` once nothing is left to expire
+#[tracing::instrument(skip_all, fields(n_purged=tracing::field::Empty))]
+pub async fn try_idem_expiration(
+    pool: &PgPool,
+    settings: &IdempotencySettings,
+) -> Result<Option<u64>, anyhow::Error> {
+    let n_purged = sqlx::query(
+        "DELETE FROM idempotency WHERE ctid IN (
+                SELECT ctid FROM idempotency
+                WHERE updated_at + $1 <= now()
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )",
+    )
+    .bind(settings.retention())
+    .bind(settings.sweep_batch_size)
+    .execute(pool)
+    .await?
+    .rows_affected();
 
-        Ok(Some(n_rows))
-    } else {
-        Ok(None)
+    tracing::Span::current().record("n_purged", n_purged);
+    if n_purged == 0 {
+        return Ok(None);
     }
+    metrics().idempotency_purged_records.inc_by(n_purged);
+
+    Ok(Some(n_purged))
 }
 
 pub async fn run_idem_worker_until_stopped(
     configuration: Arc<Settings>,
 ) -> Result<(), anyhow::Error> {
     let connection_pool = get_connection_pool(&configuration.database);
+    let settings = &configuration.idempotency;
 
     loop {
-        match try_idem_expiration(
-            &connection_pool,
-            configuration.application.idempotency_expiration,
-        )
-        .await
-        {
-            Ok(_) => {
-                tokio::time::sleep(Duration::from_secs(
-                    configuration.application.idempotency_expiration,
-                ))
-                .await;
-            }
+        heartbeat("idempotency_worker", settings.sweep_interval());
+        match try_idem_expiration(&connection_pool, settings).await {
+            // Batches follow each other until the backlog is gone
+            Ok(Some(_)) => {}
+            Ok(None) => tokio::time::sleep(settings.sweep_interval()).await,
             Err(_) => tokio::time::sleep(Duration::from_secs(3)).await,
         }
     }
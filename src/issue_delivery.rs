@@ -1,52 +1,92 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use anyhow::Context;
+
+use crate::configuration::DeliverySettings;
+use crate::configuration::Settings;
+use crate::health::heartbeat;
+use crate::metrics::metrics;
 use crate::model::task_issue::Issue;
+use crate::notification::{NotificationEvent, UnsubscribeLinks};
+use crate::notification_channel::{
+    ChannelNotification, DeliveryFailure, NotificationChannelKind, NotificationChannels,
+};
 use crate::repository::pgdb;
-use crate::{configuration::Settings, startup::get_connection_pool};
-use crate::{domain::email::ProfileEmail, email_client::EmailClient};
-use sqlx::{PgPool, Postgres, Transaction};
+use crate::telemetry::link_to_traceparent;
+use governor::DefaultDirectRateLimiter;
+use sqlx::postgres::{PgListener, PgPoolOptions};
+use sqlx::{Acquire, FromRow, PgPool, Postgres, Transaction};
 use std::sync::Arc;
-use tracing::{Span, field::display};
+use strum_macros::Display;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tracing::Span;
 use uuid::Uuid;
 
-type PgTx = Transaction<'static, Postgres>;
+type PgTx<'c> = Transaction<'c, Postgres>;
+
+/// Postgres channel notified when new deliveries are queued
+pub const DELIVERY_CHANNEL: &str = "issue_delivery";
 
 pub enum ExecutionOutcome {
     TaskCompleted,
     EmptyQueue,
 }
 
+/// Outcome of a single attempt, as written to `delivery_log`
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+pub enum DeliveryStatus {
+    Sent,
+    /// Failed, another attempt is scheduled
+    Deferred,
+    /// Failed for good, the delivery was dead-lettered
+    Failed,
+}
+
+#[derive(FromRow)]
+struct DeliveryRecipient {
+    id: Uuid,
+    locale: String,
+    notification_webhook_url: Option<String>,
+}
+
+/// Takes up to a batch of due deliveries off the queue. Their rows stay locked
+/// until the transaction ends, so concurrent workers never pick the same delivery
 #[tracing::instrument(skip_all)]
-async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTx, Issue)>, anyhow::Error> {
-    let mut tx = pool.begin().await?;
+async fn dequeue_deliveries(
+    tx: &mut PgTx<'_>,
+    batch_size: i64,
+) -> Result<Vec<Issue>, anyhow::Error> {
     // Dynamic execute-after period using exponential backoff on last attept column
-    let result = sqlx::query_as::<_, Issue>(
-        "SELECT task_issue_id, profile_email 
+    let tasks = sqlx::query_as::<_, Issue>(
+        "SELECT task_issue_id, profile_email, channel, event_type, n_retries, traceparent
             FROM issue_delivery_queue
             WHERE last_attempt IS NULL 
             OR last_attempt + (interval '5 minutes' * power(2, n_retries)) <= now()
             FOR UPDATE SKIP LOCKED
-            LIMIT 1",
+            LIMIT $1",
     )
-    .fetch_optional(&mut *tx)
+    .bind(batch_size)
+    .fetch_all(&mut **tx)
     .await?;
 
-    if let Some(r) = result {
-        Ok(Some((tx, r)))
-    } else {
-        Ok(None)
-    }
+    Ok(tasks)
 }
 
 #[tracing::instrument(skip_all)]
-async fn delete_task(tx: &mut PgTx, issue_id: Uuid, email: &str) -> Result<(), anyhow::Error> {
+async fn delete_task(tx: &mut PgTx<'_>, task: &Issue) -> Result<(), anyhow::Error> {
     sqlx::query(
         "DELETE FROM issue_delivery_queue
     WHERE task_issue_id= $1
-    AND profile_email=$2",
+    AND profile_email=$2
+    AND channel = $3
+    AND event_type = $4",
     )
-    .bind(issue_id)
-    .bind(email)
+    .bind(task.task_issue_id)
+    .bind(&task.profile_email)
+    .bind(&task.channel)
+    .bind(&task.event_type)
     .execute(&mut **tx)
     .await?;
 
@@ -54,76 +94,306 @@ async fn delete_task(tx: &mut PgTx, issue_id: Uuid, email: &str) -> Result<(), a
 }
 
 #[tracing::instrument(skip_all)]
-async fn update_task(tx: &mut PgTx, issue_id: Uuid, email: &str) -> Result<(), anyhow::Error> {
+async fn update_task(tx: &mut PgTx<'_>, task: &Issue) -> Result<(), anyhow::Error> {
     sqlx::query(
         "UPDATE issue_delivery_queue
                                 SET n_retries = n_retries + 1,
                                 last_attempt = now()
                                 WHERE task_issue_id = $1
-                                AND profile_email = $2",
+                                AND profile_email = $2
+                                AND channel = $3
+                                AND event_type = $4",
     )
-    .bind(issue_id)
-    .bind(email)
+    .bind(task.task_issue_id)
+    .bind(&task.profile_email)
+    .bind(&task.channel)
+    .bind(&task.event_type)
     .execute(&mut **tx)
     .await?;
 
     Ok(())
 }
 
-#[tracing::instrument(skip_all, fields(task_issue_id=tracing::field::Empty, profile_email=tracing::field::Empty))]
-pub async fn try_execute_delivery(
+/// Logs an attempt and bumps the task's summary counters once a delivery is settled
+#[tracing::instrument(skip_all)]
+async fn record_attempt(
+    tx: &mut PgTx<'_>,
+    task: &Issue,
+    status: DeliveryStatus,
+    provider_message_id: Option<String>,
+    error: Option<&str>,
+    latency: Duration,
+) -> Result<(), anyhow::Error> {
+    sqlx::query(
+        "INSERT INTO delivery_log
+                (id, task_issue_id, profile_email, channel, event_type, status,
+                    provider_message_id, error, latency_ms)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+    )
+    .bind(Uuid::new_v4())
+    .bind(task.task_issue_id)
+    .bind(&task.profile_email)
+    .bind(&task.channel)
+    .bind(&task.event_type)
+    .bind(status.to_string())
+    .bind(provider_message_id)
+    .bind(error)
+    .bind(latency.as_millis().min(i32::MAX as u128) as i32)
+    .execute(&mut **tx)
+    .await?;
+    metrics()
+        .delivery_attempts
+        .with_label_values(&[task.channel.as_str(), &status.to_string()])
+        .inc();
+
+    if status != DeliveryStatus::Deferred {
+        sqlx::query(
+            "UPDATE task SET deliveries_sent = deliveries_sent + ($2 = 'sent')::INT,
+                    deliveries_failed = deliveries_failed + ($2 = 'failed')::INT
+                WHERE id = $1",
+        )
+        .bind(task.task_issue_id)
+        .bind(status.to_string())
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Moves a delivery to the dead-letter table, keeping the error that ended it
+#[tracing::instrument(skip_all)]
+async fn dead_letter_task(
+    tx: &mut PgTx<'_>,
+    task: &Issue,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query(
+        "WITH failed AS (
+                DELETE FROM issue_delivery_queue
+                WHERE task_issue_id = $2
+                AND profile_email = $3
+                AND channel = $4
+                AND event_type = $5
+                RETURNING task_issue_id, profile_email, channel, event_type, n_retries, created_at
+            )
+            INSERT INTO issue_delivery_dead_letter
+                (id, task_issue_id, profile_email, channel, event_type, n_retries, last_error, queued_at)
+            SELECT $1, task_issue_id, profile_email, channel, event_type, n_retries, $6, created_at
+            FROM failed",
+    )
+    .bind(Uuid::new_v4())
+    .bind(task.task_issue_id)
+    .bind(&task.profile_email)
+    .bind(&task.channel)
+    .bind(&task.event_type)
+    .bind(last_error)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn attempt_delivery(
     pool: &PgPool,
-    email_client: &EmailClient,
-) -> Result<ExecutionOutcome, anyhow::Error> {
-    let task = dequeue_task(pool).await?;
+    channels: &NotificationChannels,
+    unsubscribe_links: &UnsubscribeLinks,
+    channel: NotificationChannelKind,
+    task: &Issue,
+) -> Result<Option<String>, DeliveryFailure> {
+    let event: NotificationEvent = task
+        .event_type
+        .parse()
+        .map_err(|_| DeliveryFailure::Permanent(format!("Unknown event {}", task.event_type)))?;
+    let issue = pgdb::db_get_task(pool, task.task_issue_id)
+        .await
+        .context("Failed to load the delivered task")?;
+    let Some(recipient) = sqlx::query_as::<_, DeliveryRecipient>(
+        "SELECT id, locale, notification_webhook_url FROM profile WHERE email = $1",
+    )
+    .bind(&task.profile_email)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to load the recipient profile")?
+    else {
+        return Err(DeliveryFailure::Permanent(
+            "No profile uses the address the delivery was queued for".to_string(),
+        ));
+    };
 
-    if task.is_none() {
-        return Ok(ExecutionOutcome::EmptyQueue);
+    let unsubscribe_url = unsubscribe_links.url(recipient.id, event)?;
+    let notification = ChannelNotification {
+        event,
+        task: &issue,
+        profile_id: recipient.id,
+        profile_email: &task.profile_email,
+        locale: &recipient.locale,
+        webhook_url: recipient.notification_webhook_url.as_deref(),
+        unsubscribe_url: unsubscribe_url.as_str(),
+    };
+
+    channels.get(channel).deliver(&notification).await
+}
+
+#[tracing::instrument(skip_all, fields(task_issue_id=%task.task_issue_id, profile_email=%task.profile_email, channel=%task.channel, event=%task.event_type))]
+async fn execute_delivery(
+    tx: &mut PgTx<'_>,
+    pool: &PgPool,
+    channels: &NotificationChannels,
+    unsubscribe_links: &UnsubscribeLinks,
+    settings: &DeliverySettings,
+    task: &Issue,
+) -> Result<(), anyhow::Error> {
+    if let Some(traceparent) = &task.traceparent {
+        link_to_traceparent(traceparent);
     }
+    let started = Instant::now();
+    let outcome = match task.channel.parse::<NotificationChannelKind>() {
+        Ok(channel) => attempt_delivery(pool, channels, unsubscribe_links, channel, task).await,
+        Err(_) => Err(DeliveryFailure::Permanent(format!(
+            "Unknown notification channel {}",
+            task.channel
+        ))),
+    };
+    let latency = started.elapsed();
 
-    let (mut tx, task) = task.unwrap();
-
-    Span::current()
-        .record("task_issue_id", display(task.task_issue_id))
-        .record("profile_email", display(&task.profile_email));
-
-    match ProfileEmail::parse(task.profile_email.clone()) {
-        Ok(email) => {
-            let issue = pgdb::db_get_task(pool, task.task_issue_id).await?;
-
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
-                    &issue.task_type,
-                    &issue.source_file,
-                    &issue.source_file,
-                )
-                .await
-            {
-                tracing::error!(error.cause_chain = ?e, error.message=%e, "Failed to deliver issue to a confirmed profile. Skipping and retrying");
-                update_task(&mut tx, task.task_issue_id, &task.profile_email).await?;
-            }
+    match outcome {
+        Ok(message_id) => {
+            record_attempt(tx, task, DeliveryStatus::Sent, message_id, None, latency).await?;
+            delete_task(tx, task).await?;
+        }
+        Err(DeliveryFailure::Transient(e)) if task.n_retries < settings.max_retries => {
+            tracing::error!(error.cause_chain = ?e, error.message=%e, n_retries = task.n_retries, "Failed to deliver issue to a confirmed profile. Retrying later");
+            let error = format!("{e:#}");
+            record_attempt(
+                tx,
+                task,
+                DeliveryStatus::Deferred,
+                None,
+                Some(&error),
+                latency,
+            )
+            .await?;
+            update_task(tx, task).await?;
         }
         Err(e) => {
-            tracing::error!(error.cause_chain = ?e, error.message=%e, "Skipping a confirmed profile. Their stored contact details are invalid");
+            tracing::error!(error.message=%e, n_retries = task.n_retries, "Giving up on a delivery. Moving it to the dead-letter queue");
+            let error = e.to_string();
+            record_attempt(
+                tx,
+                task,
+                DeliveryStatus::Failed,
+                None,
+                Some(&error),
+                latency,
+            )
+            .await?;
+            dead_letter_task(tx, task, &error).await?;
         }
     }
 
-    delete_task(&mut tx, task.task_issue_id, &task.profile_email).await?;
+    Ok(())
+}
+
+/// Attempts up to a batch of due deliveries. Each one is settled in its own
+/// savepoint, so a failure part way through never sends the earlier ones again
+#[tracing::instrument(skip_all, fields(n_deliveries=tracing::field::Empty))]
+pub async fn try_execute_delivery(
+    pool: &PgPool,
+    channels: &NotificationChannels,
+    unsubscribe_links: &UnsubscribeLinks,
+    settings: &DeliverySettings,
+    rate_limiter: Option<&DefaultDirectRateLimiter>,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    // The limit protects the email provider, other channels are not throttled.
+    // Waiting on it with rows locked would hold up every other worker, so the
+    // first permit is taken before the batch is dequeued and the rest of the
+    // batch only goes on while permits are free
+    let mut reserved_permit = match rate_limiter {
+        Some(rate_limiter) => {
+            rate_limiter.until_ready().await;
+            true
+        }
+        None => false,
+    };
+
+    let mut tx = pool.begin().await?;
+    let tasks = dequeue_deliveries(&mut tx, settings.batch_size).await?;
+    let mut n_deliveries = 0;
+    let mut first_error = None;
+
+    for task in &tasks {
+        let is_email = matches!(
+            task.channel.parse::<NotificationChannelKind>(),
+            Ok(NotificationChannelKind::Email)
+        );
+        if let (true, Some(rate_limiter)) = (is_email, rate_limiter) {
+            if reserved_permit {
+                reserved_permit = false;
+            } else if rate_limiter.check().is_err() {
+                // The rows left are released on commit and picked up by the next pass
+                break;
+            }
+        }
+        n_deliveries += 1;
+        Span::current().record("n_deliveries", n_deliveries);
+
+        let mut savepoint = (&mut tx).begin().await?;
+        match execute_delivery(
+            &mut savepoint,
+            pool,
+            channels,
+            unsubscribe_links,
+            settings,
+            task,
+        )
+        .await
+        {
+            Ok(()) => savepoint.commit().await?,
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, error.message=%e, "Failed to settle a delivery");
+                savepoint.rollback().await?;
+                first_error.get_or_insert(e);
+            }
+        }
+    }
 
     tx.commit().await?;
+    if let Some(e) = first_error {
+        return Err(e);
+    }
+
+    if n_deliveries == 0 {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
 
     Ok(ExecutionOutcome::TaskCompleted)
 }
 
 async fn delivery_worker_loop(
     pool: PgPool,
-    email_client: EmailClient,
+    channels: NotificationChannels,
+    unsubscribe_links: UnsubscribeLinks,
+    settings: DeliverySettings,
+    rate_limiter: Option<Arc<DefaultDirectRateLimiter>>,
+    mut wake_up: watch::Receiver<()>,
 ) -> Result<(), anyhow::Error> {
     loop {
-        match try_execute_delivery(&pool, &email_client).await {
+        heartbeat("delivery_worker", settings.poll_interval());
+        // Deliveries enqueued while this pass runs trigger another one right away
+        wake_up.mark_unchanged();
+        match try_execute_delivery(
+            &pool,
+            &channels,
+            &unsubscribe_links,
+            &settings,
+            rate_limiter.as_deref(),
+        )
+        .await
+        {
             Ok(ExecutionOutcome::EmptyQueue) => {
-                tokio::time::sleep(Duration::from_secs(10)).await;
+                // Retries coming out of their backoff are only found by polling
+                let _ = tokio::time::timeout(settings.poll_interval(), wake_up.changed()).await;
             }
             Err(_) => {
                 tokio::time::sleep(Duration::from_secs(3)).await;
@@ -133,12 +403,66 @@ async fn delivery_worker_loop(
     }
 }
 
+/// Wakes the idle workers whenever `enqueue_delivery_tasks` commits new rows
+async fn listen_for_deliveries(
+    pool: PgPool,
+    wake_up: watch::Sender<()>,
+) -> Result<(), anyhow::Error> {
+    let mut listener = PgListener::connect_with(&pool).await?;
+    listener.listen(DELIVERY_CHANNEL).await?;
+
+    loop {
+        match listener.recv().await {
+            Ok(_) => {
+                wake_up.send_replace(());
+            }
+            Err(e) => {
+                // The listener reconnects on the next call
+                tracing::error!(error.cause_chain = ?e, error.message=%e, "Lost the delivery notification channel");
+                tokio::time::sleep(Duration::from_secs(3)).await;
+            }
+        }
+    }
+}
+
 pub async fn run_delivery_worker_until_stopped(
     configuration: Arc<Settings>,
 ) -> Result<(), anyhow::Error> {
-    let connection_pool = get_connection_pool(&configuration.database);
+    let settings = &configuration.delivery;
+    let n_workers = settings.workers.max(1);
+    // Each worker holds its delivery's transaction while reading through a second
+    // connection, and the listener keeps one of its own
+    let connection_pool = PgPoolOptions::new()
+        .max_connections(2 * n_workers as u32 + 1)
+        .connect_lazy_with(configuration.database.with_db());
+
+    let channels = NotificationChannels::new(
+        configuration.email_client.client(),
+        connection_pool.clone(),
+        configuration.email_client.timeout(),
+        settings.webhook_allow_private_addresses,
+    )?;
+    let unsubscribe_links = configuration.application.unsubscribe_links();
+    // Shared by every worker so that the limit holds for the whole pool
+    let rate_limiter = settings.rate_limiter().map(Arc::new);
+    let (wake_up, woken_up) = watch::channel(());
 
-    let email_client = configuration.email_client.client();
+    let mut workers = JoinSet::new();
+    workers.spawn(listen_for_deliveries(connection_pool.clone(), wake_up));
+    for _ in 0..n_workers {
+        workers.spawn(delivery_worker_loop(
+            connection_pool.clone(),
+            channels.clone(),
+            unsubscribe_links.clone(),
+            settings.clone(),
+            rate_limiter.clone(),
+            woken_up.clone(),
+        ));
+    }
 
-    delivery_worker_loop(connection_pool, email_client).await
+    // Workers only return on failure, which takes the whole pool down
+    match workers.join_next().await {
+        Some(outcome) => outcome?,
+        None => Ok(()),
+    }
 }
@@ -1,14 +1,28 @@
+pub mod audit;
 pub mod authentication;
+pub mod authorization;
 pub mod configuration;
+pub mod digest;
 pub mod domain;
 pub mod email_client;
+pub mod email_template;
 pub mod error;
+pub mod health;
 pub mod idempotency;
 pub mod issue_delivery;
+pub mod login_protection;
+pub mod metrics;
 pub mod model;
+pub mod notification;
+pub mod notification_channel;
+pub mod oidc;
+pub mod privacy;
 pub mod repository;
 pub mod routes;
 pub mod session_state;
 pub mod startup;
+pub mod suppression;
 pub mod telemetry;
+pub mod token_cleanup;
+pub mod two_factor;
 pub mod util;
@@ -0,0 +1,269 @@
+use actix_web::HttpRequest;
+use anyhow::Context;
+use sqlx::{PgExecutor, PgPool, Postgres, Row, Transaction};
+use strum_macros::Display;
+use uuid::Uuid;
+
+use crate::audit::{AuditAction, AuditEvent, record_audit_event};
+use crate::configuration::LoginProtectionSettings;
+
+#[derive(Display, Debug, Clone, Copy)]
+#[strum(serialize_all = "snake_case")]
+enum AttemptScope {
+    Username,
+    IpAddress,
+}
+
+/// Address the per-IP lockout keys on. Forwarding headers are only believed when
+/// the request comes from a trusted proxy, as anyone else could make them up
+pub fn client_ip(req: &HttpRequest, settings: &LoginProtectionSettings) -> String {
+    let peer_ip = req.peer_addr().map(|addr| addr.ip());
+    let forwarded_ip = peer_ip
+        .filter(|ip| settings.trusted_proxies.0.contains(ip))
+        .and_then(|_| {
+            req.connection_info()
+                .realip_remote_addr()
+                .map(str::to_string)
+        });
+
+    forwarded_ip
+        .or_else(|| peer_ip.map(|ip| ip.to_string()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Delay imposed after the `failures`-th consecutive failure, doubling each time
+/// and never exceeding the lockout itself.
+pub fn backoff_seconds(failures: u32, settings: &LoginProtectionSettings) -> u64 {
+    let exponent = failures.saturating_sub(1).min(63);
+    settings
+        .backoff_base_seconds
+        .saturating_mul(1u64 << exponent)
+        .min(settings.lockout_seconds)
+}
+
+/// Returns the number of seconds the caller has to wait before trying again, if any.
+#[tracing::instrument(name = "Check login attempts", skip(pool))]
+pub async fn check_login_allowed(
+    pool: &PgPool,
+    username: &str,
+    ip_address: &str,
+) -> Result<Option<u64>, anyhow::Error> {
+    let row = sqlx::query(
+        "SELECT CEIL(EXTRACT(EPOCH FROM MAX(next_attempt_at) - now()))::BIGINT AS retry_after
+                FROM login_attempts
+                WHERE ((scope = $1 AND key = $2) OR (scope = $3 AND key = $4))
+                AND next_attempt_at > now()",
+    )
+    .bind(AttemptScope::Username.to_string())
+    .bind(username)
+    .bind(AttemptScope::IpAddress.to_string())
+    .bind(ip_address)
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform query to check login attempts")?;
+
+    Ok(row
+        .get::<Option<i64>, _>("retry_after")
+        .map(|s| s.max(1) as u64))
+}
+
+async fn increment_failures(
+    tx: &mut Transaction<'_, Postgres>,
+    scope: AttemptScope,
+    key: &str,
+    settings: &LoginProtectionSettings,
+) -> Result<u32, sqlx::Error> {
+    // Failures older than the lockout window are forgotten
+    let row = sqlx::query(
+        "INSERT INTO login_attempts (scope, key, failures, last_failure_at)
+                VALUES ($1, $2, 1, now())
+                ON CONFLICT (scope, key) DO UPDATE SET
+                    failures = CASE
+                        WHEN login_attempts.last_failure_at < now() - make_interval(secs => $3)
+                        THEN 1
+                        ELSE login_attempts.failures + 1
+                    END,
+                    last_failure_at = now()
+                RETURNING failures",
+    )
+    .bind(scope.to_string())
+    .bind(key)
+    .bind(settings.lockout_seconds as f64)
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(row.get::<i32, _>("failures") as u32)
+}
+
+async fn delay_next_attempt(
+    tx: &mut Transaction<'_, Postgres>,
+    scope: AttemptScope,
+    key: &str,
+    seconds: u64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE login_attempts SET next_attempt_at = now() + make_interval(secs => $3)
+                WHERE scope = $1 AND key = $2",
+    )
+    .bind(scope.to_string())
+    .bind(key)
+    .bind(seconds as f64)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Record failed login", skip(pool, settings))]
+pub async fn record_login_failure(
+    pool: &PgPool,
+    settings: &LoginProtectionSettings,
+    username: &str,
+    ip_address: &str,
+) -> Result<(), anyhow::Error> {
+    let mut tx = pool.begin().await?;
+
+    let failures = increment_failures(&mut tx, AttemptScope::Username, username, settings).await?;
+    if failures >= settings.max_failed_attempts {
+        delay_next_attempt(
+            &mut tx,
+            AttemptScope::Username,
+            username,
+            settings.lockout_seconds,
+        )
+        .await?;
+
+        let profile_id = sqlx::query("SELECT id FROM profile WHERE username = $1")
+            .bind(username)
+            .fetch_optional(&mut *tx)
+            .await?
+            .map(|r| r.get::<Uuid, _>("id"));
+
+        tracing::warn!(failures, "Account locked after repeated failed logins");
+
+        record_audit_event(
+            &mut *tx,
+            AuditEvent {
+                actor_id: None,
+                profile_id,
+                action: AuditAction::AccountLocked,
+                details: serde_json::json!({
+                    "username": username,
+                    "ip_address": ip_address,
+                    "failures": failures,
+                    "lockout_seconds": settings.lockout_seconds,
+                }),
+            },
+        )
+        .await?;
+    } else {
+        let delay = backoff_seconds(failures, settings);
+        delay_next_attempt(&mut tx, AttemptScope::Username, username, delay).await?;
+    }
+
+    // A single address is only ever locked out, never slowed down, so that
+    // clients sharing a NAT are not penalised for each other's typos
+    let failures =
+        increment_failures(&mut tx, AttemptScope::IpAddress, ip_address, settings).await?;
+    if failures >= settings.max_failed_attempts_per_ip {
+        delay_next_attempt(
+            &mut tx,
+            AttemptScope::IpAddress,
+            ip_address,
+            settings.lockout_seconds,
+        )
+        .await?;
+
+        tracing::warn!(failures, "IP address locked after repeated failed logins");
+
+        record_audit_event(
+            &mut *tx,
+            AuditEvent {
+                actor_id: None,
+                profile_id: None,
+                action: AuditAction::IpAddressLocked,
+                details: serde_json::json!({
+                    "ip_address": ip_address,
+                    "failures": failures,
+                    "lockout_seconds": settings.lockout_seconds,
+                }),
+            },
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Lifts any delay or lockout on a username; returns whether there was one to lift
+#[tracing::instrument(name = "Clear failed logins", skip(executor))]
+pub async fn clear_login_failures<'e, E>(executor: E, username: &str) -> Result<bool, sqlx::Error>
+where
+    E: PgExecutor<'e>,
+{
+    let n_deleted = sqlx::query("DELETE FROM login_attempts WHERE scope = $1 AND key = $2")
+        .bind(AttemptScope::Username.to_string())
+        .bind(username)
+        .execute(executor)
+        .await?
+        .rows_affected();
+
+    Ok(n_deleted > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backoff_seconds, client_ip};
+    use crate::configuration::{LoginProtectionSettings, TrustedProxies};
+    use actix_web::test::TestRequest;
+
+    fn settings() -> LoginProtectionSettings {
+        LoginProtectionSettings {
+            max_failed_attempts: 5,
+            max_failed_attempts_per_ip: 50,
+            lockout_seconds: 900,
+            backoff_base_seconds: 1,
+            trusted_proxies: TrustedProxies(vec!["10.0.0.1".parse().unwrap()]),
+        }
+    }
+
+    #[test]
+    fn forwarded_address_is_only_believed_from_a_trusted_proxy() {
+        let settings = settings();
+        let request = |peer: &str| {
+            TestRequest::default()
+                .peer_addr(peer.parse().unwrap())
+                .insert_header(("X-Forwarded-For", "203.0.113.7"))
+                .to_http_request()
+        };
+
+        assert_eq!(
+            client_ip(&request("10.0.0.1:4000"), &settings),
+            "203.0.113.7"
+        );
+        assert_eq!(
+            client_ip(&request("198.51.100.2:4000"), &settings),
+            "198.51.100.2"
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_with_each_failure() {
+        let settings = settings();
+
+        assert_eq!(backoff_seconds(1, &settings), 1);
+        assert_eq!(backoff_seconds(2, &settings), 2);
+        assert_eq!(backoff_seconds(3, &settings), 4);
+        assert_eq!(backoff_seconds(4, &settings), 8);
+    }
+
+    #[test]
+    fn backoff_never_exceeds_the_lockout() {
+        let settings = settings();
+
+        assert_eq!(backoff_seconds(20, &settings), 900);
+        assert_eq!(backoff_seconds(u32::MAX, &settings), 900);
+    }
+}
@@ -2,10 +2,15 @@ use std::fmt::{Debug, Display};
 use std::sync::Arc;
 
 use taskservice::configuration::get_configuration;
+use taskservice::digest::run_digest_worker_until_stopped;
 use taskservice::idempotency::run_idem_worker_until_stopped;
 use taskservice::issue_delivery::run_delivery_worker_until_stopped;
+use taskservice::privacy::run_privacy_worker_until_stopped;
 use taskservice::startup::Application;
-use taskservice::telemetry::{get_tracing_subscriber, init_tracing_subscriber};
+use taskservice::telemetry::{
+    get_tracing_subscriber, init_tracing_subscriber, otlp_tracer_provider,
+};
+use taskservice::token_cleanup::run_token_cleanup_worker_until_stopped;
 use tokio::task::JoinError;
 
 fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
@@ -24,23 +29,46 @@ fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>
 
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
-    let subscriber = get_tracing_subscriber("taskservice".into(), "info".into(), std::io::stdout);
-    init_tracing_subscriber(subscriber);
     // Panic if we can't read configuration
     let configuration = get_configuration().expect("Failed to read configuration");
+    let tracer_provider = configuration
+        .telemetry
+        .otlp_endpoint
+        .as_deref()
+        .map(|endpoint| otlp_tracer_provider("taskservice".into(), endpoint))
+        .transpose()?;
+    let subscriber = get_tracing_subscriber(
+        "taskservice".into(),
+        "info".into(),
+        std::io::stdout,
+        tracer_provider.as_ref(),
+    );
+    init_tracing_subscriber(subscriber);
     let configuration = Arc::new(configuration);
     let application = Application::build(&configuration).await?;
     let application_task = tokio::spawn(application.run_until_stopped());
     let delivery_worker = tokio::spawn(run_delivery_worker_until_stopped(Arc::clone(
         &configuration,
     )));
+    let digest_worker = tokio::spawn(run_digest_worker_until_stopped(Arc::clone(&configuration)));
     let idempotency_worker =
         tokio::spawn(run_idem_worker_until_stopped(Arc::clone(&configuration)));
+    let token_cleanup_worker = tokio::spawn(run_token_cleanup_worker_until_stopped(Arc::clone(
+        &configuration,
+    )));
+    let privacy_worker = tokio::spawn(run_privacy_worker_until_stopped(Arc::clone(&configuration)));
 
     tokio::select! {
         o = application_task => {report_exit("API", o);},
         o = delivery_worker => {report_exit("delivery_worker", o);},
-        o = idempotency_worker => {report_exit("idempotency_worker", o);}
+        o = digest_worker => {report_exit("digest_worker", o);},
+        o = idempotency_worker => {report_exit("idempotency_worker", o);},
+        o = token_cleanup_worker => {report_exit("token_cleanup_worker", o);},
+        o = privacy_worker => {report_exit("privacy_worker", o);}
     };
+    // Sends the spans still waiting in the batch
+    if let Some(tracer_provider) = tracer_provider {
+        tracer_provider.shutdown()?;
+    }
     Ok(())
 }
@@ -0,0 +1,257 @@
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use anyhow::Context;
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use sqlx::{PgPool, Row};
+
+use crate::idempotency::idempotency_stats;
+
+/// Requests that matched no route share one label, so that probing random
+/// paths cannot blow up the number of series. Task metrics carry no task type
+/// for the same reason, as types are whatever reporters send
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Tasks run for minutes to days, far longer than the default buckets
+const TASK_DURATION_BUCKETS: &[f64] = &[
+    1.0,
+    10.0,
+    60.0,
+    300.0,
+    900.0,
+    3600.0,
+    4.0 * 3600.0,
+    12.0 * 3600.0,
+    86400.0,
+    7.0 * 86400.0,
+];
+
+/// Counters and histograms are updated where things happen; gauges are read
+/// from the database on every scrape
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub tasks: IntGaugeVec,
+    pub task_duration: Histogram,
+    pub delivery_queue_depth: IntGaugeVec,
+    pub delivery_queue_retrying: IntGaugeVec,
+    pub delivery_attempts: IntCounterVec,
+    pub idempotency_records: IntGauge,
+    pub idempotency_table_bytes: IntGauge,
+    pub idempotency_purged_records: IntCounter,
+    pub db_pool_connections: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+
+        let metrics = Self {
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests handled"),
+                &["method", "route", "status"],
+            )?,
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "Time taken to handle HTTP requests",
+                ),
+                &["method", "route", "status"],
+            )?,
+            tasks: IntGaugeVec::new(Opts::new("tasks", "Tasks by state"), &["state"])?,
+            task_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "task_duration_seconds",
+                    "Time from a task's start to its completion",
+                )
+                .buckets(TASK_DURATION_BUCKETS.to_vec()),
+            )?,
+            delivery_queue_depth: IntGaugeVec::new(
+                Opts::new(
+                    "delivery_queue_depth",
+                    "Notifications waiting to be delivered",
+                ),
+                &["channel"],
+            )?,
+            delivery_queue_retrying: IntGaugeVec::new(
+                Opts::new(
+                    "delivery_queue_retrying",
+                    "Queued notifications that failed at least once",
+                ),
+                &["channel"],
+            )?,
+            delivery_attempts: IntCounterVec::new(
+                Opts::new("delivery_attempts_total", "Delivery attempts by outcome"),
+                &["channel", "status"],
+            )?,
+            idempotency_records: IntGauge::new(
+                "idempotency_records",
+                "Idempotency keys currently stored",
+            )?,
+            idempotency_table_bytes: IntGauge::new(
+                "idempotency_table_bytes",
+                "Size of the idempotency table with its indexes",
+            )?,
+            idempotency_purged_records: IntCounter::new(
+                "idempotency_purged_records_total",
+                "Expired idempotency keys deleted",
+            )?,
+            db_pool_connections: IntGaugeVec::new(
+                Opts::new(
+                    "db_pool_connections",
+                    "Connections of the API's database pool",
+                ),
+                &["state"],
+            )?,
+            registry,
+        };
+
+        let collectors: [Box<dyn Collector>; 11] = [
+            Box::new(metrics.http_requests.clone()),
+            Box::new(metrics.http_request_duration.clone()),
+            Box::new(metrics.tasks.clone()),
+            Box::new(metrics.task_duration.clone()),
+            Box::new(metrics.delivery_queue_depth.clone()),
+            Box::new(metrics.delivery_queue_retrying.clone()),
+            Box::new(metrics.delivery_attempts.clone()),
+            Box::new(metrics.idempotency_records.clone()),
+            Box::new(metrics.idempotency_table_bytes.clone()),
+            Box::new(metrics.idempotency_purged_records.clone()),
+            Box::new(metrics.db_pool_connections.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector)?;
+        }
+
+        Ok(metrics)
+    }
+}
+
+static METRICS: LazyLock<Metrics> =
+    LazyLock::new(|| Metrics::new().expect("Failed to register metrics"));
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Counts every request and its latency, labelled with the route pattern
+/// rather than the path so that ids don't end up in label values
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+
+    let outcome = next.call(req).await;
+    let status = match &outcome {
+        Ok(res) => res.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    metrics().http_requests.with_label_values(&labels).inc();
+    metrics()
+        .http_request_duration
+        .with_label_values(&labels)
+        .observe(started.elapsed().as_secs_f64());
+
+    outcome
+}
+
+async fn refresh_task_gauges(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let rows = sqlx::query(
+        "SELECT state::TEXT AS state, COUNT(*) AS n_tasks
+            FROM task
+            GROUP BY state",
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to count tasks")?;
+
+    // States with no tasks left must not keep reporting their last count
+    metrics().tasks.reset();
+    for row in rows {
+        let state: Option<String> = row.get("state");
+        metrics()
+            .tasks
+            .with_label_values(&[state.as_deref().unwrap_or_default()])
+            .set(row.get("n_tasks"));
+    }
+
+    Ok(())
+}
+
+async fn refresh_delivery_gauges(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let rows = sqlx::query(
+        "SELECT channel, COUNT(*) AS depth,
+                COUNT(*) FILTER (WHERE n_retries > 0) AS retrying
+            FROM issue_delivery_queue
+            GROUP BY channel",
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to measure the delivery queue")?;
+
+    metrics().delivery_queue_depth.reset();
+    metrics().delivery_queue_retrying.reset();
+    for row in rows {
+        let channel: String = row.get("channel");
+        metrics()
+            .delivery_queue_depth
+            .with_label_values(&[&channel])
+            .set(row.get("depth"));
+        metrics()
+            .delivery_queue_retrying
+            .with_label_values(&[&channel])
+            .set(row.get("retrying"));
+    }
+
+    Ok(())
+}
+
+async fn refresh_idempotency_gauges(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let stats = idempotency_stats(pool).await?;
+    metrics().idempotency_records.set(stats.records);
+    metrics().idempotency_table_bytes.set(stats.table_bytes);
+
+    Ok(())
+}
+
+fn refresh_pool_gauges(pool: &PgPool) {
+    let size = pool.size() as i64;
+    let idle = pool.num_idle() as i64;
+    let max = pool.options().get_max_connections() as i64;
+    let connections = &metrics().db_pool_connections;
+    connections.with_label_values(&["idle"]).set(idle);
+    connections.with_label_values(&["in_use"]).set(size - idle);
+    connections.with_label_values(&["max"]).set(max);
+}
+
+/// Reads the gauges from the database and renders every metric in the
+/// Prometheus text format
+#[tracing::instrument(name = "Rendering metrics", skip(pool))]
+pub async fn render_metrics(pool: &PgPool) -> Result<String, anyhow::Error> {
+    // Measured first, so that the scrape's own queries don't count as in use
+    refresh_pool_gauges(pool);
+    refresh_task_gauges(pool).await?;
+    refresh_delivery_gauges(pool).await?;
+    refresh_idempotency_gauges(pool).await?;
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&metrics().registry.gather(), &mut buffer)
+        .context("Failed to encode metrics")?;
+
+    Ok(String::from_utf8(buffer)?)
+}
@@ -1,5 +1,7 @@
+use crate::configuration::PasswordHashingSettings;
 use crate::domain::{
-    email::ProfileEmail, name::ProfileName, password::Password, username::ProfileUsername,
+    email::ProfileEmail, locale::ProfileLocale, name::ProfileName, password::Password,
+    username::ProfileUsername,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
@@ -14,17 +16,25 @@ pub struct Profile {
     pub email: ProfileEmail,
     pub username: ProfileUsername,
     pub password: Password,
+    pub locale: ProfileLocale,
 }
 
-impl TryFrom<ProfileCreateRequest> for Profile {
-    type Error = anyhow::Error;
-
-    fn try_from(value: ProfileCreateRequest) -> Result<Self, Self::Error> {
+impl Profile {
+    /// Validates a creation request, hashing its password with the configured parameters
+    pub fn parse(
+        value: ProfileCreateRequest,
+        password_hashing: &PasswordHashingSettings,
+    ) -> Result<Self, anyhow::Error> {
         let first_name = ProfileName::parse(value.first_name)?;
         let last_name = ProfileName::parse(value.last_name)?;
         let email = ProfileEmail::parse(value.email)?;
         let username = ProfileUsername::parse(value.username)?;
-        let password = Password::parse(value.password)?;
+        let password = Password::parse(value.password, password_hashing)?;
+        let locale = value
+            .locale
+            .map(ProfileLocale::parse)
+            .transpose()?
+            .unwrap_or_default();
 
         Ok(Profile {
             id: Uuid::new_v4(),
@@ -33,6 +43,7 @@ impl TryFrom<ProfileCreateRequest> for Profile {
             email,
             username,
             password,
+            locale,
         })
     }
 }
@@ -43,10 +54,17 @@ pub struct ProfileUpdate {
     pub id: String,
     pub first_name: Option<String>,
     pub last_name: Option<String>,
+    #[serde(default)]
+    pub locale: Option<String>,
 }
 
 impl ProfileUpdate {
-    pub fn new(id: &str, first_name: Option<&str>, last_name: Option<&str>) -> ProfileUpdate {
+    pub fn new(
+        id: &str,
+        first_name: Option<&str>,
+        last_name: Option<&str>,
+        locale: Option<&str>,
+    ) -> ProfileUpdate {
         let f_name = first_name.map(|v| v.to_string());
 
         let l_name = last_name.map(|v| v.to_string());
@@ -55,6 +73,7 @@ impl ProfileUpdate {
             id: id.to_string(),
             first_name: f_name,
             last_name: l_name,
+            locale: locale.map(|v| v.to_string()),
         }
     }
 }
@@ -65,9 +84,10 @@ pub struct ProfileResponse {
     pub first_name: String,
     pub last_name: String,
     pub email: String,
+    pub locale: String,
 }
 
-#[derive(Deserialize, Serialize, ToSchema)]
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
 pub struct ProfileIdentifier {
     pub id: Uuid,
 }
@@ -79,4 +99,7 @@ pub struct ProfileCreateRequest {
     pub email: String,
     pub username: String,
     pub password: String,
+    /// Language of the emails sent to the profile, `en` when left out
+    #[serde(default)]
+    pub locale: Option<String>,
 }
@@ -25,10 +25,16 @@ pub struct Task {
     pub state: TaskState,
     pub source_file: String,
     pub result_file: Option<String>,
+    pub workspace: Option<String>,
 }
 
 impl Task {
-    pub fn new(reporter_id: Uuid, task_type: String, source_file: String) -> Task {
+    pub fn new(
+        reporter_id: Uuid,
+        task_type: String,
+        source_file: String,
+        workspace: Option<String>,
+    ) -> Task {
         Task {
             reporter_id,
             id: Uuid::new_v4(),
@@ -36,6 +42,7 @@ impl Task {
             state: TaskState::NotStarted,
             source_file,
             result_file: None,
+            workspace,
         }
     }
 
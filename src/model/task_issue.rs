@@ -7,4 +7,9 @@ use uuid::Uuid;
 pub struct Issue {
     pub task_issue_id: Uuid,
     pub profile_email: String,
+    pub channel: String,
+    pub event_type: String,
+    pub n_retries: i32,
+    /// `traceparent` of the request that queued the delivery
+    pub traceparent: Option<String>,
 }
@@ -0,0 +1,328 @@
+use anyhow::Context;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool, Postgres, Row, Transaction};
+use strum_macros::{Display, EnumString};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Events a profile can be notified about. Completed and failed tasks are only
+/// reported in digests
+#[derive(
+    Serialize, Deserialize, Display, EnumString, ToSchema, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum NotificationEvent {
+    TaskCreated,
+    TaskCompleted,
+    TaskFailed,
+}
+
+impl NotificationEvent {
+    /// Every event, as listed in digests
+    pub const ALL: [Self; 3] = [Self::TaskCreated, Self::TaskCompleted, Self::TaskFailed];
+}
+
+#[derive(
+    Serialize,
+    Deserialize,
+    Display,
+    EnumString,
+    ToSchema,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum DigestFrequency {
+    #[default]
+    Immediate,
+    Hourly,
+    Daily,
+}
+
+/// Turns notifications for an event on or off. Leaving `task_type` or
+/// `workspace` empty makes the rule apply to any value
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct NotificationRule {
+    pub event_type: NotificationEvent,
+    #[serde(default)]
+    pub task_type: Option<String>,
+    #[serde(default)]
+    pub workspace: Option<String>,
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct NotificationPreferences {
+    #[serde(default)]
+    pub digest_frequency: DigestFrequency,
+    #[serde(default)]
+    pub rules: Vec<NotificationRule>,
+}
+
+#[tracing::instrument(name = "Get notification preferences", skip(pool))]
+pub async fn get_notification_preferences(
+    pool: &PgPool,
+    profile_id: Uuid,
+) -> Result<Option<NotificationPreferences>, anyhow::Error> {
+    let Some(digest_frequency) =
+        sqlx::query_scalar::<_, String>("SELECT digest_frequency FROM profile WHERE id = $1")
+            .bind(profile_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to retrieve the digest frequency")?
+    else {
+        return Ok(None);
+    };
+
+    let rules = sqlx::query(
+        "SELECT event_type, task_type, workspace, enabled
+                FROM notification_preferences
+                WHERE profile_id = $1
+                ORDER BY event_type, task_type NULLS FIRST, workspace NULLS FIRST",
+    )
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the notification rules")?
+    .into_iter()
+    .map(|r| {
+        Ok(NotificationRule {
+            event_type: r.get::<String, _>("event_type").parse()?,
+            task_type: r.get("task_type"),
+            workspace: r.get("workspace"),
+            enabled: r.get("enabled"),
+        })
+    })
+    .collect::<Result<Vec<_>, strum::ParseError>>()
+    .context("Stored notification rule has an unknown event type")?;
+
+    Ok(Some(NotificationPreferences {
+        digest_frequency: digest_frequency
+            .parse()
+            .context("Stored digest frequency is invalid")?,
+        rules,
+    }))
+}
+
+/// Replaces every rule of the profile with the given ones
+#[tracing::instrument(name = "Replace notification preferences", skip(tx, preferences))]
+pub async fn replace_notification_preferences(
+    tx: &mut Transaction<'_, Postgres>,
+    profile_id: Uuid,
+    preferences: &NotificationPreferences,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE profile SET digest_frequency = $1 WHERE id = $2")
+        .bind(preferences.digest_frequency.to_string())
+        .bind(profile_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query("DELETE FROM notification_preferences WHERE profile_id = $1")
+        .bind(profile_id)
+        .execute(&mut **tx)
+        .await?;
+
+    for rule in &preferences.rules {
+        store_notification_rule(&mut **tx, profile_id, rule).await?;
+    }
+
+    Ok(())
+}
+
+async fn store_notification_rule<'e, E>(
+    executor: E,
+    profile_id: Uuid,
+    rule: &NotificationRule,
+) -> Result<(), sqlx::Error>
+where
+    E: PgExecutor<'e>,
+{
+    sqlx::query(
+        "INSERT INTO notification_preferences (profile_id, event_type, task_type, workspace, enabled)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT ON CONSTRAINT notification_preferences_rule_key
+                DO UPDATE SET enabled = EXCLUDED.enabled, updated_at = now()",
+    )
+    .bind(profile_id)
+    .bind(rule.event_type.to_string())
+    .bind(&rule.task_type)
+    .bind(&rule.workspace)
+    .bind(rule.enabled)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Switches an event off for every task type and workspace. Narrower rules that
+/// still enable the event are dropped, otherwise they would keep winning
+#[tracing::instrument(name = "Unsubscribe from notifications", skip(tx))]
+pub async fn unsubscribe(
+    tx: &mut Transaction<'_, Postgres>,
+    profile_id: Uuid,
+    event_type: NotificationEvent,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "DELETE FROM notification_preferences
+                WHERE profile_id = $1 AND event_type = $2 AND enabled",
+    )
+    .bind(profile_id)
+    .bind(event_type.to_string())
+    .execute(&mut **tx)
+    .await?;
+
+    store_notification_rule(
+        &mut **tx,
+        profile_id,
+        &NotificationRule {
+            event_type,
+            task_type: None,
+            workspace: None,
+            enabled: false,
+        },
+    )
+    .await
+}
+
+/// Links sent before digests had their own were for a single event
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum UnsubscribeEvents {
+    One(NotificationEvent),
+    Many(Vec<NotificationEvent>),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct UnsubscribeClaims {
+    sub: Uuid,
+    evt: UnsubscribeEvents,
+}
+
+/// Builds and checks the signed one-click unsubscribe links put in notification emails.
+/// The links do not expire so that old emails keep working
+#[derive(Clone, Debug)]
+pub struct UnsubscribeLinks {
+    base_uri: String,
+    secret_key: String,
+}
+
+impl UnsubscribeLinks {
+    pub fn new(base_uri: &str, secret_key: &str) -> Self {
+        Self {
+            base_uri: base_uri.to_string(),
+            secret_key: secret_key.to_string(),
+        }
+    }
+
+    pub fn url(
+        &self,
+        profile_id: Uuid,
+        event_type: NotificationEvent,
+    ) -> Result<Url, anyhow::Error> {
+        self.signed_url(UnsubscribeClaims {
+            sub: profile_id,
+            evt: UnsubscribeEvents::One(event_type),
+        })
+    }
+
+    /// Stops every event a digest can list, not just the one it was sent for
+    pub fn digest_url(&self, profile_id: Uuid) -> Result<Url, anyhow::Error> {
+        self.signed_url(UnsubscribeClaims {
+            sub: profile_id,
+            evt: UnsubscribeEvents::Many(NotificationEvent::ALL.to_vec()),
+        })
+    }
+
+    fn signed_url(&self, claims: UnsubscribeClaims) -> Result<Url, anyhow::Error> {
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(self.secret_key.as_ref()),
+        )?;
+
+        Url::parse(&format!(
+            "{}/notifications/unsubscribe?token={}",
+            self.base_uri, token
+        ))
+        .context("Invalid application base uri")
+    }
+
+    pub fn verify(&self, token: &str) -> Result<(Uuid, Vec<NotificationEvent>), anyhow::Error> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+
+        let claims = decode::<UnsubscribeClaims>(
+            token,
+            &DecodingKey::from_secret(self.secret_key.as_ref()),
+            &validation,
+        )?
+        .claims;
+
+        let events = match claims.evt {
+            UnsubscribeEvents::One(event_type) => vec![event_type],
+            UnsubscribeEvents::Many(events) => events,
+        };
+
+        Ok((claims.sub, events))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NotificationEvent, UnsubscribeLinks};
+    use claims::assert_err;
+    use uuid::Uuid;
+
+    fn token(links: &UnsubscribeLinks, profile_id: Uuid) -> String {
+        let url = links
+            .url(profile_id, NotificationEvent::TaskCreated)
+            .unwrap();
+        url.query_pairs()
+            .find(|(k, _)| k == "token")
+            .unwrap()
+            .1
+            .into_owned()
+    }
+
+    #[test]
+    fn unsubscribe_token_round_trips() {
+        let links = UnsubscribeLinks::new("http://127.0.0.1", "secret");
+        let profile_id = Uuid::new_v4();
+
+        let verified = links.verify(&token(&links, profile_id)).unwrap();
+
+        assert_eq!(verified, (profile_id, vec![NotificationEvent::TaskCreated]));
+    }
+
+    #[test]
+    fn digest_unsubscribe_token_covers_every_event() {
+        let links = UnsubscribeLinks::new("http://127.0.0.1", "secret");
+        let profile_id = Uuid::new_v4();
+        let url = links.digest_url(profile_id).unwrap();
+        let token = url.query_pairs().find(|(k, _)| k == "token").unwrap().1;
+
+        let verified = links.verify(&token).unwrap();
+
+        assert_eq!(verified, (profile_id, NotificationEvent::ALL.to_vec()));
+    }
+
+    #[test]
+    fn unsubscribe_token_signed_with_another_key_is_rejected() {
+        let links = UnsubscribeLinks::new("http://127.0.0.1", "secret");
+        let forged = token(
+            &UnsubscribeLinks::new("http://127.0.0.1", "another-secret"),
+            Uuid::new_v4(),
+        );
+
+        assert_err!(links.verify(&forged));
+    }
+}
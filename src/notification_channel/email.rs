@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+
+use super::{ChannelNotification, DeliveryFailure, NotificationChannel};
+use crate::domain::email::ProfileEmail;
+use crate::email_client::EmailClient;
+use crate::email_template::{EmailTemplate, render_email};
+use crate::notification::NotificationEvent;
+
+/// Emails new tasks right away. Completed and failed tasks are only emailed in digests
+#[derive(Clone, Debug)]
+pub struct EmailChannel {
+    email_client: EmailClient,
+}
+
+impl EmailChannel {
+    pub fn new(email_client: EmailClient) -> Self {
+        Self { email_client }
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for EmailChannel {
+    async fn deliver(
+        &self,
+        notification: &ChannelNotification<'_>,
+    ) -> Result<Option<String>, DeliveryFailure> {
+        if notification.event != NotificationEvent::TaskCreated {
+            return Err(DeliveryFailure::Permanent(format!(
+                "{} events are only emailed in digests",
+                notification.event
+            )));
+        }
+        let email = ProfileEmail::parse(notification.profile_email.to_string()).map_err(|e| {
+            DeliveryFailure::Permanent(format!("The stored contact details are invalid: {e}"))
+        })?;
+
+        let task = notification.task;
+        let message = render_email(
+            EmailTemplate::TaskCreated,
+            notification.locale,
+            serde_json::json!({
+                "task_id": task.id,
+                "task_type": task.task_type,
+                "source_file": task.source_file,
+                "workspace": task.workspace,
+                "unsubscribe_url": notification.unsubscribe_url,
+            }),
+        )?;
+
+        let message_id = self
+            .email_client
+            .send_notification_email(
+                &email,
+                &message.subject,
+                &message.html,
+                &message.text,
+                notification.unsubscribe_url,
+            )
+            .await?;
+
+        Ok(message_id)
+    }
+}
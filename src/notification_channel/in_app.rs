@@ -0,0 +1,116 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{ChannelNotification, DeliveryFailure, NotificationChannel};
+
+/// Stores notifications in the profile's inbox
+#[derive(Clone, Debug)]
+pub struct InAppChannel {
+    pool: PgPool,
+}
+
+impl InAppChannel {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for InAppChannel {
+    async fn deliver(
+        &self,
+        notification: &ChannelNotification<'_>,
+    ) -> Result<Option<String>, DeliveryFailure> {
+        // Written outside the delivery's transaction, so a retry must not add it twice
+        let id = sqlx::query_scalar::<_, Uuid>(
+            "INSERT INTO inbox_notifications (id, profile_id, task_id, event_type, message)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT ON CONSTRAINT inbox_notifications_event_key DO NOTHING
+                RETURNING id",
+        )
+        .bind(Uuid::new_v4())
+        .bind(notification.profile_id)
+        .bind(notification.task.id)
+        .bind(notification.event.to_string())
+        .bind(notification.summary())
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to store the notification in the inbox")?;
+
+        Ok(id.map(|id| id.to_string()))
+    }
+}
+
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct InboxNotification {
+    id: Uuid,
+    task_id: Uuid,
+    event_type: String,
+    message: String,
+    read: bool,
+    created_at: String,
+}
+
+impl InboxNotification {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// Lists up to `limit` of the profile's notifications, newest first, starting
+/// after the one `cursor` points to
+#[tracing::instrument(name = "List inbox notifications", skip(pool))]
+pub async fn list_inbox(
+    pool: &PgPool,
+    profile_id: Uuid,
+    unread_only: bool,
+    cursor: Option<Uuid>,
+    limit: i64,
+) -> Result<Vec<InboxNotification>, sqlx::Error> {
+    sqlx::query_as::<_, InboxNotification>(
+        "SELECT id, task_id, event_type, message, read_at IS NOT NULL AS read,
+                    to_char(created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS created_at
+                FROM inbox_notifications
+                WHERE profile_id = $1
+                AND NOT ($2 AND read_at IS NOT NULL)
+                AND ($3::UUID IS NULL OR (created_at, id) < (
+                    SELECT c.created_at, c.id FROM inbox_notifications c
+                    WHERE c.id = $3 AND c.profile_id = $1
+                ))
+                ORDER BY created_at DESC, id DESC
+                LIMIT $4",
+    )
+    .bind(profile_id)
+    .bind(unread_only)
+    .bind(cursor)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+/// Marks one of the profile's notifications as read or unread. Returns false when
+/// the profile has no such notification
+#[tracing::instrument(name = "Mark inbox notification", skip(pool))]
+pub async fn mark_inbox_notification(
+    pool: &PgPool,
+    profile_id: Uuid,
+    notification_id: Uuid,
+    read: bool,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE inbox_notifications
+                SET read_at = CASE WHEN $3 THEN COALESCE(read_at, now()) END
+                WHERE id = $1 AND profile_id = $2",
+    )
+    .bind(notification_id)
+    .bind(profile_id)
+    .bind(read)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
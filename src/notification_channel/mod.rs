@@ -0,0 +1,332 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Row, Transaction};
+use strum_macros::{Display, EnumString};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::email_client::EmailClient;
+use crate::model::task::Task;
+use crate::notification::NotificationEvent;
+
+mod email;
+mod in_app;
+mod webhook;
+
+pub use email::EmailChannel;
+pub use in_app::{InAppChannel, InboxNotification, list_inbox, mark_inbox_notification};
+pub use webhook::{WebhookChannel, WebhookUrlError, check_webhook_url};
+
+/// Where a notification is delivered. Each profile picks its channels per event
+#[derive(
+    Serialize,
+    Deserialize,
+    Display,
+    EnumString,
+    ToSchema,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum NotificationChannelKind {
+    Email,
+    /// A JSON POST to the profile's webhook URL, e.g. a chat system's incoming webhook
+    Webhook,
+    /// The inbox served by `GET /notifications`
+    InApp,
+}
+
+/// The channels one event is delivered on. Events left out are emailed
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct EventChannels {
+    pub event_type: NotificationEvent,
+    pub channels: Vec<NotificationChannelKind>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct ChannelPreferences {
+    /// Required when any event is delivered over the webhook channel
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    #[serde(default)]
+    pub events: Vec<EventChannels>,
+}
+
+impl ChannelPreferences {
+    /// Returns why the preferences cannot be stored, if they cannot. The webhook
+    /// URL's host is resolved, and has to be public unless private addresses are allowed
+    pub async fn validate(&self, allow_private_addresses: bool) -> Result<(), String> {
+        if let Some(webhook_url) = &self.webhook_url {
+            check_webhook_url(webhook_url, allow_private_addresses)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        let uses_webhook = self
+            .events
+            .iter()
+            .any(|event| event.channels.contains(&NotificationChannelKind::Webhook));
+        if uses_webhook && self.webhook_url.is_none() {
+            return Err("The webhook channel needs a webhook URL".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[tracing::instrument(name = "Get channel preferences", skip(pool))]
+pub async fn get_channel_preferences(
+    pool: &PgPool,
+    profile_id: Uuid,
+) -> Result<Option<ChannelPreferences>, anyhow::Error> {
+    let Some(webhook_url) = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT notification_webhook_url FROM profile WHERE id = $1",
+    )
+    .bind(profile_id)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the webhook URL")?
+    else {
+        return Ok(None);
+    };
+
+    let rows = sqlx::query(
+        "SELECT event_type, channel FROM notification_channels
+                WHERE profile_id = $1
+                ORDER BY event_type, channel",
+    )
+    .bind(profile_id)
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the notification channels")?;
+
+    let mut events: Vec<EventChannels> = Vec::new();
+    for row in rows {
+        let event_type: NotificationEvent = row
+            .get::<String, _>("event_type")
+            .parse()
+            .context("Stored notification channel has an unknown event type")?;
+        let channel = row
+            .get::<String, _>("channel")
+            .parse()
+            .context("Stored notification channel is unknown")?;
+        match events.last_mut() {
+            Some(last) if last.event_type == event_type => last.channels.push(channel),
+            _ => events.push(EventChannels {
+                event_type,
+                channels: vec![channel],
+            }),
+        }
+    }
+
+    Ok(Some(ChannelPreferences {
+        webhook_url,
+        events,
+    }))
+}
+
+/// Replaces the webhook URL and every channel choice of the profile
+#[tracing::instrument(name = "Replace channel preferences", skip(tx, preferences))]
+pub async fn replace_channel_preferences(
+    tx: &mut Transaction<'_, Postgres>,
+    profile_id: Uuid,
+    preferences: &ChannelPreferences,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE profile SET notification_webhook_url = $1 WHERE id = $2")
+        .bind(&preferences.webhook_url)
+        .bind(profile_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query("DELETE FROM notification_channels WHERE profile_id = $1")
+        .bind(profile_id)
+        .execute(&mut **tx)
+        .await?;
+
+    for event in &preferences.events {
+        for channel in &event.channels {
+            sqlx::query(
+                "INSERT INTO notification_channels (profile_id, event_type, channel)
+                        VALUES ($1, $2, $3)
+                        ON CONFLICT DO NOTHING",
+            )
+            .bind(profile_id)
+            .bind(event.event_type.to_string())
+            .bind(channel.to_string())
+            .execute(&mut **tx)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Why an attempt did not reach the recipient
+#[derive(thiserror::Error, Debug)]
+pub enum DeliveryFailure {
+    /// A later attempt may succeed
+    #[error("{0:#}")]
+    Transient(#[from] anyhow::Error),
+    /// Retrying cannot help
+    #[error("{0}")]
+    Permanent(String),
+}
+
+/// One event about one task, addressed to one profile
+pub struct ChannelNotification<'a> {
+    pub event: NotificationEvent,
+    pub task: &'a Task,
+    pub profile_id: Uuid,
+    pub profile_email: &'a str,
+    pub locale: &'a str,
+    pub webhook_url: Option<&'a str>,
+    pub unsubscribe_url: &'a str,
+}
+
+impl ChannelNotification<'_> {
+    /// One line describing the event, for channels that have no templates
+    pub fn summary(&self) -> String {
+        let task = self.task;
+        let workspace = task
+            .workspace
+            .as_ref()
+            .map(|workspace| format!(" in {workspace}"))
+            .unwrap_or_default();
+        match self.event {
+            NotificationEvent::TaskCreated => {
+                format!(
+                    "New {} task{workspace}: {}",
+                    task.task_type, task.source_file
+                )
+            }
+            NotificationEvent::TaskCompleted => format!(
+                "{} task completed{workspace}: {}",
+                task.task_type, task.source_file
+            ),
+            NotificationEvent::TaskFailed => format!(
+                "{} task failed{workspace}: {}",
+                task.task_type, task.source_file
+            ),
+        }
+    }
+}
+
+/// Delivers notifications through one channel
+#[async_trait]
+pub trait NotificationChannel: Send + Sync + std::fmt::Debug {
+    /// Returns the id the channel gave the notification, when it hands one back
+    async fn deliver(
+        &self,
+        notification: &ChannelNotification<'_>,
+    ) -> Result<Option<String>, DeliveryFailure>;
+}
+
+/// Every channel the delivery workers can send through
+#[derive(Clone, Debug)]
+pub struct NotificationChannels {
+    email: EmailChannel,
+    webhook: WebhookChannel,
+    in_app: InAppChannel,
+}
+
+impl NotificationChannels {
+    pub fn new(
+        email_client: EmailClient,
+        pool: PgPool,
+        timeout: Duration,
+        allow_private_webhooks: bool,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            email: EmailChannel::new(email_client),
+            webhook: WebhookChannel::new(timeout, allow_private_webhooks)?,
+            in_app: InAppChannel::new(pool),
+        })
+    }
+
+    pub fn get(&self, kind: NotificationChannelKind) -> &dyn NotificationChannel {
+        match kind {
+            NotificationChannelKind::Email => &self.email,
+            NotificationChannelKind::Webhook => &self.webhook,
+            NotificationChannelKind::InApp => &self.in_app,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelNotification, ChannelPreferences, EventChannels, NotificationChannelKind};
+    use crate::model::task::Task;
+    use crate::notification::NotificationEvent;
+    use claims::{assert_err, assert_ok};
+    use uuid::Uuid;
+
+    fn webhook_preferences(webhook_url: Option<&str>) -> ChannelPreferences {
+        ChannelPreferences {
+            webhook_url: webhook_url.map(str::to_string),
+            events: vec![EventChannels {
+                event_type: NotificationEvent::TaskFailed,
+                channels: vec![NotificationChannelKind::Webhook],
+            }],
+        }
+    }
+
+    #[actix_web::test]
+    async fn webhook_channel_needs_an_http_url() {
+        assert_ok!(
+            webhook_preferences(Some("https://93.184.215.14/hooks/1"))
+                .validate(false)
+                .await
+        );
+        assert_err!(webhook_preferences(None).validate(false).await);
+        assert_err!(
+            webhook_preferences(Some("ftp://chat.example.com/hooks/1"))
+                .validate(false)
+                .await
+        );
+        assert_err!(webhook_preferences(Some("not a url")).validate(false).await);
+        assert_err!(
+            webhook_preferences(Some("http://127.0.0.1/hooks/1"))
+                .validate(false)
+                .await
+        );
+    }
+
+    #[test]
+    fn summaries_name_the_event_the_task_and_its_workspace() {
+        let task = Task::new(
+            Uuid::new_v4(),
+            "bug".to_string(),
+            "crash.log".to_string(),
+            Some("ops".to_string()),
+        );
+        let summary = |event| {
+            ChannelNotification {
+                event,
+                task: &task,
+                profile_id: Uuid::new_v4(),
+                profile_email: "ursula@example.com",
+                locale: "en",
+                webhook_url: None,
+                unsubscribe_url: "https://taskx.example.com/notifications/unsubscribe",
+            }
+            .summary()
+        };
+
+        assert_eq!(
+            summary(NotificationEvent::TaskCreated),
+            "New bug task in ops: crash.log"
+        );
+        assert_eq!(
+            summary(NotificationEvent::TaskFailed),
+            "bug task failed in ops: crash.log"
+        );
+    }
+}
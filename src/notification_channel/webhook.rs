@@ -0,0 +1,234 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+
+use super::{ChannelNotification, DeliveryFailure, NotificationChannel};
+
+/// Why a webhook URL may not be called
+#[derive(thiserror::Error, Debug)]
+pub enum WebhookUrlError {
+    #[error("Invalid webhook URL")]
+    Invalid,
+    #[error("The webhook URL must use http or https")]
+    UnsupportedScheme,
+    #[error("The webhook URL's host cannot be resolved")]
+    Unresolvable(#[source] std::io::Error),
+    #[error("The webhook URL must point to a public address")]
+    NotPublic,
+}
+
+/// Loopback, private, link-local and unspecified addresses would let profiles
+/// reach services inside our own network through their webhooks
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            // 100.64.0.0/10 is shared by carrier-grade NATs
+            let shared = a == 100 && (b & 0xc0) == 64;
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_address(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                let unique_local = (first & 0xfe00) == 0xfc00;
+                let link_local = (first & 0xffc0) == 0xfe80;
+                !(ip.is_loopback() || ip.is_unspecified() || unique_local || link_local)
+            }
+        },
+    }
+}
+
+async fn lookup_public(host: &str, port: u16) -> Result<Vec<SocketAddr>, WebhookUrlError> {
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(WebhookUrlError::Unresolvable)?
+        .collect();
+    if addresses
+        .iter()
+        .any(|address| !is_public_address(address.ip()))
+    {
+        return Err(WebhookUrlError::NotPublic);
+    }
+
+    Ok(addresses)
+}
+
+/// Checks that the webhook URL is an http or https URL whose host only resolves
+/// to public addresses, unless private ones are allowed
+pub async fn check_webhook_url(
+    webhook_url: &str,
+    allow_private_addresses: bool,
+) -> Result<Url, WebhookUrlError> {
+    let url = Url::parse(webhook_url).map_err(|_| WebhookUrlError::Invalid)?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(WebhookUrlError::UnsupportedScheme);
+    }
+    if allow_private_addresses {
+        return Ok(url);
+    }
+
+    let port = url
+        .port_or_known_default()
+        .ok_or(WebhookUrlError::Invalid)?;
+    let host = url.host_str().ok_or(WebhookUrlError::Invalid)?;
+    // IPv6 hosts come in brackets
+    match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) if !is_public_address(ip) => return Err(WebhookUrlError::NotPublic),
+        Ok(_) => {}
+        Err(_) => {
+            lookup_public(host, port).await?;
+        }
+    }
+
+    Ok(url)
+}
+
+/// Resolves the hosts the webhook client connects to, so that a name which
+/// passed `check_webhook_url` cannot be pointed at a private address afterwards
+#[derive(Debug)]
+struct PublicAddressResolver;
+
+impl Resolve for PublicAddressResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            // The port is filled in by the client
+            let addresses = lookup_public(name.as_str(), 0).await?;
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Posts notifications as JSON to the URL the profile configured. The `text`
+/// field is what incoming webhooks of most chat systems display
+#[derive(Clone, Debug)]
+pub struct WebhookChannel {
+    http_client: Client,
+    allow_private_addresses: bool,
+}
+
+impl WebhookChannel {
+    pub fn new(timeout: Duration, allow_private_addresses: bool) -> Result<Self, anyhow::Error> {
+        // Redirects are not followed, as they could lead anywhere
+        let mut builder = Client::builder().timeout(timeout).redirect(Policy::none());
+        if !allow_private_addresses {
+            builder = builder.dns_resolver(Arc::new(PublicAddressResolver));
+        }
+        let http_client = builder
+            .build()
+            .context("Failed to build the webhook HTTP client")?;
+
+        Ok(Self {
+            http_client,
+            allow_private_addresses,
+        })
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for WebhookChannel {
+    async fn deliver(
+        &self,
+        notification: &ChannelNotification<'_>,
+    ) -> Result<Option<String>, DeliveryFailure> {
+        let Some(webhook_url) = notification.webhook_url else {
+            return Err(DeliveryFailure::Permanent(
+                "The profile has no webhook URL".to_string(),
+            ));
+        };
+        let webhook_url = match check_webhook_url(webhook_url, self.allow_private_addresses).await {
+            Ok(url) => url,
+            Err(e @ WebhookUrlError::Unresolvable(_)) => {
+                return Err(DeliveryFailure::Transient(e.into()));
+            }
+            Err(e) => return Err(DeliveryFailure::Permanent(e.to_string())),
+        };
+
+        let task = notification.task;
+        let response = self
+            .http_client
+            .post(webhook_url)
+            .json(&serde_json::json!({
+                "text": notification.summary(),
+                "event_type": notification.event,
+                "task": {
+                    "id": task.id,
+                    "task_type": task.task_type,
+                    "source_file": task.source_file,
+                    "workspace": task.workspace,
+                },
+            }))
+            .send()
+            .await
+            .context("Failed to call the notification webhook")?;
+
+        let status = response.status();
+        // The receiver will keep rejecting a request it found invalid, and
+        // redirects are never followed
+        if (status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS)
+            || status.is_redirection()
+        {
+            return Err(DeliveryFailure::Permanent(format!(
+                "The notification webhook rejected the notification: {status}"
+            )));
+        }
+        response
+            .error_for_status()
+            .context("The notification webhook failed")?;
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WebhookUrlError, check_webhook_url};
+
+    #[actix_web::test]
+    async fn webhooks_cannot_reach_private_addresses() {
+        for url in [
+            "http://127.0.0.1:9090/metrics",
+            "http://localhost/",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.0.0.7/",
+            "http://192.168.1.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://[::ffff:127.0.0.1]/",
+        ] {
+            assert!(
+                matches!(
+                    check_webhook_url(url, false).await,
+                    Err(WebhookUrlError::NotPublic)
+                ),
+                "{url} was accepted"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn public_addresses_and_allowed_private_ones_pass() {
+        assert!(
+            check_webhook_url("https://93.184.215.14/hooks/1", false)
+                .await
+                .is_ok()
+        );
+        assert!(
+            check_webhook_url("http://127.0.0.1:9090/", true)
+                .await
+                .is_ok()
+        );
+    }
+}
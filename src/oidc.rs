@@ -0,0 +1,468 @@
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header, jwk::JwkSet};
+use rand::{Rng, distr::Alphanumeric};
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Row, Transaction};
+use uuid::Uuid;
+
+use crate::audit::{AuditAction, AuditEvent, record_audit_event};
+use crate::configuration::{OidcProviderSettings, OidcProviders, PasswordHashingSettings};
+use crate::domain::email::ProfileEmail;
+use crate::domain::password::Password;
+use crate::util::token_generator::{generate_profile_token, hash_token};
+
+const LOGIN_STATE_EXPIRE_MINUTES: i32 = 10;
+const SCOPES: &str = "openid email profile";
+
+/// HTTP client and configuration for the OpenID Connect providers
+pub struct OidcClient {
+    http_client: reqwest::Client,
+    providers: Vec<OidcProviderSettings>,
+    base_uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+}
+
+/// Parameters of an authorization request that have to survive the round trip to the provider
+pub struct LoginState {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+impl LoginState {
+    pub fn generate() -> Self {
+        Self {
+            state: generate_profile_token(),
+            nonce: generate_profile_token(),
+            code_verifier: generate_code_verifier(),
+        }
+    }
+}
+
+/// PKCE verifier; 64 characters from the unreserved set allowed by RFC 7636
+fn generate_code_verifier() -> String {
+    let mut rng = rand::rng();
+
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(64)
+        .collect()
+}
+
+/// `S256` code challenge for a PKCE verifier
+pub fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+impl OidcClient {
+    pub fn new(providers: &OidcProviders, base_uri: &str, timeout: std::time::Duration) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("Failed to build the OpenID Connect HTTP client");
+
+        Self {
+            http_client,
+            providers: providers.0.clone(),
+            base_uri: base_uri.to_string(),
+        }
+    }
+
+    fn redirect_uri(&self, provider: &OidcProviderSettings) -> String {
+        format!("{}/login/oidc/{}/callback", self.base_uri, provider.name)
+    }
+
+    pub fn provider(&self, name: &str) -> Option<&OidcProviderSettings> {
+        self.providers.iter().find(|p| p.name == name)
+    }
+
+    #[tracing::instrument(name = "Discover OpenID provider", skip(self, provider), fields(issuer = %provider.issuer))]
+    pub async fn discover(
+        &self,
+        provider: &OidcProviderSettings,
+    ) -> Result<ProviderMetadata, anyhow::Error> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            provider.issuer.trim_end_matches('/')
+        );
+
+        let metadata: ProviderMetadata = self
+            .http_client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Failed to parse the provider metadata")?;
+
+        if metadata.issuer != provider.issuer {
+            anyhow::bail!(
+                "Provider metadata was issued by {}, expected {}",
+                metadata.issuer,
+                provider.issuer
+            );
+        }
+
+        Ok(metadata)
+    }
+
+    pub fn authorization_url(
+        &self,
+        provider: &OidcProviderSettings,
+        metadata: &ProviderMetadata,
+        login_state: &LoginState,
+    ) -> Result<Url, anyhow::Error> {
+        Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", provider.client_id.as_str()),
+                ("redirect_uri", self.redirect_uri(provider).as_str()),
+                ("scope", SCOPES),
+                ("state", login_state.state.as_str()),
+                ("nonce", login_state.nonce.as_str()),
+                (
+                    "code_challenge",
+                    code_challenge(&login_state.code_verifier).as_str(),
+                ),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .context("Invalid authorization endpoint")
+    }
+
+    /// Redeems the authorization code for the ID token
+    #[tracing::instrument(name = "Exchange authorization code", skip_all)]
+    pub async fn exchange_code(
+        &self,
+        provider: &OidcProviderSettings,
+        metadata: &ProviderMetadata,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<String, anyhow::Error> {
+        let response: TokenResponse = self
+            .http_client
+            .post(&metadata.token_endpoint)
+            .basic_auth(&provider.client_id, Some(&provider.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", self.redirect_uri(provider).as_str()),
+                ("client_id", provider.client_id.as_str()),
+                ("code_verifier", code_verifier),
+            ])
+            .send()
+            .await?
+            .error_for_status()
+            .context("Provider rejected the authorization code")?
+            .json()
+            .await
+            .context("Failed to parse the token response")?;
+
+        Ok(response.id_token)
+    }
+
+    /// Checks the ID token signature against the provider's published keys, along with
+    /// its issuer, audience, expiry and the nonce sent with the authorization request
+    #[tracing::instrument(name = "Validate ID token", skip_all)]
+    pub async fn validate_id_token(
+        &self,
+        provider: &OidcProviderSettings,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, anyhow::Error> {
+        let header = decode_header(id_token).context("Malformed ID token")?;
+
+        // Symmetric algorithms would let anyone holding the client secret mint tokens
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            anyhow::bail!("ID token is not signed with an asymmetric key");
+        }
+
+        let jwks: JwkSet = self
+            .http_client
+            .get(&metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Failed to parse the provider key set")?;
+
+        let kid = header.kid.context("ID token does not name a signing key")?;
+        let jwk = jwks
+            .find(&kid)
+            .context("ID token is signed with an unknown key")?;
+        let key = DecodingKey::from_jwk(jwk).context("Unsupported signing key")?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .context("Invalid ID token")?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            anyhow::bail!("ID token nonce does not match the authorization request");
+        }
+
+        Ok(claims)
+    }
+}
+
+#[tracing::instrument(name = "Store OpenID login state", skip(pool, login_state))]
+pub async fn store_login_state(
+    pool: &PgPool,
+    provider: &str,
+    login_state: &LoginState,
+) -> Result<(), anyhow::Error> {
+    sqlx::query(
+        "INSERT INTO oidc_login_states (state_hash, provider, code_verifier, nonce, expires_at)
+                VALUES ($1, $2, $3, $4, now() + make_interval(mins => $5))",
+    )
+    .bind(hash_token(&login_state.state))
+    .bind(provider)
+    .bind(&login_state.code_verifier)
+    .bind(&login_state.nonce)
+    .bind(LOGIN_STATE_EXPIRE_MINUTES)
+    .execute(pool)
+    .await
+    .context("Failed to store the OpenID login state")?;
+
+    Ok(())
+}
+
+/// Returns the `(nonce, code_verifier)` pair for a pending login; a state can only be used once.
+#[tracing::instrument(name = "Consume OpenID login state", skip(pool, state))]
+pub async fn consume_login_state(
+    pool: &PgPool,
+    provider: &str,
+    state: &str,
+) -> Result<Option<(String, String)>, anyhow::Error> {
+    let row = sqlx::query(
+        "DELETE FROM oidc_login_states
+                WHERE state_hash = $1
+                RETURNING provider, nonce, code_verifier, expires_at > now() AS is_live",
+    )
+    .bind(hash_token(state))
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform query to retrieve the OpenID login state")?;
+
+    Ok(row
+        .filter(|r| r.get::<bool, _>("is_live") && r.get::<String, _>("provider") == provider)
+        .map(|r| (r.get("nonce"), r.get("code_verifier"))))
+}
+
+async fn find_linked_profile(
+    tx: &mut Transaction<'_, Postgres>,
+    provider: &str,
+    subject: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT profile_id FROM external_identities WHERE provider = $1 AND subject = $2",
+    )
+    .bind(provider)
+    .bind(subject)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(row.map(|r| r.get("profile_id")))
+}
+
+/// Usernames must be unique, so the email is used and suffixed if it is already taken
+async fn available_username(
+    tx: &mut Transaction<'_, Postgres>,
+    email: &ProfileEmail,
+) -> Result<String, sqlx::Error> {
+    let mut username = email.as_ref().to_string();
+
+    while sqlx::query("SELECT 1 FROM profile WHERE username = $1")
+        .bind(&username)
+        .fetch_optional(&mut **tx)
+        .await?
+        .is_some()
+    {
+        username = format!("{}-{}", email.as_ref(), &generate_profile_token()[..6]);
+    }
+
+    Ok(username)
+}
+
+#[tracing::instrument(
+    name = "Create profile from external identity",
+    skip(tx, claims, password_hashing)
+)]
+async fn create_external_profile(
+    tx: &mut Transaction<'_, Postgres>,
+    email: &ProfileEmail,
+    claims: &IdTokenClaims,
+    password_hashing: &PasswordHashingSettings,
+) -> Result<Uuid, anyhow::Error> {
+    let profile_id = Uuid::new_v4();
+    let username = available_username(tx, email).await?;
+    // Nobody knows this password; one can be set through the password reset flow
+    let password = Password::parse(generate_profile_token(), password_hashing)?;
+    let first_name = claims
+        .given_name
+        .clone()
+        .unwrap_or_else(|| email.as_ref().split('@').next().unwrap_or_default().into());
+
+    sqlx::query(
+        "INSERT INTO profile(id, first_name, last_name, email, status, username, password) VALUES($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(profile_id)
+    .bind(first_name)
+    .bind(claims.family_name.clone().unwrap_or_default())
+    .bind(email.as_ref())
+    .bind("confirmed")
+    .bind(username)
+    .bind(password.phash_as_ref())
+    .execute(&mut **tx)
+    .await
+    .context("Failed to insert new profile in the database")?;
+
+    Ok(profile_id)
+}
+
+pub enum ExternalIdentity {
+    Resolved(Uuid),
+    /// The provider has not verified the email
+    EmailNotVerified,
+    /// The profile with that email is suspended or deleted
+    ProfileUnavailable,
+}
+
+/// Maps an external identity onto a profile. Identities seen before resolve to the profile
+/// they were linked to; otherwise the profile with the same verified email is linked, or a
+/// confirmed profile is created. Suspended and deleted profiles are never linked.
+#[tracing::instrument(name = "Resolve external identity", skip(pool, claims, password_hashing), fields(subject = %claims.sub))]
+pub async fn resolve_external_identity(
+    pool: &PgPool,
+    provider: &str,
+    claims: &IdTokenClaims,
+    password_hashing: &PasswordHashingSettings,
+) -> Result<ExternalIdentity, anyhow::Error> {
+    let mut tx = pool.begin().await?;
+
+    if let Some(profile_id) = find_linked_profile(&mut tx, provider, &claims.sub).await? {
+        return Ok(ExternalIdentity::Resolved(profile_id));
+    }
+
+    let email = match (&claims.email, claims.email_verified) {
+        (Some(email), true) => ProfileEmail::parse(email.clone())?,
+        _ => return Ok(ExternalIdentity::EmailNotVerified),
+    };
+
+    let existing = sqlx::query("SELECT id, status FROM profile WHERE email = $1 FOR UPDATE")
+        .bind(email.as_ref())
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to perform query to retrieve profile by email")?
+        .map(|r| (r.get::<Uuid, _>("id"), r.get::<String, _>("status")));
+
+    let (profile_id, created) = match existing {
+        // An identity provider must not be a way around a suspension or an erasure
+        Some((_, status)) if status == "suspended" || status == "deleted" => {
+            return Ok(ExternalIdentity::ProfileUnavailable);
+        }
+        Some((profile_id, status)) => {
+            // The provider has vouched for the address, so a pending profile needs no further confirmation
+            if status == "pending_confirmation" {
+                sqlx::query("UPDATE profile SET status = 'confirmed' WHERE id = $1")
+                    .bind(profile_id)
+                    .execute(&mut *tx)
+                    .await
+                    .context("Failed to confirm the profile")?;
+            }
+            (profile_id, false)
+        }
+        None => (
+            create_external_profile(&mut tx, &email, claims, password_hashing).await?,
+            true,
+        ),
+    };
+
+    sqlx::query(
+        "INSERT INTO external_identities (provider, subject, profile_id) VALUES ($1, $2, $3)",
+    )
+    .bind(provider)
+    .bind(&claims.sub)
+    .bind(profile_id)
+    .execute(&mut *tx)
+    .await
+    .context("Failed to link the external identity")?;
+
+    record_audit_event(
+        &mut *tx,
+        AuditEvent {
+            actor_id: None,
+            profile_id: Some(profile_id),
+            action: AuditAction::ExternalIdentityLinked,
+            details: serde_json::json!({
+                "provider": provider,
+                "subject": claims.sub,
+                "profile_created": created,
+            }),
+        },
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(ExternalIdentity::Resolved(profile_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoginState, code_challenge};
+
+    #[test]
+    fn code_challenge_matches_rfc_7636_example() {
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+
+        assert_eq!(
+            code_challenge(verifier),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn code_verifier_has_a_valid_length() {
+        let login_state = LoginState::generate();
+
+        assert!((43..=128).contains(&login_state.code_verifier.len()));
+    }
+}
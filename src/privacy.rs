@@ -0,0 +1,448 @@
+use std::io::{Cursor, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Row, Transaction};
+use strum_macros::Display;
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+
+use crate::audit::{AuditAction, AuditEvent, record_audit_event};
+use crate::authentication::compute_password;
+use crate::configuration::{
+    DataPrivacySettings, DeletionMode, DeletionTaskPolicy, PasswordHashingSettings, Settings,
+};
+use crate::health::heartbeat;
+use crate::issue_delivery::ExecutionOutcome;
+use crate::startup::get_connection_pool;
+use crate::telemetry::spawn_blocking_with_tracing;
+use crate::util::token_generator::generate_profile_token;
+
+type PgTx = Transaction<'static, Postgres>;
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+pub enum ExportStatus {
+    Pending,
+    Completed,
+}
+
+/// Gathers everything stored about a profile into a single JSON document, one
+/// key per section. Password hashes and TOTP secrets are never exported
+#[tracing::instrument(name = "Build data export bundle", skip(tx))]
+async fn build_export_bundle(
+    tx: &mut PgTx,
+    profile_id: Uuid,
+) -> Result<serde_json::Value, sqlx::Error> {
+    sqlx::query_scalar::<_, serde_json::Value>(
+        "SELECT jsonb_build_object(
+            'profile', (
+                SELECT to_jsonb(p) FROM (
+                    SELECT id, first_name, last_name, email, username, status, totp_enabled,
+                        digest_frequency, notification_webhook_url, undeliverable_reason, undeliverable_at,
+                        created_at, updated_at
+                    FROM profile WHERE id = $1
+                ) p
+            ),
+            'tasks', (
+                SELECT COALESCE(jsonb_agg(to_jsonb(t) ORDER BY t.created_at), '[]'::jsonb) FROM (
+                    SELECT id, task_type, workspace, state, source_file, result_file, created_at, updated_at
+                    FROM task WHERE reporter_id = $1
+                ) t
+            ),
+            'idempotency_records', (
+                SELECT COALESCE(jsonb_agg(to_jsonb(i) ORDER BY i.created_at), '[]'::jsonb) FROM (
+                    SELECT idempotency_key, response_status_code, created_at, updated_at
+                    FROM idempotency WHERE profile_id = $1
+                ) i
+            ),
+            'deliveries', (
+                SELECT COALESCE(jsonb_agg(to_jsonb(d)), '[]'::jsonb) FROM (
+                    SELECT task_issue_id, profile_email, channel, event_type, n_retries, last_attempt
+                    FROM issue_delivery_queue
+                    WHERE profile_email = (SELECT email FROM profile WHERE id = $1)
+                ) d
+            ),
+            'delivery_log', (
+                SELECT COALESCE(jsonb_agg(to_jsonb(l) ORDER BY l.attempted_at), '[]'::jsonb) FROM (
+                    SELECT id, task_issue_id, channel, event_type, status, provider_message_id, error,
+                        latency_ms, attempted_at
+                    FROM delivery_log
+                    WHERE profile_email = (SELECT email FROM profile WHERE id = $1)
+                ) l
+            ),
+            'notification_preferences', (
+                SELECT COALESCE(jsonb_agg(to_jsonb(n)), '[]'::jsonb) FROM (
+                    SELECT event_type, task_type, workspace, enabled, updated_at
+                    FROM notification_preferences WHERE profile_id = $1
+                ) n
+            ),
+            'notification_channels', (
+                SELECT COALESCE(jsonb_agg(to_jsonb(c)), '[]'::jsonb) FROM (
+                    SELECT event_type, channel FROM notification_channels WHERE profile_id = $1
+                ) c
+            ),
+            'inbox_notifications', (
+                SELECT COALESCE(jsonb_agg(to_jsonb(i) ORDER BY i.created_at), '[]'::jsonb) FROM (
+                    SELECT id, task_id, event_type, message, read_at, created_at
+                    FROM inbox_notifications WHERE profile_id = $1
+                ) i
+            ),
+            'external_identities', (
+                SELECT COALESCE(jsonb_agg(to_jsonb(e) ORDER BY e.created_at), '[]'::jsonb) FROM (
+                    SELECT provider, subject, created_at
+                    FROM external_identities WHERE profile_id = $1
+                ) e
+            ),
+            'audit_log', (
+                SELECT COALESCE(jsonb_agg(to_jsonb(a) ORDER BY a.created_at), '[]'::jsonb) FROM (
+                    SELECT id, actor_id, profile_id, action, details, created_at
+                    FROM audit_log WHERE profile_id = $1 OR actor_id = $1
+                ) a
+            )
+        )",
+    )
+    .bind(profile_id)
+    .fetch_one(&mut **tx)
+    .await
+}
+
+/// Packs each section of an export bundle into its own JSON file
+pub fn zip_export_bundle(bundle: &serde_json::Value) -> Result<Vec<u8>, anyhow::Error> {
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    if let Some(sections) = bundle.as_object() {
+        for (name, section) in sections {
+            writer
+                .start_file(format!("{name}.json"), options)
+                .context("Failed to add a section to the export archive")?;
+            writer.write_all(&serde_json::to_vec_pretty(section)?)?;
+        }
+    }
+
+    let archive = writer
+        .finish()
+        .context("Failed to finish the export archive")?;
+
+    Ok(archive.into_inner())
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_export(pool: &PgPool) -> Result<Option<(PgTx, Uuid, Uuid)>, anyhow::Error> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query(
+        "SELECT id, profile_id
+            FROM data_exports
+            WHERE status = $1
+            ORDER BY created_at
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1",
+    )
+    .bind(ExportStatus::Pending.to_string())
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    Ok(result.map(|r| (tx, r.get("id"), r.get("profile_id"))))
+}
+
+/// Builds the next pending export and keeps it downloadable for `export_expire_hours`
+#[tracing::instrument(skip_all, fields(export_id=tracing::field::Empty, profile_id=tracing::field::Empty))]
+pub async fn try_execute_export(
+    pool: &PgPool,
+    settings: &DataPrivacySettings,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut tx, export_id, profile_id)) = dequeue_export(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+
+    tracing::Span::current()
+        .record("export_id", tracing::field::display(export_id))
+        .record("profile_id", tracing::field::display(profile_id));
+
+    let bundle = build_export_bundle(&mut tx, profile_id)
+        .await
+        .context("Failed to gather the profile data")?;
+
+    sqlx::query(
+        "UPDATE data_exports
+                SET status = $1, bundle = $2, completed_at = now(),
+                    expires_at = now() + make_interval(hours => $3)
+                WHERE id = $4",
+    )
+    .bind(ExportStatus::Completed.to_string())
+    .bind(bundle)
+    .bind(settings.export_expire_hours as i32)
+    .bind(export_id)
+    .execute(&mut *tx)
+    .await
+    .context("Failed to store the export bundle")?;
+
+    tx.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[tracing::instrument(name = "Expire data exports", skip(pool))]
+pub async fn try_expire_exports(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_deleted = sqlx::query("DELETE FROM data_exports WHERE expires_at <= now()")
+        .execute(pool)
+        .await
+        .context("Failed to delete expired data exports")?
+        .rows_affected();
+
+    Ok(n_deleted)
+}
+
+/// Removes or anonymizes a profile and everything tied to it according to the
+/// configured deletion mode and task policy. Audit entries are kept, but their
+/// details are cleared since they may hold personal data
+#[tracing::instrument(name = "Erase profile", skip(tx, settings, password_hashing))]
+pub async fn erase_profile(
+    tx: &mut PgTx,
+    profile_id: Uuid,
+    settings: &DataPrivacySettings,
+    password_hashing: &PasswordHashingSettings,
+) -> Result<bool, anyhow::Error> {
+    let Some(row) = sqlx::query("SELECT email, username FROM profile WHERE id = $1 FOR UPDATE")
+        .bind(profile_id)
+        .fetch_optional(&mut **tx)
+        .await?
+    else {
+        return Ok(false);
+    };
+    let email: String = row.get("email");
+    let username: String = row.get("username");
+
+    match settings.task_policy {
+        DeletionTaskPolicy::Purge => {
+            sqlx::query("DELETE FROM task WHERE reporter_id = $1")
+                .bind(profile_id)
+                .execute(&mut **tx)
+                .await
+                .context("Failed to purge the profile's tasks")?;
+        }
+        DeletionTaskPolicy::Reassign => {
+            let new_reporter = settings
+                .task_reassign_to
+                .filter(|id| *id != profile_id)
+                .context("DELETION_TASK_REASSIGN_TO must name another profile to reassign tasks")?;
+            sqlx::query("UPDATE task SET reporter_id = $1 WHERE reporter_id = $2")
+                .bind(new_reporter)
+                .bind(profile_id)
+                .execute(&mut **tx)
+                .await
+                .context("Failed to reassign the profile's tasks")?;
+        }
+    }
+
+    sqlx::query("DELETE FROM issue_delivery_queue WHERE profile_email = $1")
+        .bind(&email)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query("DELETE FROM issue_delivery_dead_letter WHERE profile_email = $1")
+        .bind(&email)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query("DELETE FROM delivery_log WHERE profile_email = $1")
+        .bind(&email)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query("DELETE FROM login_attempts WHERE scope = 'username' AND key = $1")
+        .bind(&username)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query("UPDATE audit_log SET details = '{}' WHERE profile_id = $1 OR actor_id = $1")
+        .bind(profile_id)
+        .execute(&mut **tx)
+        .await?;
+
+    match settings.deletion_mode {
+        DeletionMode::HardDelete => {
+            sqlx::query("DELETE FROM profile WHERE id = $1")
+                .bind(profile_id)
+                .execute(&mut **tx)
+                .await
+                .context("Failed to delete the profile")?;
+        }
+        DeletionMode::Anonymize => anonymize_profile(tx, profile_id, password_hashing).await?,
+    }
+
+    record_audit_event(
+        &mut **tx,
+        AuditEvent {
+            actor_id: None,
+            profile_id: Some(profile_id),
+            action: AuditAction::ProfileErased,
+            details: serde_json::json!({
+                "mode": format!("{:?}", settings.deletion_mode),
+                "task_policy": format!("{:?}", settings.task_policy),
+            }),
+        },
+    )
+    .await
+    .context("Failed to record the erasure in the audit log")?;
+
+    Ok(true)
+}
+
+async fn anonymize_profile(
+    tx: &mut PgTx,
+    profile_id: Uuid,
+    password_hashing: &PasswordHashingSettings,
+) -> Result<(), anyhow::Error> {
+    // Nobody knows this password, so the profile can never be logged into again
+    let password_hashing = password_hashing.clone();
+    let password = spawn_blocking_with_tracing(move || {
+        compute_password(generate_profile_token(), &password_hashing)
+    })
+    .await?
+    .context("Failed to hash placeholder password")?;
+
+    sqlx::query(
+        "UPDATE profile SET
+                first_name = 'Deleted', last_name = 'Profile',
+                email = 'deleted-' || id || '@invalid', username = 'deleted-' || id,
+                password = $1, status = 'deleted', totp_secret = NULL, totp_enabled = false,
+                undeliverable_reason = NULL, undeliverable_at = NULL,
+                notification_webhook_url = NULL,
+                token_version = token_version + 1, updated_at = now()
+                WHERE id = $2",
+    )
+    .bind(password)
+    .bind(profile_id)
+    .execute(&mut **tx)
+    .await
+    .context("Failed to anonymize the profile")?;
+
+    for table in [
+        "profile_tokens",
+        "password_reset_tokens",
+        "totp_recovery_codes",
+        "login_challenges",
+        "external_identities",
+        "email_change_requests",
+        "notification_preferences",
+        "pending_notifications",
+        "notification_channels",
+        "inbox_notifications",
+        "idempotency",
+        "data_exports",
+        "deletion_requests",
+    ] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE profile_id = $1"))
+            .bind(profile_id)
+            .execute(&mut **tx)
+            .await
+            .with_context(|| format!("Failed to delete the profile's {table}"))?;
+    }
+
+    Ok(())
+}
+
+/// Erases the next profile whose deletion grace period has ended
+#[tracing::instrument(skip_all, fields(profile_id=tracing::field::Empty))]
+pub async fn try_execute_deletion(
+    pool: &PgPool,
+    settings: &DataPrivacySettings,
+    password_hashing: &PasswordHashingSettings,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut tx = pool.begin().await?;
+    let Some(profile_id) = sqlx::query_scalar::<_, Uuid>(
+        "SELECT profile_id
+            FROM deletion_requests
+            WHERE scheduled_for <= now()
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1",
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+
+    tracing::Span::current().record("profile_id", tracing::field::display(profile_id));
+
+    erase_profile(&mut tx, profile_id, settings, password_hashing).await?;
+
+    tx.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn privacy_worker_loop(
+    pool: PgPool,
+    settings: &DataPrivacySettings,
+    password_hashing: &PasswordHashingSettings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        heartbeat(
+            "privacy_worker",
+            Duration::from_secs(settings.worker_interval_seconds),
+        );
+        let exports = try_execute_export(&pool, settings).await;
+        let deletions = try_execute_deletion(&pool, settings, password_hashing).await;
+
+        if let Err(e) = &exports {
+            tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to build a data export");
+        }
+        if let Err(e) = &deletions {
+            tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to erase a profile");
+        }
+
+        match (exports, deletions) {
+            (Ok(ExecutionOutcome::EmptyQueue), Ok(ExecutionOutcome::EmptyQueue)) => {
+                if let Err(e) = try_expire_exports(&pool).await {
+                    tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to expire data exports");
+                }
+                tokio::time::sleep(Duration::from_secs(settings.worker_interval_seconds)).await;
+            }
+            (Err(_), _) | (_, Err(_)) => {
+                tokio::time::sleep(Duration::from_secs(3)).await;
+            }
+            _ => {}
+        }
+    }
+}
+
+pub async fn run_privacy_worker_until_stopped(
+    configuration: Arc<Settings>,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+
+    privacy_worker_loop(
+        connection_pool,
+        &configuration.data_privacy,
+        &configuration.password_hashing,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::zip_export_bundle;
+
+    #[test]
+    fn every_section_is_written_to_its_own_file() {
+        let bundle = serde_json::json!({
+            "profile": {"username": "ursula"},
+            "tasks": [{"task_type": "report"}],
+        });
+
+        let archive = zip_export_bundle(&bundle).unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(archive)).unwrap();
+        assert_eq!(archive.len(), 2);
+        let mut profile = String::new();
+        archive
+            .by_name("profile.json")
+            .unwrap()
+            .read_to_string(&mut profile)
+            .unwrap();
+        let profile: serde_json::Value = serde_json::from_str(&profile).unwrap();
+        assert_eq!(profile["username"], "ursula");
+    }
+}
@@ -1,6 +1,10 @@
+use crate::issue_delivery::DELIVERY_CHANNEL;
 use crate::model::profile::{Profile, ProfileResponse, ProfileUpdate};
-use crate::model::task::{Task, TaskUpdate};
+use crate::model::task::{Task, TaskState, TaskUpdate};
 use crate::model::task_issue::Issue;
+use crate::notification::{DigestFrequency, NotificationEvent};
+use crate::notification_channel::NotificationChannelKind;
+use crate::telemetry::current_traceparent;
 use anyhow::Context;
 use sqlx::{PgPool, Postgres, QueryBuilder, Row, Transaction};
 use uuid::Uuid;
@@ -10,64 +14,85 @@ pub async fn db_create_task(
     task: &Task,
 ) -> Result<(), sqlx::Error> {
     // let mut tx = pool.begin().await.unwrap();
-    sqlx::query("INSERT INTO task(reporter_id, id, task_type, state, source_file, result_file) VALUES($1, $2, $3, $4, $5, $6)")
+    sqlx::query("INSERT INTO task(reporter_id, id, task_type, state, source_file, result_file, workspace) VALUES($1, $2, $3, $4, $5, $6, $7)")
         .bind(task.reporter_id)
         .bind(task.id)
         .bind(task.task_type.clone())
         .bind(&task.state)
         .bind(task.source_file.clone())
         .bind(task.result_file.as_ref())
+        .bind(task.workspace.as_ref())
         .execute(&mut **tx).await?;
 
     Ok(())
 }
 
-pub async fn db_update_task(pool: &PgPool, task_update: TaskUpdate) -> Result<(), sqlx::Error> {
-    let mut tx = pool.begin().await.unwrap();
+pub async fn db_update_task(
+    tx: &mut Transaction<'_, Postgres>,
+    task_update: TaskUpdate,
+) -> Result<(), sqlx::Error> {
     let mut builder = QueryBuilder::new("UPDATE task SET ");
     let mut separated = builder.separated(", ");
 
     if let Some(pid) = task_update.profile_id {
-        separated.push("reporter_id = ").push_bind(pid);
+        separated.push("reporter_id = ").push_bind_unseparated(pid);
     }
 
     if let Some(task_type) = task_update.task_type {
-        separated.push("task_type = ").push_bind(task_type);
+        separated
+            .push("task_type = ")
+            .push_bind_unseparated(task_type);
     }
 
     if let Some(state) = task_update.state {
-        separated.push("state = ").push_bind(state);
+        // Pausing and resuming keeps the time of the first start
+        if state == TaskState::InProgress {
+            separated.push("started_at = COALESCE(started_at, now())");
+        }
+        separated.push("state = ").push_bind_unseparated(state);
     }
 
     if let Some(source_file) = task_update.source_file {
-        separated.push("state = ").push_bind(source_file);
+        separated
+            .push("source_file = ")
+            .push_bind_unseparated(source_file);
     }
 
     if let Some(result_file) = task_update.result_file {
-        separated.push("state = ").push_bind(result_file);
+        separated
+            .push("result_file = ")
+            .push_bind_unseparated(result_file);
     }
 
     builder
         .push(" WHERE id = ")
         .push_bind(task_update.task_uuid);
 
-    let result = builder.build().execute(&mut *tx).await;
+    builder.build().execute(&mut **tx).await?;
 
-    match result {
-        Ok(_) => {
-            tx.commit().await?;
-            Ok(())
-        }
-        Err(e) => {
-            tx.rollback().await?;
-            Err(e)
-        }
-    }
+    Ok(())
+}
+
+/// Seconds since the task was first started, if it ever was
+#[tracing::instrument(skip_all)]
+pub async fn db_task_run_time(
+    tx: &mut Transaction<'_, Postgres>,
+    task_id: Uuid,
+) -> Result<Option<f64>, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT EXTRACT(EPOCH FROM now() - started_at)::FLOAT8 AS run_time FROM task WHERE id = $1",
+    )
+    .bind(task_id)
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(row.get("run_time"))
 }
+
 #[tracing::instrument(skip_all)]
 pub async fn db_get_task(pool: &PgPool, task_id: Uuid) -> Result<Task, sqlx::Error> {
     let result = sqlx::query_as::<_, Task>(
-        "SELECT reporter_id, id, task_type, state, source_file, result_file FROM task WHERE id= $1",
+        "SELECT reporter_id, id, task_type, state, source_file, result_file, workspace FROM task WHERE id= $1",
     )
     .bind(task_id)
     .fetch_one(pool)
@@ -85,7 +110,7 @@ pub async fn db_create_profile(
     profile: &Profile,
 ) -> Result<(), sqlx::Error> {
     sqlx::query(
-        "INSERT INTO profile(id, first_name, last_name, email, status, username, password) VALUES($1, $2, $3, $4, $5, $6, $7)",
+        "INSERT INTO profile(id, first_name, last_name, email, status, username, password, locale) VALUES($1, $2, $3, $4, $5, $6, $7, $8)",
     )
     .bind(profile.id)
     .bind(profile.first_name.as_ref())
@@ -94,6 +119,7 @@ pub async fn db_create_profile(
     .bind("pending_confirmation")
     .bind(profile.username.as_ref())
     .bind(profile.password.phash_as_ref())
+    .bind(profile.locale.as_ref())
     .execute(&mut **tx)
     .await?;
     Ok(())
@@ -101,7 +127,7 @@ pub async fn db_create_profile(
 
 pub async fn db_get_profile(pool: &PgPool, id: &Uuid) -> Result<ProfileResponse, sqlx::Error> {
     let result = sqlx::query_as::<_, ProfileResponse>(
-        "SELECT id, first_name, last_name, email FROM profile WHERE id=$1",
+        "SELECT id, first_name, last_name, email, locale FROM profile WHERE id=$1",
     )
     .bind(id)
     .persistent(false)
@@ -111,32 +137,6 @@ pub async fn db_get_profile(pool: &PgPool, id: &Uuid) -> Result<ProfileResponse,
     Ok(result)
 }
 
-pub async fn delete_profile(pool: &PgPool, id: &Uuid) -> Result<(), sqlx::Error> {
-    let profile = db_get_profile(pool, id).await;
-
-    match profile {
-        Ok(_) => {
-            let mut tx = pool.begin().await.unwrap();
-            let result = sqlx::query("DELETE FROM profile WHERE id = '$1' RETURNING id")
-                .bind(id)
-                .fetch_one(&mut *tx)
-                .await;
-
-            match result {
-                Ok(_) => {
-                    tx.commit().await?;
-                    Ok(())
-                }
-                Err(e) => {
-                    tx.rollback().await?;
-                    Err(e)
-                }
-            }
-        }
-        Err(e) => Err(e),
-    }
-}
-
 pub async fn db_update_profile(
     pool: &PgPool,
     profile_update: &ProfileUpdate,
@@ -153,6 +153,10 @@ pub async fn db_update_profile(
         separated.push("last_name = ").push_bind(l_name);
     }
 
+    if let Some(locale) = profile_update.locale.clone() {
+        separated.push("locale = ").push_bind(locale);
+    }
+
     builder
         .push(" WHERE id = ")
         .push_bind(profile_update.id.clone());
@@ -182,19 +186,122 @@ pub async fn get_username(profile_id: Uuid, pool: &PgPool) -> Result<String, any
     Ok(row.get("username"))
 }
 
-#[tracing::instrument(skip_all)]
+#[tracing::instrument(name = "Get Profile Id By Email", skip(pool, email))]
+pub async fn get_profile_id_by_email(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query("SELECT id FROM profile WHERE email = $1")
+        .bind(email)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to perform query to retrieve profile id")?;
+
+    Ok(row.map(|r| r.get("id")))
+}
+
+#[tracing::instrument(name = "Get Token Version", skip(pool))]
+pub async fn get_token_version(
+    profile_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<i32>, anyhow::Error> {
+    let row = sqlx::query("SELECT token_version FROM profile WHERE id = $1")
+        .bind(profile_id)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to perform query to retrieve token version")?;
+
+    Ok(row.map(|r| r.get("token_version")))
+}
+
+#[tracing::instrument(name = "Get Profile Locale", skip(pool))]
+pub async fn get_profile_locale(
+    profile_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query("SELECT locale FROM profile WHERE id = $1")
+        .bind(profile_id)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to perform query to retrieve profile locale")?;
+
+    Ok(row.map(|r| r.get("locale")))
+}
+
+/// Notifies every confirmed profile whose notification rules allow the event.
+/// The most specific matching rule wins and, between equally specific rules, opting out does.
+/// Each profile is notified on the channels it picked for the event, email if it picked none,
+/// and only gets emails while its address is deliverable.
+/// Webhook and in-app notifications are queued right away. New tasks are emailed right away
+/// too, unless the profile gets digests, in which case the event waits in
+/// `pending_notifications` like every other emailed event
+#[tracing::instrument(skip_all, fields(event = %event))]
 pub async fn enqueue_delivery_tasks(
     tx: &mut Transaction<'_, Postgres>,
     task: &Task,
+    event: NotificationEvent,
 ) -> Result<(), sqlx::Error> {
-    sqlx::query(
-        "INSERT INTO issue_delivery_queue (task_issue_id, profile_email, n_retries)
-                SELECT $1, email, 0 FROM profile WHERE status = 'confirmed'",
+    let n_queued = sqlx::query_scalar::<_, i64>(
+        "WITH recipients AS (
+                    SELECT p.id, p.email, p.digest_frequency = $6 AS immediate,
+                        p.undeliverable_reason IS NULL AS deliverable
+                    FROM profile p
+                    WHERE p.status = 'confirmed'
+                    AND COALESCE((
+                        SELECT np.enabled FROM notification_preferences np
+                        WHERE np.profile_id = p.id
+                        AND np.event_type = $2
+                        AND (np.task_type IS NULL OR np.task_type = $3)
+                        AND (np.workspace IS NULL OR np.workspace = $4)
+                        ORDER BY (np.task_type IS NOT NULL)::INT + (np.workspace IS NOT NULL)::INT DESC,
+                            np.enabled ASC
+                        LIMIT 1
+                    ), true)
+                ),
+                channels AS (
+                    SELECT * FROM (
+                        SELECT r.id, r.email, r.immediate, r.deliverable,
+                            COALESCE(nc.channel, $7) AS channel
+                        FROM recipients r
+                        LEFT JOIN notification_channels nc
+                            ON nc.profile_id = r.id AND nc.event_type = $2
+                    ) c
+                    -- Bounced and complained addresses only stop emails
+                    WHERE channel <> $7 OR deliverable
+                ),
+                queued AS (
+                    INSERT INTO issue_delivery_queue
+                        (task_issue_id, profile_email, channel, event_type, n_retries, traceparent)
+                    SELECT $1, email, channel, $2, 0, $8 FROM channels
+                    WHERE channel <> $7 OR (immediate AND $2 = $5)
+                    RETURNING 1
+                ),
+                pending AS (
+                    INSERT INTO pending_notifications (id, profile_id, task_id, event_type)
+                    SELECT gen_random_uuid(), id, $1, $2 FROM channels
+                    WHERE channel = $7 AND NOT immediate
+                )
+                SELECT COUNT(*) FROM queued",
     )
     .bind(task.id)
-    .execute(&mut **tx)
+    .bind(event.to_string())
+    .bind(&task.task_type)
+    .bind(&task.workspace)
+    .bind(NotificationEvent::TaskCreated.to_string())
+    .bind(DigestFrequency::Immediate.to_string())
+    .bind(NotificationChannelKind::Email.to_string())
+    .bind(current_traceparent())
+    .fetch_one(&mut **tx)
     .await?;
 
+    // Delivered on commit, so workers never wake up before the rows are visible
+    if n_queued > 0 {
+        sqlx::query("SELECT pg_notify($1, '')")
+            .bind(DELIVERY_CHANNEL)
+            .execute(&mut **tx)
+            .await?;
+    }
+
     Ok(())
 }
 
@@ -205,7 +312,7 @@ pub async fn get_delivery_issue(
     issue_id: Uuid,
 ) -> Result<Option<Issue>, sqlx::Error> {
     let result = sqlx::query_as::<_, Issue>(
-        "SELECT task_issue_id, profile_email 
+        "SELECT task_issue_id, profile_email, channel, event_type, n_retries, traceparent
                 FROM issue_delivery_queue
                 WHERE task_issue_id=$1
                 AND profile_email = $2",
@@ -0,0 +1,290 @@
+use actix_web::{HttpResponse, web};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::audit::{AuditAction, AuditEvent, record_audit_event};
+use crate::domain::id::ProfileId;
+use crate::error::authentication::StdResponse;
+use crate::telemetry::current_traceparent;
+use crate::util::e500;
+
+const MAX_PER_PAGE: i64 = 100;
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct DeadLetterSearch {
+    task_id: Option<Uuid>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+/// A delivery that ran out of retries or could never succeed
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct DeadLetter {
+    id: Uuid,
+    task_issue_id: Uuid,
+    profile_email: String,
+    channel: String,
+    event_type: String,
+    n_retries: i32,
+    last_error: String,
+    queued_at: String,
+    failed_at: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeadLetterPage {
+    dead_letters: Vec<DeadLetter>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PurgedDeadLetters {
+    purged: u64,
+}
+
+fn dead_letter_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(StdResponse {
+        message: "No Dead Letter Found",
+    })
+}
+
+#[tracing::instrument(name = "List Dead Letters", skip(pool))]
+#[utoipa::path(get, path = "/admin/dead-letters", params(DeadLetterSearch),
+responses((status=200, body=DeadLetterPage, description="Page of failed deliveries, most recent first"), (status=401, description="Authentication failed"), (status=403, description="Not an admin")))]
+pub async fn list_dead_letters(
+    pool: web::Data<PgPool>,
+    search: web::Query<DeadLetterSearch>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let search = search.into_inner();
+    let page = search.page.unwrap_or(1).max(1);
+    let per_page = search.per_page.unwrap_or(20).clamp(1, MAX_PER_PAGE);
+
+    let total = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM issue_delivery_dead_letter
+                WHERE ($1::UUID IS NULL OR task_issue_id = $1)",
+    )
+    .bind(search.task_id)
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to count dead letters")
+    .map_err(e500)?;
+
+    let dead_letters = sqlx::query_as::<_, DeadLetter>(
+        "SELECT id, task_issue_id, profile_email, channel, event_type, n_retries, last_error,
+                    to_char(queued_at AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS queued_at,
+                    to_char(failed_at AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS failed_at
+                FROM issue_delivery_dead_letter
+                WHERE ($1::UUID IS NULL OR task_issue_id = $1)
+                ORDER BY failed_at DESC, id
+                LIMIT $2 OFFSET $3",
+    )
+    .bind(search.task_id)
+    .bind(per_page)
+    .bind((page - 1) * per_page)
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve dead letters")
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(DeadLetterPage {
+        dead_letters,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+/// Puts a failed delivery back in the queue with a fresh retry budget
+#[tracing::instrument(name = "Requeue Dead Letter", skip(pool))]
+#[utoipa::path(post, path = "/admin/dead-letters/{id}/requeue",
+params(("id" = Uuid, Path, description="Dead Letter Id")),
+responses((status=200, description="Delivery requeued"), (status=401, description="Authentication failed"), (status=403, description="Not an admin"), (status=404, description="No Dead Letter Found")))]
+pub async fn requeue_dead_letter(
+    pool: web::Data<PgPool>,
+    dead_letter_id: web::Path<Uuid>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let dead_letter_id = dead_letter_id.into_inner();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let Some((task_issue_id, profile_email, channel, event_type)) =
+        sqlx::query_as::<_, (Uuid, String, String, String)>(
+            "DELETE FROM issue_delivery_dead_letter WHERE id = $1
+                RETURNING task_issue_id, profile_email, channel, event_type",
+        )
+        .bind(dead_letter_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to remove the dead letter")
+        .map_err(e500)?
+    else {
+        return Ok(dead_letter_not_found());
+    };
+
+    // The delivery is no longer settled, so it leaves the task's failed count
+    sqlx::query(
+        "UPDATE task SET deliveries_failed = GREATEST(deliveries_failed - 1, 0) WHERE id = $1",
+    )
+    .bind(task_issue_id)
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the task delivery summary")
+    .map_err(e500)?;
+
+    // The same delivery may have been queued again since, e.g. by an email change
+    sqlx::query(
+        "INSERT INTO issue_delivery_queue
+                    (task_issue_id, profile_email, channel, event_type, n_retries, traceparent)
+                VALUES ($1, $2, $3, $4, 0, $5)
+                ON CONFLICT DO NOTHING",
+    )
+    .bind(task_issue_id)
+    .bind(&profile_email)
+    .bind(&channel)
+    .bind(&event_type)
+    .bind(current_traceparent())
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to requeue the delivery")
+    .map_err(e500)?;
+
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent {
+            actor_id: Some(profile_id.0),
+            profile_id: None,
+            action: AuditAction::DeadLetterRequeued,
+            details: serde_json::json!({
+                "dead_letter_id": dead_letter_id,
+                "task_issue_id": task_issue_id,
+                "profile_email": profile_email,
+                "channel": channel,
+                "event_type": event_type,
+            }),
+        },
+    )
+    .await
+    .context("Failed to record the requeue in the audit log")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to requeue dead letter")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "Delivery requeued",
+    }))
+}
+
+#[tracing::instrument(name = "Purge Dead Letter", skip(pool))]
+#[utoipa::path(delete, path = "/admin/dead-letters/{id}",
+params(("id" = Uuid, Path, description="Dead Letter Id")),
+responses((status=200, description="Dead letter purged"), (status=401, description="Authentication failed"), (status=403, description="Not an admin"), (status=404, description="No Dead Letter Found")))]
+pub async fn purge_dead_letter(
+    pool: web::Data<PgPool>,
+    dead_letter_id: web::Path<Uuid>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let dead_letter_id = dead_letter_id.into_inner();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let n_purged = sqlx::query("DELETE FROM issue_delivery_dead_letter WHERE id = $1")
+        .bind(dead_letter_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to purge the dead letter")
+        .map_err(e500)?
+        .rows_affected();
+    if n_purged == 0 {
+        return Ok(dead_letter_not_found());
+    }
+
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent {
+            actor_id: Some(profile_id.0),
+            profile_id: None,
+            action: AuditAction::DeadLettersPurged,
+            details: serde_json::json!({ "dead_letter_id": dead_letter_id }),
+        },
+    )
+    .await
+    .context("Failed to record the purge in the audit log")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to purge dead letter")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "Dead letter purged",
+    }))
+}
+
+/// Purges every dead letter, or only those of one task when `task_id` is given
+#[tracing::instrument(name = "Purge Dead Letters", skip(pool))]
+#[utoipa::path(delete, path = "/admin/dead-letters", params(DeadLetterSearch),
+responses((status=200, body=PurgedDeadLetters, description="Number of purged dead letters"), (status=401, description="Authentication failed"), (status=403, description="Not an admin")))]
+pub async fn purge_dead_letters(
+    pool: web::Data<PgPool>,
+    search: web::Query<DeadLetterSearch>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let purged = sqlx::query(
+        "DELETE FROM issue_delivery_dead_letter
+                WHERE ($1::UUID IS NULL OR task_issue_id = $1)",
+    )
+    .bind(search.task_id)
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to purge dead letters")
+    .map_err(e500)?
+    .rows_affected();
+
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent {
+            actor_id: Some(profile_id.0),
+            profile_id: None,
+            action: AuditAction::DeadLettersPurged,
+            details: serde_json::json!({ "task_id": search.task_id, "purged": purged }),
+        },
+    )
+    .await
+    .context("Failed to record the purge in the audit log")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to purge dead letters")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(PurgedDeadLetters { purged }))
+}
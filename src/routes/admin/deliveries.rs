@@ -0,0 +1,116 @@
+use actix_web::{HttpResponse, web};
+use anyhow::Context;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::authentication::StdResponse;
+use crate::issue_delivery::DeliveryStatus;
+use crate::util::e500;
+
+#[derive(Serialize, ToSchema)]
+pub struct DeliverySummary {
+    sent: i32,
+    failed: i32,
+    pending: i64,
+}
+
+/// Where the delivery to one recipient stands on one channel, from its latest attempt
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct RecipientDelivery {
+    profile_email: String,
+    channel: String,
+    event_type: String,
+    /// `queued`, `sent`, `deferred` or `failed`
+    status: String,
+    attempts: i64,
+    provider_message_id: Option<String>,
+    last_error: Option<String>,
+    latency_ms: Option<i32>,
+    last_attempt_at: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TaskDeliveries {
+    task_id: Uuid,
+    summary: DeliverySummary,
+    recipients: Vec<RecipientDelivery>,
+}
+
+/// Lists every recipient of a task's notification. Recipients' addresses and
+/// errors are only shown to admins
+#[tracing::instrument(name = "Get Task Deliveries", skip(pool))]
+#[utoipa::path(get, path = "/admin/task/{id}/deliveries",
+params(("id" = Uuid, Path, description="Task Id")),
+responses((status=200, body=TaskDeliveries, description="Delivery status per recipient"), (status=401, description="Authentication failed"), (status=403, description="Not an admin"), (status=404, description="No Task Found")))]
+pub async fn get_task_deliveries(
+    pool: web::Data<PgPool>,
+    task_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let task_id = task_id.into_inner();
+
+    let Some((sent, failed, pending)) = sqlx::query_as::<_, (i32, i32, i64)>(
+        "SELECT deliveries_sent, deliveries_failed,
+                    (SELECT COUNT(*) FROM issue_delivery_queue WHERE task_issue_id = $1)
+                FROM task WHERE id = $1",
+    )
+    .bind(task_id)
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to retrieve the task")
+    .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().json(StdResponse {
+            message: "No Task Found",
+        }));
+    };
+
+    // A dead letter put back in the queue shows as queued again until its next attempt
+    let recipients = sqlx::query_as::<_, RecipientDelivery>(
+        "WITH recipients AS (
+                    SELECT profile_email, channel, event_type FROM delivery_log WHERE task_issue_id = $1
+                    UNION
+                    SELECT profile_email, channel, event_type FROM issue_delivery_queue WHERE task_issue_id = $1
+                )
+                SELECT r.profile_email, r.channel, r.event_type,
+                    CASE WHEN q.profile_email IS NOT NULL AND l.status IS DISTINCT FROM $2
+                        THEN 'queued' ELSE l.status END AS status,
+                    (SELECT COUNT(*) FROM delivery_log c
+                        WHERE c.task_issue_id = $1 AND c.profile_email = r.profile_email
+                        AND c.channel = r.channel AND c.event_type = r.event_type) AS attempts,
+                    l.provider_message_id,
+                    l.error AS last_error,
+                    l.latency_ms,
+                    to_char(l.attempted_at AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS last_attempt_at
+                FROM recipients r
+                LEFT JOIN issue_delivery_queue q
+                    ON q.task_issue_id = $1 AND q.profile_email = r.profile_email
+                    AND q.channel = r.channel AND q.event_type = r.event_type
+                LEFT JOIN LATERAL (
+                    SELECT status, provider_message_id, error, latency_ms, attempted_at
+                    FROM delivery_log d
+                    WHERE d.task_issue_id = $1 AND d.profile_email = r.profile_email
+                    AND d.channel = r.channel AND d.event_type = r.event_type
+                    ORDER BY d.attempted_at DESC
+                    LIMIT 1
+                ) l ON true
+                ORDER BY r.profile_email, r.event_type, r.channel",
+    )
+    .bind(task_id)
+    .bind(DeliveryStatus::Deferred.to_string())
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve the task deliveries")
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(TaskDeliveries {
+        task_id,
+        summary: DeliverySummary {
+            sent,
+            failed,
+            pending,
+        },
+        recipients,
+    }))
+}
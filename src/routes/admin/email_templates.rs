@@ -0,0 +1,68 @@
+use actix_web::{HttpResponse, web};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::email_template::{
+    DEFAULT_LOCALE, EmailTemplate, RenderedEmail, render_email, resolve_locale,
+};
+use crate::error::authentication::StdResponse;
+use crate::util::e500;
+
+#[derive(Deserialize, Serialize, ToSchema, Default, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewFormat {
+    #[default]
+    Json,
+    Html,
+    Text,
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct PreviewParameters {
+    locale: Option<String>,
+    #[serde(default)]
+    format: PreviewFormat,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TemplatePreview {
+    template: String,
+    locale: String,
+    #[serde(flatten)]
+    email: RenderedEmail,
+}
+
+/// Renders a template with sample data, either as JSON holding every part or
+/// as a single part that can be opened in a browser
+#[tracing::instrument(name = "Preview Email Template")]
+#[utoipa::path(get, path = "/admin/email-templates/{name}/preview",
+params(("name" = String, Path, description="Template name"), PreviewParameters),
+responses((status=200, body=TemplatePreview, description="Rendered template"), (status=401, description="Authentication failed"), (status=403, description="Not an admin"), (status=404, description="No Template Found")))]
+pub async fn preview_email_template(
+    name: web::Path<String>,
+    parameters: web::Query<PreviewParameters>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Ok(template) = name.parse::<EmailTemplate>() else {
+        return Ok(HttpResponse::NotFound().json(StdResponse {
+            message: "No Template Found",
+        }));
+    };
+    let locale = resolve_locale(parameters.locale.as_deref().unwrap_or(DEFAULT_LOCALE));
+
+    let email = render_email(template, locale, template.sample_context()).map_err(e500)?;
+
+    Ok(match parameters.format {
+        PreviewFormat::Json => HttpResponse::Ok().json(TemplatePreview {
+            template: template.to_string(),
+            locale: locale.to_string(),
+            email,
+        }),
+        PreviewFormat::Html => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(email.html),
+        PreviewFormat::Text => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(email.text),
+    })
+}
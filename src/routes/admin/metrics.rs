@@ -0,0 +1,43 @@
+use actix_web::{HttpResponse, web};
+use sqlx::PgPool;
+
+use crate::authentication::{PasswordHashStats, password_hash_stats};
+use crate::configuration::PasswordHashingSettings;
+use crate::idempotency::{IdempotencyStats, idempotency_stats};
+use crate::metrics::render_metrics;
+use crate::util::e500;
+
+#[tracing::instrument(name = "Password Hash Metrics", skip(pool, password_hashing))]
+#[utoipa::path(get, path = "/admin/metrics/password-hashes",
+responses((status=200, body=PasswordHashStats, description="Number of profiles and how many of them still have legacy password hashes"), (status=401, description="Authentication failed"), (status=403, description="Not an admin")))]
+pub async fn password_hash_metrics(
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashingSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let stats = password_hash_stats(&pool, &password_hashing)
+        .await
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+#[tracing::instrument(name = "Idempotency Metrics", skip(pool))]
+#[utoipa::path(get, path = "/admin/metrics/idempotency",
+responses((status=200, body=IdempotencyStats, description="Size of the idempotency table and how many keys have expired"), (status=401, description="Authentication failed"), (status=403, description="Not an admin")))]
+pub async fn idempotency_metrics(
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let stats = idempotency_stats(&pool).await.map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+/// Served on the metrics port rather than with the API, so it needs no login
+#[tracing::instrument(name = "Prometheus Metrics", skip(pool))]
+pub async fn prometheus_metrics(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let metrics = render_metrics(&pool).await.map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(metrics))
+}
@@ -1,2 +1,12 @@
 pub mod dashboard;
+pub mod dead_letters;
+pub mod deliveries;
+pub mod email_templates;
+pub mod metrics;
+pub mod notifications;
 pub mod password;
+pub mod privacy;
+pub mod profiles;
+pub mod suppressions;
+pub mod two_factor;
+pub mod users;
@@ -0,0 +1,116 @@
+use actix_web::{HttpResponse, web};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::configuration::DeliverySettings;
+use crate::domain::id::ProfileId;
+use crate::error::authentication::StdResponse;
+use crate::notification::{
+    NotificationPreferences, get_notification_preferences, replace_notification_preferences,
+};
+use crate::notification_channel::{
+    ChannelPreferences, get_channel_preferences, replace_channel_preferences,
+};
+use crate::util::e500;
+
+#[tracing::instrument(name = "Get Notification Preferences", skip(pool))]
+#[utoipa::path(get, path = "/admin/notifications/preferences",
+responses((status=200, body=NotificationPreferences, description="Notification preferences"), (status=401, description="Authentication failed")))]
+pub async fn get_preferences(
+    pool: web::Data<PgPool>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let preferences = get_notification_preferences(&pool, profile_id.0)
+        .await
+        .map_err(e500)?
+        .context("Logged in profile no longer exists")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+#[tracing::instrument(name = "Update Notification Preferences", skip(pool, preferences))]
+#[utoipa::path(put, path = "/admin/notifications/preferences", request_body = NotificationPreferences,
+responses((status=200, body=NotificationPreferences, description="Notification preferences replaced"), (status=400, description="Invalid preferences"), (status=401, description="Authentication failed")))]
+pub async fn update_preferences(
+    pool: web::Data<PgPool>,
+    preferences: web::Json<NotificationPreferences>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let preferences = preferences.into_inner();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    replace_notification_preferences(&mut transaction, profile_id.0, &preferences)
+        .await
+        .context("Failed to store the notification preferences")
+        .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store notification preferences")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+#[tracing::instrument(name = "Get Notification Channels", skip(pool))]
+#[utoipa::path(get, path = "/admin/notifications/channels",
+responses((status=200, body=ChannelPreferences, description="Channels per event"), (status=401, description="Authentication failed")))]
+pub async fn get_channels(
+    pool: web::Data<PgPool>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let preferences = get_channel_preferences(&pool, profile_id.0)
+        .await
+        .map_err(e500)?
+        .context("Logged in profile no longer exists")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+#[tracing::instrument(
+    name = "Update Notification Channels",
+    skip(pool, preferences, delivery)
+)]
+#[utoipa::path(put, path = "/admin/notifications/channels", request_body = ChannelPreferences,
+responses((status=200, body=ChannelPreferences, description="Channels per event replaced"), (status=400, description="Invalid channels"), (status=401, description="Authentication failed")))]
+pub async fn update_channels(
+    pool: web::Data<PgPool>,
+    preferences: web::Json<ChannelPreferences>,
+    delivery: web::Data<DeliverySettings>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let preferences = preferences.into_inner();
+    if let Err(message) = preferences
+        .validate(delivery.webhook_allow_private_addresses)
+        .await
+    {
+        return Ok(HttpResponse::BadRequest().json(StdResponse { message: &message }));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    replace_channel_preferences(&mut transaction, profile_id.0, &preferences)
+        .await
+        .context("Failed to store the notification channels")
+        .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store notification channels")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(preferences))
+}
@@ -4,6 +4,7 @@ use sqlx::PgPool;
 use utoipa::ToSchema;
 
 use crate::authentication::{Credentials, update_password, validate_credentials};
+use crate::configuration::PasswordHashingSettings;
 use crate::domain::id::ProfileId;
 use crate::domain::password::Password;
 use crate::error::authentication::AuthError;
@@ -21,11 +22,12 @@ pub struct PasswordChange {
     new_password_check: String,
 }
 
-#[tracing::instrument(name = "Change Password", skip(form, pool))]
+#[tracing::instrument(name = "Change Password", skip(form, pool, password_hashing))]
 #[utoipa::path(post, path = "/admin/password", responses((status=200, description="Change successful"), (status=303, description="Wrong Entry"), (status=500, description="Something went wrong on our end")))]
 pub async fn change_password(
     form: web::Form<PasswordChange>,
     pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashingSettings>,
     profile_id: web::ReqData<ProfileId>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let profile_id = profile_id.0;
@@ -49,7 +51,7 @@ pub async fn change_password(
         password: SecretBox::new(Box::new(form.0.current_password)),
     };
 
-    if let Err(e) = validate_credentials(credentials, &pool).await {
+    if let Err(e) = validate_credentials(credentials, &pool, &password_hashing).await {
         return match e {
             AuthError::InvalidCredentials(_) => {
                 Ok(HttpResponse::Unauthorized().json(StdResponse {
@@ -60,13 +62,13 @@ pub async fn change_password(
         };
     }
 
-    if let Err(e) = Password::parse(form.0.new_password.clone()) {
+    if let Err(e) = Password::parse(form.0.new_password.clone(), &password_hashing) {
         return Ok(HttpResponse::UnprocessableEntity().json(StdResponse {
             message: &e.to_string(),
         }));
     }
 
-    update_password(profile_id, form.0.new_password, &pool)
+    update_password(profile_id, form.0.new_password, &pool, &password_hashing)
         .await
         .map_err(e500)?;
 
@@ -0,0 +1,262 @@
+use actix_web::{HttpResponse, http::header, web};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::audit::{AuditAction, AuditEvent, record_audit_event};
+use crate::configuration::DataPrivacySettings;
+use crate::domain::id::ProfileId;
+use crate::error::authentication::StdResponse;
+use crate::privacy::{ExportStatus, zip_export_bundle};
+use crate::util::e500;
+
+#[derive(Serialize, ToSchema)]
+pub struct DataExport {
+    export_id: Uuid,
+    status: String,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Default, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Zip,
+    Json,
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadParameters {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeletionScheduled {
+    message: String,
+    scheduled_for: String,
+}
+
+fn export_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(StdResponse {
+        message: "No Export Found",
+    })
+}
+
+#[tracing::instrument(name = "Request Data Export", skip(pool))]
+#[utoipa::path(post, path = "/admin/data/export",
+responses((status=202, body=DataExport, description="Export queued"), (status=401, description="Authentication failed"), (status=500, description="Something went wrong on our end")))]
+pub async fn request_data_export(
+    pool: web::Data<PgPool>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let export_id = Uuid::new_v4();
+
+    sqlx::query("INSERT INTO data_exports (id, profile_id, status) VALUES ($1, $2, $3)")
+        .bind(export_id)
+        .bind(profile_id.0)
+        .bind(ExportStatus::Pending.to_string())
+        .execute(pool.get_ref())
+        .await
+        .context("Failed to queue the data export")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Accepted().json(DataExport {
+        export_id,
+        status: ExportStatus::Pending.to_string(),
+    }))
+}
+
+#[tracing::instrument(name = "Get Data Export", skip(pool))]
+#[utoipa::path(get, path = "/admin/data/export/{id}",
+params(("id" = Uuid, Path, description="Export Id")),
+responses((status=200, body=DataExport, description="Export status"), (status=401, description="Authentication failed"), (status=404, description="No Export Found")))]
+pub async fn get_data_export(
+    pool: web::Data<PgPool>,
+    export_id: web::Path<Uuid>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let export_id = export_id.into_inner();
+
+    let status = sqlx::query_scalar::<_, String>(
+        "SELECT status FROM data_exports WHERE id = $1 AND profile_id = $2",
+    )
+    .bind(export_id)
+    .bind(profile_id.0)
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to retrieve the data export")
+    .map_err(e500)?;
+
+    Ok(match status {
+        Some(status) => HttpResponse::Ok().json(DataExport { export_id, status }),
+        None => export_not_found(),
+    })
+}
+
+#[tracing::instrument(name = "Download Data Export", skip(pool))]
+#[utoipa::path(get, path = "/admin/data/export/{id}/download",
+params(("id" = Uuid, Path, description="Export Id"), DownloadParameters),
+responses((status=200, description="Export archive"), (status=401, description="Authentication failed"), (status=404, description="No Export Found"), (status=409, description="Export is not ready yet")))]
+pub async fn download_data_export(
+    pool: web::Data<PgPool>,
+    export_id: web::Path<Uuid>,
+    parameters: web::Query<DownloadParameters>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let export_id = export_id.into_inner();
+
+    let Some(row) =
+        sqlx::query("SELECT status, bundle FROM data_exports WHERE id = $1 AND profile_id = $2")
+            .bind(export_id)
+            .bind(profile_id.0)
+            .fetch_optional(pool.get_ref())
+            .await
+            .context("Failed to retrieve the data export")
+            .map_err(e500)?
+    else {
+        return Ok(export_not_found());
+    };
+
+    let Some(bundle) = row.get::<Option<serde_json::Value>, _>("bundle") else {
+        return Ok(HttpResponse::Conflict().json(StdResponse {
+            message: "Export is not ready yet",
+        }));
+    };
+
+    match parameters.format {
+        ExportFormat::Json => Ok(HttpResponse::Ok()
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"export-{export_id}.json\""),
+            ))
+            .json(bundle)),
+        ExportFormat::Zip => {
+            let archive = zip_export_bundle(&bundle).map_err(e500)?;
+            Ok(HttpResponse::Ok()
+                .content_type("application/zip")
+                .insert_header((
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"export-{export_id}.zip\""),
+                ))
+                .body(archive))
+        }
+    }
+}
+
+/// Schedules the deletion of `profile_id` once the grace period has ended, on
+/// behalf of `actor_id`
+pub(crate) async fn schedule_profile_deletion(
+    pool: &PgPool,
+    settings: &DataPrivacySettings,
+    actor_id: Uuid,
+    profile_id: Uuid,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    // Asking again keeps the original schedule rather than extending it
+    let scheduled_for = sqlx::query_scalar::<_, String>(
+        "INSERT INTO deletion_requests (profile_id, scheduled_for)
+                VALUES ($1, now() + make_interval(hours => $2))
+                ON CONFLICT (profile_id) DO UPDATE SET profile_id = EXCLUDED.profile_id
+                RETURNING to_char(scheduled_for AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"')",
+    )
+    .bind(profile_id)
+    .bind(settings.deletion_grace_period_hours as i32)
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to schedule the profile deletion")
+    .map_err(e500)?;
+
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent {
+            actor_id: Some(actor_id),
+            profile_id: Some(profile_id),
+            action: AuditAction::DeletionRequested,
+            details: serde_json::json!({ "scheduled_for": scheduled_for }),
+        },
+    )
+    .await
+    .context("Failed to record the deletion request in the audit log")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to schedule deletion")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Accepted().json(DeletionScheduled {
+        message: "Profile deletion scheduled".to_string(),
+        scheduled_for,
+    }))
+}
+
+#[tracing::instrument(name = "Request Profile Deletion", skip(pool, settings))]
+#[utoipa::path(post, path = "/admin/data/deletion",
+responses((status=202, body=DeletionScheduled, description="Deletion scheduled"), (status=401, description="Authentication failed"), (status=500, description="Something went wrong on our end")))]
+pub async fn request_profile_deletion(
+    pool: web::Data<PgPool>,
+    settings: web::Data<DataPrivacySettings>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    schedule_profile_deletion(&pool, &settings, profile_id.0, profile_id.0).await
+}
+
+#[tracing::instrument(name = "Cancel Profile Deletion", skip(pool))]
+#[utoipa::path(delete, path = "/admin/data/deletion",
+responses((status=200, description="Deletion cancelled"), (status=401, description="Authentication failed"), (status=404, description="No deletion scheduled")))]
+pub async fn cancel_profile_deletion(
+    pool: web::Data<PgPool>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let n_deleted = sqlx::query("DELETE FROM deletion_requests WHERE profile_id = $1")
+        .bind(profile_id.0)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to cancel the profile deletion")
+        .map_err(e500)?
+        .rows_affected();
+
+    if n_deleted == 0 {
+        return Ok(HttpResponse::NotFound().json(StdResponse {
+            message: "No deletion scheduled",
+        }));
+    }
+
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent {
+            actor_id: Some(profile_id.0),
+            profile_id: Some(profile_id.0),
+            action: AuditAction::DeletionCancelled,
+            details: serde_json::json!({}),
+        },
+    )
+    .await
+    .context("Failed to record the cancellation in the audit log")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to cancel deletion")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "Profile deletion cancelled",
+    }))
+}
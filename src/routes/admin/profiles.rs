@@ -0,0 +1,69 @@
+use actix_web::{HttpResponse, web};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::audit::{AuditAction, AuditEvent, record_audit_event};
+use crate::domain::id::ProfileId;
+use crate::error::authentication::StdResponse;
+use crate::login_protection::clear_login_failures;
+use crate::model::profile::ProfileIdentifier;
+use crate::util::e500;
+
+#[tracing::instrument(name = "Unlock Profile", skip(pool))]
+#[utoipa::path(post, path = "/admin/profiles/{id}/unlock",
+params(("id" = String, Path, description="Profile Id")),
+responses((status=200, description="Profile unlocked"), (status=403, description="Not an admin"), (status=404, description="No Profile Found"), (status=500, description="Something went wrong on our end")))]
+pub async fn unlock_profile(
+    pool: web::Data<PgPool>,
+    profile_identifier: web::Path<ProfileIdentifier>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_id = profile_identifier.into_inner().id;
+
+    let Some(username) =
+        sqlx::query_scalar::<_, String>("SELECT username FROM profile WHERE id = $1")
+            .bind(target_id)
+            .fetch_optional(pool.get_ref())
+            .await
+            .context("Failed to perform query to retrieve username")
+            .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().json(StdResponse {
+            message: "No Profile Found",
+        }));
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let was_locked = clear_login_failures(&mut *transaction, &username)
+        .await
+        .context("Failed to clear failed login attempts")
+        .map_err(e500)?;
+
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent {
+            actor_id: Some(profile_id.0),
+            profile_id: Some(target_id),
+            action: AuditAction::AccountUnlocked,
+            details: serde_json::json!({ "username": username, "was_locked": was_locked }),
+        },
+    )
+    .await
+    .context("Failed to record the unlock in the audit log")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unlock profile")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "Profile unlocked",
+    }))
+}
@@ -0,0 +1,139 @@
+use actix_web::{HttpResponse, web};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::audit::{AuditAction, AuditEvent, record_audit_event};
+use crate::domain::id::ProfileId;
+use crate::error::authentication::StdResponse;
+use crate::util::e500;
+
+const MAX_PER_PAGE: i64 = 100;
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct SuppressionSearch {
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+/// An address the email provider reported as hard bounced or complaining
+#[derive(Serialize, FromRow, ToSchema)]
+pub struct Suppression {
+    profile_id: Uuid,
+    email: String,
+    /// `hard_bounce` or `complaint`
+    reason: String,
+    suppressed_at: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SuppressionPage {
+    suppressions: Vec<Suppression>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+#[tracing::instrument(name = "List Suppressions", skip(pool))]
+#[utoipa::path(get, path = "/admin/suppressions", params(SuppressionSearch),
+responses((status=200, body=SuppressionPage, description="Page of suppressed addresses, most recent first"), (status=401, description="Authentication failed"), (status=403, description="Not an admin")))]
+pub async fn list_suppressions(
+    pool: web::Data<PgPool>,
+    search: web::Query<SuppressionSearch>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let search = search.into_inner();
+    let page = search.page.unwrap_or(1).max(1);
+    let per_page = search.per_page.unwrap_or(20).clamp(1, MAX_PER_PAGE);
+
+    let total = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM profile WHERE undeliverable_reason IS NOT NULL",
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to count suppressed addresses")
+    .map_err(e500)?;
+
+    let suppressions = sqlx::query_as::<_, Suppression>(
+        "SELECT id AS profile_id, email, undeliverable_reason AS reason,
+                    to_char(undeliverable_at AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS suppressed_at
+                FROM profile
+                WHERE undeliverable_reason IS NOT NULL
+                ORDER BY undeliverable_at DESC, id
+                LIMIT $1 OFFSET $2",
+    )
+    .bind(per_page)
+    .bind((page - 1) * per_page)
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve suppressed addresses")
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(SuppressionPage {
+        suppressions,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+/// Lets notifications reach the address again, e.g. once its mailbox is fixed
+#[tracing::instrument(name = "Clear Suppression", skip(pool))]
+#[utoipa::path(delete, path = "/admin/suppressions/{profile_id}",
+params(("profile_id" = Uuid, Path, description="Profile Id")),
+responses((status=200, description="Suppression cleared"), (status=401, description="Authentication failed"), (status=403, description="Not an admin"), (status=404, description="No Suppression Found")))]
+pub async fn clear_suppression(
+    pool: web::Data<PgPool>,
+    suppressed_id: web::Path<Uuid>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let suppressed_id = suppressed_id.into_inner();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let Some(reason) = sqlx::query_scalar::<_, String>(
+        "UPDATE profile p SET undeliverable_reason = NULL, undeliverable_at = NULL
+                FROM (SELECT id, undeliverable_reason FROM profile WHERE id = $1 FOR UPDATE) old
+                WHERE p.id = old.id AND old.undeliverable_reason IS NOT NULL
+                RETURNING old.undeliverable_reason",
+    )
+    .bind(suppressed_id)
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to clear the suppression")
+    .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().json(StdResponse {
+            message: "No Suppression Found",
+        }));
+    };
+
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent {
+            actor_id: Some(profile_id.0),
+            profile_id: Some(suppressed_id),
+            action: AuditAction::SuppressionCleared,
+            details: serde_json::json!({ "reason": reason }),
+        },
+    )
+    .await
+    .context("Failed to record the cleared suppression in the audit log")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to clear suppression")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "Suppression cleared",
+    }))
+}
@@ -0,0 +1,117 @@
+use actix_web::{HttpResponse, web};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+use crate::domain::id::ProfileId;
+use crate::error::authentication::StdResponse;
+use crate::repository::pgdb::get_username;
+use crate::two_factor::{
+    TotpSettings, enable_totp, generate_recovery_codes, generate_totp_secret, get_totp_settings,
+    provisioning_uri, store_pending_totp_secret, verify_totp_code,
+};
+use crate::util::e500;
+
+/// The session outlived its profile
+fn profile_not_found() -> HttpResponse {
+    HttpResponse::Unauthorized().json(StdResponse {
+        message: "Profile not found",
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TwoFactorEnrollment {
+    secret: String,
+    provisioning_uri: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TwoFactorVerification {
+    code: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TwoFactorRecoveryCodes {
+    message: String,
+    recovery_codes: Vec<String>,
+}
+
+#[tracing::instrument(name = "Enroll Two-Factor Authentication", skip(pool))]
+#[utoipa::path(post, path = "/admin/2fa/enroll", responses((status=200, body=TwoFactorEnrollment, description="Enrollment started"), (status=401, description="Profile not found"), (status=409, description="Two-factor authentication already enabled"), (status=500, description="Something went wrong on our end")))]
+pub async fn enroll_two_factor(
+    pool: web::Data<PgPool>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let profile_id = profile_id.0;
+
+    let Some(settings) = get_totp_settings(profile_id, &pool).await.map_err(e500)? else {
+        return Ok(profile_not_found());
+    };
+    if settings.enabled {
+        return Ok(HttpResponse::Conflict().json(StdResponse {
+            message: "Two-factor authentication is already enabled",
+        }));
+    }
+
+    let username = get_username(profile_id, &pool).await.map_err(e500)?;
+    let secret = generate_totp_secret();
+    let provisioning_uri = provisioning_uri(&secret, &username).map_err(e500)?;
+
+    store_pending_totp_secret(profile_id, &secret, &pool)
+        .await
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(TwoFactorEnrollment {
+        secret,
+        provisioning_uri,
+    }))
+}
+
+#[tracing::instrument(name = "Verify Two-Factor Authentication", skip(form, pool))]
+#[utoipa::path(post, path = "/admin/2fa/verify", request_body = TwoFactorVerification, responses((status=200, body=TwoFactorRecoveryCodes, description="Two-factor authentication enabled"), (status=401, description="Invalid code or profile not found"), (status=409, description="Two-factor authentication already enabled"), (status=422, description="Enrollment not started"), (status=500, description="Something went wrong on our end")))]
+pub async fn verify_two_factor(
+    form: web::Form<TwoFactorVerification>,
+    pool: web::Data<PgPool>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let profile_id = profile_id.0;
+
+    let Some(settings) = get_totp_settings(profile_id, &pool).await.map_err(e500)? else {
+        return Ok(profile_not_found());
+    };
+    let secret = match settings {
+        TotpSettings { enabled: true, .. } => {
+            return Ok(HttpResponse::Conflict().json(StdResponse {
+                message: "Two-factor authentication is already enabled",
+            }));
+        }
+        TotpSettings { secret: None, .. } => {
+            return Ok(HttpResponse::UnprocessableEntity().json(StdResponse {
+                message: "Two-factor enrollment has not been started",
+            }));
+        }
+        TotpSettings {
+            secret: Some(secret),
+            ..
+        } => secret,
+    };
+
+    let Some(step) = verify_totp_code(&secret, &form.0.code, settings.last_step).map_err(e500)?
+    else {
+        return Ok(HttpResponse::Unauthorized().json(StdResponse {
+            message: "Invalid two-factor code",
+        }));
+    };
+
+    // Recovery codes are only ever shown once, here
+    let recovery_codes = generate_recovery_codes();
+
+    enable_totp(profile_id, step, &recovery_codes, &pool)
+        .await
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(TwoFactorRecoveryCodes {
+        message: "Two-factor authentication enabled".to_string(),
+        recovery_codes,
+    }))
+}
@@ -0,0 +1,625 @@
+use actix_web::{HttpResponse, http::header, web};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::audit::{AuditAction, AuditEvent, record_audit_event};
+use crate::authentication::{compute_password, create_impersonation_token};
+use crate::authorization::{Role, get_roles, has_role};
+use crate::configuration::{PasswordHashingSettings, ProfileConfirmationSettings};
+use crate::domain::email::ProfileEmail;
+use crate::domain::id::ProfileId;
+use crate::email_client::EmailClient;
+use crate::error::authentication::StdResponse;
+use crate::model::profile::ProfileIdentifier;
+use crate::repository::pgdb::get_token_version;
+use crate::routes::password_reset::{send_password_reset_email, store_reset_token};
+use crate::routes::profile::{send_confirmation_email, store_token};
+use crate::startup::{ApplicationBaseUri, ResetTokenExpiryTime, SecretKey};
+use crate::telemetry::spawn_blocking_with_tracing;
+use crate::util::e500;
+use crate::util::token_generator::generate_profile_token;
+
+const MAX_PER_PAGE: i64 = 100;
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct UserSearch {
+    /// Matched against the username, email and names
+    q: Option<String>,
+    status: Option<String>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserSummary {
+    id: Uuid,
+    username: String,
+    email: String,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    status: String,
+    roles: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserPage {
+    users: Vec<UserSummary>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserDetail {
+    id: Uuid,
+    username: String,
+    email: String,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    status: String,
+    roles: Vec<String>,
+    totp_enabled: bool,
+    locked: bool,
+}
+
+#[derive(Deserialize, ToSchema, Debug, Default)]
+pub struct SuspensionReason {
+    reason: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Impersonation {
+    message: String,
+    profile_id: Uuid,
+}
+
+struct Target {
+    username: String,
+    email: String,
+    status: String,
+    locale: String,
+}
+
+fn user_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(StdResponse {
+        message: "No Profile Found",
+    })
+}
+
+#[tracing::instrument(name = "Get target profile", skip(pool))]
+async fn get_target(pool: &PgPool, profile_id: Uuid) -> Result<Option<Target>, sqlx::Error> {
+    let row = sqlx::query("SELECT username, email, status, locale FROM profile WHERE id = $1")
+        .bind(profile_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(|r| Target {
+        username: r.get("username"),
+        email: r.get("email"),
+        status: r.get("status"),
+        locale: r.get("locale"),
+    }))
+}
+
+async fn audit(
+    pool: &PgPool,
+    actor_id: Uuid,
+    profile_id: Option<Uuid>,
+    action: AuditAction,
+    details: serde_json::Value,
+) -> Result<(), actix_web::Error> {
+    record_audit_event(
+        pool,
+        AuditEvent {
+            actor_id: Some(actor_id),
+            profile_id,
+            action,
+            details,
+        },
+    )
+    .await
+    .context("Failed to record the admin action in the audit log")
+    .map_err(e500)
+}
+
+#[tracing::instrument(name = "List Users", skip(pool))]
+#[utoipa::path(get, path = "/admin/users", params(UserSearch),
+responses((status=200, body=UserPage, description="Page of matching profiles"), (status=401, description="Authentication failed"), (status=403, description="Not an admin")))]
+pub async fn list_users(
+    pool: web::Data<PgPool>,
+    search: web::Query<UserSearch>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let search = search.into_inner();
+    let page = search.page.unwrap_or(1).max(1);
+    let per_page = search.per_page.unwrap_or(20).clamp(1, MAX_PER_PAGE);
+    let query = search.q.filter(|q| !q.trim().is_empty());
+
+    let rows = sqlx::query(
+        "SELECT p.id, p.username, p.email, p.first_name, p.last_name, p.status,
+                    COALESCE(array_agg(r.role ORDER BY r.role) FILTER (WHERE r.role IS NOT NULL), '{}') AS roles,
+                    COUNT(*) OVER () AS total
+                FROM profile p
+                LEFT JOIN profile_roles r ON r.profile_id = p.id
+                WHERE ($1::TEXT IS NULL
+                    OR position(lower($1) IN lower(p.username || ' ' || p.email || ' '
+                        || COALESCE(p.first_name, '') || ' ' || COALESCE(p.last_name, ''))) > 0)
+                AND ($2::TEXT IS NULL OR p.status = $2)
+                GROUP BY p.id
+                ORDER BY p.created_at, p.id
+                LIMIT $3 OFFSET $4",
+    )
+    .bind(&query)
+    .bind(&search.status)
+    .bind(per_page)
+    .bind((page - 1) * per_page)
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to search profiles")
+    .map_err(e500)?;
+
+    let total = rows.first().map(|r| r.get("total")).unwrap_or_default();
+    let users = rows
+        .into_iter()
+        .map(|r| UserSummary {
+            id: r.get("id"),
+            username: r.get("username"),
+            email: r.get("email"),
+            first_name: r.get("first_name"),
+            last_name: r.get("last_name"),
+            status: r.get("status"),
+            roles: r.get("roles"),
+        })
+        .collect();
+
+    audit(
+        &pool,
+        profile_id.0,
+        None,
+        AuditAction::ProfilesListed,
+        serde_json::json!({ "q": query, "status": search.status, "page": page }),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(UserPage {
+        users,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+#[tracing::instrument(name = "Get User", skip(pool))]
+#[utoipa::path(get, path = "/admin/users/{id}",
+params(("id" = String, Path, description="Profile Id")),
+responses((status=200, body=UserDetail, description="Profile status and roles"), (status=401, description="Authentication failed"), (status=403, description="Not an admin"), (status=404, description="No Profile Found")))]
+pub async fn get_user(
+    pool: web::Data<PgPool>,
+    profile_identifier: web::Path<ProfileIdentifier>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_id = profile_identifier.into_inner().id;
+
+    let Some(row) = sqlx::query(
+        "SELECT id, username, email, first_name, last_name, status, totp_enabled,
+                    EXISTS (
+                        SELECT 1 FROM login_attempts
+                        WHERE scope = 'username' AND key = profile.username AND next_attempt_at > now()
+                    ) AS locked
+                FROM profile WHERE id = $1",
+    )
+    .bind(target_id)
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to retrieve the profile")
+    .map_err(e500)?
+    else {
+        return Ok(user_not_found());
+    };
+
+    let roles = get_roles(&pool, target_id)
+        .await
+        .context("Failed to retrieve the profile roles")
+        .map_err(e500)?;
+
+    audit(
+        &pool,
+        profile_id.0,
+        Some(target_id),
+        AuditAction::ProfileViewed,
+        serde_json::json!({}),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(UserDetail {
+        id: row.get("id"),
+        username: row.get("username"),
+        email: row.get("email"),
+        first_name: row.get("first_name"),
+        last_name: row.get("last_name"),
+        status: row.get("status"),
+        roles,
+        totp_enabled: row.get("totp_enabled"),
+        locked: row.get("locked"),
+    }))
+}
+
+#[tracing::instrument(name = "Suspend User", skip(pool, body))]
+#[utoipa::path(post, path = "/admin/users/{id}/suspend", request_body = SuspensionReason,
+params(("id" = String, Path, description="Profile Id")),
+responses((status=200, description="Profile suspended"), (status=401, description="Authentication failed"), (status=403, description="Not an admin"), (status=404, description="No Profile Found"), (status=409, description="Profile cannot be suspended")))]
+pub async fn suspend_user(
+    pool: web::Data<PgPool>,
+    profile_identifier: web::Path<ProfileIdentifier>,
+    body: Option<web::Json<SuspensionReason>>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_id = profile_identifier.into_inner().id;
+
+    if target_id == profile_id.0 {
+        return Ok(HttpResponse::Conflict().json(StdResponse {
+            message: "You cannot suspend your own profile",
+        }));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    // Bumping the token version ends every live session of the profile
+    let Some(previous_status) = sqlx::query_scalar::<_, String>(
+        "UPDATE profile SET
+                    status_before_suspension = status,
+                    status = 'suspended',
+                    token_version = token_version + 1
+                WHERE id = $1 AND status <> 'suspended'
+                RETURNING status_before_suspension",
+    )
+    .bind(target_id)
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to suspend the profile")
+    .map_err(e500)?
+    else {
+        return Ok(match get_target(&pool, target_id).await.map_err(e500)? {
+            Some(_) => HttpResponse::Conflict().json(StdResponse {
+                message: "Profile is already suspended",
+            }),
+            None => user_not_found(),
+        });
+    };
+
+    // Otherwise a pending profile could confirm itself out of the suspension
+    sqlx::query("DELETE FROM profile_tokens WHERE profile_id = $1 AND used_at IS NULL")
+        .bind(target_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to revoke the confirmation tokens")
+        .map_err(e500)?;
+
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent {
+            actor_id: Some(profile_id.0),
+            profile_id: Some(target_id),
+            action: AuditAction::AccountSuspended,
+            details: serde_json::json!({
+                "previous_status": previous_status,
+                "reason": body.and_then(|b| b.into_inner().reason),
+            }),
+        },
+    )
+    .await
+    .context("Failed to record the suspension in the audit log")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to suspend profile")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "Profile suspended",
+    }))
+}
+
+#[tracing::instrument(name = "Reactivate User", skip(pool))]
+#[utoipa::path(post, path = "/admin/users/{id}/reactivate",
+params(("id" = String, Path, description="Profile Id")),
+responses((status=200, description="Profile reactivated"), (status=401, description="Authentication failed"), (status=403, description="Not an admin"), (status=404, description="No Profile Found"), (status=409, description="Profile is not suspended")))]
+pub async fn reactivate_user(
+    pool: web::Data<PgPool>,
+    profile_identifier: web::Path<ProfileIdentifier>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_id = profile_identifier.into_inner().id;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let Some(status) = sqlx::query_scalar::<_, String>(
+        "UPDATE profile SET
+                    status = COALESCE(status_before_suspension, 'confirmed'),
+                    status_before_suspension = NULL
+                WHERE id = $1 AND status = 'suspended'
+                RETURNING status",
+    )
+    .bind(target_id)
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to reactivate the profile")
+    .map_err(e500)?
+    else {
+        return Ok(match get_target(&pool, target_id).await.map_err(e500)? {
+            Some(_) => HttpResponse::Conflict().json(StdResponse {
+                message: "Profile is not suspended",
+            }),
+            None => user_not_found(),
+        });
+    };
+
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent {
+            actor_id: Some(profile_id.0),
+            profile_id: Some(target_id),
+            action: AuditAction::AccountReactivated,
+            details: serde_json::json!({ "status": status }),
+        },
+    )
+    .await
+    .context("Failed to record the reactivation in the audit log")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reactivate profile")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "Profile reactivated",
+    }))
+}
+
+#[tracing::instrument(
+    name = "Force Password Reset",
+    skip(pool, email_client, base_uri, reset_token_expiry, password_hashing)
+)]
+#[utoipa::path(post, path = "/admin/users/{id}/password-reset",
+params(("id" = String, Path, description="Profile Id")),
+responses((status=200, description="Password invalidated and reset link sent"), (status=401, description="Authentication failed"), (status=403, description="Not an admin"), (status=404, description="No Profile Found"), (status=500, description="Something went wrong on our end")))]
+pub async fn force_password_reset(
+    pool: web::Data<PgPool>,
+    profile_identifier: web::Path<ProfileIdentifier>,
+    email_client: web::Data<EmailClient>,
+    base_uri: web::Data<ApplicationBaseUri>,
+    reset_token_expiry: web::Data<ResetTokenExpiryTime>,
+    password_hashing: web::Data<PasswordHashingSettings>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_id = profile_identifier.into_inner().id;
+
+    let Some(target) = get_target(&pool, target_id).await.map_err(e500)? else {
+        return Ok(user_not_found());
+    };
+    let email = ProfileEmail::parse(target.email)
+        .context("Stored email address is invalid")
+        .map_err(e500)?;
+
+    // Nobody knows the replacement, so the reset link is the only way back in
+    let password_hashing = password_hashing.into_inner();
+    let password = spawn_blocking_with_tracing(move || {
+        compute_password(generate_profile_token(), &password_hashing)
+    })
+    .await
+    .context("Failed to spawn blocking task")
+    .map_err(e500)?
+    .map_err(e500)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    sqlx::query(
+        "UPDATE profile SET password = $1, token_version = token_version + 1 WHERE id = $2",
+    )
+    .bind(password)
+    .bind(target_id)
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to invalidate the password")
+    .map_err(e500)?;
+
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent {
+            actor_id: Some(profile_id.0),
+            profile_id: Some(target_id),
+            action: AuditAction::PasswordResetForced,
+            details: serde_json::json!({ "username": target.username }),
+        },
+    )
+    .await
+    .context("Failed to record the forced reset in the audit log")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to force password reset")
+        .map_err(e500)?;
+
+    let reset_token = generate_profile_token();
+    store_reset_token(&pool, target_id, &reset_token, reset_token_expiry.0)
+        .await
+        .context("Failed to store the password reset token.")
+        .map_err(e500)?;
+
+    send_password_reset_email(
+        &email_client,
+        &email,
+        &target.locale,
+        &base_uri.0,
+        &reset_token,
+    )
+    .await
+    .context("Failed to send password reset email")
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "Password reset link sent",
+    }))
+}
+
+#[tracing::instrument(
+    name = "Admin Resend Confirmation",
+    skip(pool, email_client, base_uri, confirmation)
+)]
+#[utoipa::path(post, path = "/admin/users/{id}/resend-confirmation",
+params(("id" = String, Path, description="Profile Id")),
+responses((status=200, description="Confirmation link sent"), (status=401, description="Authentication failed"), (status=403, description="Not an admin"), (status=404, description="No Profile Found"), (status=409, description="Profile is not pending confirmation")))]
+pub async fn resend_user_confirmation(
+    pool: web::Data<PgPool>,
+    profile_identifier: web::Path<ProfileIdentifier>,
+    email_client: web::Data<EmailClient>,
+    base_uri: web::Data<ApplicationBaseUri>,
+    confirmation: web::Data<ProfileConfirmationSettings>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_id = profile_identifier.into_inner().id;
+
+    let Some(target) = get_target(&pool, target_id).await.map_err(e500)? else {
+        return Ok(user_not_found());
+    };
+    if target.status != "pending_confirmation" {
+        return Ok(HttpResponse::Conflict().json(StdResponse {
+            message: "Profile is not pending confirmation",
+        }));
+    }
+    let email = ProfileEmail::parse(target.email)
+        .context("Stored email address is invalid")
+        .map_err(e500)?;
+
+    let profile_token = generate_profile_token();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    store_token(
+        &mut transaction,
+        target_id,
+        &profile_token,
+        confirmation.token_expire_minutes,
+    )
+    .await
+    .context("Failed to store the confirmation token.")
+    .map_err(e500)?;
+
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent {
+            actor_id: Some(profile_id.0),
+            profile_id: Some(target_id),
+            action: AuditAction::ConfirmationResent,
+            details: serde_json::json!({}),
+        },
+    )
+    .await
+    .context("Failed to record the resend in the audit log")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store confirmation token")
+        .map_err(e500)?;
+
+    send_confirmation_email(
+        &email_client,
+        &email,
+        &target.locale,
+        &base_uri.0,
+        &profile_token,
+    )
+    .await
+    .context("Failed to send a confirmation email.")
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "Confirmation link sent",
+    }))
+}
+
+/// Issues a short-lived access token for the profile, returned in the Authorization
+/// header like a login. The token names the admin, who every request made with it
+/// is attributed to. The admin's own session is left untouched
+#[tracing::instrument(name = "Impersonate User", skip(pool, secret))]
+#[utoipa::path(post, path = "/admin/users/{id}/impersonate",
+params(("id" = String, Path, description="Profile Id")),
+responses((status=200, body=Impersonation, description="Access token issued for the profile"), (status=401, description="Authentication failed"), (status=403, description="Not an admin or the profile is an admin"), (status=404, description="No Profile Found"), (status=409, description="Profile is suspended")))]
+pub async fn impersonate_user(
+    pool: web::Data<PgPool>,
+    profile_identifier: web::Path<ProfileIdentifier>,
+    secret: web::Data<SecretKey>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_id = profile_identifier.into_inner().id;
+
+    let Some(target) = get_target(&pool, target_id).await.map_err(e500)? else {
+        return Ok(user_not_found());
+    };
+    if target.status == "suspended" {
+        return Ok(HttpResponse::Conflict().json(StdResponse {
+            message: "Profile is suspended",
+        }));
+    }
+    // Impersonating another admin would be a way around their audit trail
+    if has_role(&pool, target_id, Role::Admin)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(HttpResponse::Forbidden().json(StdResponse {
+            message: "Admin profiles cannot be impersonated",
+        }));
+    }
+
+    let token_version = get_token_version(target_id, &pool)
+        .await
+        .map_err(e500)?
+        .unwrap_or_default();
+    let access_token =
+        create_impersonation_token(target_id, token_version, profile_id.0, &secret.0)
+            .map_err(e500)?;
+
+    audit(
+        &pool,
+        profile_id.0,
+        Some(target_id),
+        AuditAction::ProfileImpersonated,
+        serde_json::json!({ "username": target.username }),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", access_token)))
+        .json(Impersonation {
+            message: format!("Impersonating {}", target.username),
+            profile_id: target_id,
+        }))
+}
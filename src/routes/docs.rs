@@ -8,6 +8,8 @@ use utoipa::OpenApi;
     ),
     paths(
         crate::routes::health_check::health_check,
+        crate::routes::health_check::health_live,
+        crate::routes::health_check::health_ready,
         crate::routes::task::get_task,
         crate::routes::task::create_task,
         crate::routes::task::start_task,
@@ -19,12 +21,57 @@ use utoipa::OpenApi;
         crate::routes::profile::update_profile,
         crate::routes::profile::delete_profile,
         crate::routes::profile_confirm::confirm_profile,
+        crate::routes::profile_confirm::resend_confirmation,
+        crate::routes::profile_email::request_email_change,
+        crate::routes::profile_email::confirm_email_change,
+        crate::routes::profile_email::revert_email_change,
         crate::routes::login::log_in,
         crate::routes::login::log_in_check,
         crate::routes::login::refresh_token,
+        crate::routes::login::log_in_two_factor,
+        crate::routes::oidc::oidc_login,
+        crate::routes::oidc::oidc_callback,
+        crate::routes::notifications::unsubscribe_form,
+        crate::routes::notifications::unsubscribe_from_notifications,
+        crate::routes::notifications::list_notifications,
+        crate::routes::notifications::mark_notification_read,
+        crate::routes::notifications::mark_notification_unread,
+        crate::routes::email_events::receive_email_events,
+        crate::routes::password_reset::forgot_password,
+        crate::routes::password_reset::password_reset_form,
+        crate::routes::password_reset::reset_password,
         crate::routes::admin::dashboard::admin_dashboard,
         crate::routes::admin::password::change_password,
-        crate::routes::admin::password::logout
+        crate::routes::admin::password::logout,
+        crate::routes::admin::two_factor::enroll_two_factor,
+        crate::routes::admin::two_factor::verify_two_factor,
+        crate::routes::admin::profiles::unlock_profile,
+        crate::routes::admin::users::list_users,
+        crate::routes::admin::users::get_user,
+        crate::routes::admin::users::suspend_user,
+        crate::routes::admin::users::reactivate_user,
+        crate::routes::admin::users::force_password_reset,
+        crate::routes::admin::users::resend_user_confirmation,
+        crate::routes::admin::users::impersonate_user,
+        crate::routes::admin::email_templates::preview_email_template,
+        crate::routes::admin::dead_letters::list_dead_letters,
+        crate::routes::admin::dead_letters::requeue_dead_letter,
+        crate::routes::admin::dead_letters::purge_dead_letter,
+        crate::routes::admin::dead_letters::purge_dead_letters,
+        crate::routes::admin::deliveries::get_task_deliveries,
+        crate::routes::admin::suppressions::list_suppressions,
+        crate::routes::admin::suppressions::clear_suppression,
+        crate::routes::admin::notifications::get_preferences,
+        crate::routes::admin::notifications::update_preferences,
+        crate::routes::admin::notifications::get_channels,
+        crate::routes::admin::notifications::update_channels,
+        crate::routes::admin::privacy::request_data_export,
+        crate::routes::admin::privacy::get_data_export,
+        crate::routes::admin::privacy::download_data_export,
+        crate::routes::admin::privacy::request_profile_deletion,
+        crate::routes::admin::privacy::cancel_profile_deletion,
+        crate::routes::admin::metrics::password_hash_metrics,
+        crate::routes::admin::metrics::idempotency_metrics
 
     )
 )]
@@ -0,0 +1,102 @@
+use actix_web::{HttpRequest, HttpResponse, web};
+use anyhow::Context;
+use serde::Serialize;
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+use crate::audit::{AuditAction, AuditEvent, record_audit_event};
+use crate::error::authentication::StdResponse;
+use crate::suppression::{ProviderEvents, WebhookSignatures, suppress_address};
+use crate::util::e500;
+
+#[derive(Serialize, ToSchema)]
+pub struct ProcessedEvents {
+    received: usize,
+    suppressed: usize,
+}
+
+/// Bounce and spam complaint callbacks from the email provider. Hard bounced and
+/// complaining addresses stop receiving notifications until an admin clears them
+#[tracing::instrument(name = "Receive Email Events", skip(request, body, pool, signatures))]
+#[utoipa::path(post, path = "/email/events",
+request_body(content = String, content_type = "application/json", description = "One provider event or a batch of them"),
+params(("X-Webhook-Timestamp" = i64, Header, description="Unix time the callback was signed at"),
+    ("X-Webhook-Signature" = String, Header, description="Hex HMAC-SHA256 of `{timestamp}.{body}`")),
+responses((status=200, body=ProcessedEvents, description="Events processed"), (status=400, description="Invalid events"), (status=401, description="Invalid signature"), (status=500, description="Something went wrong on our end")))]
+pub async fn receive_email_events(
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    signatures: web::Data<WebhookSignatures>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let timestamp = header("X-Webhook-Timestamp").and_then(|value| value.parse::<i64>().ok());
+    let signature = header("X-Webhook-Signature");
+
+    let verified = match (timestamp, signature) {
+        (Some(timestamp), Some(signature)) => signatures
+            .verify(timestamp, &body, signature, chrono::Utc::now().timestamp())
+            .inspect_err(|e| tracing::warn!(error = %e, "Rejected email provider callback")),
+        _ => Err(anyhow::anyhow!("Missing webhook signature")),
+    };
+    if verified.is_err() {
+        return Ok(HttpResponse::Unauthorized().json(StdResponse {
+            message: "Invalid signature",
+        }));
+    }
+
+    let Ok(events) = serde_json::from_slice::<ProviderEvents>(&body) else {
+        return Ok(HttpResponse::BadRequest().json(StdResponse {
+            message: "Invalid events",
+        }));
+    };
+    let events = events.into_vec();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let mut suppressed = 0;
+    for (email, reason) in events.iter().filter_map(|event| event.suppression()) {
+        let Some(profile_id) = suppress_address(&mut transaction, email, reason)
+            .await
+            .context("Failed to suppress the address")
+            .map_err(e500)?
+        else {
+            continue;
+        };
+        tracing::warn!(%profile_id, %reason, "Address marked as undeliverable");
+        suppressed += 1;
+
+        record_audit_event(
+            &mut *transaction,
+            AuditEvent {
+                actor_id: None,
+                profile_id: Some(profile_id),
+                action: AuditAction::AddressSuppressed,
+                details: serde_json::json!({ "reason": reason.to_string() }),
+            },
+        )
+        .await
+        .context("Failed to record the suppression in the audit log")
+        .map_err(e500)?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to suppress addresses")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(ProcessedEvents {
+        received: events.len(),
+        suppressed,
+    }))
+}
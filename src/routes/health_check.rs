@@ -1,5 +1,10 @@
 use actix_web::HttpResponse;
 use actix_web::get;
+use actix_web::web::Data;
+use redis::aio::ConnectionManager;
+use sqlx::PgPool;
+
+use crate::health::{Readiness, ShutdownState, readiness};
 
 #[utoipa::path(get,
     path="/health_check",
@@ -8,3 +13,32 @@ use actix_web::get;
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+/// Answers as long as the process can serve requests at all; dependencies are
+/// left to readiness, so that an outage doesn't get every instance restarted
+#[utoipa::path(get,
+    path="/health/live",
+    responses((status=200, description="The process is running")))]
+#[get("/health/live")]
+pub async fn health_live() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({"status": "alive"}))
+}
+
+#[utoipa::path(get,
+    path="/health/ready",
+    responses((status=200, body=Readiness, description="Every dependency is up"),
+        (status=503, body=Readiness, description="A dependency is down or the instance is shutting down")))]
+#[get("/health/ready")]
+pub async fn health_ready(
+    pool: Data<PgPool>,
+    redis: Data<ConnectionManager>,
+    shutdown: Data<ShutdownState>,
+) -> HttpResponse {
+    let readiness = readiness(&pool, redis.get_ref().clone(), &shutdown).await;
+
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
@@ -1,13 +1,24 @@
 use actix_web::{HttpRequest, HttpResponse, cookie::Cookie, get, http::header, post, web};
 
+use anyhow::Context;
 use secrecy::SecretBox;
 use sqlx::PgPool;
 use utoipa::ToSchema;
+use uuid::Uuid;
 
 use crate::{
     authentication::{Credentials, create_token, validate_credentials, validate_refresh_token},
+    configuration::{LoginProtectionSettings, PasswordHashingSettings},
     error::authentication::{AuthError, LoginError, StdResponse},
+    login_protection::{
+        check_login_allowed, clear_login_failures, client_ip, record_login_failure,
+    },
+    repository::pgdb::get_token_version,
     startup::{ExpiryTime, SecretKey},
+    two_factor::{
+        complete_login_challenge, create_login_challenge, get_totp_settings,
+        login_challenge_username,
+    },
 };
 
 use crate::session_state::TypedSession;
@@ -20,59 +31,213 @@ pub struct LoginData {
     password: String,
 }
 
-#[tracing::instrument(name = "Logging In", skip(form, pool, session))]
-#[utoipa::path(post, path = "/login", responses((status=200, description="Authentication successful"), (status=401, description="Authentication failed")))]
+#[derive(serde::Deserialize, ToSchema, Debug)]
+pub struct TwoFactorLoginData {
+    challenge: String,
+    code: String,
+}
+
+#[derive(serde::Serialize)]
+struct TwoFactorChallengeResponse<'a> {
+    message: &'a str,
+    challenge: &'a str,
+}
+
+/// Renews the session and issues the access and refresh tokens for an authenticated profile
+async fn start_authenticated_session(
+    profile_id: Uuid,
+    pool: &PgPool,
+    session: TypedSession,
+    expiry_time: u64,
+    secret: &str,
+) -> Result<HttpResponse, LoginError> {
+    // Suspended profiles keep their credentials but may not start a session, and
+    // deleted ones may still be reached through a linked external identity
+    let status = sqlx::query_scalar::<_, String>("SELECT status FROM profile WHERE id = $1")
+        .bind(profile_id)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to retrieve the profile status")?;
+    match status.as_deref() {
+        Some("suspended") => {
+            return Err(LoginError::AuthError(anyhow::anyhow!("Account suspended")));
+        }
+        Some("deleted") | None => {
+            return Err(LoginError::AuthError(anyhow::anyhow!("Account deleted")));
+        }
+        _ => {}
+    }
+
+    let token_version = get_token_version(profile_id, pool)
+        .await?
+        .unwrap_or_default();
+
+    session.renew();
+
+    session
+        .insert_profile_id(profile_id)
+        .map_err(|e| LoginError::UnexpectedError(e.into()))?;
+    session
+        .insert_token_version(token_version)
+        .map_err(|e| LoginError::UnexpectedError(e.into()))?;
+
+    FlashMessage::info("Authorized").send();
+    dbg!("login");
+
+    let access_token = create_token(profile_id, token_version, expiry_time, secret)?;
+
+    let refresh_token = create_token(profile_id, token_version, 30 * 24 * 60, secret)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", access_token)))
+        .cookie(
+            Cookie::build("refresh_token", refresh_token)
+                .http_only(true)
+                .finish(),
+        )
+        .json(StdResponse {
+            message: "Login Successful",
+        }))
+}
+
+/// Either starts the session or, when two-factor authentication is enabled,
+/// issues the challenge that has to be completed first
+pub(crate) async fn complete_first_factor(
+    profile_id: Uuid,
+    pool: &PgPool,
+    session: TypedSession,
+    expiry_time: u64,
+    secret: &str,
+) -> Result<HttpResponse, LoginError> {
+    // Tokens are withheld until the second factor is presented
+    let Some(totp) = get_totp_settings(profile_id, pool).await? else {
+        return Err(LoginError::AuthError(anyhow::anyhow!("Profile not found")));
+    };
+    if totp.enabled {
+        let challenge = create_login_challenge(profile_id, pool).await?;
+
+        return Ok(HttpResponse::Ok().json(TwoFactorChallengeResponse {
+            message: "Two-factor authentication required",
+            challenge: &challenge,
+        }));
+    }
+
+    start_authenticated_session(profile_id, pool, session, expiry_time, secret).await
+}
+
+#[tracing::instrument(
+    name = "Logging In",
+    skip(req, form, pool, session, login_protection, password_hashing)
+)]
+#[utoipa::path(post, path = "/login", responses((status=200, description="Authentication successful or two-factor challenge issued"), (status=401, description="Authentication failed"), (status=429, description="Too many failed attempts")))]
 #[post("/login")]
+#[allow(clippy::too_many_arguments)]
 async fn log_in(
+    req: HttpRequest,
     form: web::Form<LoginData>,
     pool: web::Data<PgPool>,
     secret: web::Data<SecretKey>,
     expiry_time: web::Data<ExpiryTime>,
+    login_protection: web::Data<LoginProtectionSettings>,
+    password_hashing: web::Data<PasswordHashingSettings>,
     session: TypedSession,
 ) -> Result<HttpResponse, LoginError> {
     let secret = &secret.into_inner().0;
+    let username = form.0.username;
+    let ip_address = client_ip(&req, &login_protection);
+
+    tracing::Span::current().record("username", tracing::field::display(&username));
+
+    if let Some(retry_after) = check_login_allowed(&pool, &username, &ip_address).await? {
+        return Err(LoginError::TooManyAttempts(retry_after));
+    }
+
     let credentials = Credentials {
-        username: form.0.username,
+        username: username.clone(),
         password: SecretBox::new(Box::new(form.0.password)),
     };
 
-    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
-
-    match validate_credentials(credentials, &pool).await {
+    match validate_credentials(credentials, &pool, &password_hashing).await {
         Ok(profile_id) => {
             tracing::Span::current().record("profile_id", tracing::field::display(&profile_id));
 
-            session.renew();
-
-            session
-                .insert_profile_id(profile_id)
-                .map_err(|e| LoginError::UnexpectedError(e.into()))?;
+            // Failures are only forgotten once the second factor, if any, is accepted too
+            let two_factor_pending = get_totp_settings(profile_id, &pool)
+                .await?
+                .is_some_and(|totp| totp.enabled);
+            if !two_factor_pending {
+                clear_login_failures(pool.get_ref(), &username)
+                    .await
+                    .context("Failed to clear failed login attempts")?;
+            }
 
-            FlashMessage::info("Authorized").send();
-            dbg!("login");
-
-            let access_token = create_token(profile_id, expiry_time.into_inner().0, secret)?;
-
-            let refresh_token = create_token(profile_id, 30 * 24 * 60, secret)?;
-
-            Ok(HttpResponse::Ok()
-                .insert_header((header::AUTHORIZATION, format!("Bearer {}", access_token)))
-                .cookie(
-                    Cookie::build("refresh_token", refresh_token)
-                        .http_only(true)
-                        .finish(),
-                )
-                .json(StdResponse {
-                    message: "Login Successful",
-                }))
+            complete_first_factor(
+                profile_id,
+                &pool,
+                session,
+                expiry_time.into_inner().0,
+                secret,
+            )
+            .await
         }
         Err(e) => match e {
-            AuthError::InvalidCredentials(_) => Err(LoginError::AuthError(e.into())),
+            AuthError::InvalidCredentials(_) => {
+                record_login_failure(&pool, &login_protection, &username, &ip_address).await?;
+                Err(LoginError::AuthError(e.into()))
+            }
             AuthError::UnexpectedError(_) => Err(LoginError::UnexpectedError(e.into())),
         },
     }
 }
 
+#[tracing::instrument(
+    name = "Logging In With Second Factor",
+    skip(req, form, pool, session, login_protection)
+)]
+#[utoipa::path(post, path = "/login/2fa", request_body = TwoFactorLoginData, responses((status=200, description="Authentication successful"), (status=401, description="Authentication failed"), (status=429, description="Too many failed attempts")))]
+#[post("/login/2fa")]
+async fn log_in_two_factor(
+    req: HttpRequest,
+    form: web::Form<TwoFactorLoginData>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<SecretKey>,
+    expiry_time: web::Data<ExpiryTime>,
+    login_protection: web::Data<LoginProtectionSettings>,
+    session: TypedSession,
+) -> Result<HttpResponse, LoginError> {
+    let invalid = || LoginError::AuthError(anyhow::anyhow!("Invalid two-factor challenge or code"));
+    let ip_address = client_ip(&req, &login_protection);
+
+    let Some(username) = login_challenge_username(&form.0.challenge, &pool).await? else {
+        return Err(invalid());
+    };
+
+    if let Some(retry_after) = check_login_allowed(&pool, &username, &ip_address).await? {
+        return Err(LoginError::TooManyAttempts(retry_after));
+    }
+
+    let Some(profile_id) = complete_login_challenge(&form.0.challenge, &form.0.code, &pool).await?
+    else {
+        record_login_failure(&pool, &login_protection, &username, &ip_address).await?;
+        return Err(invalid());
+    };
+
+    tracing::Span::current().record("profile_id", tracing::field::display(&profile_id));
+
+    clear_login_failures(pool.get_ref(), &username)
+        .await
+        .context("Failed to clear failed login attempts")?;
+
+    start_authenticated_session(
+        profile_id,
+        &pool,
+        session,
+        expiry_time.into_inner().0,
+        &secret.into_inner().0,
+    )
+    .await
+}
+
 #[tracing::instrument(name = "Logging In", skip(flash_msgs))]
 #[utoipa::path(get, path = "/login", responses((status=200, description="Successful login"), (status=401, description="Authentication failed")))]
 #[get("/login")]
@@ -86,10 +251,11 @@ async fn log_in_check(flash_msgs: IncomingFlashMessages) -> HttpResponse {
     HttpResponse::Ok().json(StdResponse { message: &msg })
 }
 
-#[tracing::instrument(name = "Refresh Token", skip(req))]
+#[tracing::instrument(name = "Refresh Token", skip(req, pool))]
 #[utoipa::path(get, path = "/refresh-token", responses((status=200, description="Successful refresh"), (status=401, description="Refresh failed")))]
 pub async fn refresh_token(
     req: HttpRequest,
+    pool: web::Data<PgPool>,
     secret: web::Data<SecretKey>,
     expiry_time: web::Data<ExpiryTime>,
 ) -> Result<HttpResponse, LoginError> {
@@ -99,9 +265,24 @@ pub async fn refresh_token(
 
     match refresh_token {
         Some(_) => {
-            let profile_id = validate_refresh_token(&req, secret).map_err(LoginError::AuthError)?;
-            let access_token = create_token(profile_id, expiry_time.into_inner().0, secret)?;
-            let refresh_token = create_token(profile_id, 30 * 24 * 60, secret)?;
+            let claims = validate_refresh_token(&req, secret).map_err(LoginError::AuthError)?;
+            let profile_id = claims.sub;
+
+            if claims.act.is_some() {
+                return Err(LoginError::AuthError(anyhow::anyhow!(
+                    "Impersonation tokens cannot be refreshed"
+                )));
+            }
+
+            if get_token_version(profile_id, &pool).await? != Some(claims.ver) {
+                return Err(LoginError::AuthError(anyhow::anyhow!(
+                    "Refresh token has been revoked"
+                )));
+            }
+
+            let access_token =
+                create_token(profile_id, claims.ver, expiry_time.into_inner().0, secret)?;
+            let refresh_token = create_token(profile_id, claims.ver, 30 * 24 * 60, secret)?;
 
             Ok(HttpResponse::Ok()
                 .insert_header((header::AUTHORIZATION, format!("Bearer {}", access_token)))
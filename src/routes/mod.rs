@@ -1,10 +1,15 @@
 pub mod admin;
 pub mod docs;
+pub mod email_events;
 pub mod health_check;
 pub mod index;
 pub mod login;
+pub mod notifications;
+pub mod oidc;
+pub mod password_reset;
 pub mod profile;
 pub mod profile_confirm;
+pub mod profile_email;
 pub mod task;
 
 pub use health_check::*;
@@ -0,0 +1,197 @@
+use actix_web::{HttpResponse, web};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::domain::id::ProfileId;
+use crate::error::authentication::StdResponse;
+use crate::notification::{UnsubscribeLinks, unsubscribe};
+use crate::notification_channel::{InboxNotification, list_inbox, mark_inbox_notification};
+use crate::util::e500;
+
+const MAX_PER_PAGE: i64 = 100;
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+fn invalid_unsubscribe_link() -> HttpResponse {
+    HttpResponse::Unauthorized().json(StdResponse {
+        message: "Invalid unsubscribe link",
+    })
+}
+
+/// Where the link in the email leads. Only asks for confirmation, since mail scanners
+/// and link prefetchers follow links without the recipient knowing
+#[tracing::instrument(name = "Unsubscribe Form", skip(parameters, unsubscribe_links))]
+#[utoipa::path(get, path = "/notifications/unsubscribe", params(UnsubscribeParameters),
+responses((status=200, description="Unsubscribe confirmation page"), (status=401, description="Invalid unsubscribe link")))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> HttpResponse {
+    // Signed tokens are base64url with dots, safe to put in the page as they are
+    if unsubscribe_links.verify(&parameters.token).is_err() {
+        return invalid_unsubscribe_link();
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Unsubscribe</title></head>
+<body>
+<form action="/notifications/unsubscribe?token={}" method="post">
+<input type="hidden" name="List-Unsubscribe" value="One-Click">
+<button type="submit">Unsubscribe</button>
+</form>
+</body>
+</html>"#,
+            parameters.token
+        ))
+}
+
+/// The POST mail clients send for one-click unsubscribe (RFC 8058), and the one
+/// the confirmation page sends
+#[tracing::instrument(name = "Unsubscribe", skip(parameters, pool, unsubscribe_links))]
+#[utoipa::path(post, path = "/notifications/unsubscribe", params(UnsubscribeParameters),
+responses((status=200, description="Unsubscribed"), (status=401, description="Invalid unsubscribe link"), (status=500, description="Something went wrong on our end")))]
+pub async fn unsubscribe_from_notifications(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Ok((profile_id, events)) = unsubscribe_links.verify(&parameters.token) else {
+        return Ok(invalid_unsubscribe_link());
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let profile_exists = sqlx::query("SELECT id FROM profile WHERE id = $1 FOR UPDATE")
+        .bind(profile_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to retrieve the profile to unsubscribe")
+        .map_err(e500)?
+        .is_some();
+    if !profile_exists {
+        return Ok(invalid_unsubscribe_link());
+    }
+
+    for event_type in events {
+        unsubscribe(&mut transaction, profile_id, event_type)
+            .await
+            .context("Failed to store the unsubscribe rule")
+            .map_err(e500)?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "You have been unsubscribed",
+    }))
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct InboxSearch {
+    /// Only list notifications that were not read yet
+    #[serde(default)]
+    unread: bool,
+    /// `next_cursor` of the previous page
+    cursor: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Inbox {
+    notifications: Vec<InboxNotification>,
+    /// Set when there are older notifications to list
+    next_cursor: Option<Uuid>,
+}
+
+#[tracing::instrument(name = "List Notifications", skip(pool))]
+#[utoipa::path(get, path = "/notifications", params(InboxSearch),
+responses((status=200, body=Inbox, description="Page of in-app notifications, most recent first"), (status=401, description="Authentication failed")))]
+pub async fn list_notifications(
+    pool: web::Data<PgPool>,
+    search: web::Query<InboxSearch>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let limit = search.limit.unwrap_or(20).clamp(1, MAX_PER_PAGE);
+
+    // One more than asked tells whether there is a next page
+    let mut notifications =
+        list_inbox(&pool, profile_id.0, search.unread, search.cursor, limit + 1)
+            .await
+            .context("Failed to retrieve the inbox")
+            .map_err(e500)?;
+    let next_cursor = if notifications.len() as i64 > limit {
+        notifications.truncate(limit as usize);
+        notifications.last().map(InboxNotification::id)
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(Inbox {
+        notifications,
+        next_cursor,
+    }))
+}
+
+async fn mark_notification(
+    pool: &PgPool,
+    profile_id: ProfileId,
+    notification_id: Uuid,
+    read: bool,
+) -> Result<HttpResponse, actix_web::Error> {
+    let found = mark_inbox_notification(pool, profile_id.0, notification_id, read)
+        .await
+        .context("Failed to update the notification")
+        .map_err(e500)?;
+
+    if !found {
+        return Ok(HttpResponse::NotFound().json(StdResponse {
+            message: "No Notification Found",
+        }));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(name = "Mark Notification Read", skip(pool))]
+#[utoipa::path(post, path = "/notifications/{id}/read",
+params(("id" = Uuid, Path, description="Notification Id")),
+responses((status=204, description="Marked as read"), (status=401, description="Authentication failed"), (status=404, description="No Notification Found")))]
+pub async fn mark_notification_read(
+    pool: web::Data<PgPool>,
+    notification_id: web::Path<Uuid>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    mark_notification(&pool, *profile_id, notification_id.into_inner(), true).await
+}
+
+#[tracing::instrument(name = "Mark Notification Unread", skip(pool))]
+#[utoipa::path(delete, path = "/notifications/{id}/read",
+params(("id" = Uuid, Path, description="Notification Id")),
+responses((status=204, description="Marked as unread"), (status=401, description="Authentication failed"), (status=404, description="No Notification Found")))]
+pub async fn mark_notification_unread(
+    pool: web::Data<PgPool>,
+    notification_id: web::Path<Uuid>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    mark_notification(&pool, *profile_id, notification_id.into_inner(), false).await
+}
@@ -0,0 +1,148 @@
+use actix_web::{HttpResponse, get, http::header, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+use utoipa::IntoParams;
+
+use crate::configuration::PasswordHashingSettings;
+use crate::error::authentication::{LoginError, StdResponse};
+use crate::oidc::{
+    ExternalIdentity, LoginState, OidcClient, consume_login_state, resolve_external_identity,
+    store_login_state,
+};
+use crate::routes::login::complete_first_factor;
+use crate::session_state::TypedSession;
+use crate::startup::{ExpiryTime, SecretKey};
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct OidcCallback {
+    code: Option<String>,
+    state: String,
+    error: Option<String>,
+}
+
+fn unknown_provider() -> HttpResponse {
+    HttpResponse::NotFound().json(StdResponse {
+        message: "Unknown identity provider",
+    })
+}
+
+#[tracing::instrument(
+    name = "Starting OpenID Connect Login",
+    skip(pool, oidc_client, session)
+)]
+#[utoipa::path(get, path = "/login/oidc/{provider}",
+params(("provider" = String, Path, description="Identity provider name")),
+responses((status=303, description="Redirect to the identity provider"), (status=404, description="Unknown identity provider"), (status=500, description="Something went wrong on our end")))]
+#[get("/login/oidc/{provider}")]
+async fn oidc_login(
+    provider: web::Path<String>,
+    pool: web::Data<PgPool>,
+    oidc_client: web::Data<OidcClient>,
+    session: TypedSession,
+) -> Result<HttpResponse, LoginError> {
+    let Some(provider) = oidc_client.provider(&provider) else {
+        return Ok(unknown_provider());
+    };
+
+    let metadata = oidc_client.discover(provider).await?;
+    let login_state = LoginState::generate();
+
+    store_login_state(&pool, &provider.name, &login_state).await?;
+    session
+        .insert_oidc_state(&login_state.state)
+        .map_err(|e| LoginError::UnexpectedError(e.into()))?;
+
+    let authorization_url = oidc_client.authorization_url(provider, &metadata, &login_state)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, authorization_url.as_str()))
+        .finish())
+}
+
+#[tracing::instrument(
+    name = "Completing OpenID Connect Login",
+    skip(query, pool, oidc_client, session, password_hashing)
+)]
+#[utoipa::path(get, path = "/login/oidc/{provider}/callback",
+params(("provider" = String, Path, description="Identity provider name"), OidcCallback),
+responses((status=200, description="Authentication successful or two-factor challenge issued"), (status=401, description="Authentication failed"), (status=404, description="Unknown identity provider")))]
+#[get("/login/oidc/{provider}/callback")]
+#[allow(clippy::too_many_arguments)]
+async fn oidc_callback(
+    provider: web::Path<String>,
+    query: web::Query<OidcCallback>,
+    pool: web::Data<PgPool>,
+    oidc_client: web::Data<OidcClient>,
+    secret: web::Data<SecretKey>,
+    expiry_time: web::Data<ExpiryTime>,
+    password_hashing: web::Data<PasswordHashingSettings>,
+    session: TypedSession,
+) -> Result<HttpResponse, LoginError> {
+    let Some(provider) = oidc_client.provider(&provider) else {
+        return Ok(unknown_provider());
+    };
+    let query = query.into_inner();
+
+    // Otherwise anyone could log a victim into the attacker's account by
+    // getting them to open a callback the attacker started
+    let session_state = session
+        .take_oidc_state()
+        .map_err(|e| LoginError::UnexpectedError(e.into()))?;
+    if session_state.as_deref() != Some(query.state.as_str()) {
+        return Err(LoginError::AuthError(anyhow::anyhow!(
+            "Login state was not issued to this session"
+        )));
+    }
+
+    let (nonce, code_verifier) = consume_login_state(&pool, &provider.name, &query.state)
+        .await?
+        .ok_or_else(|| LoginError::AuthError(anyhow::anyhow!("Invalid or expired login state")))?;
+
+    if let Some(error) = query.error {
+        return Err(LoginError::AuthError(anyhow::anyhow!(
+            "Identity provider returned an error: {error}"
+        )));
+    }
+    let code = query
+        .code
+        .ok_or_else(|| LoginError::AuthError(anyhow::anyhow!("Missing authorization code")))?;
+
+    let metadata = oidc_client.discover(provider).await?;
+
+    let id_token = oidc_client
+        .exchange_code(provider, &metadata, &code, &code_verifier)
+        .await
+        .map_err(LoginError::AuthError)?;
+
+    let claims = oidc_client
+        .validate_id_token(provider, &metadata, &id_token, &nonce)
+        .await
+        .map_err(LoginError::AuthError)?;
+
+    let profile_id =
+        match resolve_external_identity(&pool, &provider.name, &claims, &password_hashing).await? {
+            ExternalIdentity::Resolved(profile_id) => profile_id,
+            ExternalIdentity::EmailNotVerified => {
+                return Err(LoginError::AuthError(anyhow::anyhow!(
+                    "Identity provider has not verified the email address"
+                )));
+            }
+            ExternalIdentity::ProfileUnavailable => {
+                return Err(LoginError::AuthError(anyhow::anyhow!(
+                    "Account suspended or deleted"
+                )));
+            }
+        };
+
+    tracing::Span::current().record("profile_id", tracing::field::display(&profile_id));
+
+    complete_first_factor(
+        profile_id,
+        &pool,
+        session,
+        expiry_time.into_inner().0,
+        &secret.into_inner().0,
+    )
+    .await
+}
@@ -0,0 +1,294 @@
+use actix_web::{HttpResponse, get, post, web};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, Row, Transaction};
+use tracing::Instrument;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::configuration::PasswordHashingSettings;
+use crate::domain::email::ProfileEmail;
+use crate::domain::password::Password;
+use crate::email_client::EmailClient;
+use crate::email_template::{EmailTemplate, render_email};
+use crate::error::authentication::StdResponse;
+use crate::startup::{ApplicationBaseUri, ResetTokenExpiryTime};
+use crate::util::e500;
+use crate::util::token_generator::{generate_profile_token, hash_token};
+
+#[derive(Deserialize, ToSchema)]
+pub struct ForgotPassword {
+    email: String,
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct PasswordResetLink {
+    reset_token: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PasswordReset {
+    reset_token: String,
+    new_password: String,
+    new_password_check: String,
+}
+
+/// Returns the profile id along with the locale its emails are written in
+#[tracing::instrument(name = "Get profile_id from email", skip(pool, email))]
+async fn get_profile_id_from_email(
+    pool: &PgPool,
+    email: &ProfileEmail,
+) -> Result<Option<(Uuid, String)>, sqlx::Error> {
+    let result = sqlx::query("SELECT id, locale FROM profile WHERE email = $1")
+        .bind(email.as_ref())
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(result.map(|r| (r.get("id"), r.get("locale"))))
+}
+
+#[tracing::instrument(
+    name = "Store password reset token in the database",
+    skip(pool, reset_token)
+)]
+pub(crate) async fn store_reset_token(
+    pool: &PgPool,
+    profile_id: Uuid,
+    reset_token: &str,
+    expiry_minutes: u64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO password_reset_tokens (token_hash, profile_id, expires_at)
+                VALUES ($1, $2, now() + make_interval(mins => $3))",
+    )
+    .bind(hash_token(reset_token))
+    .bind(profile_id)
+    .bind(expiry_minutes as i32)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Consume password reset token", skip(tx, reset_token))]
+async fn consume_reset_token(
+    tx: &mut Transaction<'_, Postgres>,
+    reset_token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query(
+        "SELECT profile_id FROM password_reset_tokens
+                WHERE token_hash = $1
+                AND used_at IS NULL
+                AND expires_at > now()
+                FOR UPDATE",
+    )
+    .bind(hash_token(reset_token))
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    let Some(profile_id) = result.map(|r| r.get::<Uuid, _>("profile_id")) else {
+        return Ok(None);
+    };
+
+    // Any other outstanding link for the profile is spent along with this one
+    sqlx::query(
+        "UPDATE password_reset_tokens SET used_at = now()
+                WHERE profile_id = $1
+                AND used_at IS NULL",
+    )
+    .bind(profile_id)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(Some(profile_id))
+}
+
+#[tracing::instrument(
+    name = "Sending a password reset email",
+    skip(email_client, email, reset_token)
+)]
+pub(crate) async fn send_password_reset_email(
+    email_client: &EmailClient,
+    email: &ProfileEmail,
+    locale: &str,
+    base_uri: &str,
+    reset_token: &str,
+) -> Result<(), anyhow::Error> {
+    let reset_link = format!("{}/password/reset?reset_token={}", base_uri, reset_token);
+    let message = render_email(
+        EmailTemplate::PasswordReset,
+        locale,
+        serde_json::json!({ "reset_link": reset_link }),
+    )?;
+
+    email_client
+        .send_email(email, &message.subject, &message.html, &message.text)
+        .await
+}
+
+/// Stores a reset token for the profile and emails it the link
+#[tracing::instrument(
+    name = "Issue password reset link",
+    skip(pool, email_client, email, base_uri)
+)]
+async fn issue_reset_link(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    profile_id: Uuid,
+    email: &ProfileEmail,
+    locale: &str,
+    base_uri: &str,
+    expiry_minutes: u64,
+) -> Result<(), anyhow::Error> {
+    let reset_token = generate_profile_token();
+
+    store_reset_token(pool, profile_id, &reset_token, expiry_minutes)
+        .await
+        .context("Failed to store the password reset token.")?;
+
+    send_password_reset_email(email_client, email, locale, base_uri, &reset_token).await
+}
+
+#[tracing::instrument(name = "Forgot Password", skip(form, pool, email_client))]
+#[utoipa::path(post, path = "/password/forgot", request_body = ForgotPassword, responses((status=200, description="Reset link sent if the account exists"), (status=500, description="Something went wrong on our end")))]
+#[post("/password/forgot")]
+pub async fn forgot_password(
+    form: web::Form<ForgotPassword>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_uri: web::Data<ApplicationBaseUri>,
+    reset_token_expiry: web::Data<ResetTokenExpiryTime>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // The same response is returned whether or not the account exists
+    let response = HttpResponse::Ok().json(StdResponse {
+        message: "If an account with that email exists, a password reset link has been sent.",
+    });
+
+    let Ok(email) = ProfileEmail::parse(form.0.email) else {
+        return Ok(response);
+    };
+
+    let Some((profile_id, locale)) = get_profile_id_from_email(&pool, &email)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(response);
+    };
+
+    // The link is sent after responding, so that the email provider's latency
+    // does not tell known accounts apart from unknown ones
+    let expiry_minutes = reset_token_expiry.0;
+    tokio::spawn(
+        async move {
+            if let Err(e) = issue_reset_link(
+                &pool,
+                &email_client,
+                profile_id,
+                &email,
+                &locale,
+                &base_uri.0,
+                expiry_minutes,
+            )
+            .await
+            {
+                tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to send password reset email");
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
+
+    Ok(response)
+}
+
+/// The page the emailed link opens. It only collects the new password; the token
+/// is checked and spent when the form is posted
+#[tracing::instrument(name = "Password Reset Form", skip(query))]
+#[utoipa::path(get, path = "/password/reset", params(PasswordResetLink), responses((status=200, description="Form to choose a new password"), (status=400, description="Malformed reset link")))]
+#[get("/password/reset")]
+pub async fn password_reset_form(query: web::Query<PasswordResetLink>) -> HttpResponse {
+    // Reset tokens are alphanumeric, which also makes them safe to put in the page
+    let reset_token = &query.reset_token;
+    if reset_token.is_empty() || !reset_token.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return HttpResponse::BadRequest().json(StdResponse {
+            message: "Malformed reset link",
+        });
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Reset your password</title></head>
+<body>
+<form action="/password/reset" method="post">
+<input type="hidden" name="reset_token" value="{reset_token}">
+<label>New password <input type="password" name="new_password" required></label>
+<label>Repeat new password <input type="password" name="new_password_check" required></label>
+<button type="submit">Reset password</button>
+</form>
+</body>
+</html>"#
+        ))
+}
+
+#[tracing::instrument(name = "Reset Password", skip(form, pool, password_hashing))]
+#[utoipa::path(post, path = "/password/reset", request_body = PasswordReset, responses((status=200, description="Reset successful"), (status=401, description="Invalid or expired reset token"), (status=422, description="Invalid new password"), (status=500, description="Something went wrong on our end")))]
+#[post("/password/reset")]
+pub async fn reset_password(
+    form: web::Form<PasswordReset>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashingSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if form.0.new_password != form.0.new_password_check {
+        return Ok(HttpResponse::UnprocessableEntity().json(StdResponse {
+            message: "New Passwords don't Match",
+        }));
+    }
+
+    let password = match Password::parse(form.0.new_password, &password_hashing) {
+        Ok(password) => password,
+        Err(e) => {
+            return Ok(HttpResponse::UnprocessableEntity().json(StdResponse {
+                message: &e.to_string(),
+            }));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let Some(profile_id) = consume_reset_token(&mut transaction, &form.0.reset_token)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::Unauthorized().json(StdResponse {
+            message: "Invalid or expired reset token",
+        }));
+    };
+
+    // Bumping the token version revokes every live session and refresh token
+    sqlx::query(
+        "UPDATE profile SET password = $1, token_version = token_version + 1 WHERE id = $2",
+    )
+    .bind(password.phash_as_ref())
+    .bind(profile_id)
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to reset user's password in the db")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset password")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "Password Reset Successful",
+    }))
+}
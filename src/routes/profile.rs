@@ -1,19 +1,33 @@
+use crate::authorization::{Role, has_role};
+use crate::configuration::{
+    DataPrivacySettings, PasswordHashingSettings, ProfileConfirmationSettings,
+};
+use crate::domain::email::ProfileEmail;
+use crate::domain::id::ProfileId;
+use crate::domain::locale::ProfileLocale;
 use crate::email_client::EmailClient;
+use crate::email_template::{EmailTemplate, render_email};
+use crate::error::authentication::StdResponse;
 use crate::error::profile::ProfileError;
 use crate::error::store_token::StoreTokenError;
+use crate::idempotency::idempotent_requests;
 use crate::model::profile::{
     Profile, ProfileCreateRequest, ProfileIdentifier, ProfileResponse, ProfileUpdate,
 };
 use crate::repository::pgdb;
+use crate::routes::admin::privacy::{DeletionScheduled, schedule_profile_deletion};
 use crate::startup::ApplicationBaseUri;
+use crate::util::e500;
 use crate::util::token_generator::generate_profile_token;
+use actix_web::middleware::from_fn;
 use actix_web::{
-    HttpResponse, delete, get, post, put,
-    web::{Data, Json, Path},
+    HttpResponse, get, post, put,
+    web::{Data, Json, Path, ReqData},
 };
 
 use anyhow::Context;
 use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
 #[utoipa::path(get, path = "/profiles/{id:[0-9a-fA-F-]{36}}",
 params(("id" = String, Path, description="Profile Id")),
@@ -32,33 +46,27 @@ pub async fn get_profile(
 
 #[tracing::instrument(
     name = "Sending a confirmation email to a new profile",
-    skip(email_client, profile)
+    skip(email_client, email)
 )]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
-    profile: Profile,
+    email: &ProfileEmail,
+    locale: &str,
     base_uri: &str,
     profile_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/profile/confirm?profile_token={}",
         base_uri, profile_token
     );
+    let message = render_email(
+        EmailTemplate::Confirmation,
+        locale,
+        serde_json::json!({ "confirmation_link": confirmation_link }),
+    )?;
 
     email_client
-        .send_email(
-            &profile.email,
-            "Welcome",
-            &format!(
-                "Welcome to Taskx!<br/>\
-            Click <a href=\"{}\">here</a> to confirm your account.",
-                confirmation_link
-            ),
-            &format!(
-                "Welcome to Taskx!\nVisit {} to confirm your account",
-                confirmation_link,
-            ),
-        )
+        .send_email(email, &message.subject, &message.html, &message.text)
         .await
         .map_err(|e| {
             tracing::error!("Failed to send confirmation email {e:?}",);
@@ -71,36 +79,42 @@ pub async fn send_confirmation_email(
 #[tracing::instrument(name = "Store profile token in the database", skip(profile_token, tx))]
 pub async fn store_token(
     tx: &mut Transaction<'_, Postgres>,
-    profile: &Profile,
+    profile_id: Uuid,
     profile_token: &str,
+    expiry_minutes: u64,
 ) -> Result<(), StoreTokenError> {
-    sqlx::query("INSERT INTO profile_tokens(profile_token, profile_id) VALUES($1, $2)")
-        .bind(profile_token)
-        .bind(profile.id)
-        .execute(&mut **tx)
-        .await
-        .map_err(StoreTokenError)?;
+    sqlx::query(
+        "INSERT INTO profile_tokens(profile_token, profile_id, expires_at)
+                VALUES($1, $2, now() + make_interval(mins => $3))",
+    )
+    .bind(profile_token)
+    .bind(profile_id)
+    .bind(expiry_minutes as i32)
+    .execute(&mut **tx)
+    .await
+    .map_err(StoreTokenError)?;
 
     Ok(())
 }
 
 #[tracing::instrument(name = "Registering a new profile", 
-skip(pool, request, email_client),
+skip(pool, request, email_client, password_hashing),
 fields(profile_fname=%request.first_name, profile_email=%request.email, profile_username=%request.username)
 )]
 #[utoipa::path(post, path = "/profile",
+params(("Idempotency-Key" = Option<String>, Header, description="Replays the first response when the request is retried")),
 responses((status=200, body=Profile, description="User creation successful"), (status=404, description="User creation unsuccessful"),))]
-#[post("/profile")]
+#[post("/profile", wrap = "from_fn(idempotent_requests)")]
 pub async fn create_profile(
     pool: Data<PgPool>,
     request: Json<ProfileCreateRequest>,
     email_client: Data<EmailClient>,
     base_uri: Data<ApplicationBaseUri>,
+    confirmation: Data<ProfileConfirmationSettings>,
+    password_hashing: Data<PasswordHashingSettings>,
 ) -> Result<HttpResponse, ProfileError> {
-    let profile: Profile = request
-        .into_inner()
-        .try_into()
-        .map_err(|e: anyhow::Error| ProfileError::ValidationError(e.to_string()))?;
+    let profile = Profile::parse(request.into_inner(), &password_hashing)
+        .map_err(|e| ProfileError::ValidationError(e.to_string()))?;
 
     // Check if the profile already exists
     let r = pgdb::db_get_profile(&pool, &profile.id).await;
@@ -120,18 +134,29 @@ pub async fn create_profile(
 
     let profile_token = generate_profile_token();
 
-    store_token(&mut transaction, &profile, &profile_token)
-        .await
-        .context("Failed to store the confirmation token for a new profile.")?;
+    store_token(
+        &mut transaction,
+        profile.id,
+        &profile_token,
+        confirmation.token_expire_minutes,
+    )
+    .await
+    .context("Failed to store the confirmation token for a new profile.")?;
 
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store new profile")?;
 
-    send_confirmation_email(&email_client, profile, &base_uri.0, &profile_token)
-        .await
-        .context("Failed to send a confirmation email.")?;
+    send_confirmation_email(
+        &email_client,
+        &profile.email,
+        profile.locale.as_ref(),
+        &base_uri.0,
+        &profile_token,
+    )
+    .await
+    .context("Failed to send a confirmation email.")?;
 
     Ok(HttpResponse::Ok().body(profile_token))
 }
@@ -143,10 +168,16 @@ pub async fn update_profile(
     pool: Data<PgPool>,
     request: Json<ProfileUpdate>,
 ) -> Result<Json<ProfileUpdate>, ProfileError> {
+    if let Some(locale) = &request.locale {
+        ProfileLocale::parse(locale.clone())
+            .map_err(|e| ProfileError::ValidationError(e.to_string()))?;
+    }
+
     let p_update = ProfileUpdate::new(
         &request.id,
         request.first_name.as_deref(),
         request.last_name.as_deref(),
+        request.locale.as_deref(),
     );
     pgdb::db_update_profile(pool.get_ref(), &p_update)
         .await
@@ -155,17 +186,37 @@ pub async fn update_profile(
     Ok(Json(p_update))
 }
 
+#[tracing::instrument(name = "Delete Profile", skip(pool, data_privacy))]
 #[utoipa::path(delete, path = "/profile/{id}",
 params(("id" = String, Path, description="Profile Id")),
-responses((status=200, description="User deletion successful"), (status=404, description="User deletion unsuccessful"),))]
-#[delete("/profile/{id}")]
+responses((status=202, body=DeletionScheduled, description="Deletion scheduled"), (status=401, description="Authentication failed"), (status=403, description="Not the profile's owner or an admin"), (status=404, description="No Profile Found"),))]
 pub async fn delete_profile(
     pool: Data<PgPool>,
     profile_identifier: Path<ProfileIdentifier>,
-) -> Result<HttpResponse, ProfileError> {
-    pgdb::delete_profile(pool.get_ref(), &profile_identifier.into_inner().id)
+    data_privacy: Data<DataPrivacySettings>,
+    caller: ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let profile_id = profile_identifier.into_inner().id;
+
+    let allowed =
+        caller.0 == profile_id || has_role(&pool, caller.0, Role::Admin).await.map_err(e500)?;
+    if !allowed {
+        return Ok(HttpResponse::Forbidden().json(StdResponse {
+            message: "You are not allowed to perform this action",
+        }));
+    }
+
+    let exists = sqlx::query("SELECT id FROM profile WHERE id = $1 AND status <> 'deleted'")
+        .bind(profile_id)
+        .fetch_optional(pool.get_ref())
         .await
-        .context("Failed to delete profile")?;
+        .context("Failed to retrieve the profile")
+        .map_err(e500)?
+        .is_some();
+    if !exists {
+        return Ok(HttpResponse::NotFound().body("No Profile Found"));
+    }
 
-    Ok(HttpResponse::Ok().body("Deletion successful"))
+    // Goes through the same grace period as a deletion the owner asks for
+    schedule_profile_deletion(&pool, &data_privacy, caller.0, profile_id).await
 }
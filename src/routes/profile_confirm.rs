@@ -1,68 +1,269 @@
 use actix_web::{
     HttpResponse, get,
-    web::{Data, Query},
+    http::header,
+    post,
+    web::{Data, Form, Query},
 };
 use anyhow::Context;
 use serde::Deserialize;
-use sqlx::{PgPool, Row};
+use sqlx::{PgPool, Postgres, Row, Transaction};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::configuration::ProfileConfirmationSettings;
+use crate::domain::email::ProfileEmail;
+use crate::email_client::EmailClient;
+use crate::error::authentication::StdResponse;
 use crate::error::profile::ProfileError;
+use crate::routes::profile::{send_confirmation_email, store_token};
+use crate::startup::ApplicationBaseUri;
+use crate::util::token_generator::generate_profile_token;
 
 #[derive(Deserialize, Debug)]
 pub struct Parameters {
     profile_token: String,
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct ResendConfirmation {
+    email: String,
+}
+
+pub enum TokenStatus {
+    Valid(Uuid),
+    Used,
+    Expired,
+}
+
 #[tracing::instrument(name = "Confirm a pending profile" skip(parameters, pool))]
-#[utoipa::path(get, path = "/profile/confirm", params(("profile_token" = String, Query, description="Profile Token")),)]
+#[utoipa::path(get, path = "/profile/confirm", params(("profile_token" = String, Query, description="Profile Token")),
+responses((status=200, description="Profile confirmed"), (status=401, description="Invalid confirmation token"), (status=409, description="Confirmation token has already been used or the profile is not pending confirmation"), (status=410, description="Confirmation token has expired")))]
 #[get("/profile/confirm")]
 pub async fn confirm_profile(
     parameters: Query<Parameters>,
     pool: Data<PgPool>,
 ) -> Result<HttpResponse, ProfileError> {
-    let id = get_profile_id_from_token(&pool, &parameters.profile_token)
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let status = get_token_status(&mut transaction, &parameters.profile_token)
         .await
         .context("Associated profile id not found")?;
 
-    match id {
-        None => Ok(HttpResponse::Unauthorized().finish()),
-        Some(profile_id) => {
-            confirm_subscriber(&pool, profile_id)
+    match status {
+        None => Ok(HttpResponse::Unauthorized().json(StdResponse {
+            message: "Invalid confirmation token",
+        })),
+        Some(TokenStatus::Used) => Ok(HttpResponse::Conflict().json(StdResponse {
+            message: "Confirmation token has already been used",
+        })),
+        Some(TokenStatus::Expired) => Ok(HttpResponse::Gone().json(StdResponse {
+            message: "Confirmation token has expired",
+        })),
+        Some(TokenStatus::Valid(profile_id)) => {
+            mark_token_used(&mut transaction, &parameters.profile_token)
                 .await
-                .context("failed to confirm new profile")?;
-            Ok(HttpResponse::Ok().finish())
+                .context("failed to mark the confirmation token as used")?;
+            // Suspended profiles stay suspended, whatever tokens they still hold
+            if !confirm_subscriber(&mut transaction, profile_id)
+                .await
+                .context("failed to confirm new profile")?
+            {
+                return Ok(HttpResponse::Conflict().json(StdResponse {
+                    message: "Profile is not pending confirmation",
+                }));
+            }
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to confirm profile")?;
+
+            Ok(HttpResponse::Ok().json(StdResponse {
+                message: "Profile confirmed",
+            }))
         }
     }
 }
 
-#[tracing::instrument(name = "Mark Profile as Confirmed", skip(profile_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, profile_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query("UPDATE profile SET status = $1 WHERE id= $2")
-        .bind("confirmed")
-        .bind(profile_id)
-        .execute(pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to execute query: {e:?}");
-            e
-        })?;
+#[tracing::instrument(name = "Mark Profile as Confirmed", skip(profile_id, tx))]
+pub async fn confirm_subscriber(
+    tx: &mut Transaction<'_, Postgres>,
+    profile_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE profile SET status = $1 WHERE id= $2 AND status = 'pending_confirmation'",
+    )
+    .bind("confirmed")
+    .bind(profile_id)
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {e:?}");
+        e
+    })?;
 
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
-#[tracing::instrument(name = "Get profile_id from token", skip(pool, profile_token))]
-pub async fn get_profile_id_from_token(
-    pool: &PgPool,
+#[tracing::instrument(name = "Get status of profile token", skip(tx, profile_token))]
+pub async fn get_token_status(
+    tx: &mut Transaction<'_, Postgres>,
     profile_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query("SELECT profile_id FROM profile_tokens WHERE profile_token= $1")
+) -> Result<Option<TokenStatus>, sqlx::Error> {
+    let result = sqlx::query(
+        "SELECT profile_id, used_at IS NOT NULL AS is_used, expires_at <= now() AS is_expired
+                FROM profile_tokens
+                WHERE profile_token= $1
+                FOR UPDATE",
+    )
+    .bind(profile_token)
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {e:?}");
+        e
+    })?;
+
+    Ok(result.map(|r| {
+        if r.get::<bool, _>("is_used") {
+            TokenStatus::Used
+        } else if r.get::<bool, _>("is_expired") {
+            TokenStatus::Expired
+        } else {
+            TokenStatus::Valid(r.get::<Uuid, _>("profile_id"))
+        }
+    }))
+}
+
+async fn mark_token_used(
+    tx: &mut Transaction<'_, Postgres>,
+    profile_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE profile_tokens SET used_at = now() WHERE profile_token = $1")
         .bind(profile_token)
-        .fetch_optional(pool)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Counts a resend against the address; returns the seconds until the window
+/// resets if the address has used up its allowance
+#[tracing::instrument(name = "Check confirmation resend limit", skip(pool, settings))]
+async fn check_resend_limit(
+    pool: &PgPool,
+    email: &str,
+    settings: &ProfileConfirmationSettings,
+) -> Result<Option<u64>, sqlx::Error> {
+    let row = sqlx::query(
+        "INSERT INTO confirmation_resends (email, sent_count, window_started_at)
+                VALUES ($1, 1, now())
+                ON CONFLICT (email) DO UPDATE SET
+                    sent_count = CASE
+                        WHEN confirmation_resends.window_started_at <= now() - make_interval(mins => $2)
+                        THEN 1
+                        ELSE confirmation_resends.sent_count + 1
+                    END,
+                    window_started_at = CASE
+                        WHEN confirmation_resends.window_started_at <= now() - make_interval(mins => $2)
+                        THEN now()
+                        ELSE confirmation_resends.window_started_at
+                    END
+                RETURNING sent_count,
+                    CEIL(EXTRACT(EPOCH FROM window_started_at + make_interval(mins => $2) - now()))::BIGINT AS retry_after",
+    )
+    .bind(email)
+    .bind(settings.resend_window_minutes as i32)
+    .fetch_one(pool)
+    .await?;
+
+    let over_limit = row.get::<i32, _>("sent_count") as u32 > settings.resend_limit;
+
+    Ok(over_limit.then(|| row.get::<i64, _>("retry_after").max(1) as u64))
+}
+
+#[tracing::instrument(name = "Get pending profile by email", skip(pool, email))]
+async fn get_pending_profile_id(
+    pool: &PgPool,
+    email: &ProfileEmail,
+) -> Result<Option<(Uuid, String)>, sqlx::Error> {
+    let result = sqlx::query(
+        "SELECT id, locale FROM profile WHERE email = $1 AND status = 'pending_confirmation'",
+    )
+    .bind(email.as_ref())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(result.map(|r| (r.get("id"), r.get("locale"))))
+}
+
+#[tracing::instrument(
+    name = "Resend profile confirmation",
+    skip(form, pool, email_client, confirmation)
+)]
+#[utoipa::path(post, path = "/profile/confirm/resend", request_body = ResendConfirmation,
+responses((status=200, description="Confirmation link sent if a pending profile exists"), (status=429, description="Too many confirmation emails requested for this address"), (status=500, description="Something went wrong on our end")))]
+#[post("/profile/confirm/resend")]
+pub async fn resend_confirmation(
+    form: Form<ResendConfirmation>,
+    pool: Data<PgPool>,
+    email_client: Data<EmailClient>,
+    base_uri: Data<ApplicationBaseUri>,
+    confirmation: Data<ProfileConfirmationSettings>,
+) -> Result<HttpResponse, ProfileError> {
+    // The same response is returned whether or not a pending profile exists
+    let response = HttpResponse::Ok().json(StdResponse {
+        message: "If a pending profile with that email exists, a new confirmation link has been sent.",
+    });
+
+    let Ok(email) = ProfileEmail::parse(form.0.email) else {
+        return Ok(response);
+    };
+
+    if let Some(retry_after) = check_resend_limit(&pool, email.as_ref(), &confirmation)
         .await
-        .map_err(|e| {
-            tracing::error!("Failed to execute query: {e:?}");
-            e
-        })?;
-    Ok(result.map(|r| r.get::<Uuid, _>("profile_id")))
+        .context("Failed to check the confirmation resend limit")?
+    {
+        return Ok(HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+            .json(StdResponse {
+                message: "Too many confirmation emails requested. Try again later",
+            }));
+    }
+
+    let Some((profile_id, locale)) = get_pending_profile_id(&pool, &email)
+        .await
+        .context("Failed to retrieve the pending profile")?
+    else {
+        return Ok(response);
+    };
+
+    let profile_token = generate_profile_token();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    store_token(
+        &mut transaction,
+        profile_id,
+        &profile_token,
+        confirmation.token_expire_minutes,
+    )
+    .await
+    .context("Failed to store the confirmation token.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store confirmation token")?;
+
+    send_confirmation_email(&email_client, &email, &locale, &base_uri.0, &profile_token)
+        .await
+        .context("Failed to send a confirmation email.")?;
+
+    Ok(response)
 }
@@ -0,0 +1,474 @@
+use actix_web::{HttpResponse, get, web};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, Row, Transaction};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::audit::{AuditAction, AuditEvent, record_audit_event};
+use crate::configuration::EmailChangeSettings;
+use crate::domain::email::ProfileEmail;
+use crate::domain::id::ProfileId;
+use crate::email_client::EmailClient;
+use crate::email_template::{EmailTemplate, render_email};
+use crate::error::authentication::StdResponse;
+use crate::repository::pgdb::get_profile_locale;
+use crate::startup::ApplicationBaseUri;
+use crate::util::e500;
+use crate::util::token_generator::{generate_profile_token, hash_token};
+
+#[derive(Deserialize, ToSchema)]
+pub struct EmailChange {
+    new_email: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EmailChangeParameters {
+    token: String,
+}
+
+struct EmailChangeRequest {
+    id: Uuid,
+    profile_id: Uuid,
+    old_email: String,
+    new_email: String,
+}
+
+fn email_in_use() -> HttpResponse {
+    HttpResponse::Conflict().json(StdResponse {
+        message: "Email already in use",
+    })
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .is_some_and(|e| e.is_unique_violation())
+}
+
+#[tracing::instrument(name = "Get profile email", skip(pool))]
+async fn get_profile_email(pool: &PgPool, profile_id: Uuid) -> Result<String, sqlx::Error> {
+    let row = sqlx::query("SELECT email FROM profile WHERE id = $1")
+        .bind(profile_id)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.get("email"))
+}
+
+#[tracing::instrument(name = "Check if email is in use", skip(pool, email))]
+async fn email_exists(pool: &PgPool, email: &ProfileEmail) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query("SELECT 1 FROM profile WHERE email = $1")
+        .bind(email.as_ref())
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(
+    name = "Store email change request",
+    skip(tx, confirm_token, revert_token, settings)
+)]
+async fn store_email_change_request(
+    tx: &mut Transaction<'_, Postgres>,
+    profile_id: Uuid,
+    old_email: &str,
+    new_email: &ProfileEmail,
+    confirm_token: &str,
+    revert_token: &str,
+    settings: &EmailChangeSettings,
+) -> Result<(), sqlx::Error> {
+    // A new request supersedes any that is still waiting for confirmation
+    sqlx::query(
+        "DELETE FROM email_change_requests
+                WHERE profile_id = $1
+                AND confirmed_at IS NULL
+                AND reverted_at IS NULL",
+    )
+    .bind(profile_id)
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO email_change_requests
+                (id, profile_id, old_email, new_email, confirm_token_hash, revert_token_hash, expires_at, revert_expires_at)
+                VALUES ($1, $2, $3, $4, $5, $6, now() + make_interval(mins => $7), now() + make_interval(mins => $8))",
+    )
+    .bind(Uuid::new_v4())
+    .bind(profile_id)
+    .bind(old_email)
+    .bind(new_email.as_ref())
+    .bind(hash_token(confirm_token))
+    .bind(hash_token(revert_token))
+    .bind(settings.confirm_expire_minutes as i32)
+    .bind(settings.revert_expire_minutes as i32)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Pending deliveries follow the profile to its new address
+#[tracing::instrument(name = "Move pending deliveries", skip(tx))]
+async fn move_pending_deliveries(
+    tx: &mut Transaction<'_, Postgres>,
+    from_email: &str,
+    to_email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE issue_delivery_queue SET profile_email = $2
+                WHERE profile_email = $1
+                AND (task_issue_id, channel, event_type) NOT IN (
+                    SELECT task_issue_id, channel, event_type
+                    FROM issue_delivery_queue WHERE profile_email = $2
+                )",
+    )
+    .bind(from_email)
+    .bind(to_email)
+    .execute(&mut **tx)
+    .await?;
+
+    // Whatever is left was already queued for the new address
+    sqlx::query("DELETE FROM issue_delivery_queue WHERE profile_email = $1")
+        .bind(from_email)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Sending email change messages",
+    skip(email_client, confirm_token, revert_token)
+)]
+async fn send_email_change_messages(
+    email_client: &EmailClient,
+    old_email: &ProfileEmail,
+    new_email: &ProfileEmail,
+    locale: &str,
+    base_uri: &str,
+    confirm_token: &str,
+    revert_token: &str,
+) -> Result<(), anyhow::Error> {
+    let confirm_link = format!("{}/profile/email/confirm?token={}", base_uri, confirm_token);
+    let revert_link = format!("{}/profile/email/revert?token={}", base_uri, revert_token);
+
+    let confirmation = render_email(
+        EmailTemplate::EmailChangeConfirm,
+        locale,
+        serde_json::json!({ "confirm_link": confirm_link }),
+    )?;
+    email_client
+        .send_email(
+            new_email,
+            &confirmation.subject,
+            &confirmation.html,
+            &confirmation.text,
+        )
+        .await?;
+
+    let notice = render_email(
+        EmailTemplate::EmailChangeNotice,
+        locale,
+        serde_json::json!({ "new_email": new_email.as_ref(), "revert_link": revert_link }),
+    )?;
+    email_client
+        .send_email(old_email, &notice.subject, &notice.html, &notice.text)
+        .await
+}
+
+#[tracing::instrument(
+    name = "Request email change",
+    skip(form, pool, email_client, settings)
+)]
+#[utoipa::path(post, path = "/profile/email", request_body = EmailChange,
+responses((status=200, description="Confirmation link sent to the new address"), (status=401, description="Authentication failed"), (status=409, description="Email already in use"), (status=422, description="Invalid new email"), (status=500, description="Something went wrong on our end")))]
+pub async fn request_email_change(
+    form: web::Form<EmailChange>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_uri: web::Data<ApplicationBaseUri>,
+    settings: web::Data<EmailChangeSettings>,
+    profile_id: web::ReqData<ProfileId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let profile_id = profile_id.into_inner().0;
+
+    let new_email = match ProfileEmail::parse(form.0.new_email) {
+        Ok(email) => email,
+        Err(e) => {
+            return Ok(HttpResponse::UnprocessableEntity().json(StdResponse {
+                message: &e.to_string(),
+            }));
+        }
+    };
+
+    let old_email = get_profile_email(&pool, profile_id)
+        .await
+        .context("Failed to retrieve the current email")
+        .map_err(e500)?;
+
+    if old_email == new_email.as_ref() {
+        return Ok(HttpResponse::UnprocessableEntity().json(StdResponse {
+            message: "New email is the same as the current one",
+        }));
+    }
+
+    if email_exists(&pool, &new_email).await.map_err(e500)? {
+        return Ok(email_in_use());
+    }
+
+    let confirm_token = generate_profile_token();
+    let revert_token = generate_profile_token();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    store_email_change_request(
+        &mut transaction,
+        profile_id,
+        &old_email,
+        &new_email,
+        &confirm_token,
+        &revert_token,
+        &settings,
+    )
+    .await
+    .context("Failed to store the email change request")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store email change request")
+        .map_err(e500)?;
+
+    let old_email = ProfileEmail::parse(old_email).map_err(e500)?;
+
+    let locale = get_profile_locale(profile_id, &pool)
+        .await
+        .map_err(e500)?
+        .unwrap_or_default();
+
+    send_email_change_messages(
+        &email_client,
+        &old_email,
+        &new_email,
+        &locale,
+        &base_uri.0,
+        &confirm_token,
+        &revert_token,
+    )
+    .await
+    .context("Failed to send the email change messages")
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "A confirmation link has been sent to the new email address",
+    }))
+}
+
+#[tracing::instrument(name = "Confirm email change", skip(parameters, pool))]
+#[utoipa::path(get, path = "/profile/email/confirm", params(("token" = String, Query, description="Email change confirmation token")),
+responses((status=200, description="Email address changed"), (status=401, description="Invalid or expired confirmation token"), (status=409, description="Email already in use"), (status=500, description="Something went wrong on our end")))]
+#[get("/profile/email/confirm")]
+pub async fn confirm_email_change(
+    parameters: web::Query<EmailChangeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let Some(request) = sqlx::query(
+        "SELECT id, profile_id, old_email, new_email FROM email_change_requests
+                WHERE confirm_token_hash = $1
+                AND confirmed_at IS NULL
+                AND reverted_at IS NULL
+                AND expires_at > now()
+                FOR UPDATE",
+    )
+    .bind(hash_token(&parameters.token))
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to perform query to retrieve the email change request")
+    .map_err(e500)?
+    .map(|r| EmailChangeRequest {
+        id: r.get("id"),
+        profile_id: r.get("profile_id"),
+        old_email: r.get("old_email"),
+        new_email: r.get("new_email"),
+    }) else {
+        return Ok(HttpResponse::Unauthorized().json(StdResponse {
+            message: "Invalid or expired confirmation token",
+        }));
+    };
+
+    // The UNIQUE constraint settles a race with another profile claiming the address
+    let n_updated = match sqlx::query("UPDATE profile SET email = $1 WHERE id = $2 AND email = $3")
+        .bind(&request.new_email)
+        .bind(request.profile_id)
+        .bind(&request.old_email)
+        .execute(&mut *transaction)
+        .await
+    {
+        Ok(result) => result.rows_affected(),
+        Err(e) if is_unique_violation(&e) => return Ok(email_in_use()),
+        Err(e) => return Err(e500(e)),
+    };
+
+    if n_updated == 0 {
+        return Ok(HttpResponse::Unauthorized().json(StdResponse {
+            message: "Invalid or expired confirmation token",
+        }));
+    }
+
+    move_pending_deliveries(&mut transaction, &request.old_email, &request.new_email)
+        .await
+        .context("Failed to move pending deliveries to the new email")
+        .map_err(e500)?;
+
+    sqlx::query("UPDATE email_change_requests SET confirmed_at = now() WHERE id = $1")
+        .bind(request.id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(e500)?;
+
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent {
+            actor_id: Some(request.profile_id),
+            profile_id: Some(request.profile_id),
+            action: AuditAction::EmailChanged,
+            details: serde_json::json!({
+                "old_email": request.old_email,
+                "new_email": request.new_email,
+            }),
+        },
+    )
+    .await
+    .context("Failed to record the email change in the audit log")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change email")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "Email address changed",
+    }))
+}
+
+#[tracing::instrument(name = "Revert email change", skip(parameters, pool))]
+#[utoipa::path(get, path = "/profile/email/revert", params(("token" = String, Query, description="Email change revert token")),
+responses((status=200, description="Email change reverted"), (status=401, description="Invalid or expired revert token"), (status=409, description="Email already in use"), (status=500, description="Something went wrong on our end")))]
+#[get("/profile/email/revert")]
+pub async fn revert_email_change(
+    parameters: web::Query<EmailChangeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let Some((request, is_confirmed)) = sqlx::query(
+        "SELECT id, profile_id, old_email, new_email, confirmed_at IS NOT NULL AS is_confirmed
+                FROM email_change_requests
+                WHERE revert_token_hash = $1
+                AND reverted_at IS NULL
+                AND revert_expires_at > now()
+                FOR UPDATE",
+    )
+    .bind(hash_token(&parameters.token))
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to perform query to retrieve the email change request")
+    .map_err(e500)?
+    .map(|r| {
+        (
+            EmailChangeRequest {
+                id: r.get("id"),
+                profile_id: r.get("profile_id"),
+                old_email: r.get("old_email"),
+                new_email: r.get("new_email"),
+            },
+            r.get::<bool, _>("is_confirmed"),
+        )
+    }) else {
+        return Ok(HttpResponse::Unauthorized().json(StdResponse {
+            message: "Invalid or expired revert token",
+        }));
+    };
+
+    // An unconfirmed request is simply cancelled. A confirmed one may have been made by
+    // someone else holding the account, so their sessions are revoked along with it.
+    if is_confirmed {
+        let n_updated = match sqlx::query(
+            "UPDATE profile SET email = $1, token_version = token_version + 1
+                    WHERE id = $2 AND email = $3",
+        )
+        .bind(&request.old_email)
+        .bind(request.profile_id)
+        .bind(&request.new_email)
+        .execute(&mut *transaction)
+        .await
+        {
+            Ok(result) => result.rows_affected(),
+            Err(e) if is_unique_violation(&e) => return Ok(email_in_use()),
+            Err(e) => return Err(e500(e)),
+        };
+
+        // The address has changed again since, so this change is no longer the one to undo
+        if n_updated == 0 {
+            return Ok(HttpResponse::Unauthorized().json(StdResponse {
+                message: "Invalid or expired revert token",
+            }));
+        }
+
+        move_pending_deliveries(&mut transaction, &request.new_email, &request.old_email)
+            .await
+            .context("Failed to move pending deliveries back to the old email")
+            .map_err(e500)?;
+    }
+
+    sqlx::query("UPDATE email_change_requests SET reverted_at = now() WHERE id = $1")
+        .bind(request.id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(e500)?;
+
+    record_audit_event(
+        &mut *transaction,
+        AuditEvent {
+            actor_id: None,
+            profile_id: Some(request.profile_id),
+            action: AuditAction::EmailChangeReverted,
+            details: serde_json::json!({
+                "old_email": request.old_email,
+                "new_email": request.new_email,
+                "was_confirmed": is_confirmed,
+            }),
+        },
+    )
+    .await
+    .context("Failed to record the email change revert in the audit log")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to revert email change")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "Email change reverted",
+    }))
+}
@@ -1,11 +1,14 @@
 use crate::domain::id::ProfileId;
 use crate::error::authentication::StdResponse;
 use crate::error::task::TaskError;
-use crate::idempotency::{IdempotencyKey, NextAction, save_response, try_idem_processing};
+use crate::idempotency::idempotent_requests;
+use crate::metrics::metrics;
 use crate::model::task::Task;
 use crate::model::task::{TaskState, TaskUpdate};
+use crate::notification::NotificationEvent;
 use crate::repository::pgdb;
-use crate::util::{e400, e500};
+use crate::util::e500;
+use actix_web::middleware::from_fn;
 use actix_web::{
     HttpResponse, get, put,
     web::{Data, Json, Path, ReqData},
@@ -31,7 +34,8 @@ pub struct TaskCompletionRequest {
 pub struct TaskCreateRequest {
     task_type: String,
     source_file: String,
-    idempotency_key: String,
+    #[serde(default)]
+    workspace: Option<String>,
 }
 
 async fn state_transition(
@@ -46,18 +50,54 @@ async fn state_transition(
 
     task.can_transition_to(&new_state)?;
 
+    // Only finished tasks are worth a notification
+    let event = match new_state {
+        TaskState::Completed => Some(NotificationEvent::TaskCompleted),
+        TaskState::Failed => Some(NotificationEvent::TaskFailed),
+        _ => None,
+    };
+    let is_completion = new_state == TaskState::Completed;
     let task_update = TaskUpdate::new(task_id, None, None, Some(new_state), None, result_file);
 
-    pgdb::db_update_task(pool.get_ref(), task_update)
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    pgdb::db_update_task(&mut transaction, task_update)
         .await
         .context("Failed to update task")?;
 
+    if let Some(event) = event {
+        pgdb::enqueue_delivery_tasks(&mut transaction, &task, event)
+            .await
+            .context("Failed to enqueue delivery tasks")?;
+    }
+
+    // Tasks completed without ever being started have no duration to report
+    let run_time = if is_completion {
+        pgdb::db_task_run_time(&mut transaction, task_id)
+            .await
+            .context("Failed to read how long the task ran")?
+    } else {
+        None
+    };
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update task")?;
+
+    if let Some(run_time) = run_time {
+        metrics().task_duration.observe(run_time);
+    }
+
     Ok(TaskIdentifier { task_id })
 }
-#[utoipa::path(get, path = "/task/{task_global_id}",
-params(("task_gloabal_id"= String, Path, description="Global Id")),
+#[utoipa::path(get, path = "/task/{task_id}",
+params(("task_id"= String, Path, description="Global Id")),
 responses((status=200, body=Task, description="Task by ID"), (status=404, description="Task not found"),))]
-#[get("/task/{task_global_id}")]
+#[get("/task/{task_id}")]
 pub async fn get_task(
     pool: Data<PgPool>,
     task_identifier: Path<TaskIdentifier>,
@@ -77,6 +117,7 @@ username=tracing::field::Empty, profile_id=tracing::field::Empty)
 #[utoipa::path(
     post,
     path="/admin/task",
+    params(("Idempotency-Key" = Option<String>, Header, description="Replays the first response when the request is retried")),
     request_body=TaskCreateRequest,
     responses((status=201, description="Task created successfuly"))
 )]
@@ -90,54 +131,51 @@ pub async fn create_task(
     let TaskCreateRequest {
         task_type,
         source_file,
-        idempotency_key,
+        workspace,
     } = task_request.0;
-    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
-
-    let cookiex = FlashMessage::success("The task has been created and sent out");
 
-    let mut transaction = match try_idem_processing(&pool, &idempotency_key, profile_id)
+    let mut transaction = pool
+        .begin()
         .await
-        .map_err(e500)?
-    {
-        NextAction::StartProcessing(tx) => tx,
-        NextAction::ReturnSavedResponse(sr) => {
-            cookiex.send();
-            return Ok(sr);
-        }
-    };
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
 
-    let task = Task::new(profile_id, task_type.clone(), source_file.clone());
+    let task = Task::new(
+        profile_id,
+        task_type.clone(),
+        source_file.clone(),
+        workspace,
+    );
 
     pgdb::db_create_task(&mut transaction, &task)
         .await
         .context("Failed to create new task")
         .map_err(e500)?;
 
-    pgdb::enqueue_delivery_tasks(&mut transaction, &task)
+    pgdb::enqueue_delivery_tasks(&mut transaction, &task, NotificationEvent::TaskCreated)
         .await
         .context("Failed to enqueue delivery tasks")
         .map_err(e500)?;
 
-    let response = HttpResponse::Ok().json(StdResponse {
-        message: "Task successfully created",
-    });
-
-    let response = save_response(transaction, &idempotency_key, profile_id, response)
+    transaction
+        .commit()
         .await
+        .context("Failed to commit SQL transaction to create a task")
         .map_err(e500)?;
 
-    cookiex.send();
+    FlashMessage::success("The task has been created and sent out").send();
 
-    Ok(response)
+    Ok(HttpResponse::Ok().json(StdResponse {
+        message: "Task successfully created",
+    }))
 }
 
-#[utoipa::path(put, path="/task/{task_global_id}",
-params(("task_global_id" = String, Path, description="Global Id")),
+#[utoipa::path(put, path="/task/{task_id}",
+params(("task_id" = String, Path, description="Global Id"), ("Idempotency-Key" = Option<String>, Header, description="Replays the first response when the request is retried")),
 request_body = TaskIdentifier,
 responses((status=200, description="Task start successful"), 
             (status=424, description="Task start unsuccessful")))]
-#[put("/task/{task_global_id}/start")]
+#[put("/task/{task_id}/start", wrap = "from_fn(idempotent_requests)")]
 pub async fn start_task(
     pool: Data<PgPool>,
     task_identifier: Path<TaskIdentifier>,
@@ -153,11 +191,11 @@ pub async fn start_task(
     Ok(HttpResponse::Ok().body("Successful"))
 }
 
-#[utoipa::path(put, path="/task/{task_global_id}",
-params(("task_global_id" = String, Path, description="Global Id")),
+#[utoipa::path(put, path="/task/{task_id}",
+params(("task_id" = String, Path, description="Global Id"), ("Idempotency-Key" = Option<String>, Header, description="Replays the first response when the request is retried")),
 request_body= TaskIdentifier,
 responses((status=200, description="Task pause successful"), (status=424, description="Task pause unsuccessful")))]
-#[put("/task/{task_global_id}/pause")]
+#[put("/task/{task_id}/pause", wrap = "from_fn(idempotent_requests)")]
 pub async fn pause_task(
     pool: Data<PgPool>,
     task_identifier: Path<TaskIdentifier>,
@@ -172,11 +210,11 @@ pub async fn pause_task(
 
     Ok(HttpResponse::Ok().body("Successful"))
 }
-#[utoipa::path(put, path="/task/{task_global_id}/complete",
-params(("task_global_id" = String, Path, description="Global Id")),
+#[utoipa::path(put, path="/task/{task_id}/complete",
+params(("task_id" = String, Path, description="Global Id"), ("Idempotency-Key" = Option<String>, Header, description="Replays the first response when the request is retried")),
 request_body=TaskCompletionRequest,
 responses((status=200, description="Task completion successful"), (status=424, description="Task completion unsuccessful")))]
-#[put("/task/{task_global_id}/complete")]
+#[put("/task/{task_id}/complete", wrap = "from_fn(idempotent_requests)")]
 pub async fn complete_task(
     pool: Data<PgPool>,
     task_identifier: Path<TaskIdentifier>,
@@ -193,10 +231,10 @@ pub async fn complete_task(
     Ok(HttpResponse::Ok().body("Successful"))
 }
 
-#[utoipa::path(put, path="/task/{task_global_id}/fail",
-params(("task_global_id"=String, Path, description="Global Id")),
+#[utoipa::path(put, path="/task/{task_id}/fail",
+params(("task_id"=String, Path, description="Global Id"), ("Idempotency-Key" = Option<String>, Header, description="Replays the first response when the request is retried")),
 responses((status=200, description="Task fail successful"), (status=424, description="Task fail unsuccessful")))]
-#[put("/task/{task_global_id}/fail")]
+#[put("/task/{task_id}/fail", wrap = "from_fn(idempotent_requests)")]
 pub async fn fail_task(
     pool: Data<PgPool>,
     task_identifier: Path<TaskIdentifier>,
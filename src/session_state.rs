@@ -8,6 +8,8 @@ pub struct TypedSession(Session);
 
 impl TypedSession {
     const PROFILE_ID_KEY: &'static str = "profile_id";
+    const TOKEN_VERSION_KEY: &'static str = "token_version";
+    const OIDC_STATE_KEY: &'static str = "oidc_state";
 
     pub fn renew(&self) {
         self.0.renew();
@@ -21,6 +23,26 @@ impl TypedSession {
         self.0.get(Self::PROFILE_ID_KEY)
     }
 
+    pub fn insert_token_version(&self, token_version: i32) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::TOKEN_VERSION_KEY, token_version)
+    }
+
+    pub fn get_token_version(&self) -> Result<Option<i32>, SessionGetError> {
+        self.0.get(Self::TOKEN_VERSION_KEY)
+    }
+
+    /// Ties an OpenID Connect login to the browser that started it
+    pub fn insert_oidc_state(&self, state: &str) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::OIDC_STATE_KEY, state)
+    }
+
+    /// Returns the state of the login in progress, which can only be used once
+    pub fn take_oidc_state(&self) -> Result<Option<String>, SessionGetError> {
+        let state = self.0.get(Self::OIDC_STATE_KEY)?;
+        self.0.remove(Self::OIDC_STATE_KEY);
+        Ok(state)
+    }
+
     pub fn log_out(self) {
         self.0.purge();
     }
@@ -1,13 +1,50 @@
 use crate::authentication::reject_anonymous_users;
+use crate::authorization::require_admin_role;
 use crate::configuration::{DatabaseSettings, Settings};
 use crate::email_client::EmailClient;
+use crate::health::ShutdownState;
+use crate::idempotency::idempotent_requests;
+use crate::metrics::record_http_metrics;
+use crate::oidc::OidcClient;
 use crate::routes;
 use crate::routes::admin::dashboard::admin_dashboard;
+use crate::routes::admin::dead_letters::{
+    list_dead_letters, purge_dead_letter, purge_dead_letters, requeue_dead_letter,
+};
+use crate::routes::admin::deliveries::get_task_deliveries;
+use crate::routes::admin::email_templates::preview_email_template;
+use crate::routes::admin::metrics::{
+    idempotency_metrics, password_hash_metrics, prometheus_metrics,
+};
+use crate::routes::admin::notifications::{
+    get_channels, get_preferences, update_channels, update_preferences,
+};
 use crate::routes::admin::password::{change_password, logout};
-use crate::routes::health_check::health_check;
-use crate::routes::login::{log_in, log_in_check, refresh_token};
+use crate::routes::admin::privacy::{
+    cancel_profile_deletion, download_data_export, get_data_export, request_data_export,
+    request_profile_deletion,
+};
+use crate::routes::admin::profiles::unlock_profile;
+use crate::routes::admin::suppressions::{clear_suppression, list_suppressions};
+use crate::routes::admin::two_factor::{enroll_two_factor, verify_two_factor};
+use crate::routes::admin::users::{
+    force_password_reset, get_user, impersonate_user, list_users, reactivate_user,
+    resend_user_confirmation, suspend_user,
+};
+use crate::routes::email_events::receive_email_events;
+use crate::routes::health_check::{health_check, health_live, health_ready};
+use crate::routes::login::{log_in, log_in_check, log_in_two_factor, refresh_token};
+use crate::routes::notifications::{
+    list_notifications, mark_notification_read, mark_notification_unread, unsubscribe_form,
+    unsubscribe_from_notifications,
+};
+use crate::routes::oidc::{oidc_callback, oidc_login};
+use crate::routes::password_reset::{forgot_password, password_reset_form, reset_password};
 use crate::routes::profile::{create_profile, delete_profile, get_profile, update_profile};
-use crate::routes::profile_confirm::confirm_profile;
+use crate::routes::profile_confirm::{confirm_profile, resend_confirmation};
+use crate::routes::profile_email::{
+    confirm_email_change, request_email_change, revert_email_change,
+};
 use crate::routes::task::{
     complete_task, create_task, fail_task, get_task, pause_task, start_task,
 };
@@ -22,6 +59,8 @@ use actix_web_flash_messages::{FlashMessagesFramework, storage::CookieMessageSto
 use sqlx::PgPool;
 use sqlx::postgres::PgPoolOptions;
 use std::net::TcpListener;
+use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
 use tracing_actix_web::TracingLogger;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
@@ -35,14 +74,15 @@ pub struct SecretKey(pub String);
 #[derive(Debug)]
 pub struct ExpiryTime(pub u64);
 
+#[derive(Debug)]
+pub struct ResetTokenExpiryTime(pub u64);
+
 async fn run(
     listener: TcpListener,
     pg_pool: PgPool,
     email_client: EmailClient,
-    base_uri: &str,
-    secret: &str,
-    redis_uri: &str,
-    expiry_time: u64,
+    configuration: &Settings,
+    shutdown: ShutdownState,
 ) -> Result<Server, anyhow::Error> {
     unsafe {
         // std::env::set_var("RUST_LOG", "trace");
@@ -50,13 +90,42 @@ async fn run(
     }
     let pg_pool = Data::new(pg_pool);
     let email_client = Data::new(email_client);
-    let base_uri = Data::new(ApplicationBaseUri(base_uri.to_string()));
+    let base_uri = Data::new(ApplicationBaseUri(
+        configuration.application.app_uri.clone(),
+    ));
+    let secret = &configuration.application.secret_key;
     let secret_key = Key::from(secret.as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
-    let redis_store = RedisSessionStore::new(redis_uri).await?;
+    let redis_store = RedisSessionStore::new(&configuration.redis_uri).await?;
+    // A connection of its own, for readiness checks
+    let redis = Data::new(
+        redis::Client::open(configuration.redis_uri.as_str())?
+            .get_connection_manager()
+            .await?,
+    );
+    let shutdown = Data::new(shutdown);
     let secret = Data::new(SecretKey(secret.to_string()));
-    let expiry = Data::new(ExpiryTime(expiry_time));
+    let expiry = Data::new(ExpiryTime(
+        configuration.application.access_token_expire_minutes,
+    ));
+    let reset_token_expiry = Data::new(ResetTokenExpiryTime(
+        configuration.application.password_reset_expire_minutes,
+    ));
+    let login_protection = Data::new(configuration.login_protection.clone());
+    let password_hashing = Data::new(configuration.password_hashing.clone());
+    let profile_confirmation = Data::new(configuration.profile_confirmation.clone());
+    let email_change = Data::new(configuration.email_change.clone());
+    let data_privacy = Data::new(configuration.data_privacy.clone());
+    let delivery = Data::new(configuration.delivery.clone());
+    let idempotency = Data::new(configuration.idempotency.clone());
+    let unsubscribe_links = Data::new(configuration.application.unsubscribe_links());
+    let webhook_signatures = Data::new(configuration.email_client.webhook_signatures());
+    let oidc_client = Data::new(OidcClient::new(
+        &configuration.oidc_providers,
+        &configuration.application.app_uri,
+        configuration.email_client.timeout(),
+    ));
 
     let server = HttpServer::new(move || {
         // let pgdb_repo = PGDBRepository::init();
@@ -70,36 +139,177 @@ async fn run(
                 secret_key.clone(),
             ))
             .wrap(logger)
+            .wrap(from_fn(record_http_metrics))
             .app_data(pg_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_uri.clone())
             .app_data(secret.clone())
             .app_data(expiry.clone())
+            .app_data(reset_token_expiry.clone())
+            .app_data(login_protection.clone())
+            .app_data(password_hashing.clone())
+            .app_data(oidc_client.clone())
+            .app_data(profile_confirmation.clone())
+            .app_data(email_change.clone())
+            .app_data(data_privacy.clone())
+            .app_data(delivery.clone())
+            .app_data(idempotency.clone())
+            .app_data(unsubscribe_links.clone())
+            .app_data(webhook_signatures.clone())
+            .app_data(redis.clone())
+            .app_data(shutdown.clone())
             .route("/", web::get().to(routes::index::index_page))
             .service(SwaggerUi::new("/docs/{_:.*}").url("/api-docs/openapi.json", openapi.clone()))
             .service(health_check)
+            .service(health_live)
+            .service(health_ready)
             .service(get_task)
             .service(pause_task)
             .service(complete_task)
             .service(start_task)
             .service(fail_task)
             .service(create_profile)
-            .service(delete_profile)
+            .service(
+                web::resource("/profile/{id:[0-9a-fA-F-]{36}}")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route(web::delete().to(delete_profile)),
+            )
             .service(confirm_profile)
+            .service(resend_confirmation)
+            .service(
+                web::resource("/profile/email")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route(web::post().to(request_email_change)),
+            )
+            .service(confirm_email_change)
+            .service(revert_email_change)
             .service(get_profile)
             .service(update_profile)
             .service(log_in)
             .service(log_in_check)
+            .service(log_in_two_factor)
+            .service(oidc_login)
+            .service(oidc_callback)
+            .service(
+                web::resource("/notifications/unsubscribe")
+                    .route(web::get().to(unsubscribe_form))
+                    .route(web::post().to(unsubscribe_from_notifications)),
+            )
+            .service(
+                web::scope("/notifications")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("", web::get().to(list_notifications))
+                    .route("/{id}/read", web::post().to(mark_notification_read))
+                    .route("/{id}/read", web::delete().to(mark_notification_unread)),
+            )
+            .route("/email/events", web::post().to(receive_email_events))
+            .service(forgot_password)
+            .service(password_reset_form)
+            .service(reset_password)
             .service(
                 web::scope("/admin")
                     .wrap(from_fn(reject_anonymous_users))
                     .route("/dashboard", web::get().to(admin_dashboard))
                     .route("/password", web::post().to(change_password))
                     .route("/logout", web::post().to(logout))
-                    .route("/task", web::post().to(create_task))
-                    .route("/refresh-token", web::get().to(refresh_token)),
+                    .service(
+                        web::resource("/task")
+                            .wrap(from_fn(idempotent_requests))
+                            .route(web::post().to(create_task)),
+                    )
+                    .service(
+                        web::resource("/task/{id}/deliveries")
+                            .wrap(from_fn(require_admin_role))
+                            .route(web::get().to(get_task_deliveries)),
+                    )
+                    .route("/refresh-token", web::get().to(refresh_token))
+                    .route("/2fa/enroll", web::post().to(enroll_two_factor))
+                    .route("/2fa/verify", web::post().to(verify_two_factor))
+                    .service(
+                        web::resource("/profiles/{id}/unlock")
+                            .wrap(from_fn(require_admin_role))
+                            .route(web::post().to(unlock_profile)),
+                    )
+                    .service(
+                        web::resource("/email-templates/{name}/preview")
+                            .wrap(from_fn(require_admin_role))
+                            .route(web::get().to(preview_email_template)),
+                    )
+                    .service(
+                        web::scope("/users")
+                            .wrap(from_fn(require_admin_role))
+                            .route("", web::get().to(list_users))
+                            .route("/{id}", web::get().to(get_user))
+                            .route("/{id}/suspend", web::post().to(suspend_user))
+                            .route("/{id}/reactivate", web::post().to(reactivate_user))
+                            .route("/{id}/password-reset", web::post().to(force_password_reset))
+                            .route(
+                                "/{id}/resend-confirmation",
+                                web::post().to(resend_user_confirmation),
+                            )
+                            .route("/{id}/impersonate", web::post().to(impersonate_user)),
+                    )
+                    .service(
+                        web::scope("/dead-letters")
+                            .wrap(from_fn(require_admin_role))
+                            .route("", web::get().to(list_dead_letters))
+                            .route("", web::delete().to(purge_dead_letters))
+                            .route("/{id}", web::delete().to(purge_dead_letter))
+                            .route("/{id}/requeue", web::post().to(requeue_dead_letter)),
+                    )
+                    .service(
+                        web::scope("/suppressions")
+                            .wrap(from_fn(require_admin_role))
+                            .route("", web::get().to(list_suppressions))
+                            .route("/{profile_id}", web::delete().to(clear_suppression)),
+                    )
+                    .route("/notifications/preferences", web::get().to(get_preferences))
+                    .route(
+                        "/notifications/preferences",
+                        web::put().to(update_preferences),
+                    )
+                    .route("/notifications/channels", web::get().to(get_channels))
+                    .route("/notifications/channels", web::put().to(update_channels))
+                    .route("/data/export", web::post().to(request_data_export))
+                    .route("/data/export/{id}", web::get().to(get_data_export))
+                    .route(
+                        "/data/export/{id}/download",
+                        web::get().to(download_data_export),
+                    )
+                    .route("/data/deletion", web::post().to(request_profile_deletion))
+                    .route("/data/deletion", web::delete().to(cancel_profile_deletion))
+                    .service(
+                        web::resource("/metrics/password-hashes")
+                            .wrap(from_fn(require_admin_role))
+                            .route(web::get().to(password_hash_metrics)),
+                    )
+                    .service(
+                        web::resource("/metrics/idempotency")
+                            .wrap(from_fn(require_admin_role))
+                            .route(web::get().to(idempotency_metrics)),
+                    ),
             )
     })
+    // Shutdown is driven by `Application`, which drains readiness first
+    .disable_signals()
+    .listen(listener)?
+    .run();
+
+    Ok(server)
+}
+
+/// Serves `/metrics` apart from the API, with no session or login in the way
+fn run_metrics_server(listener: TcpListener, pg_pool: PgPool) -> Result<Server, anyhow::Error> {
+    let pg_pool = Data::new(pg_pool);
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(TracingLogger::default())
+            .app_data(pg_pool.clone())
+            .route("/metrics", web::get().to(prometheus_metrics))
+    })
+    .workers(1)
+    .disable_signals()
     .listen(listener)?
     .run();
 
@@ -110,9 +320,23 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new().connect_lazy_with(configuration.with_db())
 }
 
+/// Resolves on SIGTERM or Ctrl-C
+async fn termination_signal() -> Result<(), std::io::Error> {
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        outcome = tokio::signal::ctrl_c() => outcome,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
 pub struct Application {
     port: u16,
+    metrics_port: u16,
     server: Server,
+    metrics_server: Server,
+    shutdown: ShutdownState,
+    shutdown_drain: Duration,
 }
 
 impl Application {
@@ -127,25 +351,65 @@ impl Application {
         );
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
+        let metrics_listener = TcpListener::bind(format!(
+            "{}:{}",
+            configuration.application.host, configuration.metrics.port
+        ))?;
+        let metrics_port = metrics_listener.local_addr().unwrap().port();
+        // Sharing the pool lets the metrics report on the connections the API uses
+        let metrics_server = run_metrics_server(metrics_listener, pool.clone())?;
+        let shutdown = ShutdownState::default();
         let server = run(
             listener,
             pool,
             email_client,
-            &configuration.application.app_uri,
-            &configuration.application.secret_key,
-            &configuration.redis_uri,
-            configuration.application.access_token_expire_minutes,
+            configuration,
+            shutdown.clone(),
         )
         .await?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            metrics_port,
+            server,
+            metrics_server,
+            shutdown,
+            shutdown_drain: configuration.health.shutdown_drain(),
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    pub fn metrics_port(&self) -> u16 {
+        self.metrics_port
+    }
+
+    pub fn shutdown_state(&self) -> ShutdownState {
+        self.shutdown.clone()
+    }
+
+    /// Runs until SIGTERM or Ctrl-C. Readiness fails for the drain period
+    /// before the servers stop, letting requests in flight finish
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let handles = [self.server.handle(), self.metrics_server.handle()];
+        let shutdown = self.shutdown;
+        let shutdown_drain = self.shutdown_drain;
+        tokio::spawn(async move {
+            if let Err(e) = termination_signal().await {
+                tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to listen for termination signals");
+                return;
+            }
+            tracing::info!("Shutting down after a {:?} drain", shutdown_drain);
+            shutdown.begin();
+            tokio::time::sleep(shutdown_drain).await;
+            for handle in handles {
+                handle.stop(true).await;
+            }
+        });
+
+        tokio::try_join!(self.server, self.metrics_server)?;
+        Ok(())
     }
 }
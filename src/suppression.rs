@@ -0,0 +1,228 @@
+use anyhow::{Context, anyhow};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use sqlx::{Postgres, Transaction};
+use strum_macros::Display;
+use uuid::Uuid;
+
+use crate::notification_channel::NotificationChannelKind;
+
+/// Signed webhook requests older than this are rejected, so a captured call
+/// cannot be replayed later
+const MAX_WEBHOOK_AGE_SECONDS: i64 = 300;
+
+/// Why an address no longer receives notifications
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+pub enum SuppressionReason {
+    HardBounce,
+    Complaint,
+}
+
+/// One event reported by the email provider. Anything other than a bounce or
+/// a spam complaint is accepted and ignored
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProviderEvent {
+    Bounce {
+        email: String,
+        #[serde(default)]
+        hard_bounce: bool,
+        #[serde(default)]
+        error: Option<String>,
+    },
+    Spam {
+        email: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+impl ProviderEvent {
+    /// The address to suppress, if the event calls for it
+    pub fn suppression(&self) -> Option<(&str, SuppressionReason)> {
+        match self {
+            ProviderEvent::Bounce {
+                email,
+                hard_bounce: true,
+                ..
+            } => Some((email, SuppressionReason::HardBounce)),
+            ProviderEvent::Spam { email } => Some((email, SuppressionReason::Complaint)),
+            _ => None,
+        }
+    }
+}
+
+/// Providers post either a single event or a batch of them
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ProviderEvents {
+    Batch(Vec<ProviderEvent>),
+    Single(ProviderEvent),
+}
+
+impl ProviderEvents {
+    pub fn into_vec(self) -> Vec<ProviderEvent> {
+        match self {
+            ProviderEvents::Batch(events) => events,
+            ProviderEvents::Single(event) => vec![event],
+        }
+    }
+}
+
+/// Checks the `X-Webhook-Signature` of provider callbacks: a hex HMAC-SHA256
+/// of `{timestamp}.{body}` keyed with `EMAIL_WEBHOOK_SECRET`
+#[derive(Clone, Debug)]
+pub struct WebhookSignatures {
+    secret: Option<String>,
+}
+
+impl WebhookSignatures {
+    pub fn new(secret: Option<String>) -> Self {
+        Self { secret }
+    }
+
+    fn mac(&self, timestamp: i64, body: &[u8]) -> Result<Hmac<Sha256>, anyhow::Error> {
+        let secret = self
+            .secret
+            .as_deref()
+            .filter(|secret| !secret.is_empty())
+            .context("No webhook secret is configured")?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+        mac.update(format!("{timestamp}.").as_bytes());
+        mac.update(body);
+        Ok(mac)
+    }
+
+    pub fn sign(&self, timestamp: i64, body: &[u8]) -> Result<String, anyhow::Error> {
+        Ok(hex::encode(
+            self.mac(timestamp, body)?.finalize().into_bytes(),
+        ))
+    }
+
+    pub fn verify(
+        &self,
+        timestamp: i64,
+        body: &[u8],
+        signature: &str,
+        now: i64,
+    ) -> Result<(), anyhow::Error> {
+        if now.abs_diff(timestamp) > MAX_WEBHOOK_AGE_SECONDS as u64 {
+            return Err(anyhow!("The webhook timestamp is too old"));
+        }
+        let signature = hex::decode(signature).context("The webhook signature is not hex")?;
+
+        self.mac(timestamp, body)?
+            .verify_slice(&signature)
+            .context("The webhook signature does not match")
+    }
+}
+
+/// Stops emails to an address and drops the ones still queued or held for a digest. The
+/// profile keeps getting its webhook and in-app notifications.
+/// Returns the profile using the address, if any
+#[tracing::instrument(name = "Suppress address", skip(tx))]
+pub async fn suppress_address(
+    tx: &mut Transaction<'_, Postgres>,
+    email: &str,
+    reason: SuppressionReason,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let profile_id = sqlx::query_scalar::<_, Uuid>(
+        "UPDATE profile SET undeliverable_reason = $2, undeliverable_at = now()
+                WHERE email = $1
+                AND undeliverable_reason IS DISTINCT FROM $2
+                RETURNING id",
+    )
+    .bind(email)
+    .bind(reason.to_string())
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    sqlx::query("DELETE FROM issue_delivery_queue WHERE profile_email = $1 AND channel = $2")
+        .bind(email)
+        .bind(NotificationChannelKind::Email.to_string())
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query(
+        "DELETE FROM pending_notifications
+                WHERE profile_id IN (SELECT id FROM profile WHERE email = $1)",
+    )
+    .bind(email)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(profile_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProviderEvent, ProviderEvents, SuppressionReason, WebhookSignatures};
+    use claims::{assert_err, assert_ok};
+
+    const NOW: i64 = 1_760_000_000;
+
+    #[test]
+    fn signed_requests_are_accepted() {
+        let signatures = WebhookSignatures::new(Some("secret".to_string()));
+        let signature = signatures.sign(NOW, b"[]").unwrap();
+
+        assert_ok!(signatures.verify(NOW, b"[]", &signature, NOW + 10));
+    }
+
+    #[test]
+    fn tampered_stale_or_unconfigured_requests_are_rejected() {
+        let signatures = WebhookSignatures::new(Some("secret".to_string()));
+        let signature = signatures.sign(NOW, b"[]").unwrap();
+
+        assert_err!(signatures.verify(NOW, b"[{}]", &signature, NOW));
+        assert_err!(signatures.verify(NOW, b"[]", &signature, NOW + 301));
+        assert_err!(signatures.verify(i64::MIN, b"[]", &signature, NOW));
+        assert_err!(
+            WebhookSignatures::new(Some("other".to_string())).verify(NOW, b"[]", &signature, NOW)
+        );
+        assert_err!(WebhookSignatures::new(None).verify(NOW, b"[]", &signature, NOW));
+    }
+
+    #[test]
+    fn only_hard_bounces_and_complaints_suppress_addresses() {
+        let events: ProviderEvents = serde_json::from_value(serde_json::json!([
+            { "event": "bounce", "email": "a@example.com", "hard_bounce": true },
+            { "event": "bounce", "email": "b@example.com", "hard_bounce": false },
+            { "event": "spam", "email": "c@example.com" },
+            { "event": "open", "email": "d@example.com" },
+        ]))
+        .unwrap();
+
+        let suppressions: Vec<_> = events
+            .into_vec()
+            .iter()
+            .map(|event| event.suppression().map(|(e, r)| (e.to_string(), r)))
+            .collect();
+
+        assert_eq!(
+            suppressions,
+            vec![
+                Some(("a@example.com".to_string(), SuppressionReason::HardBounce)),
+                None,
+                Some(("c@example.com".to_string(), SuppressionReason::Complaint)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn a_single_event_is_accepted() {
+        let events: ProviderEvents = serde_json::from_value(
+            serde_json::json!({ "event": "spam", "email": "a@example.com" }),
+        )
+        .unwrap();
+
+        assert_eq!(
+            events.into_vec(),
+            vec![ProviderEvent::Spam {
+                email: "a@example.com".to_string()
+            }]
+        );
+    }
+}
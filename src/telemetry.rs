@@ -1,14 +1,44 @@
+use std::collections::HashMap;
+
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{TraceContextExt, TracerProvider};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
 use tokio::task::JoinHandle;
-use tracing::{Subscriber, subscriber::set_global_default};
+use tracing::{Span, Subscriber, subscriber::set_global_default};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::{EnvFilter, Registry, layer::SubscriberExt};
 
+/// W3C trace-context header, also the key it is stored under in a carrier
+const TRACEPARENT: &str = "traceparent";
+
+/// Batches spans and sends them to an OTLP/HTTP collector at `endpoint`.
+/// The provider has to be shut down on exit to flush the last batch
+pub fn otlp_tracer_provider(
+    service_name: String,
+    endpoint: &str,
+) -> Result<SdkTracerProvider, anyhow::Error> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name).build())
+        .build())
+}
+
 pub fn get_tracing_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer_provider: Option<&SdkTracerProvider>,
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
@@ -16,10 +46,14 @@ where
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
 
+    let otlp_layer = tracer_provider
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(name.clone())));
+
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
 
     Registry::default()
         .with(env_filter)
+        .with(otlp_layer)
         .with(JsonStorageLayer)
         .with(formatting_layer)
 }
@@ -27,9 +61,32 @@ where
 pub fn init_tracing_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
 
+    // `TracingLogger` picks incoming `traceparent` headers up through it
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
     set_global_default(subscriber).expect("Failed to se subscriber");
 }
 
+/// The `traceparent` of the current span, or `None` when spans aren't exported
+pub fn current_traceparent() -> Option<String> {
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&Span::current().context(), &mut carrier);
+
+    carrier.remove(TRACEPARENT)
+}
+
+/// Links the current span to the span a `traceparent` was taken from, so that
+/// work picked up later in the background can be traced back to its request
+pub fn link_to_traceparent(traceparent: &str) {
+    let carrier = HashMap::from([(TRACEPARENT.to_string(), traceparent.to_string())]);
+    let context = TraceContextPropagator::new().extract(&carrier);
+    let span_context = context.span().span_context().clone();
+
+    if span_context.is_valid() {
+        Span::current().add_link(span_context);
+    }
+}
+
 pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
 where
     F: FnOnce() -> R + Send + 'static,
@@ -39,3 +96,83 @@ where
 
     tokio::task::spawn_blocking(move || current_span.in_scope(f))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{current_traceparent, get_tracing_subscriber, link_to_traceparent};
+    use crate::telemetry::otlp_tracer_provider;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn no_traceparent_without_an_exporter() {
+        let subscriber = get_tracing_subscriber("test".into(), "info".into(), std::io::sink, None);
+
+        let traceparent = tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("create_task").in_scope(current_traceparent)
+        });
+
+        assert_eq!(traceparent, None);
+    }
+
+    #[test]
+    fn background_spans_link_back_to_the_traceparent() {
+        // Arrange
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber =
+            get_tracing_subscriber("test".into(), "info".into(), std::io::sink, Some(&provider));
+
+        // Act
+        tracing::subscriber::with_default(subscriber, || {
+            let traceparent = tracing::info_span!("create_task")
+                .in_scope(current_traceparent)
+                .unwrap();
+            tracing::info_span!("execute_delivery").in_scope(|| link_to_traceparent(&traceparent));
+        });
+
+        // Assert
+        let spans = exporter.get_finished_spans().unwrap();
+        let request = spans.iter().find(|s| s.name == "create_task").unwrap();
+        let delivery = spans.iter().find(|s| s.name == "execute_delivery").unwrap();
+        assert_ne!(
+            delivery.span_context.trace_id(),
+            request.span_context.trace_id()
+        );
+        assert_eq!(delivery.links.len(), 1);
+        let link = &delivery.links.links[0].span_context;
+        assert_eq!(link.trace_id(), request.span_context.trace_id());
+        assert_eq!(link.span_id(), request.span_context.span_id());
+    }
+
+    #[actix_web::test]
+    async fn spans_are_exported_to_the_otlp_collector() {
+        // Arrange
+        let collector = MockServer::start().await;
+        Mock::given(path("/v1/traces"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1..)
+            .mount(&collector)
+            .await;
+        let provider = otlp_tracer_provider("test".into(), &collector.uri()).unwrap();
+        let subscriber =
+            get_tracing_subscriber("test".into(), "info".into(), std::io::sink, Some(&provider));
+
+        // Act
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("validate_credentials").in_scope(|| {});
+        });
+        // Flushing waits on the exporter thread, which must not block the test's runtime
+        tokio::task::spawn_blocking(move || provider.shutdown())
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Assert
+        // Mock expectations are checked on drop
+    }
+}
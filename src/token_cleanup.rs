@@ -0,0 +1,56 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::configuration::{ProfileConfirmationSettings, Settings};
+use crate::health::heartbeat;
+use crate::startup::get_connection_pool;
+
+/// Deletes confirmation tokens past their retention and resend counters whose window has closed
+#[tracing::instrument(name = "Expire profile tokens", skip(pool, settings))]
+pub async fn try_expire_profile_tokens(
+    pool: &PgPool,
+    settings: &ProfileConfirmationSettings,
+) -> Result<u64, anyhow::Error> {
+    let n_tokens = sqlx::query(
+        "DELETE FROM profile_tokens WHERE expires_at <= now() - make_interval(mins => $1)",
+    )
+    .bind(settings.token_retention_minutes as i32)
+    .execute(pool)
+    .await
+    .context("Failed to delete expired profile tokens")?
+    .rows_affected();
+
+    sqlx::query(
+        "DELETE FROM confirmation_resends
+                WHERE window_started_at <= now() - make_interval(mins => $1)",
+    )
+    .bind(settings.resend_window_minutes as i32)
+    .execute(pool)
+    .await
+    .context("Failed to delete stale confirmation resend counters")?;
+
+    Ok(n_tokens)
+}
+
+pub async fn run_token_cleanup_worker_until_stopped(
+    configuration: Arc<Settings>,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let settings = &configuration.profile_confirmation;
+
+    loop {
+        heartbeat(
+            "token_cleanup_worker",
+            Duration::from_secs(settings.cleanup_interval_seconds),
+        );
+        match try_expire_profile_tokens(&connection_pool, settings).await {
+            Ok(_) => {
+                tokio::time::sleep(Duration::from_secs(settings.cleanup_interval_seconds)).await;
+            }
+            Err(_) => tokio::time::sleep(Duration::from_secs(3)).await,
+        }
+    }
+}
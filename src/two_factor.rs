@@ -0,0 +1,374 @@
+use anyhow::Context;
+use rand::{Rng, distr::Alphanumeric};
+use sqlx::{PgPool, Postgres, Row, Transaction};
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+use crate::util::token_generator::{generate_profile_token, hash_token};
+
+const TOTP_ISSUER: &str = "Taskx";
+const RECOVERY_CODE_COUNT: usize = 10;
+const CHALLENGE_EXPIRE_MINUTES: i32 = 5;
+const CHALLENGE_MAX_ATTEMPTS: i32 = 5;
+const TOTP_STEP_SECONDS: u64 = 30;
+/// Steps either side of the current one whose codes are still accepted, for clock drift
+const TOTP_SKEW: u64 = 1;
+
+pub fn generate_totp_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+fn build_totp(secret: &str, account_name: &str) -> Result<TOTP, anyhow::Error> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| anyhow::anyhow!("Invalid TOTP secret: {e:?}"))?;
+
+    // `:` separates the issuer from the account in the otpauth label
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        0,
+        TOTP_STEP_SECONDS,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        account_name.replace(':', "_"),
+    )
+    .context("Failed to build TOTP")
+}
+
+/// `otpauth://` URI to be rendered as a QR code by the client
+pub fn provisioning_uri(secret: &str, account_name: &str) -> Result<String, anyhow::Error> {
+    Ok(build_totp(secret, account_name)?.get_url())
+}
+
+/// Returns the time step the code was generated for. Codes for `last_step` or an
+/// earlier step are rejected, so that an intercepted code cannot be replayed
+pub fn verify_totp_code(
+    secret: &str,
+    code: &str,
+    last_step: Option<i64>,
+) -> Result<Option<i64>, anyhow::Error> {
+    let totp = build_totp(secret, "")?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("System time is before the UNIX epoch")?
+        .as_secs();
+    let current_step = now / TOTP_STEP_SECONDS;
+
+    let step = (current_step.saturating_sub(TOTP_SKEW)..=current_step + TOTP_SKEW)
+        .find(|step| totp.check(code.trim(), step * TOTP_STEP_SECONDS))
+        .map(|step| step as i64);
+
+    Ok(step.filter(|step| last_step.is_none_or(|last_step| *step > last_step)))
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::rng();
+
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            std::iter::repeat_with(|| rng.sample(Alphanumeric))
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .take(10)
+                .collect()
+        })
+        .collect()
+}
+
+pub struct TotpSettings {
+    /// Set once enrollment has started
+    pub secret: Option<String>,
+    pub enabled: bool,
+    pub last_step: Option<i64>,
+}
+
+/// Returns `None` when there is no such profile
+#[tracing::instrument(name = "Get TOTP settings", skip(pool))]
+pub async fn get_totp_settings(
+    profile_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<TotpSettings>, anyhow::Error> {
+    let row =
+        sqlx::query("SELECT totp_secret, totp_enabled, totp_last_step FROM profile WHERE id = $1")
+            .bind(profile_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to perform query to retrieve TOTP settings")?;
+
+    Ok(row.map(|row| TotpSettings {
+        secret: row.get("totp_secret"),
+        enabled: row.get("totp_enabled"),
+        last_step: row.get("totp_last_step"),
+    }))
+}
+
+#[tracing::instrument(name = "Store pending TOTP secret", skip(pool, secret))]
+pub async fn store_pending_totp_secret(
+    profile_id: Uuid,
+    secret: &str,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    sqlx::query("UPDATE profile SET totp_secret = $1 WHERE id = $2 AND totp_enabled = false")
+        .bind(secret)
+        .bind(profile_id)
+        .execute(pool)
+        .await
+        .context("Failed to store the pending TOTP secret")?;
+
+    Ok(())
+}
+
+/// Enables TOTP once a code for `step` was verified, which then cannot be used to log in
+#[tracing::instrument(name = "Enable TOTP", skip(pool, recovery_codes))]
+pub async fn enable_totp(
+    profile_id: Uuid,
+    step: i64,
+    recovery_codes: &[String],
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("UPDATE profile SET totp_enabled = true, totp_last_step = $2 WHERE id = $1")
+        .bind(profile_id)
+        .bind(step)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to enable TOTP")?;
+
+    sqlx::query("DELETE FROM totp_recovery_codes WHERE profile_id = $1")
+        .bind(profile_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for code in recovery_codes {
+        sqlx::query("INSERT INTO totp_recovery_codes (profile_id, code_hash) VALUES ($1, $2)")
+            .bind(profile_id)
+            .bind(hash_token(code))
+            .execute(&mut *tx)
+            .await
+            .context("Failed to store a recovery code")?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Consume recovery code", skip(tx, code))]
+async fn consume_recovery_code(
+    tx: &mut Transaction<'_, Postgres>,
+    profile_id: Uuid,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    let n_updated = sqlx::query(
+        "UPDATE totp_recovery_codes SET used_at = now()
+                WHERE profile_id = $1
+                AND code_hash = $2
+                AND used_at IS NULL",
+    )
+    .bind(profile_id)
+    .bind(hash_token(&code.trim().to_ascii_lowercase()))
+    .execute(&mut **tx)
+    .await?
+    .rows_affected();
+
+    Ok(n_updated > 0)
+}
+
+#[tracing::instrument(name = "Create login challenge", skip(pool))]
+pub async fn create_login_challenge(
+    profile_id: Uuid,
+    pool: &PgPool,
+) -> Result<String, anyhow::Error> {
+    let challenge = generate_profile_token();
+
+    sqlx::query(
+        "INSERT INTO login_challenges (challenge_hash, profile_id, expires_at)
+                VALUES ($1, $2, now() + make_interval(mins => $3))",
+    )
+    .bind(hash_token(&challenge))
+    .bind(profile_id)
+    .bind(CHALLENGE_EXPIRE_MINUTES)
+    .execute(pool)
+    .await
+    .context("Failed to store the login challenge")?;
+
+    Ok(challenge)
+}
+
+/// Username of the profile a pending challenge was issued to, so that failed
+/// codes count against the same login lockout as failed passwords
+#[tracing::instrument(name = "Get login challenge username", skip(pool, challenge))]
+pub async fn login_challenge_username(
+    challenge: &str,
+    pool: &PgPool,
+) -> Result<Option<String>, anyhow::Error> {
+    let username = sqlx::query_scalar::<_, String>(
+        "SELECT p.username FROM login_challenges c
+                JOIN profile p ON p.id = c.profile_id
+                WHERE c.challenge_hash = $1
+                AND c.expires_at > now()",
+    )
+    .bind(hash_token(challenge))
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform query to retrieve the login challenge")?;
+
+    Ok(username)
+}
+
+/// Resolves a pending challenge with either a TOTP code or an unused recovery code.
+/// Returns the profile id once the challenge is satisfied, after which it cannot be reused.
+#[tracing::instrument(name = "Complete login challenge", skip(pool, challenge, code))]
+pub async fn complete_login_challenge(
+    challenge: &str,
+    code: &str,
+    pool: &PgPool,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let mut tx = pool.begin().await?;
+
+    let row = sqlx::query(
+        "SELECT profile_id FROM login_challenges
+                WHERE challenge_hash = $1
+                AND expires_at > now()
+                AND attempts < $2
+                FOR UPDATE",
+    )
+    .bind(hash_token(challenge))
+    .bind(CHALLENGE_MAX_ATTEMPTS)
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to perform query to retrieve the login challenge")?;
+
+    let Some(profile_id) = row.map(|r| r.get::<Uuid, _>("profile_id")) else {
+        return Ok(None);
+    };
+
+    // Locked, so that two challenges cannot accept the same code concurrently
+    let totp = sqlx::query(
+        "SELECT totp_secret, totp_last_step FROM profile
+                WHERE id = $1 AND totp_enabled = true
+                FOR UPDATE",
+    )
+    .bind(profile_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .and_then(|r| {
+        r.get::<Option<String>, _>("totp_secret")
+            .map(|secret| (secret, r.get::<Option<i64>, _>("totp_last_step")))
+    });
+
+    let verified = match totp {
+        Some((secret, last_step)) => match verify_totp_code(&secret, code, last_step)? {
+            Some(step) => {
+                sqlx::query("UPDATE profile SET totp_last_step = $2 WHERE id = $1")
+                    .bind(profile_id)
+                    .bind(step)
+                    .execute(&mut *tx)
+                    .await?;
+                true
+            }
+            None => consume_recovery_code(&mut tx, profile_id, code).await?,
+        },
+        None => false,
+    };
+
+    if verified {
+        sqlx::query("DELETE FROM login_challenges WHERE challenge_hash = $1")
+            .bind(hash_token(challenge))
+            .execute(&mut *tx)
+            .await?;
+    } else {
+        sqlx::query(
+            "UPDATE login_challenges SET attempts = attempts + 1 WHERE challenge_hash = $1",
+        )
+        .bind(hash_token(challenge))
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(verified.then_some(profile_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        generate_recovery_codes, generate_totp_secret, provisioning_uri, verify_totp_code,
+    };
+    use claims::assert_ok;
+    use totp_rs::{Algorithm, Secret, TOTP};
+
+    #[test]
+    fn provisioning_uri_is_an_otpauth_uri() {
+        let secret = generate_totp_secret();
+        let uri = provisioning_uri(&secret, "ursula:le:guin").unwrap();
+
+        assert!(uri.starts_with("otpauth://totp/Taskx:ursula_le_guin?"));
+        assert!(uri.contains(&format!("secret={}", secret)));
+    }
+
+    #[test]
+    fn current_code_is_accepted() {
+        let secret = generate_totp_secret();
+        let code = TOTP::new_unchecked(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            Secret::Encoded(secret.clone()).to_bytes().unwrap(),
+            None,
+            String::new(),
+        )
+        .generate_current()
+        .unwrap();
+
+        assert!(verify_totp_code(&secret, &code, None).unwrap().is_some());
+    }
+
+    #[test]
+    fn wrong_code_is_rejected() {
+        let secret = generate_totp_secret();
+        let outcome = assert_ok!(verify_totp_code(&secret, "not-a-code", None));
+
+        assert!(outcome.is_none());
+    }
+
+    #[test]
+    fn code_cannot_be_replayed() {
+        let secret = generate_totp_secret();
+        let code = TOTP::new_unchecked(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            Secret::Encoded(secret.clone()).to_bytes().unwrap(),
+            None,
+            String::new(),
+        )
+        .generate_current()
+        .unwrap();
+
+        let step = verify_totp_code(&secret, &code, None).unwrap().unwrap();
+
+        assert_eq!(verify_totp_code(&secret, &code, Some(step)).unwrap(), None);
+        assert_eq!(
+            verify_totp_code(&secret, &code, Some(step + 1)).unwrap(),
+            None
+        );
+        assert_eq!(
+            verify_totp_code(&secret, &code, Some(step - 1)).unwrap(),
+            Some(step)
+        );
+    }
+
+    #[test]
+    fn recovery_codes_are_unique() {
+        let mut codes = generate_recovery_codes();
+        codes.sort();
+        codes.dedup();
+
+        assert_eq!(codes.len(), 10);
+    }
+}
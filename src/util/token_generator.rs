@@ -1,4 +1,6 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use rand::{Rng, distr::Alphanumeric};
+use sha3::{Digest, Sha3_256};
 
 pub fn generate_profile_token() -> String {
     let mut rng = rand::rng();
@@ -8,3 +10,8 @@ pub fn generate_profile_token() -> String {
         .take(25)
         .collect()
 }
+
+/// Tokens that grant access to an account are only ever stored hashed
+pub fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha3_256::digest(token.as_bytes()))
+}
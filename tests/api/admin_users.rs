@@ -0,0 +1,343 @@
+use crate::common;
+
+mod tests {
+    use super::common::{StdResponse, TestApp, spawn_app};
+    use crate::test_profile::TestProfile;
+    use sqlx::Row;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    async fn log_in_admin(app: &TestApp) -> TestProfile {
+        let admin = TestProfile::generate(true);
+        admin.store_test_profile(&app.pool).await;
+        admin.grant_role(&app.pool, "admin").await;
+        admin.post_login(app).await;
+        admin
+    }
+
+    async fn post_user_action(app: &TestApp, action: &str) -> reqwest::Response {
+        app.api_client
+            .post(format!(
+                "{}/admin/users/{}/{}",
+                &app.address, app.test_profile.id, action
+            ))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    async fn audit_actions(app: &TestApp) -> Vec<String> {
+        sqlx::query("SELECT action FROM audit_log WHERE profile_id = $1 ORDER BY created_at")
+            .bind(app.test_profile.id)
+            .fetch_all(&app.pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.get("action"))
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn profiles_without_the_admin_role_are_forbidden() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+
+        // Act
+        let response = app
+            .api_client
+            .get(format!("{}/admin/users", &app.address))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 403);
+        let body: StdResponse = response.json().await.unwrap();
+        assert_eq!(body.message, "You are not allowed to perform this action");
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn admins_can_search_profiles_page_by_page() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        for _ in 0..3 {
+            TestProfile::generate(false)
+                .store_test_profile(&app.pool)
+                .await;
+        }
+        let admin = log_in_admin(&app).await;
+
+        // Act - Part 1 - Paginate over everyone
+        let body: serde_json::Value = app
+            .api_client
+            .get(format!("{}/admin/users", &app.address))
+            .query(&[("page", "2"), ("per_page", "2")])
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["total"], 5);
+        assert_eq!(body["users"].as_array().unwrap().len(), 2);
+
+        // Act - Part 2 - Search by username
+        let body: serde_json::Value = app
+            .api_client
+            .get(format!("{}/admin/users", &app.address))
+            .query(&[("q", admin.username.as_ref().to_uppercase())])
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(body["total"], 1);
+        assert_eq!(body["users"][0]["id"], admin.id.to_string());
+        assert_eq!(body["users"][0]["roles"], serde_json::json!(["admin"]));
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn suspended_profile_cannot_log_in_until_reactivated() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        log_in_admin(&app).await;
+
+        // Act - Part 1 - Suspend
+        let response = post_user_action(&app, "suspend").await;
+        assert_eq!(response.status().as_u16(), 200);
+        let response = post_user_action(&app, "suspend").await;
+        assert_eq!(response.status().as_u16(), 409);
+
+        let body: serde_json::Value = app
+            .api_client
+            .get(format!(
+                "{}/admin/users/{}",
+                &app.address, app.test_profile.id
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["status"], "suspended");
+
+        let response = app.test_profile.post_login(&app).await;
+        assert_eq!(response.status().as_u16(), 401);
+
+        // Act - Part 2 - Reactivate
+        let response = post_user_action(&app, "reactivate").await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        // Assert
+        let response = app.test_profile.post_login(&app).await;
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(
+            audit_actions(&app).await,
+            vec!["account_suspended", "profile_viewed", "account_reactivated"]
+        );
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn suspended_pending_profile_cannot_confirm_itself() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let store_token = |token: &'static str| {
+            sqlx::query(
+                "INSERT INTO profile_tokens (profile_token, profile_id, expires_at)
+                        VALUES ($1, $2, now() + interval '1 hour')",
+            )
+            .bind(token)
+            .bind(app.test_profile.id)
+            .execute(&app.pool)
+        };
+        let confirm = |token: &'static str| {
+            app.api_client
+                .get(format!("{}/profile/confirm", &app.address))
+                .query(&[("profile_token", token)])
+                .send()
+        };
+        store_token("issuedbeforesuspension").await.unwrap();
+        log_in_admin(&app).await;
+        let response = post_user_action(&app, "suspend").await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        // Act - Part 1 - The token issued before the suspension
+        let response = confirm("issuedbeforesuspension").await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+
+        // Act - Part 2 - A token that survived somehow
+        store_token("issuedaftersuspension").await.unwrap();
+        let response = confirm("issuedaftersuspension").await.unwrap();
+        assert_eq!(response.status().as_u16(), 409);
+
+        // Assert
+        let status: String = sqlx::query_scalar("SELECT status FROM profile WHERE id = $1")
+            .bind(app.test_profile.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(status, "suspended");
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn forced_password_reset_invalidates_the_password() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        log_in_admin(&app).await;
+        Mock::given(path("/v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&app.email_server)
+            .await;
+
+        // Act
+        let response = post_user_action(&app, "password-reset").await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let response = app.test_profile.post_login(&app).await;
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(audit_actions(&app).await, vec!["password_reset_forced"]);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn confirmation_is_only_resent_to_pending_profiles() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        log_in_admin(&app).await;
+        Mock::given(path("/v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&app.email_server)
+            .await;
+
+        // Act - Part 1 - Pending profile
+        let response = post_user_action(&app, "resend-confirmation").await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        // Act - Part 2 - Confirmed profile
+        sqlx::query("UPDATE profile SET status = 'confirmed' WHERE id = $1")
+            .bind(app.test_profile.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let response = post_user_action(&app, "resend-confirmation").await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 409);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn impersonation_token_acts_as_the_profile() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let admin = log_in_admin(&app).await;
+
+        // Act
+        let response = post_user_action(&app, "impersonate").await;
+        assert_eq!(response.status().as_u16(), 200);
+        let token = response.headers()["Authorization"]
+            .to_str()
+            .unwrap()
+            .strip_prefix("Bearer ")
+            .unwrap()
+            .to_string();
+
+        // Assert
+        let response = reqwest::Client::new()
+            .get(format!("{}/admin/users", &app.address))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 403);
+
+        let response = reqwest::Client::new()
+            .get(format!("{}/admin/dashboard", &app.address))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+
+        let response = app
+            .api_client
+            .post(format!(
+                "{}/admin/users/{}/impersonate",
+                &app.address, admin.id
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 403);
+
+        let actor_id: uuid::Uuid = sqlx::query(
+            "SELECT actor_id FROM audit_log WHERE profile_id = $1 AND action = 'profile_impersonated'",
+        )
+        .bind(app.test_profile.id)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .get("actor_id");
+        assert_eq!(actor_id, admin.id);
+
+        // Both requests made with the token are attributed to the admin
+        let requests = sqlx::query(
+            "SELECT actor_id, details FROM audit_log
+                    WHERE profile_id = $1 AND action = 'impersonated_request'
+                    ORDER BY created_at",
+        )
+        .bind(app.test_profile.id)
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(
+            requests
+                .iter()
+                .all(|r| r.get::<uuid::Uuid, _>("actor_id") == admin.id)
+        );
+        assert_eq!(
+            requests[1].get::<serde_json::Value, _>("details")["path"],
+            "/admin/dashboard"
+        );
+
+        // The token cannot be swapped for a regular one
+        let response = reqwest::Client::new()
+            .get(format!("{}/admin/refresh-token", &app.address))
+            .header("Cookie", format!("refresh_token={token}"))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+}
@@ -1,12 +1,23 @@
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
 
 use once_cell::sync::Lazy;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
-use taskservice::configuration::{DatabaseSettings, get_configuration};
+use taskservice::configuration::{
+    DataPrivacySettings, DatabaseSettings, DeliverySettings, IdempotencySettings,
+    OidcProviderSettings, OidcProviders, PasswordHashingSettings, get_configuration,
+};
+use taskservice::digest::try_send_digest;
 use taskservice::email_client::EmailClient;
+use taskservice::health::ShutdownState;
 use taskservice::idempotency::try_idem_expiration;
 use taskservice::issue_delivery::{ExecutionOutcome, try_execute_delivery};
+use taskservice::notification::UnsubscribeLinks;
+use taskservice::notification_channel::NotificationChannels;
+use taskservice::privacy::{try_execute_deletion, try_execute_export};
 use taskservice::startup::{Application, get_connection_pool};
+use taskservice::suppression::WebhookSignatures;
 use taskservice::telemetry::{get_tracing_subscriber, init_tracing_subscriber};
 use uuid::Uuid;
 use wiremock::MockServer;
@@ -30,11 +41,21 @@ pub struct TestApp {
     pub connection: PgConnection,
     pub db_name: String,
     pub email_server: MockServer,
+    pub idp_server: MockServer,
     pub port: u16,
+    pub metrics_address: String,
+    pub shutdown: ShutdownState,
     pub test_profile: TestProfile,
     pub api_client: reqwest::Client,
+    pub cookie_jar: Arc<reqwest::cookie::Jar>,
     pub email_client: EmailClient,
-    pub idempotency_expiration: u64,
+    pub notification_channels: NotificationChannels,
+    pub unsubscribe_links: UnsubscribeLinks,
+    pub idempotency: IdempotencySettings,
+    pub data_privacy: DataPrivacySettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub delivery: DeliverySettings,
+    pub webhook_signatures: WebhookSignatures,
 }
 
 impl TestApp {
@@ -86,10 +107,17 @@ impl TestApp {
         let username = self.test_profile.username.as_ref();
         let password = self.test_profile.password.as_ref();
 
-        self.api_client
+        let mut request = self
+            .api_client
             .post(format!("{}/admin/task", &self.address))
             .basic_auth(username, Some(password))
-            .json(&body)
+            .json(&body);
+        // Tests pick the key together with the task they submit
+        if let Some(idempotency_key) = body["idempotency_key"].as_str() {
+            request = request.header("Idempotency-Key", idempotency_key);
+        }
+
+        request
             .send()
             .await
             .expect("Failed to execute new task request")
@@ -111,6 +139,22 @@ impl TestApp {
             .expect("failed to execute request")
     }
 
+    pub async fn get_readiness(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/health/ready", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_metrics(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/metrics", &self.metrics_address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
     pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
@@ -123,6 +167,228 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn post_forgot_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/password/forgot", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute forgot password request")
+    }
+
+    /// Waits for emails sent after the response went out, returning all received so far
+    pub async fn wait_for_emails(&self, n_emails: usize) -> Vec<wiremock::Request> {
+        for _ in 0..100 {
+            let received = self.email_server.received_requests().await.unwrap();
+            if received.len() >= n_emails {
+                return received;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("Expected {n_emails} emails to be sent");
+    }
+
+    pub async fn post_reset_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/password/reset", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute reset password request")
+    }
+
+    pub async fn post_resend_confirmation<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/profile/confirm/resend", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute resend confirmation request")
+    }
+
+    pub async fn post_email_change<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/profile/email", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute email change request")
+    }
+
+    pub async fn get_notification_preferences(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/notifications/preferences", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute notification preferences request")
+    }
+
+    pub async fn put_notification_preferences(
+        &self,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .put(format!("{}/admin/notifications/preferences", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute notification preferences update")
+    }
+
+    pub async fn put_notification_channels(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .put(format!("{}/admin/notifications/channels", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute notification channels update")
+    }
+
+    pub async fn get_notifications(&self, unread: bool) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/notifications?unread={}", &self.address, unread))
+            .send()
+            .await
+            .expect("Failed to execute inbox request")
+    }
+
+    pub async fn post_notification_read(&self, notification_id: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/notifications/{}/read",
+                &self.address, notification_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute mark as read request")
+    }
+
+    pub async fn post_data_export(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/data/export", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute data export request")
+    }
+
+    pub async fn get_data_export(&self, export_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/data/export/{}", &self.address, export_id))
+            .send()
+            .await
+            .expect("Failed to execute data export status request")
+    }
+
+    pub async fn download_data_export(&self, export_id: &str, format: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/data/export/{}/download",
+                &self.address, export_id
+            ))
+            .query(&[("format", format)])
+            .send()
+            .await
+            .expect("Failed to execute data export download request")
+    }
+
+    pub async fn post_deletion_request(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/data/deletion", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute deletion request")
+    }
+
+    pub async fn delete_deletion_request(&self) -> reqwest::Response {
+        self.api_client
+            .delete(format!("{}/admin/data/deletion", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute deletion cancellation request")
+    }
+
+    pub async fn delete_profile(&self, profile_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .delete(format!("{}/profile/{}", &self.address, profile_id))
+            .send()
+            .await
+            .expect("Failed to execute profile deletion request")
+    }
+
+    pub async fn post_login_two_factor<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login/2fa", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute two-factor login request")
+    }
+
+    pub async fn post_two_factor_enroll(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/2fa/enroll", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute two-factor enroll request")
+    }
+
+    pub async fn post_two_factor_verify<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/2fa/verify", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute two-factor verify request")
+    }
+
+    /// Does not follow the redirect, so that the authorization request can be inspected.
+    /// Shares the session cookie with `api_client`, which completes the login
+    pub async fn get_oidc_login(&self, provider: &str) -> reqwest::Response {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_provider(self.cookie_jar.clone())
+            .build()
+            .unwrap()
+            .get(format!("{}/login/oidc/{}", &self.address, provider))
+            .send()
+            .await
+            .expect("Failed to execute OpenID login request")
+    }
+
+    pub async fn get_oidc_callback(
+        &self,
+        provider: &str,
+        query: &[(&str, &str)],
+    ) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/login/oidc/{}/callback",
+                &self.address, provider
+            ))
+            .query(query)
+            .send()
+            .await
+            .expect("Failed to execute OpenID callback request")
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
             .post(format!("{}/admin/logout", &self.address))
@@ -139,10 +405,67 @@ impl TestApp {
             .expect("Failed to execute refresh token")
     }
 
+    /// Posts provider events signed the way the email provider would sign them
+    pub async fn post_email_events(&self, body: &serde_json::Value) -> reqwest::Response {
+        let body = serde_json::to_vec(body).unwrap();
+        let timestamp = chrono::Utc::now().timestamp();
+        let signature = self.webhook_signatures.sign(timestamp, &body).unwrap();
+
+        self.api_client
+            .post(format!("{}/email/events", &self.address))
+            .header("X-Webhook-Timestamp", timestamp.to_string())
+            .header("X-Webhook-Signature", signature)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute email events request")
+    }
+
     pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_delivery(
+                &self.pool,
+                &self.notification_channels,
+                &self.unsubscribe_links,
+                &self.delivery,
+                None,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn send_due_digests(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_send_digest(&self.pool, &self.email_client, &self.unsubscribe_links)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn run_pending_exports(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_export(&self.pool, &self.data_privacy)
+                .await
+                .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn run_due_deletions(&self, settings: &DataPrivacySettings) {
         loop {
             if let ExecutionOutcome::EmptyQueue =
-                try_execute_delivery(&self.pool, &self.email_client)
+                try_execute_deletion(&self.pool, settings, &self.password_hashing)
                     .await
                     .unwrap()
             {
@@ -153,7 +476,7 @@ impl TestApp {
 
     pub async fn expire_idempotency_keys(&self) {
         loop {
-            if try_idem_expiration(&self.pool, self.idempotency_expiration)
+            if try_idem_expiration(&self.pool, &self.idempotency)
                 .await
                 .unwrap()
                 .is_none()
@@ -188,11 +511,11 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 
     if std::env::var("TEST_LOG").is_ok() {
         let subscriber =
-            get_tracing_subscriber(subscriper_name, default_filter_level, std::io::stdout);
+            get_tracing_subscriber(subscriper_name, default_filter_level, std::io::stdout, None);
         init_tracing_subscriber(subscriber);
     } else {
         let subscriber =
-            get_tracing_subscriber(subscriper_name, default_filter_level, std::io::sink);
+            get_tracing_subscriber(subscriper_name, default_filter_level, std::io::sink, None);
         init_tracing_subscriber(subscriber);
     }
 });
@@ -203,6 +526,8 @@ pub async fn spawn_app() -> TestApp {
 
     // Launch a mock server to stand in for Email(s) API
     let email_server = MockServer::start().await;
+    // and another for an OpenID Connect provider
+    let idp_server = MockServer::start().await;
 
     let configuration = {
         let mut c = get_configuration().expect("Failed to read configuration");
@@ -210,9 +535,22 @@ pub async fn spawn_app() -> TestApp {
         c.database.db_name = Uuid::new_v4().to_string();
 
         c.application.port = 0;
+        c.metrics.port = 0;
 
         // Use the mock server as email API
         c.email_client.base_uri = email_server.uri();
+        c.email_client.webhook_secret = Some(Uuid::new_v4().to_string());
+        // Keep retries of requests in flight from slowing the tests down
+        c.idempotency.in_flight_wait_milliseconds = 200;
+        // Webhooks are received by the mock server on localhost
+        c.delivery.webhook_allow_private_addresses = true;
+
+        c.oidc_providers = OidcProviders(vec![OidcProviderSettings {
+            name: "mock".to_string(),
+            issuer: idp_server.uri(),
+            client_id: "taskx".to_string(),
+            client_secret: "taskx-secret".to_string(),
+        }]);
 
         c
     };
@@ -226,26 +564,46 @@ pub async fn spawn_app() -> TestApp {
 
     let port = application.port();
     let address = format!("http://127.0.0.1:{}", port);
+    let metrics_address = format!("http://127.0.0.1:{}", application.metrics_port());
+    let shutdown = application.shutdown_state();
     let _ = tokio::spawn(application.run_until_stopped());
     let test_profile = TestProfile::generate(false);
 
     // reqwest::Client
+    let cookie_jar = Arc::new(reqwest::cookie::Jar::default());
     let api_client = reqwest::Client::builder()
-        .cookie_store(true)
+        .cookie_provider(cookie_jar.clone())
         .build()
         .unwrap();
 
+    let pool = get_connection_pool(&configuration.database);
     let test_app = TestApp {
         address,
-        pool: get_connection_pool(&configuration.database),
+        notification_channels: NotificationChannels::new(
+            configuration.email_client.client(),
+            pool.clone(),
+            configuration.email_client.timeout(),
+            configuration.delivery.webhook_allow_private_addresses,
+        )
+        .expect("Failed to build the notification channels"),
+        pool,
         connection,
         db_name: configuration.database.db_name,
         email_server,
+        idp_server,
         port,
+        metrics_address,
+        shutdown,
         test_profile,
         api_client,
+        cookie_jar,
         email_client: configuration.email_client.client(),
-        idempotency_expiration: configuration.application.idempotency_expiration,
+        unsubscribe_links: configuration.application.unsubscribe_links(),
+        idempotency: configuration.idempotency,
+        data_privacy: configuration.data_privacy,
+        password_hashing: configuration.password_hashing,
+        delivery: configuration.delivery,
+        webhook_signatures: configuration.email_client.webhook_signatures(),
     };
 
     test_app
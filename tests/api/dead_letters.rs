@@ -0,0 +1,184 @@
+use crate::common;
+
+mod tests {
+    use super::common::{TestApp, spawn_app};
+    use sqlx::Row;
+    use uuid::Uuid;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    async fn queue_failing_delivery(app: &TestApp) {
+        app.test_profile.store_test_profile(&app.pool).await;
+        sqlx::query("UPDATE profile SET status = 'confirmed' WHERE id = $1")
+            .bind(app.test_profile.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        app.test_profile.grant_role(&app.pool, "admin").await;
+        app.test_profile.post_login(app).await;
+        Mock::given(path("/v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&app.email_server)
+            .await;
+
+        let response = app
+            .post_tasks(&serde_json::json!({
+                "task_type": "feature",
+                "source_file": "init.txt",
+                "idempotency_key": Uuid::new_v4().to_string(),
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    /// Skips the backoff so the next dispatch picks the delivery up again
+    async fn make_retries_due(app: &TestApp) {
+        sqlx::query("UPDATE issue_delivery_queue SET last_attempt = now() - interval '1 day'")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+    }
+
+    async fn get_dead_letters(app: &TestApp) -> serde_json::Value {
+        let response = app
+            .api_client
+            .get(format!("{}/admin/dead-letters", &app.address))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        response.json().await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn failed_deliveries_are_retried_until_they_are_dead_lettered() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.delivery.max_retries = 1;
+        queue_failing_delivery(&app).await;
+
+        // Act - Part 1 - First attempt
+        app.dispatch_all_pending_emails().await;
+
+        // Assert - Part 1
+        let n_retries: i32 = sqlx::query("SELECT n_retries FROM issue_delivery_queue")
+            .fetch_one(&app.pool)
+            .await
+            .expect("The failed delivery should stay queued")
+            .get("n_retries");
+        assert_eq!(n_retries, 1);
+        assert_eq!(get_dead_letters(&app).await["total"], 0);
+
+        // Act - Part 2 - Last retry
+        make_retries_due(&app).await;
+        app.dispatch_all_pending_emails().await;
+
+        // Assert - Part 2
+        let n_queued: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM issue_delivery_queue")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(n_queued, 0);
+        let body = get_dead_letters(&app).await;
+        assert_eq!(body["total"], 1);
+        let dead_letter = &body["dead_letters"][0];
+        assert_eq!(
+            dead_letter["profile_email"],
+            app.test_profile.email.as_ref()
+        );
+        assert_eq!(dead_letter["n_retries"], 1);
+        assert!(
+            dead_letter["last_error"]
+                .as_str()
+                .unwrap()
+                .contains("500 Internal Server Error")
+        );
+        assert_eq!(app.email_server.received_requests().await.unwrap().len(), 2);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn dead_letters_can_be_requeued_and_purged() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.delivery.max_retries = 0;
+        queue_failing_delivery(&app).await;
+        app.dispatch_all_pending_emails().await;
+        let dead_letter_id = get_dead_letters(&app).await["dead_letters"][0]["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // Act - Part 1 - Requeue
+        let response = app
+            .api_client
+            .post(format!(
+                "{}/admin/dead-letters/{}/requeue",
+                &app.address, dead_letter_id
+            ))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert - Part 1
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(get_dead_letters(&app).await["total"], 0);
+        let n_retries: i32 = sqlx::query("SELECT n_retries FROM issue_delivery_queue")
+            .fetch_one(&app.pool)
+            .await
+            .expect("The delivery should be queued again")
+            .get("n_retries");
+        assert_eq!(n_retries, 0);
+
+        // Act - Part 2 - Fail again, then purge
+        app.dispatch_all_pending_emails().await;
+        let response = app
+            .api_client
+            .delete(format!("{}/admin/dead-letters", &app.address))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert - Part 2
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["purged"], 1);
+        assert_eq!(get_dead_letters(&app).await["total"], 0);
+
+        let response = app
+            .api_client
+            .post(format!(
+                "{}/admin/dead-letters/{}/requeue",
+                &app.address, dead_letter_id
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 404);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn dead_letters_are_only_available_to_admins() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+
+        // Act
+        let response = app
+            .api_client
+            .get(format!("{}/admin/dead-letters", &app.address))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 403);
+
+        app.drop_test_db().await;
+    }
+}
@@ -0,0 +1,155 @@
+use crate::common;
+
+mod tests {
+    use super::common::{TestApp, spawn_app};
+    use uuid::Uuid;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    /// Posts a task as a confirmed profile and returns its id. The profile is
+    /// not an admin
+    async fn post_task_as_confirmed_profile(app: &TestApp) -> String {
+        app.test_profile.store_test_profile(&app.pool).await;
+        sqlx::query("UPDATE profile SET status = 'confirmed' WHERE id = $1")
+            .bind(app.test_profile.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        app.test_profile.post_login(app).await;
+
+        let response = app
+            .post_tasks(&serde_json::json!({
+                "task_type": "feature",
+                "source_file": "init.txt",
+                "idempotency_key": Uuid::new_v4().to_string(),
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        sqlx::query_scalar::<_, Uuid>("SELECT id FROM task")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .to_string()
+    }
+
+    async fn get_task_deliveries(app: &TestApp, task_id: &str) -> reqwest::Response {
+        app.api_client
+            .get(format!(
+                "{}/admin/task/{}/deliveries",
+                &app.address, task_id
+            ))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn sent_deliveries_are_logged_with_the_provider_message_id() {
+        // Arrange
+        let mut app = spawn_app().await;
+        Mock::given(path("/v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Sent": [{ "Email": app.test_profile.email.as_ref(), "MessageID": 42 }]
+            })))
+            .expect(1)
+            .mount(&app.email_server)
+            .await;
+        let task_id = post_task_as_confirmed_profile(&app).await;
+        app.test_profile.grant_role(&app.pool, "admin").await;
+
+        // Act
+        app.dispatch_all_pending_emails().await;
+
+        // Assert
+        let response = get_task_deliveries(&app, &task_id).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            body["summary"],
+            serde_json::json!({ "sent": 1, "failed": 0, "pending": 0 })
+        );
+        let recipient = &body["recipients"][0];
+        assert_eq!(recipient["profile_email"], app.test_profile.email.as_ref());
+        assert_eq!(recipient["status"], "sent");
+        assert_eq!(recipient["attempts"], 1);
+        assert_eq!(recipient["provider_message_id"], "42");
+        assert!(recipient["last_error"].is_null());
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn every_failed_attempt_is_logged() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.delivery.max_retries = 1;
+        Mock::given(path("/v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&app.email_server)
+            .await;
+        let task_id = post_task_as_confirmed_profile(&app).await;
+        app.test_profile.grant_role(&app.pool, "admin").await;
+
+        // Act - Part 1 - First attempt is deferred
+        app.dispatch_all_pending_emails().await;
+
+        // Assert - Part 1
+        let body: serde_json::Value = get_task_deliveries(&app, &task_id)
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            body["summary"],
+            serde_json::json!({ "sent": 0, "failed": 0, "pending": 1 })
+        );
+        assert_eq!(body["recipients"][0]["status"], "deferred");
+
+        // Act - Part 2 - Last retry gives up
+        sqlx::query("UPDATE issue_delivery_queue SET last_attempt = now() - interval '1 day'")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        app.dispatch_all_pending_emails().await;
+
+        // Assert - Part 2
+        let body: serde_json::Value = get_task_deliveries(&app, &task_id)
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            body["summary"],
+            serde_json::json!({ "sent": 0, "failed": 1, "pending": 0 })
+        );
+        let recipient = &body["recipients"][0];
+        assert_eq!(recipient["status"], "failed");
+        assert_eq!(recipient["attempts"], 2);
+        assert!(
+            recipient["last_error"]
+                .as_str()
+                .unwrap()
+                .contains("503 Service Unavailable")
+        );
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn deliveries_are_hidden_from_reporters_who_are_not_admins() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let task_id = post_task_as_confirmed_profile(&app).await;
+
+        // Act
+        let response = get_task_deliveries(&app, &task_id).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 403);
+
+        app.drop_test_db().await;
+    }
+}
@@ -0,0 +1,187 @@
+use crate::common;
+
+mod tests {
+    use super::common::{TestApp, spawn_app};
+    use uuid::Uuid;
+    use wiremock::matchers::any;
+    use wiremock::{Mock, ResponseTemplate};
+
+    async fn store_confirmed_test_profile(app: &TestApp) {
+        app.test_profile.store_test_profile(&app.pool).await;
+        sqlx::query("UPDATE profile SET status = 'confirmed' WHERE id = $1")
+            .bind(app.test_profile.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+    }
+
+    async fn get_suppressions(app: &TestApp) -> reqwest::Response {
+        app.api_client
+            .get(format!("{}/admin/suppressions", &app.address))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    async fn delete_suppression(app: &TestApp, profile_id: Uuid) -> reqwest::Response {
+        app.api_client
+            .delete(format!(
+                "{}/admin/suppressions/{}",
+                &app.address, profile_id
+            ))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn hard_bounced_addresses_no_longer_receive_notifications() {
+        // Arrange
+        let mut app = spawn_app().await;
+        store_confirmed_test_profile(&app).await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&app.email_server)
+            .await;
+
+        // Act - Part 1 - The provider reports a hard bounce
+        let response = app
+            .post_email_events(&serde_json::json!([
+                { "event": "bounce", "email": app.test_profile.email.as_ref(), "hard_bounce": true },
+                { "event": "open", "email": app.test_profile.email.as_ref() },
+            ]))
+            .await;
+
+        // Assert - Part 1
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body, serde_json::json!({ "received": 2, "suppressed": 1 }));
+
+        // Act - Part 2 - A new task is not delivered to the address
+        app.test_profile.post_login(&app).await;
+        let response = app
+            .post_tasks(&serde_json::json!({
+                "task_type": "feature",
+                "source_file": "init.txt",
+                "idempotency_key": Uuid::new_v4().to_string(),
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+        app.dispatch_all_pending_emails().await;
+
+        // Assert - Part 2
+        let n_queued: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM issue_delivery_queue")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(n_queued, 0);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn unsigned_or_tampered_events_are_rejected() {
+        // Arrange
+        let mut app = spawn_app().await;
+        store_confirmed_test_profile(&app).await;
+        let body = serde_json::json!({ "event": "spam", "email": app.test_profile.email.as_ref() })
+            .to_string();
+        let timestamp = chrono::Utc::now().timestamp();
+        let signature = app
+            .webhook_signatures
+            .sign(timestamp, b"{\"event\":\"open\"}")
+            .unwrap();
+
+        for (headers, case) in [
+            (vec![], "no signature"),
+            (
+                vec![
+                    ("X-Webhook-Timestamp", timestamp.to_string()),
+                    ("X-Webhook-Signature", signature),
+                ],
+                "signature of another body",
+            ),
+        ] {
+            // Act
+            let mut request = app
+                .api_client
+                .post(format!("{}/email/events", &app.address))
+                .header("Content-Type", "application/json")
+                .body(body.clone());
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            let response = request.send().await.unwrap();
+
+            // Assert
+            assert_eq!(
+                response.status().as_u16(),
+                401,
+                "The API did not reject an event with {case}"
+            );
+        }
+        let reason: Option<String> =
+            sqlx::query_scalar("SELECT undeliverable_reason FROM profile WHERE id = $1")
+                .bind(app.test_profile.id)
+                .fetch_one(&app.pool)
+                .await
+                .unwrap();
+        assert_eq!(reason, None);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn admins_can_list_and_clear_suppressed_addresses() {
+        // Arrange
+        let mut app = spawn_app().await;
+        store_confirmed_test_profile(&app).await;
+        app.test_profile.grant_role(&app.pool, "admin").await;
+        app.test_profile.post_login(&app).await;
+        app.post_email_events(
+            &serde_json::json!({ "event": "spam", "email": app.test_profile.email.as_ref() }),
+        )
+        .await;
+
+        // Act - Part 1 - List
+        let response = get_suppressions(&app).await;
+
+        // Assert - Part 1
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["total"], 1);
+        let suppression = &body["suppressions"][0];
+        assert_eq!(suppression["profile_id"], app.test_profile.id.to_string());
+        assert_eq!(suppression["email"], app.test_profile.email.as_ref());
+        assert_eq!(suppression["reason"], "complaint");
+
+        // Act - Part 2 - Clear
+        let response = delete_suppression(&app, app.test_profile.id).await;
+
+        // Assert - Part 2
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = get_suppressions(&app).await.json().await.unwrap();
+        assert_eq!(body["total"], 0);
+        let response = delete_suppression(&app, app.test_profile.id).await;
+        assert_eq!(response.status().as_u16(), 404);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn non_admins_cannot_see_suppressed_addresses() {
+        // Arrange
+        let mut app = spawn_app().await;
+        store_confirmed_test_profile(&app).await;
+        app.test_profile.post_login(&app).await;
+
+        // Act
+        let response = get_suppressions(&app).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 403);
+
+        app.drop_test_db().await;
+    }
+}
@@ -0,0 +1,176 @@
+use crate::common;
+
+mod tests {
+    use super::common::{TestApp, spawn_app};
+    use crate::test_profile::TestProfile;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    async fn sent_emails(app: &TestApp) -> Vec<serde_json::Value> {
+        app.email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| serde_json::from_slice(&r.body).unwrap())
+            .collect()
+    }
+
+    async fn get_preview(app: &TestApp, name: &str, locale: &str) -> reqwest::Response {
+        app.api_client
+            .get(format!(
+                "{}/admin/email-templates/{}/preview",
+                &app.address, name
+            ))
+            .query(&[("locale", locale)])
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn confirmation_email_is_written_in_the_profile_language() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let test_profile = TestProfile::generate(false);
+        Mock::given(path("v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&app.email_server)
+            .await;
+
+        let mut body = HashMap::new();
+        body.insert("first_name", test_profile.first_name.as_ref());
+        body.insert("last_name", test_profile.last_name.as_ref());
+        body.insert("email", test_profile.email.as_ref());
+        body.insert("username", test_profile.username.as_ref());
+        body.insert("password", test_profile.password.as_ref());
+        body.insert("locale", "fr-CA");
+
+        // Act
+        let response = app.post_profiles(&body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let email = &sent_emails(&app).await[0];
+        assert_eq!(email["Subject"], "Bienvenue");
+        assert_eq!(email["FromName"], "Taskx");
+        assert!(
+            email["Text-part"]
+                .as_str()
+                .unwrap()
+                .contains("confirmer votre compte")
+        );
+        let links =
+            app.get_confirmation_links(&app.email_server.received_requests().await.unwrap()[0]);
+        assert_eq!(links.html, links.plain_text);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn invalid_locales_are_rejected() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let test_profile = TestProfile::generate(false);
+
+        let mut body = HashMap::new();
+        body.insert("first_name", test_profile.first_name.as_ref());
+        body.insert("last_name", test_profile.last_name.as_ref());
+        body.insert("email", test_profile.email.as_ref());
+        body.insert("username", test_profile.username.as_ref());
+        body.insert("password", test_profile.password.as_ref());
+        body.insert("locale", "<script>");
+
+        // Act
+        let response = app.post_profiles(&body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn task_notifications_are_rendered_from_the_template() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        sqlx::query("UPDATE profile SET status = 'confirmed' WHERE id = $1")
+            .bind(app.test_profile.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        app.test_profile.post_login(&app).await;
+        Mock::given(path("/v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&app.email_server)
+            .await;
+
+        // Act
+        app.post_tasks(&serde_json::json!({
+            "task_type": "feature",
+            "source_file": "spec.md",
+            "workspace": "ops",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+        app.dispatch_all_pending_emails().await;
+
+        // Assert
+        let email = &sent_emails(&app).await[0];
+        assert_eq!(email["Subject"], "New feature task");
+        let text = email["Text-part"].as_str().unwrap();
+        assert!(text.contains("A new feature task was created in ops."));
+        assert!(text.contains("Source file: spec.md"));
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn admins_can_preview_templates() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let admin = TestProfile::generate(true);
+        admin.store_test_profile(&app.pool).await;
+        admin.grant_role(&app.pool, "admin").await;
+        admin.post_login(&app).await;
+
+        // Act
+        let response = get_preview(&app, "password_reset", "fr").await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["template"], "password_reset");
+        assert_eq!(body["locale"], "fr");
+        assert_eq!(body["subject"], "Réinitialisation du mot de passe");
+        assert!(body["html"].as_str().unwrap().contains("<a href="));
+
+        let response = get_preview(&app, "no_such_template", "en").await;
+        assert_eq!(response.status().as_u16(), 404);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn previews_are_only_available_to_admins() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+
+        // Act
+        let response = get_preview(&app, "confirmation", "en").await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 403);
+
+        app.drop_test_db().await;
+    }
+}
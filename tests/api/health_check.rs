@@ -21,4 +21,61 @@ mod tests {
 
         app.drop_test_db().await;
     }
+
+    #[actix_web::test]
+    async fn liveness_answers_while_the_process_runs() {
+        // Arrange
+        let mut app = spawn_app().await;
+
+        // Act
+        let response = app
+            .api_client
+            .get(format!("{}/health/live", &app.address))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn readiness_reports_each_dependency() {
+        // Arrange
+        let mut app = spawn_app().await;
+
+        // Act
+        let response = app.get_readiness().await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let readiness: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(readiness["ready"], true);
+        for component in ["database", "redis", "migrations"] {
+            assert_eq!(readiness["components"][component]["status"], "up");
+            assert!(readiness["components"][component]["latency_ms"].is_u64());
+        }
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn readiness_fails_while_shutting_down() {
+        // Arrange
+        let mut app = spawn_app().await;
+
+        // Act
+        app.shutdown.begin();
+        let response = app.get_readiness().await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 503);
+        let readiness: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(readiness["ready"], false);
+        assert_eq!(readiness["shutting_down"], true);
+
+        app.drop_test_db().await;
+    }
 }
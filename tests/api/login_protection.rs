@@ -0,0 +1,183 @@
+use crate::common;
+
+mod tests {
+    use super::common::{StdResponse, TestApp, spawn_app};
+    use crate::test_profile::TestProfile;
+    use sqlx::Row;
+    use uuid::Uuid;
+
+    async fn post_wrong_password(app: &TestApp) -> reqwest::Response {
+        let login_body = serde_json::json!({"username": app.test_profile.username.as_ref(),
+                                                    "password": Uuid::new_v4().to_string()});
+        app.post_login(&login_body).await
+    }
+
+    /// Skips the progressive delay so that the next attempt is counted
+    async fn skip_backoff(app: &TestApp) {
+        sqlx::query("UPDATE login_attempts SET next_attempt_at = NULL")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+    }
+
+    async fn lock_test_profile(app: &TestApp) {
+        for _ in 0..5 {
+            skip_backoff(app).await;
+            let response = post_wrong_password(app).await;
+            assert_eq!(response.status().as_u16(), 401);
+        }
+    }
+
+    #[actix_web::test]
+    async fn failed_login_delays_the_next_attempt() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        post_wrong_password(&app).await;
+
+        // Act
+        let response = app.test_profile.post_login(&app).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 429);
+        assert!(response.headers().get("Retry-After").is_some());
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn account_is_locked_after_repeated_failures() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        lock_test_profile(&app).await;
+
+        // Act - the backoff alone would have expired by now
+        sqlx::query("UPDATE login_attempts SET next_attempt_at = now() + interval '10 minutes' WHERE scope = 'username' AND next_attempt_at IS NULL")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let response = app.test_profile.post_login(&app).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 429);
+        let retry_after: u64 = response.headers()["Retry-After"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 60);
+
+        let row = sqlx::query("SELECT profile_id FROM audit_log WHERE action = 'account_locked'")
+            .fetch_one(&app.pool)
+            .await
+            .expect("Lockout was not recorded in the audit log");
+        assert_eq!(row.get::<Uuid, _>("profile_id"), app.test_profile.id);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn successful_login_resets_failed_attempts() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        post_wrong_password(&app).await;
+        skip_backoff(&app).await;
+
+        // Act
+        let response = app.test_profile.post_login(&app).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let result: i64 =
+            sqlx::query("SELECT COUNT(*) AS count FROM login_attempts WHERE scope = 'username'")
+                .fetch_one(&app.pool)
+                .await
+                .unwrap()
+                .get("count");
+        assert_eq!(result, 0);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn admin_can_unlock_a_locked_account() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        lock_test_profile(&app).await;
+
+        let admin = TestProfile::generate(true);
+        admin.store_test_profile(&app.pool).await;
+        admin.grant_role(&app.pool, "admin").await;
+        admin.post_login(&app).await;
+
+        // Act
+        let response = app
+            .api_client
+            .post(format!(
+                "{}/admin/profiles/{}/unlock",
+                &app.address, app.test_profile.id
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        app.post_logout().await;
+
+        // Assert
+        let response: StdResponse = app
+            .test_profile
+            .post_login(&app)
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert!(response.message.contains("Login Successful"));
+
+        let row = sqlx::query("SELECT actor_id FROM audit_log WHERE action = 'account_unlocked'")
+            .fetch_one(&app.pool)
+            .await
+            .expect("Unlock was not recorded in the audit log");
+        assert_eq!(row.get::<Uuid, _>("actor_id"), admin.id);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn only_admins_can_unlock_an_account() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        lock_test_profile(&app).await;
+
+        let other_profile = TestProfile::generate(true);
+        other_profile.store_test_profile(&app.pool).await;
+        other_profile.post_login(&app).await;
+
+        // Act
+        let response = app
+            .api_client
+            .post(format!(
+                "{}/admin/profiles/{}/unlock",
+                &app.address, app.test_profile.id
+            ))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 403);
+        let failures: Option<i32> = sqlx::query_scalar(
+            "SELECT failures FROM login_attempts WHERE scope = 'username' AND key = $1",
+        )
+        .bind(app.test_profile.username.as_ref())
+        .fetch_optional(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!(failures, Some(5), "The failed attempts were cleared");
+
+        app.drop_test_db().await;
+    }
+}
@@ -1,10 +1,24 @@
 mod admin_dashboard;
+mod admin_users;
 mod change_password;
 mod common;
+mod dead_letters;
+mod deliveries;
+mod email_events;
+mod email_templates;
 mod health_check;
 mod login;
+mod login_protection;
+mod metrics;
+mod notifications;
+mod oidc;
+mod password_rehash;
+mod password_reset;
+mod privacy;
 mod profile_checks;
 mod profile_confirm_checks;
+mod profile_email;
 mod refresh_token;
 mod task_checks;
 mod test_profile;
+mod two_factor;
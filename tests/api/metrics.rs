@@ -0,0 +1,75 @@
+use crate::common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::spawn_app;
+    use crate::test_profile::TestProfile;
+    use uuid::Uuid;
+
+    #[actix_web::test]
+    async fn metrics_are_served_on_their_own_port() {
+        // Arrange
+        let mut app = spawn_app().await;
+
+        // Act
+        let on_metrics_port = app.get_metrics().await;
+        let on_api_port = app
+            .api_client
+            .get(format!("{}/metrics", &app.address))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(on_metrics_port.status().as_u16(), 200);
+        assert!(
+            on_metrics_port.headers()["content-type"]
+                .to_str()
+                .unwrap()
+                .starts_with("text/plain")
+        );
+        assert_eq!(on_api_port.status().as_u16(), 404);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn metrics_report_requests_tasks_and_deliveries() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let profile = TestProfile::generate(true);
+        profile.store_test_profile(&app.pool).await;
+        profile.post_login(&app).await;
+        app.post_tasks(&serde_json::json!({"task_type": "feature", "source_file": "init.txt"}))
+            .await;
+        let task_id: Uuid = sqlx::query_scalar("SELECT id FROM task")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        for transition in ["start", "complete"] {
+            app.api_client
+                .put(format!("{}/task/{}/{}", app.address, task_id, transition))
+                .json(&serde_json::json!({"result_file": "out.txt"}))
+                .send()
+                .await
+                .unwrap();
+        }
+
+        // Act
+        let response = app.get_metrics().await;
+
+        // Assert
+        let metrics = response.text().await.unwrap();
+        assert!(metrics.contains(
+            r#"http_requests_total{method="PUT",route="/task/{task_id}/complete",status="200"}"#
+        ));
+        assert!(metrics.contains(r#"tasks{state="completed"} 1"#));
+        assert!(metrics.contains("task_duration_seconds_count"));
+        assert!(!metrics.contains("task_type"));
+        assert!(metrics.contains(r#"delivery_queue_depth{channel="email"}"#));
+        assert!(metrics.contains("idempotency_table_bytes"));
+        assert!(metrics.contains(r#"db_pool_connections{state="in_use"}"#));
+
+        app.drop_test_db().await;
+    }
+}
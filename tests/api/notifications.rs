@@ -0,0 +1,565 @@
+use crate::common;
+
+mod tests {
+    use super::common::{StdResponse, TestApp, spawn_app};
+    use sqlx::Row;
+    use taskservice::digest::try_drop_undeliverable_notifications;
+    use uuid::Uuid;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    async fn log_in_confirmed_profile(app: &TestApp) {
+        app.test_profile.store_test_profile(&app.pool).await;
+        sqlx::query("UPDATE profile SET status = 'confirmed' WHERE id = $1")
+            .bind(app.test_profile.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        app.test_profile.post_login(app).await;
+    }
+
+    async fn create_task(app: &TestApp, task_type: &str, workspace: Option<&str>) {
+        let response = app
+            .post_tasks(&serde_json::json!({
+                "task_type": task_type,
+                "source_file": "init.txt",
+                "workspace": workspace,
+                "idempotency_key": Uuid::new_v4().to_string(),
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    /// Task types of the deliveries waiting in the queue
+    async fn queued_task_types(app: &TestApp) -> Vec<String> {
+        sqlx::query(
+            "SELECT t.task_type FROM issue_delivery_queue q
+                JOIN task t ON t.id = q.task_issue_id
+                ORDER BY t.task_type",
+        )
+        .fetch_all(&app.pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.get("task_type"))
+        .collect()
+    }
+
+    #[actix_web::test]
+    async fn preferences_can_be_replaced_and_read_back() {
+        // Arrange
+        let mut app = spawn_app().await;
+        log_in_confirmed_profile(&app).await;
+
+        let body: serde_json::Value = app
+            .get_notification_preferences()
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"digest_frequency": "immediate", "rules": []})
+        );
+
+        let preferences = serde_json::json!({
+            "digest_frequency": "daily",
+            "rules": [
+                {"event_type": "task_created", "task_type": null, "workspace": null, "enabled": false},
+                {"event_type": "task_created", "task_type": "feature", "workspace": "ops", "enabled": true},
+            ],
+        });
+
+        // Act
+        let response = app.put_notification_preferences(&preferences).await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        // Assert
+        let body: serde_json::Value = app
+            .get_notification_preferences()
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body, preferences);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn opted_out_task_types_are_not_enqueued() {
+        // Arrange
+        let mut app = spawn_app().await;
+        log_in_confirmed_profile(&app).await;
+        app.put_notification_preferences(&serde_json::json!({
+            "rules": [{"event_type": "task_created", "task_type": "feature", "enabled": false}],
+        }))
+        .await;
+
+        // Act
+        create_task(&app, "feature", None).await;
+        create_task(&app, "bug", None).await;
+
+        // Assert
+        assert_eq!(queued_task_types(&app).await, vec!["bug"]);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn the_most_specific_rule_wins() {
+        // Arrange
+        let mut app = spawn_app().await;
+        log_in_confirmed_profile(&app).await;
+        app.put_notification_preferences(&serde_json::json!({
+            "rules": [
+                {"event_type": "task_created", "enabled": false},
+                {"event_type": "task_created", "workspace": "ops", "enabled": true},
+            ],
+        }))
+        .await;
+
+        // Act
+        create_task(&app, "bug", Some("ops")).await;
+        create_task(&app, "feature", Some("sales")).await;
+        create_task(&app, "research", None).await;
+
+        // Assert
+        assert_eq!(queued_task_types(&app).await, vec!["bug"]);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn one_click_unsubscribe_stops_further_notifications() {
+        // Arrange
+        let mut app = spawn_app().await;
+        log_in_confirmed_profile(&app).await;
+        Mock::given(path("/v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&app.email_server)
+            .await;
+        create_task(&app, "feature", None).await;
+        app.dispatch_all_pending_emails().await;
+
+        let email_request = &app.email_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        assert_eq!(
+            body["Headers"]["List-Unsubscribe-Post"],
+            "List-Unsubscribe=One-Click"
+        );
+        let header = body["Headers"]["List-Unsubscribe"].as_str().unwrap();
+        let mut unsubscribe_link =
+            reqwest::Url::parse(header.trim_start_matches('<').trim_end_matches('>')).unwrap();
+        unsubscribe_link.set_port(Some(app.port)).unwrap();
+        assert!(
+            body["Text-part"]
+                .as_str()
+                .unwrap()
+                .contains(unsubscribe_link.query().unwrap())
+        );
+
+        // Act - Part 1 - Following the link only asks for confirmation
+        let response = reqwest::Client::new()
+            .get(unsubscribe_link.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let page = response.text().await.unwrap();
+        assert!(page.contains(r#"method="post""#));
+        assert!(page.contains(unsubscribe_link.query().unwrap()));
+        create_task(&app, "bug", None).await;
+        assert_eq!(queued_task_types(&app).await, vec!["bug"]);
+
+        // Act - Part 2 - One-click unsubscribe
+        let response = reqwest::Client::new()
+            .post(unsubscribe_link)
+            .form(&[("List-Unsubscribe", "One-Click")])
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        create_task(&app, "chore", None).await;
+        assert_eq!(queued_task_types(&app).await, vec!["bug"]);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn forged_unsubscribe_links_are_rejected() {
+        // Arrange
+        let mut app = spawn_app().await;
+
+        // Act
+        let response = reqwest::Client::new()
+            .post(format!("{}/notifications/unsubscribe", app.address))
+            .query(&[("token", "not-a-signed-token")])
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        let body: StdResponse = response.json().await.unwrap();
+        assert_eq!(body.message, "Invalid unsubscribe link");
+
+        app.drop_test_db().await;
+    }
+
+    /// Puts the pending notifications and the last digest far enough in the past
+    /// for the next digest to be due
+    async fn make_digests_due(app: &TestApp) {
+        sqlx::query("UPDATE pending_notifications SET created_at = now() - interval '2 days'")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE profile SET last_digest_at = now() - interval '2 days'")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+    }
+
+    async fn n_pending_notifications(app: &TestApp) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM pending_notifications")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn digest_profiles_get_one_summary_of_new_and_finished_tasks() {
+        // Arrange
+        let mut app = spawn_app().await;
+        log_in_confirmed_profile(&app).await;
+        app.put_notification_preferences(&serde_json::json!({ "digest_frequency": "daily" }))
+            .await;
+        Mock::given(path("/v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&app.email_server)
+            .await;
+        create_task(&app, "bug", None).await;
+        create_task(&app, "feature", Some("ops")).await;
+        let task_ids: Vec<Uuid> = sqlx::query_scalar("SELECT id FROM task ORDER BY task_type")
+            .fetch_all(&app.pool)
+            .await
+            .unwrap();
+        let client = reqwest::Client::new();
+        client
+            .put(format!("{}/task/{}/complete", app.address, task_ids[0]))
+            .json(&serde_json::json!({ "result_file": "done.txt" }))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        client
+            .put(format!("{}/task/{}/fail", app.address, task_ids[1]))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        assert!(queued_task_types(&app).await.is_empty());
+        assert_eq!(n_pending_notifications(&app).await, 4);
+
+        // Act - Part 1 - Nothing is sent before the day is over
+        app.send_due_digests().await;
+        assert_eq!(n_pending_notifications(&app).await, 4);
+
+        // Act - Part 2
+        make_digests_due(&app).await;
+        app.send_due_digests().await;
+
+        // Assert
+        assert_eq!(n_pending_notifications(&app).await, 0);
+        let email_request = &app.email_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        assert_eq!(
+            body["Subject"],
+            "Your task digest: 2 new, 1 completed, 1 failed"
+        );
+        let text = body["Text-part"].as_str().unwrap();
+        assert!(text.contains("2 new tasks since your last digest"));
+        assert!(text.contains("1 task completed:\n- bug: init.txt"));
+        assert!(text.contains("1 task failed:\n- feature in ops: init.txt"));
+        let header = body["Headers"]["List-Unsubscribe"].as_str().unwrap();
+        let mut unsubscribe_link =
+            reqwest::Url::parse(header.trim_start_matches('<').trim_end_matches('>')).unwrap();
+        unsubscribe_link.set_port(Some(app.port)).unwrap();
+
+        // Act - Part 3 - The digest's link stops every event it lists
+        let response = client.post(unsubscribe_link).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let disabled_events: Vec<String> = sqlx::query_scalar(
+            "SELECT event_type FROM notification_preferences WHERE NOT enabled ORDER BY event_type",
+        )
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            disabled_events,
+            vec!["task_completed", "task_created", "task_failed"]
+        );
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn failed_digests_are_retried_without_counting_as_sent() {
+        // Arrange
+        let mut app = spawn_app().await;
+        log_in_confirmed_profile(&app).await;
+        app.put_notification_preferences(&serde_json::json!({ "digest_frequency": "daily" }))
+            .await;
+        Mock::given(path("/v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&app.email_server)
+            .await;
+        create_task(&app, "bug", None).await;
+        make_digests_due(&app).await;
+
+        // Act
+        app.send_due_digests().await;
+
+        // Assert
+        assert_eq!(n_pending_notifications(&app).await, 1);
+        let row = sqlx::query(
+            "SELECT last_digest_at < now() - interval '1 day' AS is_old,
+                digest_retry_at > now() AS is_deferred
+                FROM profile",
+        )
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+        assert!(row.get::<bool, _>("is_old"));
+        assert!(row.get::<bool, _>("is_deferred"));
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn notifications_held_for_suppressed_addresses_are_dropped() {
+        // Arrange
+        let mut app = spawn_app().await;
+        log_in_confirmed_profile(&app).await;
+        app.put_notification_preferences(&serde_json::json!({ "digest_frequency": "daily" }))
+            .await;
+        create_task(&app, "bug", None).await;
+        sqlx::query("UPDATE profile SET undeliverable_reason = 'hard_bounce'")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        // Act
+        let n_dropped = try_drop_undeliverable_notifications(&app.pool)
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(n_dropped, 1);
+        assert_eq!(n_pending_notifications(&app).await, 0);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn immediate_profiles_are_not_notified_of_finished_tasks() {
+        // Arrange
+        let mut app = spawn_app().await;
+        log_in_confirmed_profile(&app).await;
+        create_task(&app, "bug", None).await;
+        let task_id: Uuid = sqlx::query_scalar("SELECT id FROM task")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+
+        // Act
+        reqwest::Client::new()
+            .put(format!("{}/task/{}/fail", app.address, task_id))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+
+        // Assert
+        assert_eq!(queued_task_types(&app).await, vec!["bug"]);
+        assert_eq!(n_pending_notifications(&app).await, 0);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn in_app_notifications_land_in_the_inbox_until_read() {
+        // Arrange
+        let mut app = spawn_app().await;
+        log_in_confirmed_profile(&app).await;
+        let response = app
+            .put_notification_channels(&serde_json::json!({
+                "events": [{"event_type": "task_created", "channels": ["in_app"]}],
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+        Mock::given(path("/v3/send"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&app.email_server)
+            .await;
+
+        // Act
+        create_task(&app, "bug", Some("ops")).await;
+        app.dispatch_all_pending_emails().await;
+
+        // Assert
+        let inbox: serde_json::Value = app.get_notifications(true).await.json().await.unwrap();
+        let notifications = inbox["notifications"].as_array().unwrap();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0]["message"], "New bug task in ops: init.txt");
+        assert_eq!(notifications[0]["read"], false);
+
+        let notification_id = notifications[0]["id"].as_str().unwrap();
+        let response = app.post_notification_read(notification_id).await;
+        assert_eq!(response.status().as_u16(), 204);
+
+        let inbox: serde_json::Value = app.get_notifications(true).await.json().await.unwrap();
+        assert_eq!(inbox["notifications"], serde_json::json!([]));
+        let inbox: serde_json::Value = app.get_notifications(false).await.json().await.unwrap();
+        assert_eq!(inbox["notifications"][0]["read"], true);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn the_inbox_is_paged_with_a_cursor() {
+        // Arrange
+        let mut app = spawn_app().await;
+        log_in_confirmed_profile(&app).await;
+        let response = app
+            .put_notification_channels(&serde_json::json!({
+                "events": [{"event_type": "task_created", "channels": ["in_app"]}],
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+        for workspace in ["a", "b", "c"] {
+            create_task(&app, "bug", Some(workspace)).await;
+        }
+        app.dispatch_all_pending_emails().await;
+
+        // Act
+        let first: serde_json::Value = app
+            .api_client
+            .get(format!("{}/notifications?limit=2", &app.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .json()
+            .await
+            .unwrap();
+        let cursor = first["next_cursor"].as_str().unwrap();
+        let second: serde_json::Value = app
+            .api_client
+            .get(format!(
+                "{}/notifications?limit=2&cursor={}",
+                &app.address, cursor
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .json()
+            .await
+            .unwrap();
+
+        // Assert
+        let first = first["notifications"].as_array().unwrap();
+        let second_page = second["notifications"].as_array().unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(second_page.len(), 1);
+        assert_eq!(second["next_cursor"], serde_json::Value::Null);
+        assert!(first.iter().all(|n| n["id"] != second_page[0]["id"]));
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn webhook_profiles_are_called_on_every_chosen_event() {
+        // Arrange
+        let mut app = spawn_app().await;
+        log_in_confirmed_profile(&app).await;
+        app.put_notification_channels(&serde_json::json!({
+            "webhook_url": format!("{}/chat", app.email_server.uri()),
+            "events": [
+                {"event_type": "task_created", "channels": ["email", "webhook"]},
+                {"event_type": "task_failed", "channels": ["webhook"]},
+            ],
+        }))
+        .await;
+        Mock::given(path("/v3/send"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&app.email_server)
+            .await;
+        Mock::given(path("/chat"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2)
+            .mount(&app.email_server)
+            .await;
+        create_task(&app, "bug", None).await;
+        let task_id: Uuid = sqlx::query_scalar("SELECT id FROM task")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+
+        // Act
+        reqwest::Client::new()
+            .put(format!("{}/task/{}/fail", app.address, task_id))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        app.dispatch_all_pending_emails().await;
+
+        // Assert
+        let requests = app.email_server.received_requests().await.unwrap();
+        let failed = requests
+            .iter()
+            .filter(|r| r.url.path() == "/chat")
+            .map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap())
+            .find(|body| body["event_type"] == "task_failed")
+            .unwrap();
+        assert_eq!(failed["text"], "bug task failed: init.txt");
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn the_webhook_channel_needs_a_webhook_url() {
+        // Arrange
+        let mut app = spawn_app().await;
+        log_in_confirmed_profile(&app).await;
+
+        // Act
+        let response = app
+            .put_notification_channels(&serde_json::json!({
+                "events": [{"event_type": "task_created", "channels": ["webhook"]}],
+            }))
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+        let body: StdResponse = response.json().await.unwrap();
+        assert_eq!(body.message, "The webhook channel needs a webhook URL");
+
+        app.drop_test_db().await;
+    }
+}
@@ -0,0 +1,406 @@
+use crate::common;
+
+mod tests {
+    use std::collections::HashMap;
+
+    use super::common::{StdResponse, TestApp, spawn_app};
+    use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+    use sqlx::Row;
+    use taskservice::oidc::code_challenge;
+    use uuid::Uuid;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    const PRIVATE_KEY: &str = include_str!("fixtures/idp_private_key.pem");
+    const JWKS: &str = include_str!("fixtures/idp_jwks.json");
+
+    async fn mount_provider(app: &TestApp) {
+        let issuer = app.idp_server.uri();
+
+        Mock::given(path("/.well-known/openid-configuration"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{}/authorize", issuer),
+                "token_endpoint": format!("{}/token", issuer),
+                "jwks_uri": format!("{}/jwks", issuer),
+            })))
+            .mount(&app.idp_server)
+            .await;
+
+        Mock::given(path("/jwks"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(JWKS, "application/json"))
+            .mount(&app.idp_server)
+            .await;
+    }
+
+    /// Starts a login and returns the parameters of the authorization request
+    async fn start_login(app: &TestApp) -> HashMap<String, String> {
+        let response = app.get_oidc_login("mock").await;
+        assert_eq!(response.status().as_u16(), 303);
+
+        let location =
+            reqwest::Url::parse(response.headers()["Location"].to_str().unwrap()).unwrap();
+        assert!(location.as_str().starts_with(&app.idp_server.uri()));
+
+        location.query_pairs().into_owned().collect()
+    }
+
+    fn sign_id_token(app: &TestApp, kid: &str, claims: serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+
+        let mut payload = serde_json::json!({
+            "iss": app.idp_server.uri(),
+            "aud": "taskx",
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "iat": chrono::Utc::now().timestamp(),
+        });
+        payload
+            .as_object_mut()
+            .unwrap()
+            .extend(claims.as_object().unwrap().clone());
+
+        encode(
+            &header,
+            &payload,
+            &EncodingKey::from_rsa_pem(PRIVATE_KEY.as_bytes()).unwrap(),
+        )
+        .unwrap()
+    }
+
+    async fn mount_token_endpoint(app: &TestApp, id_token: &str) {
+        Mock::given(path("/token"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "idp-access-token",
+                "token_type": "Bearer",
+                "id_token": id_token,
+            })))
+            .mount(&app.idp_server)
+            .await;
+    }
+
+    async fn complete_login(app: &TestApp, email: &str, email_verified: bool) -> reqwest::Response {
+        mount_provider(app).await;
+        let params = start_login(app).await;
+
+        let id_token = sign_id_token(
+            app,
+            "test-key",
+            serde_json::json!({
+                "sub": "external-subject",
+                "nonce": params["nonce"],
+                "email": email,
+                "email_verified": email_verified,
+                "given_name": "Ursula",
+                "family_name": "Le Guin",
+            }),
+        );
+        mount_token_endpoint(app, &id_token).await;
+
+        app.get_oidc_callback(
+            "mock",
+            &[("code", "auth-code"), ("state", &params["state"])],
+        )
+        .await
+    }
+
+    #[actix_web::test]
+    async fn unknown_provider_is_rejected() {
+        // Arrange
+        let mut app = spawn_app().await;
+
+        // Act
+        let response = app.get_oidc_login("unknown").await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 404);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn login_redirects_to_the_provider_with_a_pkce_challenge() {
+        // Arrange
+        let mut app = spawn_app().await;
+        mount_provider(&app).await;
+
+        // Act
+        let params = start_login(&app).await;
+
+        // Assert
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["client_id"], "taskx");
+        assert_eq!(params["code_challenge_method"], "S256");
+        assert!(params["scope"].contains("openid"));
+        assert!(params["redirect_uri"].ends_with("/login/oidc/mock/callback"));
+        assert!(params.contains_key("state"));
+        assert!(params.contains_key("nonce"));
+        assert!(params.contains_key("code_challenge"));
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn verified_email_links_the_existing_profile() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+
+        // Act
+        let response = complete_login(&app, app.test_profile.email.as_ref(), true).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(response.headers().get("Authorization").is_some());
+        let body: StdResponse = response.json().await.unwrap();
+        assert!(body.message.contains("Login Successful"));
+
+        let row = sqlx::query(
+            "SELECT profile_id FROM external_identities WHERE provider = 'mock' AND subject = 'external-subject'",
+        )
+        .fetch_one(&app.pool)
+        .await
+        .expect("External identity was not linked");
+        assert_eq!(row.get::<Uuid, _>("profile_id"), app.test_profile.id);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn code_verifier_matches_the_challenge() {
+        // Arrange
+        let mut app = spawn_app().await;
+        mount_provider(&app).await;
+        let params = start_login(&app).await;
+        let id_token = sign_id_token(
+            &app,
+            "test-key",
+            serde_json::json!({
+                "sub": "external-subject",
+                "nonce": params["nonce"],
+                "email": "ursula@example.com",
+                "email_verified": true,
+            }),
+        );
+        mount_token_endpoint(&app, &id_token).await;
+
+        // Act
+        app.get_oidc_callback(
+            "mock",
+            &[("code", "auth-code"), ("state", &params["state"])],
+        )
+        .await;
+
+        // Assert
+        let requests = app.idp_server.received_requests().await.unwrap();
+        let token_request = requests
+            .iter()
+            .find(|r| r.url.path() == "/token")
+            .expect("Authorization code was not redeemed");
+        let body = String::from_utf8(token_request.body.clone()).unwrap();
+        let form: HashMap<String, String> =
+            reqwest::Url::parse(&format!("http://localhost/?{}", body))
+                .unwrap()
+                .query_pairs()
+                .into_owned()
+                .collect();
+        assert_eq!(form["grant_type"], "authorization_code");
+        assert_eq!(form["code"], "auth-code");
+        assert_eq!(
+            code_challenge(&form["code_verifier"]),
+            params["code_challenge"]
+        );
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn new_email_creates_a_confirmed_profile() {
+        // Arrange
+        let mut app = spawn_app().await;
+
+        // Act
+        let response = complete_login(&app, "ursula@example.com", true).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+
+        let row = sqlx::query("SELECT first_name, status FROM profile WHERE email = $1")
+            .bind("ursula@example.com")
+            .fetch_one(&app.pool)
+            .await
+            .expect("Profile was not created");
+        assert_eq!(row.get::<String, _>("status"), "confirmed");
+        assert_eq!(row.get::<String, _>("first_name"), "Ursula");
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn unverified_email_is_rejected() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+
+        // Act
+        let response = complete_login(&app, app.test_profile.email.as_ref(), false).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+
+        let row = sqlx::query("SELECT COUNT(*) AS count FROM external_identities")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(row.get::<i64, _>("count"), 0);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn suspended_profile_cannot_log_in_through_a_provider() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        sqlx::query("UPDATE profile SET status = 'suspended' WHERE id = $1")
+            .bind(app.test_profile.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        // Act
+        let response = complete_login(&app, app.test_profile.email.as_ref(), true).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert!(response.headers().get("Authorization").is_none());
+
+        let row = sqlx::query("SELECT status FROM profile WHERE id = $1")
+            .bind(app.test_profile.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(row.get::<String, _>("status"), "suspended");
+        let row = sqlx::query("SELECT COUNT(*) AS count FROM external_identities")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(row.get::<i64, _>("count"), 0);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn login_state_cannot_be_reused() {
+        // Arrange
+        let mut app = spawn_app().await;
+        mount_provider(&app).await;
+        let params = start_login(&app).await;
+        let id_token = sign_id_token(
+            &app,
+            "test-key",
+            serde_json::json!({
+                "sub": "external-subject",
+                "nonce": params["nonce"],
+                "email": "ursula@example.com",
+                "email_verified": true,
+            }),
+        );
+        mount_token_endpoint(&app, &id_token).await;
+        let query = [("code", "auth-code"), ("state", params["state"].as_str())];
+        let response = app.get_oidc_callback("mock", &query).await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        // Act
+        let response = app.get_oidc_callback("mock", &query).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn callback_from_another_session_is_rejected() {
+        // Arrange
+        let mut app = spawn_app().await;
+        mount_provider(&app).await;
+        let params = start_login(&app).await;
+        let id_token = sign_id_token(
+            &app,
+            "test-key",
+            serde_json::json!({
+                "sub": "external-subject",
+                "nonce": params["nonce"],
+                "email": "ursula@example.com",
+                "email_verified": true,
+            }),
+        );
+        mount_token_endpoint(&app, &id_token).await;
+
+        // Act - A victim's browser opens the callback of a login the attacker started
+        let response = reqwest::Client::new()
+            .get(format!("{}/login/oidc/mock/callback", &app.address))
+            .query(&[("code", "auth-code"), ("state", params["state"].as_str())])
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        let requests = app.idp_server.received_requests().await.unwrap();
+        assert!(requests.iter().all(|r| r.url.path() != "/token"));
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn id_token_with_unknown_key_or_wrong_nonce_is_rejected() {
+        // Arrange
+        let mut app = spawn_app().await;
+        mount_provider(&app).await;
+
+        let cases = [
+            ("other-key", None, "signed with an unknown key"),
+            ("test-key", Some("another-nonce"), "with the wrong nonce"),
+        ];
+
+        for (kid, nonce, description) in cases {
+            let params = start_login(&app).await;
+            let id_token = sign_id_token(
+                &app,
+                kid,
+                serde_json::json!({
+                    "sub": "external-subject",
+                    "nonce": nonce.unwrap_or(&params["nonce"]),
+                    "email": "ursula@example.com",
+                    "email_verified": true,
+                }),
+            );
+            app.idp_server.reset().await;
+            mount_provider(&app).await;
+            mount_token_endpoint(&app, &id_token).await;
+
+            // Act
+            let response = app
+                .get_oidc_callback(
+                    "mock",
+                    &[("code", "auth-code"), ("state", &params["state"])],
+                )
+                .await;
+
+            // Assert
+            assert_eq!(
+                response.status().as_u16(),
+                401,
+                "The API did not reject an ID token {}",
+                description
+            );
+        }
+
+        app.drop_test_db().await;
+    }
+}
@@ -0,0 +1,130 @@
+use crate::common;
+
+mod tests {
+    use super::common::{StdResponse, TestApp, spawn_app};
+    use argon2::password_hash::{SaltString, rand_core};
+    use argon2::{Argon2, Params, PasswordHasher};
+    use sqlx::Row;
+
+    const CURRENT_PREFIX: &str = "$argon2id$v=19$m=15000,t=2,p=1$";
+
+    async fn set_stored_hash(app: &TestApp, hash: &str) {
+        sqlx::query("UPDATE profile SET password = $1 WHERE id = $2")
+            .bind(hash)
+            .bind(app.test_profile.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+    }
+
+    async fn get_stored_hash(app: &TestApp) -> String {
+        sqlx::query("SELECT password FROM profile WHERE id = $1")
+            .bind(app.test_profile.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .get("password")
+    }
+
+    #[actix_web::test]
+    async fn bcrypt_hash_is_upgraded_on_login() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let legacy_hash = bcrypt::hash(app.test_profile.password.as_ref(), 4).unwrap();
+        set_stored_hash(&app, &legacy_hash).await;
+
+        // Act
+        let response: StdResponse = app
+            .test_profile
+            .post_login(&app)
+            .await
+            .json()
+            .await
+            .unwrap();
+
+        // Assert
+        assert!(response.message.contains("Login Successful"));
+        assert!(get_stored_hash(&app).await.starts_with(CURRENT_PREFIX));
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn argon2_hash_with_outdated_params_is_upgraded_on_login() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let salt = SaltString::generate(&mut rand_core::OsRng);
+        let outdated_hash = Argon2::new(
+            argon2::Algorithm::Argon2i,
+            argon2::Version::V0x13,
+            Params::new(4096, 3, 1, None).unwrap(),
+        )
+        .hash_password(app.test_profile.password.as_ref().as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+        set_stored_hash(&app, &outdated_hash).await;
+
+        // Act
+        let response = app.test_profile.post_login(&app).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(get_stored_hash(&app).await.starts_with(CURRENT_PREFIX));
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn failed_login_leaves_the_hash_alone() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let legacy_hash = bcrypt::hash(app.test_profile.password.as_ref(), 4).unwrap();
+        set_stored_hash(&app, &legacy_hash).await;
+
+        // Act
+        let login_body = serde_json::json!({"username": app.test_profile.username.as_ref(),
+                                                    "password": "not-the-password"});
+        let response = app.post_login(&login_body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(get_stored_hash(&app).await, legacy_hash);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn metrics_count_legacy_hashes() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let legacy_hash = bcrypt::hash(app.test_profile.password.as_ref(), 4).unwrap();
+        set_stored_hash(&app, &legacy_hash).await;
+
+        let admin = crate::test_profile::TestProfile::generate(true);
+        admin.store_test_profile(&app.pool).await;
+        admin.post_login(&app).await;
+
+        // Act
+        let get_stats = || {
+            app.api_client
+                .get(format!("{}/admin/metrics/password-hashes", &app.address))
+                .send()
+        };
+        let forbidden = get_stats().await.unwrap();
+        admin.grant_role(&app.pool, "admin").await;
+        let response = get_stats().await.unwrap();
+
+        // Assert
+        assert_eq!(forbidden.status().as_u16(), 403);
+        assert_eq!(response.status().as_u16(), 200);
+        let stats: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(stats["total"], 2);
+        assert_eq!(stats["legacy"], 1);
+
+        app.drop_test_db().await;
+    }
+}
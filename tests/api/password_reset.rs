@@ -0,0 +1,227 @@
+use crate::common;
+
+mod tests {
+    use super::common::{StdResponse, TestApp, spawn_app};
+    use uuid::Uuid;
+    use wiremock::matchers::{any, method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    async fn request_reset_token(app: &TestApp) -> String {
+        let _mock_guard = Mock::given(path("/v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+
+        let body = serde_json::json!({"email": app.test_profile.email.as_ref()});
+        let response = app.post_forgot_password(&body).await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        let email_request = app.wait_for_emails(1).await.pop().unwrap();
+        let links = app.get_confirmation_links(&email_request);
+
+        links
+            .html
+            .query_pairs()
+            .find(|(k, _)| k == "reset_token")
+            .map(|(_, v)| v.into_owned())
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn forgot_password_does_not_reveal_unknown_accounts() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&app.email_server)
+            .await;
+
+        // Act
+        let known = serde_json::json!({"email": app.test_profile.email.as_ref()});
+        let unknown = serde_json::json!({"email": "nobody@example.com"});
+        let known_response = app.post_forgot_password(&known).await;
+        let unknown_response = app.post_forgot_password(&unknown).await;
+
+        // Assert
+        assert_eq!(known_response.status(), unknown_response.status());
+        assert_eq!(
+            known_response.text().await.unwrap(),
+            unknown_response.text().await.unwrap()
+        );
+        app.wait_for_emails(1).await;
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn reset_link_changes_the_password() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let reset_token = request_reset_token(&app).await;
+
+        // Act
+        let new_password = Uuid::new_v4().to_string();
+        let body = serde_json::json!({"reset_token": reset_token,
+                                              "new_password": &new_password,
+                                              "new_password_check": &new_password});
+        let response: StdResponse = app.post_reset_password(&body).await.json().await.unwrap();
+        assert!(response.message.contains("Password Reset Successful"));
+
+        // Assert
+        let login_body = serde_json::json!({"username": app.test_profile.username.as_ref(),
+                                                    "password": &new_password});
+        let response: StdResponse = app.post_login(&login_body).await.json().await.unwrap();
+        assert!(response.message.contains("Login Successful"));
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn reset_link_opens_a_form_without_spending_the_token() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let reset_token = request_reset_token(&app).await;
+
+        // Act
+        let response = app
+            .api_client
+            .get(format!("{}/password/reset", &app.address))
+            .query(&[("reset_token", &reset_token)])
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(
+            response.headers()["Content-Type"]
+                .to_str()
+                .unwrap()
+                .starts_with("text/html")
+        );
+        let page = response.text().await.unwrap();
+        assert!(page.contains(r#"action="/password/reset" method="post""#));
+        assert!(page.contains(&format!(r#"name="reset_token" value="{reset_token}""#)));
+
+        let new_password = Uuid::new_v4().to_string();
+        let body = serde_json::json!({"reset_token": reset_token,
+                                              "new_password": &new_password,
+                                              "new_password_check": &new_password});
+        let response = app.post_reset_password(&body).await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn reset_token_can_only_be_used_once() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let reset_token = request_reset_token(&app).await;
+
+        let new_password = Uuid::new_v4().to_string();
+        let body = serde_json::json!({"reset_token": reset_token,
+                                              "new_password": &new_password,
+                                              "new_password_check": &new_password});
+
+        // Act
+        let first = app.post_reset_password(&body).await;
+        let second = app.post_reset_password(&body).await;
+
+        // Assert
+        assert_eq!(first.status().as_u16(), 200);
+        assert_eq!(second.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn expired_reset_token_is_rejected() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let reset_token = request_reset_token(&app).await;
+
+        sqlx::query("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        // Act
+        let new_password = Uuid::new_v4().to_string();
+        let body = serde_json::json!({"reset_token": reset_token,
+                                              "new_password": &new_password,
+                                              "new_password_check": &new_password});
+        let response = app.post_reset_password(&body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn reset_enforces_password_rules() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let reset_token = request_reset_token(&app).await;
+
+        // Act
+        let body = serde_json::json!({"reset_token": &reset_token,
+                                              "new_password": "short",
+                                              "new_password_check": "short"});
+        let response = app.post_reset_password(&body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 422);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn reset_revokes_existing_sessions_and_refresh_tokens() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+
+        let response = app
+            .api_client
+            .get(format!("{}/admin/dashboard", &app.address))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+
+        // Act
+        let reset_token = request_reset_token(&app).await;
+        let new_password = Uuid::new_v4().to_string();
+        let body = serde_json::json!({"reset_token": reset_token,
+                                              "new_password": &new_password,
+                                              "new_password_check": &new_password});
+        app.post_reset_password(&body).await;
+
+        // Assert
+        let response = app
+            .api_client
+            .get(format!("{}/admin/dashboard", &app.address))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+
+        let response = app.refresh_token().await;
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+}
@@ -0,0 +1,350 @@
+use crate::common;
+
+mod tests {
+    use super::common::{StdResponse, TestApp, spawn_app};
+    use crate::test_profile::TestProfile;
+    use sqlx::Row;
+    use taskservice::configuration::{DataPrivacySettings, DeletionMode, DeletionTaskPolicy};
+    use uuid::Uuid;
+
+    async fn store_task(app: &TestApp) -> Uuid {
+        let task_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO task (reporter_id, id, task_type, state, source_file) VALUES ($1, $2, 'render', 'notstarted', 'a.txt')")
+            .bind(app.test_profile.id)
+            .bind(task_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        task_id
+    }
+
+    /// Schedules the deletion and moves it into the past, as if the grace period had ended
+    async fn schedule_due_deletion(app: &TestApp) {
+        let response = app.post_deletion_request().await;
+        assert_eq!(response.status().as_u16(), 202);
+
+        sqlx::query("UPDATE deletion_requests SET scheduled_for = now() - interval '1 minute'")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+    }
+
+    async fn profile_exists(app: &TestApp) -> bool {
+        sqlx::query("SELECT id FROM profile WHERE id = $1")
+            .bind(app.test_profile.id)
+            .fetch_optional(&app.pool)
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    #[actix_web::test]
+    async fn you_must_be_logged_in_to_export_your_data() {
+        // Arrange
+        let mut app = spawn_app().await;
+
+        // Act
+        let response = app.post_data_export().await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn export_bundles_the_profile_data() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+        let task_id = store_task(&app).await;
+        sqlx::query("INSERT INTO delivery_log (id, task_issue_id, profile_email, status, latency_ms) VALUES ($1, $2, $3, 'sent', 12)")
+            .bind(Uuid::new_v4())
+            .bind(task_id)
+            .bind(app.test_profile.email.as_ref())
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        // Act - Part 1 - Request the export
+        let response = app.post_data_export().await;
+        assert_eq!(response.status().as_u16(), 202);
+        let body: serde_json::Value = response.json().await.unwrap();
+        let export_id = body["export_id"].as_str().unwrap().to_string();
+
+        let response = app.download_data_export(&export_id, "json").await;
+        assert_eq!(response.status().as_u16(), 409);
+
+        // Act - Part 2 - Let the worker build it
+        app.run_pending_exports().await;
+        let body: serde_json::Value = app.get_data_export(&export_id).await.json().await.unwrap();
+        assert_eq!(body["status"], "completed");
+
+        // Assert
+        let response = app.download_data_export(&export_id, "json").await;
+        assert_eq!(response.status().as_u16(), 200);
+        let bundle: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(bundle["profile"]["email"], app.test_profile.email.as_ref());
+        assert!(bundle["profile"].get("password").is_none());
+        assert_eq!(bundle["tasks"][0]["id"], task_id.to_string());
+        assert!(bundle["idempotency_records"].is_array());
+        assert!(bundle["deliveries"].is_array());
+        assert_eq!(
+            bundle["delivery_log"][0]["task_issue_id"],
+            task_id.to_string()
+        );
+        assert_eq!(bundle["delivery_log"][0]["status"], "sent");
+        assert!(bundle["audit_log"].is_array());
+
+        let response = app.download_data_export(&export_id, "zip").await;
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["Content-Type"], "application/zip");
+        assert!(response.bytes().await.unwrap().starts_with(b"PK"));
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn exports_of_other_profiles_cannot_be_downloaded() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let other_profile = TestProfile::generate(true);
+        other_profile.store_test_profile(&app.pool).await;
+        let export_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO data_exports (id, profile_id, status, bundle) VALUES ($1, $2, 'completed', '{}')")
+            .bind(export_id)
+            .bind(other_profile.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+
+        // Act
+        let response = app
+            .download_data_export(&export_id.to_string(), "json")
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 404);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn profile_is_only_deleted_once_the_grace_period_ends() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+        let task_id = store_task(&app).await;
+
+        // Act - Part 1 - Within the grace period
+        let response = app.post_deletion_request().await;
+        assert_eq!(response.status().as_u16(), 202);
+        app.run_due_deletions(&app.data_privacy).await;
+        assert!(profile_exists(&app).await);
+
+        // Act - Part 2 - Once the grace period has ended
+        schedule_due_deletion(&app).await;
+        app.run_due_deletions(&app.data_privacy).await;
+
+        // Assert
+        assert!(!profile_exists(&app).await);
+        let task = sqlx::query("SELECT id FROM task WHERE id = $1")
+            .bind(task_id)
+            .fetch_optional(&app.pool)
+            .await
+            .unwrap();
+        assert!(task.is_none());
+
+        let row = sqlx::query(
+            "SELECT details FROM audit_log WHERE profile_id = $1 AND action = 'deletion_requested'",
+        )
+        .bind(app.test_profile.id)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            row.get::<serde_json::Value, _>("details"),
+            serde_json::json!({})
+        );
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn anonymized_profile_hands_its_tasks_over() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let new_reporter = TestProfile::generate(true);
+        new_reporter.store_test_profile(&app.pool).await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+        let task_id = store_task(&app).await;
+        sqlx::query("UPDATE profile SET notification_webhook_url = 'https://hooks.example.com/me' WHERE id = $1")
+            .bind(app.test_profile.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO notification_channels (profile_id, event_type, channel) VALUES ($1, 'task_created', 'in_app')")
+            .bind(app.test_profile.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO inbox_notifications (id, profile_id, task_id, event_type, message) VALUES ($1, $2, $3, 'task_created', 'A new task was created')")
+            .bind(Uuid::new_v4())
+            .bind(app.test_profile.id)
+            .bind(task_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        let settings = DataPrivacySettings {
+            deletion_mode: DeletionMode::Anonymize,
+            task_policy: DeletionTaskPolicy::Reassign,
+            task_reassign_to: Some(new_reporter.id),
+            ..app.data_privacy.clone()
+        };
+
+        // Act
+        schedule_due_deletion(&app).await;
+        app.run_due_deletions(&settings).await;
+
+        // Assert
+        let row = sqlx::query(
+            "SELECT email, username, status, notification_webhook_url FROM profile WHERE id = $1",
+        )
+        .bind(app.test_profile.id)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+        assert_ne!(
+            row.get::<String, _>("email"),
+            app.test_profile.email.as_ref()
+        );
+        assert_ne!(
+            row.get::<String, _>("username"),
+            app.test_profile.username.as_ref()
+        );
+        assert_eq!(row.get::<String, _>("status"), "deleted");
+        assert_eq!(
+            row.get::<Option<String>, _>("notification_webhook_url"),
+            None
+        );
+
+        for table in ["notification_channels", "inbox_notifications"] {
+            let n_rows: i64 = sqlx::query(&format!(
+                "SELECT COUNT(*) AS count FROM {table} WHERE profile_id = $1"
+            ))
+            .bind(app.test_profile.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .get("count");
+            assert_eq!(n_rows, 0, "The profile's {table} were not deleted");
+        }
+
+        let reporter_id: Uuid = sqlx::query("SELECT reporter_id FROM task WHERE id = $1")
+            .bind(task_id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .get("reporter_id");
+        assert_eq!(reporter_id, new_reporter.id);
+
+        let response = app.refresh_token().await;
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn cancelled_deletion_is_never_carried_out() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+        schedule_due_deletion(&app).await;
+
+        // Act
+        let response = app.delete_deletion_request().await;
+        assert_eq!(response.status().as_u16(), 200);
+        app.run_due_deletions(&app.data_privacy).await;
+
+        // Assert
+        assert!(profile_exists(&app).await);
+
+        let response = app.delete_deletion_request().await;
+        assert_eq!(response.status().as_u16(), 404);
+        let body: StdResponse = response.json().await.unwrap();
+        assert_eq!(body.message, "No deletion scheduled");
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn you_must_be_logged_in_to_delete_a_profile() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+
+        // Act
+        let response = app.delete_profile(app.test_profile.id).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert!(profile_exists(&app).await);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn other_profiles_cannot_be_deleted() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let other_profile = TestProfile::generate(true);
+        other_profile.store_test_profile(&app.pool).await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+
+        // Act
+        let response = app.delete_profile(other_profile.id).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 403);
+        let scheduled = sqlx::query("SELECT profile_id FROM deletion_requests")
+            .fetch_optional(&app.pool)
+            .await
+            .unwrap();
+        assert!(scheduled.is_none());
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn deleting_your_own_profile_waits_for_the_grace_period() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+
+        // Act
+        let response = app.delete_profile(app.test_profile.id).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 202);
+        app.run_due_deletions(&app.data_privacy).await;
+        assert!(profile_exists(&app).await);
+
+        let scheduled = sqlx::query_scalar::<_, Uuid>("SELECT profile_id FROM deletion_requests")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(scheduled, app.test_profile.id);
+
+        app.drop_test_db().await;
+    }
+}
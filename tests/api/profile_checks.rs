@@ -208,4 +208,51 @@ mod tests {
 
         app.drop_test_db().await;
     }
+
+    #[actix_web::test]
+    async fn retried_profile_creation_replays_the_first_response() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let test_profile = TestProfile::generate(false);
+        let mut body = HashMap::new();
+        body.insert("first_name", test_profile.first_name.as_ref());
+        body.insert("last_name", test_profile.first_name.as_ref());
+        body.insert("email", test_profile.email.as_ref());
+        body.insert("username", test_profile.username.as_ref());
+        body.insert("password", test_profile.password.as_ref());
+
+        Mock::given(path("v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&app.email_server)
+            .await;
+
+        let idempotency_key = uuid::Uuid::new_v4().to_string();
+        let post = || {
+            app.api_client
+                .post(format!("{}/profile", &app.address))
+                .header("Idempotency-Key", &idempotency_key)
+                .json(&body)
+                .send()
+        };
+
+        // Act
+        let first = post().await.unwrap();
+        let first_status = first.status();
+        let first_body = first.text().await.unwrap();
+        let second = post().await.unwrap();
+
+        // Assert
+        assert_eq!(first_status.as_u16(), 200);
+        assert_eq!(second.status(), first_status);
+        assert_eq!(second.text().await.unwrap(), first_body);
+        let n_profiles: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM profile")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(n_profiles, 1);
+
+        app.drop_test_db().await;
+    }
 }
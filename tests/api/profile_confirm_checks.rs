@@ -1,8 +1,10 @@
 use crate::common;
 mod tests {
-    use super::common::spawn_app;
+    use super::common::{ConfirmationLinks, StdResponse, TestApp, spawn_app};
     use sqlx::Row;
     use std::collections::HashMap;
+    use taskservice::configuration::ProfileConfirmationSettings;
+    use taskservice::token_cleanup::try_expire_profile_tokens;
     use wiremock::matchers::{method, path};
     use wiremock::{Mock, ResponseTemplate};
 
@@ -130,4 +132,193 @@ mod tests {
 
         app.drop_test_db().await;
     }
+
+    async fn create_pending_profile(app: &TestApp) -> ConfirmationLinks {
+        let test_profile = &app.test_profile;
+        let mut body = HashMap::new();
+        body.insert("first_name", test_profile.first_name.as_ref());
+        body.insert("last_name", test_profile.last_name.as_ref());
+        body.insert("email", test_profile.email.as_ref());
+        body.insert("username", test_profile.username.as_ref());
+        body.insert("password", test_profile.password.as_ref());
+
+        Mock::given(path("/v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&app.email_server)
+            .await;
+
+        app.post_profiles(&body).await;
+
+        let email_request = &app.email_server.received_requests().await.unwrap()[0];
+        app.get_confirmation_links(email_request)
+    }
+
+    #[actix_web::test]
+    async fn used_confirmation_link_is_rejected_with_a_409() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let confirmation_links = create_pending_profile(&app).await;
+        let response = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+
+        // Act
+        let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 409);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn expired_confirmation_link_is_rejected_with_a_410() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let confirmation_links = create_pending_profile(&app).await;
+        sqlx::query("UPDATE profile_tokens SET expires_at = now() - interval '1 minute'")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        // Act
+        let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 410);
+        let status: String = sqlx::query("SELECT status FROM profile")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .get("status");
+        assert_eq!(status, "pending_confirmation");
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn unknown_confirmation_token_is_rejected_with_a_401() {
+        // Arrange
+        let mut app = spawn_app().await;
+
+        // Act
+        let response = reqwest::get(format!(
+            "{}/profile/confirm?profile_token=not-a-token",
+            app.address
+        ))
+        .await
+        .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn resent_confirmation_link_confirms_the_profile() {
+        // Arrange
+        let mut app = spawn_app().await;
+        create_pending_profile(&app).await;
+
+        // Act
+        let response = app
+            .post_resend_confirmation(
+                &serde_json::json!({"email": app.test_profile.email.as_ref()}),
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        // Assert
+        let email_requests = app.email_server.received_requests().await.unwrap();
+        assert_eq!(email_requests.len(), 2);
+        let confirmation_links = app.get_confirmation_links(&email_requests[1]);
+        let response: StdResponse = reqwest::get(confirmation_links.html)
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(response.message.contains("Profile confirmed"));
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn resending_confirmation_is_rate_limited_per_address() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let body = serde_json::json!({"email": "nobody@example.com"});
+        for _ in 0..3 {
+            let response = app.post_resend_confirmation(&body).await;
+            assert_eq!(response.status().as_u16(), 200);
+        }
+
+        // Act
+        let response = app.post_resend_confirmation(&body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 429);
+        assert!(response.headers().get("Retry-After").is_some());
+
+        let response = app
+            .post_resend_confirmation(&serde_json::json!({"email": "somebody@example.com"}))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn cleanup_deletes_tokens_past_their_retention_only() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let confirmation_links = create_pending_profile(&app).await;
+        app.post_resend_confirmation(
+            &serde_json::json!({"email": app.test_profile.email.as_ref()}),
+        )
+        .await;
+        app.post_resend_confirmation(
+            &serde_json::json!({"email": app.test_profile.email.as_ref()}),
+        )
+        .await;
+        // The first token has only just expired, the second one expired long ago
+        for (offset, expired_for) in [(0, "1 minute"), (1, "2 hours")] {
+            sqlx::query(
+                "UPDATE profile_tokens SET expires_at = now() - $2::INTERVAL
+                        WHERE profile_token = (SELECT profile_token FROM profile_tokens ORDER BY created_at OFFSET $1 LIMIT 1)",
+            )
+            .bind(offset as i64)
+            .bind(expired_for)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        }
+
+        // Act
+        let settings = ProfileConfirmationSettings {
+            token_expire_minutes: 1440,
+            resend_limit: 3,
+            resend_window_minutes: 60,
+            cleanup_interval_seconds: 3600,
+            token_retention_minutes: 60,
+        };
+        let n_deleted = try_expire_profile_tokens(&app.pool, &settings)
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(n_deleted, 1);
+        let remaining: i64 = sqlx::query("SELECT COUNT(*) AS count FROM profile_tokens")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .get("count");
+        assert_eq!(remaining, 2);
+        // The token still kept is reported as expired rather than unknown
+        let response = reqwest::get(confirmation_links.html).await.unwrap();
+        assert_eq!(response.status().as_u16(), 410);
+
+        app.drop_test_db().await;
+    }
 }
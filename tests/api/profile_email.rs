@@ -0,0 +1,211 @@
+use crate::common;
+
+mod tests {
+    use super::common::{StdResponse, TestApp, spawn_app};
+    use sqlx::Row;
+    use uuid::Uuid;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    const NEW_EMAIL: &str = "ursula@example.com";
+
+    fn recipient(email_request: &wiremock::Request) -> String {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        body["Recipients"][0]["email"].as_str().unwrap().to_string()
+    }
+
+    /// Requests the change and returns the confirmation and revert links
+    async fn request_change(app: &TestApp) -> (reqwest::Url, reqwest::Url) {
+        let _mock_guard = Mock::given(path("/v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2)
+            .mount_as_scoped(&app.email_server)
+            .await;
+
+        let response = app
+            .post_email_change(&serde_json::json!({"new_email": NEW_EMAIL}))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        let email_requests = app.email_server.received_requests().await.unwrap();
+        let confirm = &email_requests[email_requests.len() - 2];
+        let revert = &email_requests[email_requests.len() - 1];
+        assert_eq!(recipient(confirm), NEW_EMAIL);
+        assert_eq!(recipient(revert), app.test_profile.email.as_ref());
+
+        (
+            app.get_confirmation_links(confirm).html,
+            app.get_confirmation_links(revert).html,
+        )
+    }
+
+    async fn stored_email(app: &TestApp) -> String {
+        sqlx::query("SELECT email FROM profile WHERE id = $1")
+            .bind(app.test_profile.id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .get("email")
+    }
+
+    #[actix_web::test]
+    async fn you_must_be_logged_in_to_change_your_email() {
+        // Arrange
+        let mut app = spawn_app().await;
+
+        // Act
+        let response = app
+            .post_email_change(&serde_json::json!({"new_email": NEW_EMAIL}))
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn email_is_only_changed_once_the_new_address_is_confirmed() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+
+        // Act - Part 1 - Request the change
+        let (confirm_link, _) = request_change(&app).await;
+        assert_eq!(stored_email(&app).await, app.test_profile.email.as_ref());
+
+        // Act - Part 2 - Follow the link sent to the new address
+        let response = reqwest::get(confirm_link.clone()).await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(stored_email(&app).await, NEW_EMAIL);
+
+        let response = reqwest::get(confirm_link).await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn pending_deliveries_follow_the_new_address() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+
+        let task_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO task (reporter_id, id, task_type, state, source_file) VALUES ($1, $2, 'render', 'notstarted', 'a.txt')")
+            .bind(app.test_profile.id)
+            .bind(task_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO issue_delivery_queue (task_issue_id, profile_email, n_retries) VALUES ($1, $2, 0)")
+            .bind(task_id)
+            .bind(app.test_profile.email.as_ref())
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        // Act
+        let (confirm_link, _) = request_change(&app).await;
+        reqwest::get(confirm_link).await.unwrap();
+
+        // Assert
+        let queued: String =
+            sqlx::query("SELECT profile_email FROM issue_delivery_queue WHERE task_issue_id = $1")
+                .bind(task_id)
+                .fetch_one(&app.pool)
+                .await
+                .unwrap()
+                .get("profile_email");
+        assert_eq!(queued, NEW_EMAIL);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn revert_link_restores_the_old_address() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+        let (confirm_link, revert_link) = request_change(&app).await;
+        reqwest::get(confirm_link).await.unwrap();
+
+        // Act
+        let response: StdResponse = reqwest::get(revert_link)
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        // Assert
+        assert!(response.message.contains("Email change reverted"));
+        assert_eq!(stored_email(&app).await, app.test_profile.email.as_ref());
+
+        // Sessions opened by whoever made the change are revoked
+        let response = app.post_logout().await;
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn revert_link_does_nothing_once_the_address_changed_again() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+        let (confirm_link, revert_link) = request_change(&app).await;
+        reqwest::get(confirm_link).await.unwrap();
+        sqlx::query("UPDATE profile SET email = 'later@example.com' WHERE id = $1")
+            .bind(app.test_profile.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        // Act
+        let response = reqwest::get(revert_link).await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(stored_email(&app).await, "later@example.com");
+        let reverted = sqlx::query(
+            "SELECT reverted_at IS NOT NULL AS reverted FROM email_change_requests WHERE profile_id = $1",
+        )
+        .bind(app.test_profile.id)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .get::<bool, _>("reverted");
+        assert!(!reverted);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn email_of_another_profile_is_rejected() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+
+        let other = crate::test_profile::TestProfile::generate(true);
+        other.store_test_profile(&app.pool).await;
+
+        // Act
+        let response = app
+            .post_email_change(&serde_json::json!({"new_email": other.email.as_ref()}))
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 409);
+
+        app.drop_test_db().await;
+    }
+}
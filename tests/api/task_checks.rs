@@ -14,7 +14,15 @@ mod tests {
     use super::common::spawn_app;
     use crate::common::{ConfirmationLinks, StdResponse, TestApp};
     use crate::test_profile::TestProfile;
+    use actix_web::HttpResponse;
+    use actix_web::http::Method;
     use sqlx::Row;
+    use sqlx::postgres::PgListener;
+    use taskservice::idempotency::{
+        IdempotencyKey, NextAction, get_saved_response, release_key, request_fingerprint,
+        save_response, try_idem_processing,
+    };
+    use taskservice::issue_delivery::{DELIVERY_CHANNEL, ExecutionOutcome, try_execute_delivery};
 
     async fn create_unconfirmed_profile(app: &TestApp, profile: &TestProfile) -> ConfirmationLinks {
         // Act
@@ -116,6 +124,77 @@ mod tests {
         app.drop_test_db().await;
     }
 
+    #[actix_web::test]
+    async fn deliveries_are_dequeued_in_batches() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.delivery.batch_size = 10;
+        for profile in [TestProfile::generate(false), TestProfile::generate(false)] {
+            profile.store_test_profile(&app.pool).await;
+        }
+        app.test_profile.store_test_profile(&app.pool).await;
+        sqlx::query("UPDATE profile SET status = 'confirmed'")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        app.test_profile.post_login(&app).await;
+
+        Mock::given(path("v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(3)
+            .mount(&app.email_server)
+            .await;
+
+        let task_request_body = serde_json::json!({"task_type": "feature", "source_file": "init.txt", "idempotency_key": Uuid::new_v4().to_string()});
+        app.post_tasks(&task_request_body).await;
+
+        // Act
+        let outcome = try_execute_delivery(
+            &app.pool,
+            &app.notification_channels,
+            &app.unsubscribe_links,
+            &app.delivery,
+            None,
+        )
+        .await
+        .unwrap();
+
+        // Assert
+        assert!(matches!(outcome, ExecutionOutcome::TaskCompleted));
+        let n_queued: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM issue_delivery_queue")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(n_queued, 0);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn queued_deliveries_notify_the_workers() {
+        // Arrange
+        let mut app = spawn_app().await;
+        create_confirmed_profile(&app, &app.test_profile).await;
+        app.test_profile.post_login(&app).await;
+
+        let mut listener = PgListener::connect_with(&app.pool).await.unwrap();
+        listener.listen(DELIVERY_CHANNEL).await.unwrap();
+
+        // Act
+        let task_request_body = serde_json::json!({"task_type": "feature", "source_file": "init.txt", "idempotency_key": Uuid::new_v4().to_string()});
+        app.post_tasks(&task_request_body).await;
+
+        // Assert
+        let notification = tokio::time::timeout(Duration::from_secs(5), listener.recv())
+            .await
+            .expect("The workers were not notified")
+            .unwrap();
+        assert_eq!(notification.channel(), DELIVERY_CHANNEL);
+
+        app.drop_test_db().await;
+    }
+
     #[actix_web::test]
     async fn tasks_returns_400_for_invalid_data() {
         // Arrange
@@ -324,7 +403,7 @@ mod tests {
         let reqs = app.email_server.received_requests().await.unwrap();
         assert_eq!(reqs.len(), 1);
 
-        std::thread::sleep(Duration::from_secs(app.idempotency_expiration));
+        std::thread::sleep(app.idempotency.retention());
 
         app.expire_idempotency_keys().await;
 
@@ -338,4 +417,259 @@ mod tests {
 
         app.drop_test_db().await;
     }
+
+    #[actix_web::test]
+    async fn idempotency_metrics_report_purged_keys() {
+        // Arrange
+        let mut app = spawn_app().await;
+        create_confirmed_profile(&app, &app.test_profile).await;
+        app.test_profile.post_login(&app).await;
+
+        Mock::given(path("/v3/send"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&app.email_server)
+            .await;
+
+        let task_request_body = serde_json::json!({"task_type": "feature", "source_file": "init.txt", "idempotency_key": Uuid::new_v4().to_string()});
+        app.post_tasks(&task_request_body).await;
+        std::thread::sleep(app.idempotency.retention());
+        app.expire_idempotency_keys().await;
+
+        // Act
+        let get_stats = || {
+            app.api_client
+                .get(format!("{}/admin/metrics/idempotency", &app.address))
+                .send()
+        };
+        let forbidden = get_stats().await.unwrap();
+        app.test_profile.grant_role(&app.pool, "admin").await;
+        let response = get_stats().await.unwrap();
+
+        // Assert
+        assert_eq!(forbidden.status().as_u16(), 403);
+        assert_eq!(response.status().as_u16(), 200);
+        let stats: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(stats["records"], 0);
+        assert!(stats["table_bytes"].as_i64().unwrap() > 0);
+        // Other tests in this binary purge keys too
+        assert!(stats["purged_records"].as_u64().unwrap() >= 1);
+
+        app.dispatch_all_pending_emails().await;
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn retried_state_transitions_replay_the_first_response() {
+        // Arrange
+        let mut app = spawn_app().await;
+        create_confirmed_profile(&app, &app.test_profile).await;
+        app.test_profile.post_login(&app).await;
+        let task_request_body = serde_json::json!({"task_type": "feature", "source_file": "init.txt", "idempotency_key": Uuid::new_v4().to_string()});
+        app.post_tasks(&task_request_body).await;
+        let task_id: Uuid = sqlx::query_scalar("SELECT id FROM task")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+
+        let idempotency_key = Uuid::new_v4().to_string();
+        let fail = |idempotency_key: Option<&str>| {
+            let request =
+                reqwest::Client::new().put(format!("{}/task/{}/fail", app.address, task_id));
+            match idempotency_key {
+                Some(key) => request.header("Idempotency-Key", key),
+                None => request,
+            }
+            .send()
+        };
+
+        // Act
+        let first = fail(Some(&idempotency_key)).await.unwrap();
+        let retried = fail(Some(&idempotency_key)).await.unwrap();
+        let repeated = fail(None).await.unwrap();
+
+        // Assert
+        assert_eq!(first.status().as_u16(), 200);
+        assert_eq!(retried.status().as_u16(), 200);
+        assert_ne!(repeated.status().as_u16(), 200);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn anonymous_clients_sharing_a_key_do_not_get_each_others_responses() {
+        // Arrange
+        let mut app = spawn_app().await;
+        create_confirmed_profile(&app, &app.test_profile).await;
+        app.test_profile.post_login(&app).await;
+        for task_type in ["feature", "bug"] {
+            app.post_tasks(&serde_json::json!({"task_type": task_type, "source_file": "init.txt", "idempotency_key": Uuid::new_v4().to_string()}))
+                .await;
+        }
+        let task_ids: Vec<Uuid> = sqlx::query_scalar("SELECT id FROM task")
+            .fetch_all(&app.pool)
+            .await
+            .unwrap();
+        let idempotency_key = Uuid::new_v4().to_string();
+        let fail = |task_id: Uuid| {
+            reqwest::Client::new()
+                .put(format!("{}/task/{}/fail", app.address, task_id))
+                .header("Idempotency-Key", &idempotency_key)
+                .send()
+        };
+
+        // Act
+        let first = fail(task_ids[0]).await.unwrap();
+        let second = fail(task_ids[1]).await.unwrap();
+
+        // Assert
+        assert_eq!(first.status().as_u16(), 200);
+        assert_eq!(second.status().as_u16(), 200);
+        let n_failed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM task WHERE state = 'failed'")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(n_failed, 2);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn reusing_an_idempotency_key_for_another_task_is_rejected() {
+        // Arrange
+        let mut app = spawn_app().await;
+        create_confirmed_profile(&app, &app.test_profile).await;
+        app.test_profile.post_login(&app).await;
+        let idempotency_key = Uuid::new_v4().to_string();
+        let response = app
+            .post_tasks(&serde_json::json!({"task_type": "feature", "source_file": "init.txt", "idempotency_key": idempotency_key}))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        // Act
+        let response = app
+            .post_tasks(&serde_json::json!({"task_type": "bug", "source_file": "init.txt", "idempotency_key": idempotency_key}))
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 422);
+        let n_tasks: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM task")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(n_tasks, 1);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn retries_of_a_request_in_flight_get_409_until_its_lock_times_out() {
+        // Arrange
+        let mut app = spawn_app().await;
+        create_confirmed_profile(&app, &app.test_profile).await;
+        app.test_profile.post_login(&app).await;
+        let task_request_body = serde_json::json!({"task_type": "feature", "source_file": "init.txt", "idempotency_key": Uuid::new_v4().to_string()});
+        app.post_tasks(&task_request_body).await;
+        let task_id: Uuid = sqlx::query_scalar("SELECT id FROM task")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+
+        // A request that claimed the key and has not answered yet
+        let idempotency_key = Uuid::new_v4().to_string();
+        let stored_key = IdempotencyKey::try_from(idempotency_key.clone())
+            .unwrap()
+            .scoped_to_request(&request_fingerprint(
+                &Method::PUT,
+                &format!("/task/{task_id}/fail"),
+                b"",
+            ));
+        sqlx::query("INSERT INTO idempotency (profile_id, idempotency_key) VALUES (NULL, $1)")
+            .bind(stored_key.as_ref())
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let fail = || {
+            reqwest::Client::new()
+                .put(format!("{}/task/{}/fail", app.address, task_id))
+                .header("Idempotency-Key", &idempotency_key)
+                .send()
+        };
+
+        // Act
+        let in_flight = fail().await.unwrap();
+        sqlx::query("UPDATE idempotency SET locked_at = now() - interval '1 day' WHERE idempotency_key = $1")
+            .bind(stored_key.as_ref())
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let after_crash = fail().await.unwrap();
+
+        // Assert
+        assert_eq!(in_flight.status().as_u16(), 409);
+        assert_eq!(in_flight.headers()["Retry-After"], "1");
+        assert_eq!(after_crash.status().as_u16(), 200);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn request_whose_key_was_taken_over_cannot_touch_it_anymore() {
+        // Arrange
+        let mut app = spawn_app().await;
+        let mut settings = app.idempotency.clone();
+        settings.lock_timeout_seconds = 0;
+        let idempotency_key = IdempotencyKey::try_from(Uuid::new_v4().to_string()).unwrap();
+        let claim =
+            || try_idem_processing(&app.pool, &idempotency_key, None, "fingerprint", &settings);
+        let NextAction::StartProcessing(stale_claim) = claim().await.unwrap() else {
+            panic!("The key was not claimed");
+        };
+        // The first request is taken for crashed and its key is taken over
+        let NextAction::StartProcessing(claim) = claim().await.unwrap() else {
+            panic!("The key was not taken over");
+        };
+
+        // Act - Part 1 - The first request finishes after all
+        save_response(
+            &app.pool,
+            &idempotency_key,
+            None,
+            stale_claim,
+            HttpResponse::Ok().body("stale"),
+        )
+        .await
+        .unwrap();
+        release_key(&app.pool, &idempotency_key, None, stale_claim)
+            .await
+            .unwrap();
+
+        // Assert - Part 1
+        let saved = get_saved_response(&app.pool, &idempotency_key, None)
+            .await
+            .unwrap();
+        assert!(saved.is_none());
+
+        // Act - Part 2
+        save_response(
+            &app.pool,
+            &idempotency_key,
+            None,
+            claim,
+            HttpResponse::Ok().body("fresh"),
+        )
+        .await
+        .unwrap();
+
+        // Assert - Part 2
+        let saved = get_saved_response(&app.pool, &idempotency_key, None)
+            .await
+            .unwrap()
+            .unwrap();
+        let body = actix_web::body::to_bytes(saved.into_body()).await.unwrap();
+        assert_eq!(body.as_ref(), b"fresh");
+
+        app.drop_test_db().await;
+    }
 }
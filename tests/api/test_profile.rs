@@ -4,6 +4,7 @@ use fake::Fake;
 use fake::faker::internet::en::{Password, SafeEmail, Username};
 use fake::faker::name::en::{FirstName, LastName};
 use sqlx::PgPool;
+use taskservice::configuration::PasswordHashingSettings;
 use taskservice::domain::{
     email::ProfileEmail, name::ProfileName, password, username::ProfileUsername,
 };
@@ -37,6 +38,7 @@ impl TestProfile {
             username: ProfileUsername::parse(Username().fake()).unwrap(),
             password: password::Password::parse(
                 Password(std::ops::Range { start: 8, end: 16 }).fake(),
+                &PasswordHashingSettings::default(),
             )
             .unwrap(),
         }
@@ -67,6 +69,15 @@ impl TestProfile {
     .expect("Failed to create test user. ");
     }
 
+    pub async fn grant_role(&self, pool: &PgPool, role: &str) {
+        sqlx::query("INSERT INTO profile_roles (profile_id, role) VALUES ($1, $2)")
+            .bind(self.id)
+            .bind(role)
+            .execute(pool)
+            .await
+            .expect("Failed to grant role to test user. ");
+    }
+
     pub async fn post_login(&self, app: &TestApp) -> reqwest::Response {
         let login_body = serde_json::json!({"username": self.username.as_ref(),
                                                     "password": self.password.as_ref()});
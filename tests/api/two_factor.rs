@@ -0,0 +1,332 @@
+use crate::common;
+
+mod tests {
+    use super::common::{StdResponse, TestApp, spawn_app};
+    use totp_rs::{Algorithm, Secret, TOTP};
+
+    #[derive(serde::Deserialize)]
+    struct Enrollment {
+        secret: String,
+        provisioning_uri: String,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct RecoveryCodes {
+        recovery_codes: Vec<String>,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Challenge {
+        challenge: String,
+    }
+
+    fn current_code(secret: &str) -> String {
+        TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            Secret::Encoded(secret.to_string()).to_bytes().unwrap(),
+            None,
+            String::new(),
+        )
+        .unwrap()
+        .generate_current()
+        .unwrap()
+    }
+
+    /// Enrolls the test profile and logs it back out, returning the secret and recovery codes
+    async fn enable_two_factor(app: &TestApp) -> (String, Vec<String>) {
+        app.test_profile.post_login(app).await;
+
+        let enrollment: Enrollment = app.post_two_factor_enroll().await.json().await.unwrap();
+        let body = serde_json::json!({"code": current_code(&enrollment.secret)});
+        let response = app.post_two_factor_verify(&body).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let codes: RecoveryCodes = response.json().await.unwrap();
+        // As if the code had been entered a step ago, so that the current one logs in
+        sqlx::query("UPDATE profile SET totp_last_step = totp_last_step - 1 WHERE id = $1")
+            .bind(app.test_profile.id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        app.post_logout().await;
+
+        (enrollment.secret, codes.recovery_codes)
+    }
+
+    /// Skips the progressive delay so that the next attempt is counted
+    async fn skip_backoff(app: &TestApp) {
+        sqlx::query("UPDATE login_attempts SET next_attempt_at = NULL")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+    }
+
+    async fn get_dashboard_status(app: &TestApp) -> u16 {
+        app.api_client
+            .get(format!("{}/admin/dashboard", &app.address))
+            .send()
+            .await
+            .unwrap()
+            .status()
+            .as_u16()
+    }
+
+    #[actix_web::test]
+    async fn enrollment_returns_a_provisioning_uri() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+
+        // Act
+        let response = app.post_two_factor_enroll().await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let enrollment: Enrollment = response.json().await.unwrap();
+        assert!(enrollment.provisioning_uri.starts_with("otpauth://totp/"));
+        assert!(enrollment.provisioning_uri.contains(&enrollment.secret));
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn verification_rejects_a_wrong_code() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        app.test_profile.post_login(&app).await;
+        app.post_two_factor_enroll().await;
+
+        // Act
+        let body = serde_json::json!({"code": "000000x"});
+        let response = app.post_two_factor_verify(&body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn login_returns_a_challenge_instead_of_tokens_when_enabled() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        enable_two_factor(&app).await;
+
+        // Act
+        let response = app.test_profile.post_login(&app).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(response.headers().get("authorization").is_none());
+        assert!(response.cookies().all(|c| c.name() != "refresh_token"));
+        let challenge: Challenge = response.json().await.unwrap();
+        assert!(!challenge.challenge.is_empty());
+
+        assert_eq!(get_dashboard_status(&app).await, 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn second_step_with_a_valid_code_issues_the_session() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let (secret, _) = enable_two_factor(&app).await;
+        let challenge: Challenge = app
+            .test_profile
+            .post_login(&app)
+            .await
+            .json()
+            .await
+            .unwrap();
+
+        // Act
+        let body =
+            serde_json::json!({"challenge": challenge.challenge, "code": current_code(&secret)});
+        let response = app.post_login_two_factor(&body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(response.headers().get("authorization").is_some());
+        let response: StdResponse = response.json().await.unwrap();
+        assert!(response.message.contains("Login Successful"));
+
+        assert_eq!(get_dashboard_status(&app).await, 200);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn totp_code_cannot_be_replayed() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let (secret, _) = enable_two_factor(&app).await;
+        let code = current_code(&secret);
+
+        // Act - 1
+        let challenge: Challenge = app
+            .test_profile
+            .post_login(&app)
+            .await
+            .json()
+            .await
+            .unwrap();
+        let body = serde_json::json!({"challenge": challenge.challenge, "code": &code});
+        let response = app.post_login_two_factor(&body).await;
+        assert_eq!(response.status().as_u16(), 200);
+        app.post_logout().await;
+
+        // Act - 2
+        let challenge: Challenge = app
+            .test_profile
+            .post_login(&app)
+            .await
+            .json()
+            .await
+            .unwrap();
+        let body = serde_json::json!({"challenge": challenge.challenge, "code": &code});
+        let response = app.post_login_two_factor(&body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn recovery_codes_can_only_be_used_once() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let (_, recovery_codes) = enable_two_factor(&app).await;
+
+        // Act - 1
+        let challenge: Challenge = app
+            .test_profile
+            .post_login(&app)
+            .await
+            .json()
+            .await
+            .unwrap();
+        let body =
+            serde_json::json!({"challenge": challenge.challenge, "code": &recovery_codes[0]});
+        let response = app.post_login_two_factor(&body).await;
+        assert_eq!(response.status().as_u16(), 200);
+        app.post_logout().await;
+
+        // Act - 2
+        let challenge: Challenge = app
+            .test_profile
+            .post_login(&app)
+            .await
+            .json()
+            .await
+            .unwrap();
+        let body =
+            serde_json::json!({"challenge": challenge.challenge, "code": &recovery_codes[0]});
+        let response = app.post_login_two_factor(&body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn challenge_is_locked_after_too_many_wrong_codes() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let (secret, _) = enable_two_factor(&app).await;
+        let challenge: Challenge = app
+            .test_profile
+            .post_login(&app)
+            .await
+            .json()
+            .await
+            .unwrap();
+
+        for _ in 0..5 {
+            skip_backoff(&app).await;
+            let body = serde_json::json!({"challenge": &challenge.challenge, "code": "wrong"});
+            let response = app.post_login_two_factor(&body).await;
+            assert_eq!(response.status().as_u16(), 401);
+        }
+        // Only the challenge's own limit is left to reject the code
+        sqlx::query("DELETE FROM login_attempts")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        // Act
+        let body =
+            serde_json::json!({"challenge": &challenge.challenge, "code": current_code(&secret)});
+        let response = app.post_login_two_factor(&body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+
+        app.drop_test_db().await;
+    }
+
+    #[actix_web::test]
+    async fn wrong_codes_count_towards_the_login_lockout() {
+        // Arrange
+        let mut app = spawn_app().await;
+        app.test_profile.store_test_profile(&app.pool).await;
+        let (secret, _) = enable_two_factor(&app).await;
+        let challenge: Challenge = app
+            .test_profile
+            .post_login(&app)
+            .await
+            .json()
+            .await
+            .unwrap();
+        let body = serde_json::json!({"challenge": &challenge.challenge, "code": "wrong"});
+        let response = app.post_login_two_factor(&body).await;
+        assert_eq!(response.status().as_u16(), 401);
+
+        // Act - 1
+        let body =
+            serde_json::json!({"challenge": &challenge.challenge, "code": current_code(&secret)});
+        let response = app.post_login_two_factor(&body).await;
+
+        // Assert - 1
+        assert_eq!(response.status().as_u16(), 429);
+
+        // Act - 2
+        for _ in 0..4 {
+            skip_backoff(&app).await;
+            let challenge: Challenge = app
+                .test_profile
+                .post_login(&app)
+                .await
+                .json()
+                .await
+                .unwrap();
+            let body = serde_json::json!({"challenge": &challenge.challenge, "code": "wrong"});
+            let response = app.post_login_two_factor(&body).await;
+            assert_eq!(response.status().as_u16(), 401);
+        }
+        let response = app.test_profile.post_login(&app).await;
+
+        // Assert - 2
+        assert_eq!(response.status().as_u16(), 429);
+        let retry_after: u64 = response.headers()["Retry-After"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 60);
+
+        app.drop_test_db().await;
+    }
+}